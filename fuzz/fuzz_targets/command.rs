@@ -0,0 +1,8 @@
+//!Разбор данных пакета: Command::parse, decode, RspPacket::new
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate gdb_rsp_researcher;
+
+fuzz_target!(|data: &[u8]| {
+    gdb_rsp_researcher::fuzz::command(data);
+});
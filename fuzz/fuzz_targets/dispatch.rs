@@ -0,0 +1,8 @@
+//!Полная обработка байт от GDB сервером с обработчиками по умолчанию, с DummyTarget и SimTarget
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate gdb_rsp_researcher;
+
+fuzz_target!(|data: &[u8]| {
+    gdb_rsp_researcher::fuzz::dispatch(data);
+});
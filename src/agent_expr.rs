@@ -0,0 +1,460 @@
+use target::Target;
+use codec::hex_decode;
+
+
+///Глубина стека вычислителя (как у gdbserver)
+pub const STACK_MAX: usize = 100;
+///Больше стольких операций не исполняется: выражение с goto может не завершиться
+pub const STEP_MAX: usize = 0x1_0000;
+///Строка %s в printf читается из памяти цели не длиннее, ширина и точность в printf не больше
+pub const PRINTF_STRING_MAX: usize = 0x1000;
+
+
+///Операции агентских выражений GDB (gdb/ax.def): (код, имя, байт операнда). Операнды - big-endian
+///printf: количество аргументов (1 байт), длина формата (2 байта), затем сам формат с '\0'
+pub const OPS: &[(u8, &str, usize)] = &[
+    (0x01, "float", 0), (0x02, "add", 0), (0x03, "sub", 0), (0x04, "mul", 0),
+    (0x05, "div_signed", 0), (0x06, "div_unsigned", 0), (0x07, "rem_signed", 0), (0x08, "rem_unsigned", 0),
+    (0x09, "lsh", 0), (0x0a, "rsh_signed", 0), (0x0b, "rsh_unsigned", 0), (0x0c, "trace", 0),
+    (0x0d, "trace_quick", 1), (0x0e, "log_not", 0), (0x0f, "bit_and", 0), (0x10, "bit_or", 0),
+    (0x11, "bit_xor", 0), (0x12, "bit_not", 0), (0x13, "equal", 0), (0x14, "less_signed", 0),
+    (0x15, "less_unsigned", 0), (0x16, "ext", 1), (0x17, "ref8", 0), (0x18, "ref16", 0),
+    (0x19, "ref32", 0), (0x1a, "ref64", 0), (0x1b, "ref_float", 0), (0x1c, "ref_double", 0),
+    (0x1d, "ref_long_double", 0), (0x1e, "l_to_d", 0), (0x1f, "d_to_l", 0), (0x20, "if_goto", 2),
+    (0x21, "goto", 2), (0x22, "const8", 1), (0x23, "const16", 2), (0x24, "const32", 4),
+    (0x25, "const64", 8), (0x26, "reg", 2), (0x27, "end", 0), (0x28, "dup", 0),
+    (0x29, "pop", 0), (0x2a, "zero_ext", 1), (0x2b, "swap", 0), (0x2c, "getv", 2),
+    (0x2d, "setv", 2), (0x2e, "tracev", 2), (0x2f, "tracenz", 0), (0x30, "trace16", 2),
+    (0x32, "pick", 1), (0x33, "rot", 0), (0x34, "printf", 3),
+];
+
+
+///Результат вычисления агентского выражения
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Evaluation
+{
+    pub value: Option<u64>,                         // Вершина стека на end (None - стек пуст)
+    pub memory: Vec<(u64, Vec<u8>)>,                // Память, собранная trace, trace_quick, trace16, tracenz
+    pub output: String,                             // Вывод printf
+}
+
+
+///Одна операция: (код, операнд, формат printf, адрес следующей операции)
+struct Instruction<'a>
+{
+    op: u8,
+    operand: u64,
+    format: &'a [u8],
+    next: usize,
+}
+
+
+///Разбор операции по адресу pc
+fn instruction(bytecode: &[u8], pc: usize) -> Result<Instruction<'_>, String>
+{
+    let op = *bytecode.get(pc).ok_or_else(|| "Выражение закончилось без end".to_string())?;
+    let &(_, _, len) = OPS.iter().find(|&&(code, _, _)| code == op).ok_or_else(|| format!("Неизвестная операция 0x{:02x}", op))?;
+    let bytes = bytecode.get(pc + 1 .. pc + 1 + len).ok_or_else(|| format!("Нет операнда у операции 0x{:02x}", op))?;
+    let operand = bytes.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+    let mut next = pc + 1 + len;
+    let mut format = &[][..];
+    if op == 0x34
+    {//printf: операнд - количество аргументов и длина формата
+        let format_len = (operand & 0xffff) as usize;
+        format = bytecode.get(next .. next + format_len).ok_or_else(|| "Нет формата printf".to_string())?;
+        next += format_len;
+    }
+    Ok(Instruction{ op, operand, format, next })
+}
+
+
+///Вычисление агентского выражения. Память и регистры (текущего потока) читаются через Target
+///Значения - 64-битные, память и регистры - little-endian. Плавающая точка и переменные трассировки не поддерживаются
+pub fn eval(bytecode: &[u8], target: &mut dyn Target) -> Result<Evaluation, String>
+{
+    let mut stack: Vec<u64> = Vec::new();
+    let mut result = Evaluation::default();
+    let mut pc = 0;
+    for _ in 0..STEP_MAX
+    {
+        let insn = instruction(bytecode, pc)?;
+        pc = insn.next;
+        let value = match insn.op
+        {
+            0x02..=0x0b | 0x0f..=0x11 | 0x13..=0x15 =>
+            {//Бинарные операции: a - под вершиной, b - вершина
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                if b == 0 && (0x05..=0x08).contains(&insn.op)
+                {
+                    return Err("Деление на ноль".to_string());
+                }
+                Some(match insn.op
+                {
+                    0x02 => a.wrapping_add(b),
+                    0x03 => a.wrapping_sub(b),
+                    0x04 => a.wrapping_mul(b),
+                    0x05 => (a as i64).wrapping_div(b as i64) as u64,
+                    0x06 => a / b,
+                    0x07 => (a as i64).wrapping_rem(b as i64) as u64,
+                    0x08 => a % b,
+                    0x09 => a.wrapping_shl(b as u32),
+                    0x0a => (a as i64).wrapping_shr(b as u32) as u64,
+                    0x0b => a.wrapping_shr(b as u32),
+                    0x0f => a & b,
+                    0x10 => a | b,
+                    0x11 => a ^ b,
+                    0x13 => u64::from(a == b),
+                    0x14 => u64::from((a as i64) < (b as i64)),
+                    _ => u64::from(a < b),
+                })
+            },
+            0x0e => Some(u64::from(pop(&mut stack)? == 0)),
+            0x12 => Some(!pop(&mut stack)?),
+            0x16 =>
+            {//ext: знаковое расширение из n бит
+                let value = pop(&mut stack)?;
+                let shift = 64 - insn.operand.clamp(1, 64) as u32;
+                Some((((value << shift) as i64) >> shift) as u64)
+            },
+            0x2a =>
+            {//zero_ext: беззнаковое расширение из n бит
+                let value = pop(&mut stack)?;
+                Some(if insn.operand < 64 {value & ((1 << insn.operand) - 1)} else {value})
+            },
+            0x17..=0x1a =>
+            {//ref8..ref64
+                let size = 1 << (insn.op - 0x17);
+                let bytes = target.read_memory(pop(&mut stack)?, size).map_err(|_| "Память недоступна".to_string())?;
+                Some(le_value(&bytes))
+            },
+            0x22..=0x25 => Some(insn.operand),
+            0x26 =>
+            {
+                let reg = target.read_register(insn.operand as usize).map_err(|_| format!("Регистр {} недоступен", insn.operand))?;
+                Some(le_value(&reg))
+            },
+            0x0c | 0x2f =>
+            {//trace, tracenz: адрес под вершиной, длина - на вершине (блок памяти кадра - не больше 64K)
+                let len = pop(&mut stack)?.min(u64::from(u16::MAX)) as usize;
+                let addr = pop(&mut stack)?;
+                let mut bytes = target.read_memory(addr, len).map_err(|_| "Память недоступна".to_string())?;
+                if let (0x2f, Some(zero)) = (insn.op, bytes.iter().position(|&b| b == 0))
+                {
+                    bytes.truncate(zero + 1);
+                }
+                result.memory.push((addr, bytes));
+                None
+            },
+            0x0d | 0x30 =>
+            {//trace_quick, trace16: адрес остается на стеке
+                let addr = *stack.last().ok_or_else(|| "Стек пуст".to_string())?;
+                let bytes = target.read_memory(addr, insn.operand as usize).map_err(|_| "Память недоступна".to_string())?;
+                result.memory.push((addr, bytes));
+                None
+            },
+            0x20 =>
+            {
+                if pop(&mut stack)? != 0
+                {
+                    pc = insn.operand as usize;
+                }
+                None
+            },
+            0x21 =>
+            {
+                pc = insn.operand as usize;
+                None
+            },
+            0x27 =>
+            {
+                result.value = stack.last().copied();
+                return Ok(result);
+            },
+            0x28 => Some(*stack.last().ok_or_else(|| "Стек пуст".to_string())?),
+            0x29 =>
+            {
+                pop(&mut stack)?;
+                None
+            },
+            0x2b =>
+            {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.extend_from_slice(&[b, a]);
+                None
+            },
+            0x32 =>
+            {//pick n: копия n-го элемента под вершиной (pick 0 - dup)
+                let depth = insn.operand as usize;
+                Some(*stack.iter().rev().nth(depth).ok_or_else(|| "Стек пуст".to_string())?)
+            },
+            0x33 =>
+            {//rot: a b c -> b c a
+                let len = stack.len();
+                if len < 3
+                {
+                    return Err("Стек пуст".to_string());
+                }
+                stack[len - 3 ..].rotate_left(1);
+                None
+            },
+            0x34 =>
+            {//printf: функция и канал (не используются), затем аргументы - первый на вершине
+                pop(&mut stack)?;
+                pop(&mut stack)?;
+                let args = (0..insn.operand >> 16).map(|_| pop(&mut stack)).collect::<Result<Vec<u64>, String>>()?;
+                let format = insn.format.strip_suffix(&[0]).ok_or_else(|| "Формат printf без '\\0'".to_string())?;
+                result.output += &printf(&String::from_utf8_lossy(format), &args, target);
+                None
+            },
+            0x01 | 0x1b..=0x1f => return Err("Плавающая точка не поддерживается".to_string()),
+            _ => return Err("Переменные трассировки не поддерживаются".to_string()), //getv, setv, tracev
+        };
+        if let Some(value) = value
+        {
+            if stack.len() >= STACK_MAX
+            {
+                return Err("Переполнение стека".to_string());
+            }
+            stack.push(value);
+        }
+    }
+    Err("Превышено количество операций".to_string())
+}
+
+
+///Условие истинно: значение выражения не 0
+///Ошибка вычисления тоже считается истинным условием (останов, GDB разберется сам)
+pub fn condition_holds(bytecode: &[u8], target: &mut dyn Target) -> bool
+{
+    eval(bytecode, target).map_or(true, |result| result.value.unwrap_or(0) != 0)
+}
+
+
+///Агентские выражения подряд, как в условиях и командах Z-пакета: X<len>,<bytecode>X<len>,<bytecode>...
+///None - некорректная запись
+pub fn parse_expressions(text: &str) -> Option<Vec<Vec<u8>>>
+{
+    let mut expressions = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty()
+    {
+        let (len, after) = rest.strip_prefix('X')?.split_once(',')?;
+        let hex_len = usize::from_str_radix(len, 16).ok()?.checked_mul(2)?;
+        expressions.push(hex_decode(after.get(..hex_len)?)?);
+        rest = &after[hex_len..];
+    }
+    Some(expressions)
+}
+
+
+///Текст выражения: "reg 10, const8 5, equal, end". Некорректная операция - "<...>" с описанием ошибки
+pub fn disassemble(bytecode: &[u8]) -> String
+{
+    let mut ops = Vec::new();
+    let mut pc = 0;
+    while pc < bytecode.len()
+    {
+        match instruction(bytecode, pc)
+        {
+            Ok(insn) =>
+            {
+                let &(_, name, len) = OPS.iter().find(|&&(code, _, _)| code == insn.op).unwrap();
+                ops.push(match (insn.op, len)
+                {
+                    (0x34, _) => format!("printf {:?} {}", String::from_utf8_lossy(insn.format.strip_suffix(&[0]).unwrap_or(insn.format)), insn.operand >> 16),
+                    (_, 0) => name.to_string(),
+                    _ => format!("{} {}", name, insn.operand),
+                });
+                pc = insn.next;
+            },
+            Err(error) =>
+            {
+                ops.push(format!("<{}>", error));
+                break;
+            },
+        }
+    }
+    ops.join(", ")
+}
+
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, String>
+{
+    stack.pop().ok_or_else(|| "Стек пуст".to_string())
+}
+
+
+///Значение little-endian (не длиннее 8 байт)
+fn le_value(bytes: &[u8]) -> u64
+{
+    bytes.iter().take(8).rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+
+///printf агентского выражения: %d %i %u %x %X %o %c %s %p %% с флагами '-' и '0', шириной и модификаторами длины
+///Без l/ll/z/j/t значение - 32-битное, %s - строка из памяти цели
+fn printf(format: &str, args: &[u64], target: &mut dyn Target) -> String
+{
+    let mut output = String::new();
+    let mut args = args.iter().copied();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next()
+    {
+        if c != '%'
+        {
+            output.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek()
+        {
+            if !"-+ #0123456789.hlLqjzt".contains(c)
+            {
+                break;
+            }
+            spec.push(c);
+            chars.next();
+        }
+        let conversion = match chars.next()
+        {
+            Some(c) => c,
+            None => break,
+        };
+        if conversion == '%'
+        {
+            output.push('%');
+            continue;
+        }
+        let arg = args.next().unwrap_or(0);
+        let long = spec.contains(|c: char| "lLqjzt".contains(c));
+        let (signed, unsigned) = if long {(arg as i64, arg)} else {(i64::from(arg as i32), u64::from(arg as u32))};
+        //Ширина и точность приходят от GDB: не больше PRINTF_STRING_MAX, иначе отступ может не поместиться в память
+        let precision = spec.split_once('.').and_then(|(_, p)| p.trim_end_matches(|c: char| c.is_alphabetic()).parse::<usize>().ok()).map(|p| p.min(PRINTF_STRING_MAX));
+        let body = match conversion
+        {
+            'd' | 'i' => signed.to_string(),
+            'u' => unsigned.to_string(),
+            'x' => format!("{:x}", unsigned),
+            'X' => format!("{:X}", unsigned),
+            'o' => format!("{:o}", unsigned),
+            'c' => char::from(arg as u8).to_string(),
+            'p' => format!("0x{:x}", arg),
+            's' =>
+            {//Строка до '\0'. Недоступная память - "(null)" как у printf
+                let mut bytes = Vec::new();
+                let limit = precision.unwrap_or(PRINTF_STRING_MAX);
+                while let Ok(byte) = target.read_memory(arg.wrapping_add(bytes.len() as u64), 1)
+                {
+                    if byte[0] == 0 || bytes.len() >= limit
+                    {
+                        break;
+                    }
+                    bytes.push(byte[0]);
+                }
+                if arg == 0 {"(null)".to_string()} else {String::from_utf8_lossy(&bytes).into_owned()}
+            },
+            other => format!("%{}{}", spec, other),
+        };
+        let width = spec.trim_start_matches(|c: char| "-+ #0".contains(c)).split(|c: char| !c.is_ascii_digit()).next().and_then(|w| w.parse::<usize>().ok()).unwrap_or(0).min(PRINTF_STRING_MAX);
+        let padding = width.saturating_sub(body.chars().count());
+        if spec.contains('-')
+        {
+            output += &body;
+            output += &" ".repeat(padding);
+        }
+        else if spec.starts_with('0') && "diuxXo".contains(conversion)
+        {
+            match body.strip_prefix('-')
+            {
+                Some(digits) => output += &format!("-{}{}", "0".repeat(padding), digits),
+                None => output += &format!("{}{}", "0".repeat(padding), body),
+            }
+        }
+        else
+        {
+            output += &" ".repeat(padding);
+            output += &body;
+        }
+    }
+    output
+}
+
+
+///Тесты для агентских выражений ================================================================================
+#[cfg(test)]
+mod test_agent_expr
+{
+    use super::*;
+    use sim::{SimTarget, A0_REG};
+
+    #[test]
+    fn test_eval()
+    {
+        let mut target = SimTarget::new(false);
+        target.write_register(A0_REG, &5u64.to_le_bytes()).unwrap();
+        target.write_memory(0x8000_1000, &[0xfe, 0xff, 0xff, 0xff, b'h', b'i', 0]).unwrap();
+        let value = |bytecode: &[u8], target: &mut SimTarget| eval(bytecode, target).map(|result| result.value);
+
+        //a0 == 5, a0 < 3
+        assert_eq!(Ok(Some(1)), value(&[0x26, 0, 10, 0x22, 5, 0x13, 0x27], &mut target));
+        assert_eq!(Ok(Some(0)), value(&[0x26, 0, 10, 0x22, 3, 0x14, 0x27], &mut target));
+        //*(int *)0x80001000 со знаковым расширением: -2
+        assert_eq!(Ok(Some(-2i64 as u64)), value(&[0x24, 0x80, 0, 0x10, 0, 0x19, 0x16, 32, 0x27], &mut target));
+        //Цикл: сумма 1..=4 через if_goto, goto и rot
+        let sum = [0x22, 0, 0x22, 4, //s = 0, n = 4
+            0x28, 0x0e, 0x20, 0, 19, //n == 0 -> конец
+            0x28, 0x33, 0x02, 0x2b, 0x22, 1, 0x03, 0x21, 0, 4, //s += n, n -= 1
+            0x29, 0x27];
+        assert_eq!(Ok(Some(10)), value(&sum, &mut target));
+
+        //Ошибки
+        assert_eq!(Err("Деление на ноль".to_string()), value(&[0x22, 1, 0x22, 0, 0x05, 0x27], &mut target));
+        assert_eq!(Err("Стек пуст".to_string()), value(&[0x02, 0x27], &mut target));
+        assert_eq!(Err("Выражение закончилось без end".to_string()), value(&[0x22, 1], &mut target));
+        assert_eq!(Err("Превышено количество операций".to_string()), value(&[0x21, 0, 0], &mut target));
+        assert!(condition_holds(&[0x01], &mut target));
+        assert!(!condition_holds(&[0x22, 0, 0x27], &mut target));
+    }
+
+    #[test]
+    fn test_trace_and_printf()
+    {
+        let mut target = SimTarget::new(false);
+        target.write_memory(0x8000_1000, b"hi\0rest").unwrap();
+        target.write_register(A0_REG, &0x8000_1000u64.to_le_bytes()).unwrap();
+        //trace a0,4; tracenz a0,8
+        let result = eval(&[0x26, 0, 10, 0x22, 4, 0x0c, 0x26, 0, 10, 0x22, 8, 0x2f, 0x26, 0, 10, 0x0d, 2, 0x27], &mut target).unwrap();
+        assert_eq!(vec![(0x8000_1000, b"hi\0r".to_vec()), (0x8000_1000, b"hi\0".to_vec()), (0x8000_1000, b"hi".to_vec())], result.memory);
+        assert_eq!(Some(0x8000_1000), result.value);
+
+        //printf "%s=%-4d|%04x|%c\n", a0, -3, 0x2a, 'z': аргументы в обратном порядке, затем функция и канал
+        let format = b"%s=%-4d|%04x|%c\n\0";
+        let mut bytecode = vec![0x22, b'z', 0x22, 0x2a, 0x22, 3, 0x12, 0x22, 1, 0x02, 0x26, 0, 10, 0x22, 0, 0x22, 0, 0x34, 4, 0, format.len() as u8];
+        bytecode.extend_from_slice(format);
+        bytecode.push(0x27);
+        assert_eq!("hi=-3  |002a|z\n", eval(&bytecode, &mut target).unwrap().output);
+        assert_eq!("reg 10, const8 0, const8 0, printf \"%s=%-4d|%04x|%c\\n\" 4, end", disassemble(&bytecode[10..]));
+        assert_eq!("const8 1, <Нет операнда у операции 0x23>", disassemble(&[0x22, 1, 0x23, 0]));
+
+        //Ширина от GDB ограничена PRINTF_STRING_MAX
+        let format = b"%99999999999999999d|%-99999999999999999x\0";
+        let mut bytecode = vec![0x22, 0x2a, 0x22, 7, 0x22, 0, 0x22, 0, 0x34, 2, 0, format.len() as u8];
+        bytecode.extend_from_slice(format);
+        bytecode.push(0x27);
+        let output = eval(&bytecode, &mut target).unwrap().output;
+        assert_eq!(2 * PRINTF_STRING_MAX + 1, output.len());
+        assert!(output.starts_with(' ') && output.contains("7|2a "));
+    }
+
+    #[test]
+    fn test_parse_expressions()
+    {
+        assert_eq!(Some(vec![vec![0x22, 1, 0x27], vec![0x27]]), parse_expressions("X3,220127X1,27"));
+        assert_eq!(Some(Vec::new()), parse_expressions(""));
+        assert_eq!(None, parse_expressions("X3,2201"));
+        assert_eq!(None, parse_expressions("cmds:0,X1,27"));
+        assert_eq!(None, parse_expressions("X8000000000000000,00"));
+    }
+}
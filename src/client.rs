@@ -0,0 +1,424 @@
+use std::io;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::str;
+
+use codec::{checksum, frame_len, hex_encode, hex_decode, escape_binary, unescape_binary};
+use host_io::{O_WRONLY, O_CREAT, O_TRUNC};
+
+
+///Размер блока файла в vFile:pread/pwrite (с экранированием пакет не длиннее PACKET_SIZE по умолчанию)
+pub const FILE_CHUNK: usize = 1024;
+
+
+///Статистика обмена клиента с сервером
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClientStats
+{
+    pub packets_sent: usize,                        // Отправлено пакетов $...#cs
+    pub packets_received: usize,                    // Принято пакетов $...#cs (включая O-пакеты)
+    pub bytes_sent: usize,                          // Отправлено байт (вместе с acknowledgment)
+    pub bytes_received: usize,                      // Принято байт (вместе с acknowledgment)
+    pub acks_received: usize,                       // Принято acknowledgment '+'
+}
+
+
+///Ответ сервера на запрос
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply
+{
+    pub data: String,                               // Данные последнего пакета ответа (без $ и #cs)
+    pub console: String,                            // Текст из O-пакетов, пришедших перед ответом
+}
+
+
+///RSP-клиент (со стороны GDB) поверх любого потока: TcpStream, UnixStream, транспорт в памяти
+pub struct RspClient<S: Read + Write>
+{
+    stream: S,
+    pending: Vec<u8>,                               // Принятые, но еще не разобранные данные
+    pub no_ack_mode: bool,                          // Включен no-acknowledgment режим
+    pub notifications: VecDeque<String>,            // Принятые уведомления %...#cs (non-stop: "Stop:T05..."), еще не забранные
+    pub stats: ClientStats,
+}
+
+
+impl<S: Read + Write> RspClient<S>
+{
+    pub fn new(stream: S) -> RspClient<S>
+    {
+        RspClient{
+            stream,
+            pending: Vec::new(),
+            no_ack_mode: false,
+            notifications: VecDeque::new(),
+            stats: ClientStats::default(),
+        }
+    }
+
+
+    ///Поток, поверх которого работает клиент
+    pub fn stream(&mut self) -> &mut S
+    {
+        &mut self.stream
+    }
+
+
+    ///Отправить пакет $data#cs. Данные должны быть уже экранированы
+    pub fn send_packet(&mut self, data: &[u8]) -> io::Result<()>
+    {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+        self.send_raw(&packet)?;
+        self.stats.packets_sent += 1;
+        Ok(())
+    }
+
+
+    ///Отправить байты как есть (^C, acknowledgment, заранее собранный пакет)
+    pub fn send_raw(&mut self, bytes: &[u8]) -> io::Result<()>
+    {
+        self.stream.write_all(bytes)?;
+        self.stream.flush()?;
+        self.stats.bytes_sent += bytes.len();
+        Ok(())
+    }
+
+
+    ///Принять следующий пакет. Acknowledgment пропускаются, на пакет отправляется '+' (если не включен no-ack режим)
+    ///Уведомления %...#cs не подтверждаются и складываются в notifications
+    ///Возвращает данные пакета (без $ и #cs)
+    pub fn read_packet(&mut self) -> io::Result<Vec<u8>>
+    {
+        loop
+        {
+            let (notification, data) = self.read_message()?;
+            if notification
+            {//Уведомление: может прийти между любыми пакетами
+                self.notifications.push_back(String::from_utf8_lossy(&data).into_owned());
+                continue;
+            }
+            return Ok(data);
+        }
+    }
+
+
+    ///Дождаться уведомления (non-stop: "Stop:T05thread:1;"). Пакет, пришедший раньше уведомления, - ошибка
+    pub fn read_notification(&mut self) -> io::Result<String>
+    {
+        if let Some(notification) = self.notifications.pop_front()
+        {
+            return Ok(notification);
+        }
+        let (notification, data) = self.read_message()?;
+        let data = String::from_utf8_lossy(&data).into_owned();
+        if notification
+        {
+            Ok(data)
+        }
+        else
+        {
+            Err(io::Error::new(io::ErrorKind::InvalidData, format!("packet {:?} instead of notification", data)))
+        }
+    }
+
+
+    ///Принять пакет или уведомление. Возвращает (это уведомление, данные без $/% и #cs)
+    fn read_message(&mut self) -> io::Result<(bool, Vec<u8>)>
+    {
+        loop
+        {
+            let frame = self.read_frame()?;
+            let start = match frame.iter().position(|&c| c == b'$' || c == b'%')
+            {
+                Some(pos) => pos,
+                None =>
+                {//Одиночный acknowledgment
+                    self.count_ack(&frame);
+                    continue;
+                },
+            };
+            self.count_ack(&frame[..start]);
+            let sharp_pos = frame.len() - 3;
+            let data = frame[start+1 .. sharp_pos].to_vec();
+            let cs = str::from_utf8(&frame[sharp_pos+1 ..]).ok().and_then(|cs| u8::from_str_radix(cs, 16).ok());
+            if cs != Some(checksum(&data))
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad checksum in {:?}", String::from_utf8_lossy(&frame))));
+            }
+            self.stats.packets_received += 1;
+            if frame[start] == b'%'
+            {//На уведомление '+' не отправляется
+                return Ok((true, data));
+            }
+            if !self.no_ack_mode
+            {
+                match self.send_raw(b"+")
+                {
+                    //Сервер мог закрыть соединение сразу после ответа (vKill): пакет уже принят
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+                    result => result?,
+                }
+            }
+            return Ok((false, data));
+        }
+    }
+
+
+    fn count_ack(&mut self, acks: &[u8])
+    {
+        self.stats.acks_received += acks.iter().filter(|&&c| c == b'+').count();
+    }
+
+
+    ///Принять одно целое сообщение (пакет или одиночный символ)
+    fn read_frame(&mut self) -> io::Result<Vec<u8>>
+    {
+        let mut read_buf = [0u8; 4096];
+        loop
+        {
+            if let Some(len) = frame_len(&self.pending)
+            {
+                return Ok(self.pending.drain(..len).collect());
+            }
+            let len = self.stream.read(&mut read_buf)?;
+            if len == 0
+            {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            self.stats.bytes_received += len;
+            self.pending.extend_from_slice(&read_buf[..len]);
+        }
+    }
+
+
+    ///Отправить команду и дождаться ответа. O-пакеты (вывод в консоль) собираются в Reply.console
+    pub fn request(&mut self, data: &str) -> io::Result<Reply>
+    {
+        self.request_bytes(data.as_bytes())
+    }
+
+
+    ///То же, что request, но для бинарных данных (X-пакет)
+    pub fn request_bytes(&mut self, data: &[u8]) -> io::Result<Reply>
+    {
+        self.send_packet(data)?;
+        let mut console = String::new();
+        loop
+        {
+            let reply = String::from_utf8_lossy(&self.read_packet()?).into_owned();
+            //"OK" тоже начинается с 'O', но это не вывод в консоль
+            if reply.starts_with('O') && reply != "OK"
+            {
+                if let Some(text) = hex_decode(&reply[1..])
+                {
+                    console.push_str(&String::from_utf8_lossy(&text));
+                    continue;
+                }
+            }
+            return Ok(Reply{ data: reply, console });
+        }
+    }
+
+
+    ///Начало сеанса как у GDB: qSupported и (если сервер поддерживает) QStartNoAckMode
+    ///Возвращает ответ на qSupported
+    pub fn handshake(&mut self, features: &str) -> io::Result<String>
+    {
+        let supported = self.request(&format!("qSupported:{}", features))?.data;
+        if supported.split(';').any(|f| f == "QStartNoAckMode+")
+        {
+            self.send_packet(b"QStartNoAckMode")?;
+            //'+' на "OK" еще отправляется, no-ack режим начинается после него
+            let reply = self.read_packet()?;
+            if reply == b"OK"
+            {
+                self.no_ack_mode = true;
+            }
+        }
+        Ok(supported)
+    }
+
+
+    ///Запись в память X-пакетом (данные экранируются)
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> io::Result<Reply>
+    {
+        let mut packet = format!("X{:x},{:x}:", addr, data.len()).into_bytes();
+        packet.extend_from_slice(&escape_binary(data));
+        self.request_bytes(&packet)
+    }
+
+
+    ///Чтение памяти m-пакетом. None - сервер ответил ошибкой или некорректными данными
+    pub fn read_memory(&mut self, addr: u64, len: usize) -> io::Result<Option<Vec<u8>>>
+    {
+        let reply = self.request(&format!("m{:x},{:x}", addr, len))?;
+        Ok(hex_decode(&reply.data).filter(|data| data.len() == len))
+    }
+
+
+    ///Чтение регистра p-пакетом (байты в порядке цели). None - сервер ответил ошибкой
+    pub fn read_register(&mut self, num: usize) -> io::Result<Option<Vec<u8>>>
+    {
+        let reply = self.request(&format!("p{:x}", num))?;
+        Ok(hex_decode(&reply.data))
+    }
+
+
+    ///Запись регистра P-пакетом (байты в порядке цели)
+    pub fn write_register(&mut self, num: usize, value: &[u8]) -> io::Result<Reply>
+    {
+        self.request(&format!("P{:x}={}", num, hex_encode(value)))
+    }
+
+
+    ///Установка (insert) или снятие matchpoint: Z/z<type>,<addr>,<kind>
+    pub fn matchpoint(&mut self, insert: bool, z_type: u8, addr: u64, kind: usize) -> io::Result<Reply>
+    {
+        self.request(&format!("{}{},{:x},{:x}", if insert {'Z'} else {'z'}, z_type, addr, kind))
+    }
+
+
+    ///Прерывание исполнения (^C). Stop Reply читается отдельно (read_packet)
+    pub fn interrupt(&mut self) -> io::Result<()>
+    {
+        self.send_raw(b"\x03")
+    }
+
+
+    ///Завершение сеанса: vKill
+    pub fn kill(&mut self) -> io::Result<Reply>
+    {
+        self.request("vKill")
+    }
+
+
+    ///vFile-запрос. Ok(Ok((результат, данные после ';'))) или Ok(Err(errno))
+    ///Пустой ответ (на сервере нет песочницы) - ошибка Unsupported
+    pub fn host_io(&mut self, data: &[u8]) -> io::Result<Result<(u64, Vec<u8>), u64>>
+    {
+        self.send_packet(data)?;
+        let reply = self.read_packet()?;
+        let (result, attachment) = match reply.iter().position(|&c| c == b';')
+        {
+            Some(pos) => (&reply[..pos], unescape_binary(&reply[pos+1 ..])),
+            None => (&reply[..], Vec::new()),
+        };
+        let result = str::from_utf8(result).ok().and_then(|r| r.strip_prefix('F'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, format!("host I/O reply {:?}", String::from_utf8_lossy(&reply))))?;
+        match result.split_once(',')
+        {
+            Some((_, errno)) => Ok(Err(u64::from_str_radix(errno, 16).unwrap_or(0))),
+            None => u64::from_str_radix(result, 16).map(|r| Ok((r, attachment)))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("host I/O result {:?}", result))),
+        }
+    }
+
+
+    ///Файл с сервера целиком (GDB: remote get)
+    pub fn get_file(&mut self, path: &str) -> io::Result<Result<Vec<u8>, u64>>
+    {
+        let fd = match self.host_io(format!("vFile:open:{},0,0", hex_encode(path.as_bytes())).as_bytes())?
+        {
+            Ok((fd, _)) => fd,
+            Err(errno) => return Ok(Err(errno)),
+        };
+        let mut data = Vec::new();
+        loop
+        {
+            match self.host_io(format!("vFile:pread:{:x},{:x},{:x}", fd, FILE_CHUNK, data.len()).as_bytes())?
+            {
+                Ok((0, _)) => break,
+                Ok((_, chunk)) => data.extend_from_slice(&chunk),
+                Err(errno) => return Ok(Err(errno)),
+            }
+        }
+        Ok(self.host_io(format!("vFile:close:{:x}", fd).as_bytes())?.map(|_| data))
+    }
+
+
+    ///Записать файл на сервер (GDB: remote put). Существующий файл заменяется
+    pub fn put_file(&mut self, path: &str, data: &[u8]) -> io::Result<Result<(), u64>>
+    {
+        let open = format!("vFile:open:{},{:x},1a4", hex_encode(path.as_bytes()), O_WRONLY | O_CREAT | O_TRUNC); //Права 0644
+        let fd = match self.host_io(open.as_bytes())?
+        {
+            Ok((fd, _)) => fd,
+            Err(errno) => return Ok(Err(errno)),
+        };
+        for (index, chunk) in data.chunks(FILE_CHUNK).enumerate()
+        {
+            let mut packet = format!("vFile:pwrite:{:x},{:x},", fd, index * FILE_CHUNK).into_bytes();
+            packet.extend_from_slice(&escape_binary(chunk));
+            if let Err(errno) = self.host_io(&packet)?
+            {
+                return Ok(Err(errno));
+            }
+        }
+        Ok(self.host_io(format!("vFile:close:{:x}", fd).as_bytes())?.map(|_| ()))
+    }
+
+
+    ///Удалить файл на сервере (GDB: remote delete)
+    pub fn delete_file(&mut self, path: &str) -> io::Result<Result<(), u64>>
+    {
+        Ok(self.host_io(format!("vFile:unlink:{}", hex_encode(path.as_bytes())).as_bytes())?.map(|_| ()))
+    }
+}
+
+
+///Тесты для RspClient ================================================================================
+#[cfg(test)]
+mod test_client
+{
+    use super::*;
+    use std::io::Cursor;
+    use codec::make_packet;
+
+    ///Поток для тестов: чтение из заготовленного ответа, запись в буфер
+    struct Loopback
+    {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Loopback
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+        {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Loopback
+    {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+        {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_request()
+    {
+        let input = format!("+{}{}", make_packet("O48690a"), make_packet("OK")).into_bytes();
+        let mut client = RspClient::new(Loopback{ input: Cursor::new(input), output: Vec::new() });
+        let reply = client.request("qRcmd,7265736574").unwrap();
+        assert_eq!("OK", reply.data);
+        assert_eq!("Hi\n", reply.console);
+        assert_eq!(format!("{}++", make_packet("qRcmd,7265736574")).into_bytes(), client.stream().output);
+        assert_eq!(2, client.stats.packets_received);
+
+        //Неверная контрольная сумма
+        let mut client = RspClient::new(Loopback{ input: Cursor::new(b"$OK#00".to_vec()), output: Vec::new() });
+        assert!(client.request("?").is_err());
+    }
+}
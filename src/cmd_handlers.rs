@@ -0,0 +1,1953 @@
+use std::str;
+use std::sync::atomic::Ordering;
+
+use gdb_server::RspPacket;
+use cmd_registry::{CmdRegistry, CmdContext};
+use codec::{hex_encode, hex_decode, unescape_binary, crc32};
+use target::{Target, TargetResult, MatchpointType, StopReason, ThreadId, ResumeAction, ptid_parts};
+use codec::make_packet;
+use session::{Session, ClientFeatures};
+use config::FeatureOverrides;
+use host_io::{HostIo, EINVAL};
+use trace::TraceExperiment;
+use agent_expr::parse_expressions;
+
+
+///Набор обработчиков по умолчанию (поведение GDB-сервера "из коробки")
+pub fn register_defaults(registry: &mut CmdRegistry)
+{
+    registry.register("?", cmd_stop_reason);
+    registry.register("g", cmd_read_all_regs);
+    registry.register("G", cmd_write_all_regs);
+    registry.register("p", cmd_read_reg);
+    registry.register("P", cmd_write_reg);
+    registry.register("m", cmd_read_mem);
+    registry.register("X", cmd_write_mem);
+    registry.register("qCRC:", cmd_q_crc);
+    registry.register("qSearch:memory:", cmd_q_search_memory);
+    registry.register("c", cmd_continue);
+    registry.register("s", cmd_step);
+    for t in 0..5
+    {//Типы matchpoint регистрируются отдельно, чтобы каждый можно было переопределить или отключить
+        registry.register(&format!("z{}", t), cmd_remove_matchpoint);
+        registry.register(&format!("Z{}", t), cmd_insert_matchpoint);
+    }
+    registry.register("qSupported", cmd_q_supported);
+    registry.register("qfThreadInfo", cmd_q_f_thread_info);
+    registry.register("qsThreadInfo", cmd_q_s_thread_info);
+    registry.register("qC", cmd_q_c);
+    registry.register("qThreadExtraInfo,", cmd_q_thread_extra_info);
+    registry.register("H", cmd_set_thread);
+    registry.register("T", cmd_thread_alive);
+    registry.register("qAttached", cmd_q_attached);
+    registry.register("qSymbol", cmd_q_symbol);
+    registry.register("qOffsets", cmd_q_offsets);
+    registry.register("qRcmd", cmd_q_rcmd);
+    registry.register("QStartNoAckMode", cmd_start_no_ack_mode);
+    registry.register("vCont?", cmd_v_cont_query);
+    registry.register("vCont;", cmd_v_cont);
+    registry.register("vKill", cmd_v_kill);
+    registry.register("QNonStop:", cmd_non_stop);
+    registry.register("vStopped", cmd_v_stopped);
+    registry.register("vCtrlC", cmd_v_ctrl_c);
+    registry.register("!", cmd_extended_mode);
+    registry.register("vAttach;", cmd_v_attach);
+    registry.register("D", cmd_detach);
+    registry.register("F", cmd_file_io_reply);
+    registry.register("vFile:setfs:", cmd_v_file_setfs);
+    registry.register("vFile:open:", cmd_v_file_open);
+    registry.register("vFile:close:", cmd_v_file_close);
+    registry.register("vFile:pread:", cmd_v_file_pread);
+    registry.register("vFile:pwrite:", cmd_v_file_pwrite);
+    registry.register("vFile:unlink:", cmd_v_file_unlink);
+    registry.register("vFile:readlink:", cmd_v_file_readlink);
+    registry.register("vFile:fstat:", cmd_v_file_fstat);
+    registry.register("QTinit", cmd_qt_init);
+    registry.register("QTDP:", cmd_qt_dp);
+    registry.register("QTDPsrc:", cmd_qt_dp_src);
+    registry.register("QTEnable:", cmd_qt_enable);
+    registry.register("QTDisable:", cmd_qt_disable);
+    registry.register("QTBuffer:", cmd_qt_buffer);
+    for setting in &["QTDV:", "QTro:", "QTDisconnected:", "QTNotes:"]
+    {
+        registry.register(setting, cmd_qt_setting);
+    }
+    registry.register("QTStart", cmd_qt_start);
+    registry.register("QTStop", cmd_qt_stop);
+    registry.register("qTStatus", cmd_q_t_status);
+    registry.register("qTP:", cmd_q_tp);
+    registry.register("QTFrame:", cmd_qt_frame);
+    registry.register("qTfP", cmd_q_tf_p);
+    registry.register("qTsP", cmd_q_ts_p);
+    registry.register("qTfV", cmd_q_t_v);
+    registry.register("qTsV", cmd_q_t_v);
+    registry.register("qTBuffer:", cmd_q_t_buffer);
+    registry.register("QTSave:", cmd_qt_save);
+}
+
+
+///Ответ "OK" или "E<nn>" по результату операции над целью
+fn reply_result(pkt: &mut RspPacket, result: TargetResult<()>)
+{
+    match result
+    {
+        Ok(()) => pkt.responce("$OK#9a"),
+        Err(e) => pkt.responce_add_usd_cs(&format!("E{:02x}", e)),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Ответ с данными в hex или "E<nn>" по результату чтения из цели
+fn reply_hex(pkt: &mut RspPacket, result: TargetResult<Vec<u8>>)
+{
+    match result
+    {
+        Ok(bytes) => pkt.responce_add_usd_cs(&hex_encode(&bytes)),
+        Err(e) => pkt.responce_add_usd_cs(&format!("E{:02x}", e)),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Данные Stop Reply Packet с учетом согласованных features
+///swbreak+/hwbreak+: причина останова на breakpoint передается полем "swbreak:;"/"hwbreak:;"
+///Если у цели есть потоки - поле "thread:<id>;" с потоком, на котором произошел останов
+pub fn stop_reply(ctx: &CmdContext, stop: StopReason) -> String
+{
+    let thread = if ctx.target.threads().is_empty() {None} else {Some(ctx.target.current_thread())};
+    thread_stop_reply(ctx.session, thread, stop)
+}
+
+
+///Данные Stop Reply Packet для потока thread (None - цель без потоков)
+pub fn thread_stop_reply(session: &Session, thread: Option<u64>, stop: StopReason) -> String
+{
+    let mut reply = match stop
+    {
+        StopReason::SwBreakpoint if session.server_supports("swbreak") => format!("{}swbreak:;", stop.reply()),
+        StopReason::HwBreakpoint if session.server_supports("hwbreak") => format!("{}hwbreak:;", stop.reply()),
+        _ => stop.reply(),
+    };
+    if let Some(tid) = thread
+    {
+        reply += &format!("thread:{};", format_thread_id(session, tid));
+    }
+    reply
+}
+
+
+///thread-id в ответе сервера: hex-номер или pPID.TID, если согласованы multiprocess extensions
+pub fn format_thread_id(session: &Session, tid: u64) -> String
+{
+    if session.multiprocess()
+    {
+        let (pid, tid) = ptid_parts(tid);
+        format!("p{:x}.{:x}", pid, tid)
+    }
+    else
+    {
+        format!("{:x}", tid)
+    }
+}
+
+
+///Запрос состояния цели (причина останова)
+///$?
+pub fn cmd_stop_reason(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда '?'");
+    if ctx.session.non_stop
+    {//Non-stop: Stop Reply первого остановленного потока, остальные - через vStopped. Все потоки исполняются - OK
+        ctx.session.stop_queue = target_threads(ctx).into_iter()
+            .filter_map(|tid| ctx.target.thread_stop(tid).map(|stop| thread_stop_reply(ctx.session, Some(tid), stop)))
+            .collect();
+        match ctx.session.stop_queue.pop_front()
+        {
+            Some(reply) =>
+            {
+                ctx.session.notification_pending = true;
+                pkt.responce_add_usd_cs(&reply);
+            },
+            None => pkt.responce("$OK#9a"),
+        }
+        pkt.need_responce = Some(true);
+        return;
+    }
+    //Stop-reply packet: Если цель остановлена (halt) - ответ T05 = SIGTRAP
+    //Stop-reply packet: Если цель прервана по ^C - ответ T02 = SIGINT
+    let stop = ctx.target.stop_reason();
+    pkt.responce_add_usd_cs(&stop_reply(ctx, stop)); //Stop-reply packet
+    pkt.need_responce = Some(true);
+}
+
+
+///Чтение всех регистров общего назначения
+///$g
+pub fn cmd_read_all_regs(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'g'");
+    if let Some(regs) = frame_registers(ctx)
+    {
+        pkt.responce_add_usd_cs(&regs);
+        pkt.need_responce = Some(true);
+        return;
+    }
+    let regs = ctx.target.read_registers();
+    reply_hex(pkt, regs);
+}
+
+
+///Регистры выбранного кадра трассировки (QTFrame) в hex. Регистры, которые не собирались, - 'x' (недоступны)
+///None - кадр не выбран, регистры читаются из цели
+fn frame_registers(ctx: &mut CmdContext) -> Option<String>
+{
+    let regs = ctx.target.trace_experiment()?.selected_frame()?.registers.clone();
+    Some(match regs
+    {
+        Some(regs) => hex_encode(&regs),
+        None => "xx".repeat(ctx.target.read_registers().map_or(0, |regs| regs.len())),
+    })
+}
+
+
+///Запись всех регистров общего назначения
+///$G<байты>
+pub fn cmd_write_all_regs(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'G'");
+    let result = match hex_decode(&pkt.data.unwrap()[1..])
+    {
+        Some(bytes) => ctx.target.write_registers(&bytes),
+        None => Err(0x01),
+    };
+    reply_result(pkt, result);
+}
+
+
+///Чтение произвольного регистра
+///$p<n>
+pub fn cmd_read_reg(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let reg = match usize::from_str_radix(&pkt.data.unwrap()[1..], 16)
+    {
+        Ok(reg_num) =>
+        {
+            log_println!("GDB-Server : Получена команда 'p'. Номер регистра {}", reg_num);
+            if let Some(regs) = frame_registers(ctx)
+            {//Регистр кадра трассировки: размер - как у регистра цели
+                let size = ctx.target.read_register(reg_num).map_or(0, |reg| reg.len() * 2);
+                let range = reg_num.checked_mul(size).and_then(|start| Some(start .. start.checked_add(size)?));
+                match range.and_then(|range| regs.get(range)).filter(|_| size > 0)
+                {
+                    Some(reg) => pkt.responce_add_usd_cs(reg),
+                    None => reply_result(pkt, Err(0x01)),
+                }
+                pkt.need_responce = Some(true);
+                return;
+            }
+            ctx.target.read_register(reg_num) //По RSP регистры передаются в little-endian
+        },
+        Err(_) => Err(0x01), //Некорректный номер регистра
+    };
+    reply_hex(pkt, reg);
+}
+
+
+///Запись произвольного регистра
+///$P<n>=<байты>
+pub fn cmd_write_reg(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    //Номер регистра - до знака '=', значение - после
+    let parsed = data[1..].split_once('=').and_then(|(num, value)| Some((usize::from_str_radix(num, 16).ok()?, value)));
+    let result = match parsed
+    {
+        Some((reg_num, value)) =>
+        {
+            log_println!("GDB-Server : Получена команда 'P'. Номер регистра {}. Значение = {}", reg_num, value); //Значение в little-endian
+            match hex_decode(value)
+            {
+                Some(bytes) => ctx.target.write_register(reg_num, &bytes),
+                None => Err(0x01),
+            }
+        },
+        None => Err(0x01), //Некорректный пакет
+    };
+    reply_result(pkt, result);
+}
+
+
+///Чтение памяти
+///$m<addr>,<len>
+pub fn cmd_read_mem(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    let (addr, bytes_len) = match parse_addr_len(&data[1..])
+    {
+        Some(v) => v,
+        None =>
+        {//Некорректный пакет
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+    log_println!("GDB-Server : Получена команда 'm'. Адрес = 0x{:x}. Количество байт для чтения = {}", addr, bytes_len);
+    //Ответ не длиннее пакета: 2 hex-цифры на байт и "$#cs". GDB допускает чтение меньшего числа байт, чем запрошено
+    let bytes_len = bytes_len.min((pkt.max_responce_len() - 4) / 2);
+    reply_hex(pkt, read_memory(ctx, addr, bytes_len));
+}
+
+
+///Память цели, а если выбран кадр трассировки - только собранная в нем память
+fn read_memory(ctx: &mut CmdContext, addr: u64, len: usize) -> TargetResult<Vec<u8>>
+{
+    let frame_mem = ctx.target.trace_experiment().and_then(|trace| trace.selected_frame()).map(|frame| frame.read_memory(addr, len).ok_or(0x01));
+    frame_mem.unwrap_or_else(|| ctx.target.read_memory(addr, len))
+}
+
+
+///Память для qCRC и qSearch:memory читается из цели частями
+const MEMORY_CHUNK: usize = 0x1000;
+///Длина области для qCRC и qSearch:memory не больше (1 МиБ, как у sweep по умолчанию), иначе ответ E01
+///Между частями проверяется ^C: прерванный запрос тоже получает E01
+pub const MEMORY_QUERY_MAX: usize = 0x10_0000;
+
+
+///CRC-32 области памяти (compare-sections)
+///$qCRC:<addr>,<length>. Ответ $C<crc32> или $E<nn>. GDB сравнивает его со своим CRC секции вместо чтения памяти m-пакетами
+pub fn cmd_q_crc(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда '{}'", data);
+    let result = match parse_addr_len(&data["qCRC:".len()..])
+    {
+        Some((addr, len)) if len <= MEMORY_QUERY_MAX => memory_crc(ctx, addr, len),
+        _ => Err(0x01), //Некорректный пакет или слишком большая длина
+    };
+    match result
+    {
+        Ok(crc) => pkt.responce_add_usd_cs(&format!("C{:08x}", crc)),
+        Err(e) => pkt.responce_add_usd_cs(&format!("E{:02x}", e)),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Пришел ^C: признак сбрасывается, запрос завершается ошибкой
+fn check_cancel(ctx: &CmdContext) -> TargetResult<()>
+{
+    if ctx.cancel_flag.swap(false, Ordering::SeqCst) {Err(0x01)} else {Ok(())}
+}
+
+
+///CRC-32 GDB памяти [addr, addr + len) с начальным значением 0xffffffff
+fn memory_crc(ctx: &mut CmdContext, addr: u64, len: usize) -> TargetResult<u32>
+{
+    let mut crc = 0xffff_ffff;
+    let mut offset = 0;
+    while offset < len
+    {
+        check_cancel(ctx)?;
+        let chunk = (len - offset).min(MEMORY_CHUNK);
+        let mem = read_memory(ctx, addr.wrapping_add(offset as u64), chunk)?;
+        if mem.len() != chunk
+        {//Цель вернула не всю память
+            return Err(0x01);
+        }
+        crc = crc32(crc, &mem);
+        offset += chunk;
+    }
+    Ok(crc)
+}
+
+
+///Поиск байтов в памяти (find)
+///$qSearch:memory:<addr>;<length>;<pattern>. Образец бинарный (как данные X-пакета), поэтому берется из input_buf
+///Ответ $1,<addr> - адрес первого совпадения, $0 - не найдено, $E<nn> - ошибка
+pub fn cmd_q_search_memory(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let input_buf = ctx.input_buf;
+    let header = input_buf.iter().position(|&c| c == b'$').map_or(0, |usd_pos| usd_pos + 1 + "qSearch:memory:".len());
+    let mut fields = input_buf[header .. input_buf.len() - 3].splitn(3, |&c| c == b';');
+    let addr = fields.next().and_then(|addr| str::from_utf8(addr).ok()).and_then(|addr| u64::from_str_radix(addr, 16).ok());
+    let len = fields.next().and_then(|len| str::from_utf8(len).ok()).and_then(|len| u64::from_str_radix(len, 16).ok());
+    let pattern = fields.next().map(unescape_binary).filter(|pattern| !pattern.is_empty());
+    log_println!("GDB-Server : Получена команда 'qSearch:memory'. Адрес = {:x?}. Длина = {:x?}. Образец = {:x?}", addr, len, pattern);
+    let result = match (addr, len, pattern)
+    {
+        (Some(addr), Some(len), Some(pattern)) if len <= MEMORY_QUERY_MAX as u64 => search_memory(ctx, addr, len, &pattern),
+        _ => Err(0x01),
+    };
+    match result
+    {
+        Ok(Some(found)) => pkt.responce_add_usd_cs(&format!("1,{:x}", found)),
+        Ok(None) => pkt.responce_add_usd_cs("0"),
+        Err(e) => pkt.responce_add_usd_cs(&format!("E{:02x}", e)),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Первое вхождение pattern в памяти [addr, addr + len). Части перекрываются на pattern.len() - 1 байт, чтобы найти совпадение на стыке
+fn search_memory(ctx: &mut CmdContext, addr: u64, len: u64, pattern: &[u8]) -> TargetResult<Option<u64>>
+{
+    let mut window: Vec<u8> = Vec::new(); //Конец предыдущей части и текущая часть
+    let mut offset = 0;
+    while offset < len
+    {
+        check_cancel(ctx)?;
+        let chunk = (len - offset).min(MEMORY_CHUNK as u64) as usize;
+        let mem = read_memory(ctx, addr.wrapping_add(offset), chunk)?;
+        if mem.len() != chunk
+        {//Цель вернула не всю память
+            return Err(0x01);
+        }
+        let start = offset - window.len() as u64;
+        window.extend_from_slice(&mem);
+        if let Some(pos) = window.windows(pattern.len()).position(|w| w == pattern)
+        {
+            return Ok(Some(addr.wrapping_add(start + pos as u64)));
+        }
+        let keep = (pattern.len() - 1).min(window.len());
+        window.drain(.. window.len() - keep);
+        offset += chunk as u64;
+    }
+    Ok(None)
+}
+
+
+///Запись в память
+///$X<addr>,<len>:<bytes>
+pub fn cmd_write_mem(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    //Так как бинарные данные могут содержать не только валидные utf8-символы, то pkt.data == from_utf8(...).unwrap() использовать нельзя
+    //Поэтому надо определять позиции символов не в pkt.data: Option<&'a str>, а в исходном input_buf: &[u8]
+    let input_buf = ctx.input_buf;
+    let (mem_addr, mem_len, colon_pos) = match parse_x_header(input_buf)
+    {
+        Some(v) => v,
+        None =>
+        {//Некорректный пакет
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+
+    log_println!("GDB-Server : Получена команда 'X'. Адрес = 0x{:x}. Количество байт для записи = {}.", mem_addr, mem_len);
+    if mem_len == 0
+    {//Пробный пустой пакет "X<addr>,0:"
+        pkt.responce("$OK#9a");
+        pkt.need_responce = Some(true);
+        return;
+    }
+    let sharp_pos = input_buf.len() - 3; //Байты данных - между ':' и '#cs'
+    let bytes = unescape_binary(&input_buf[colon_pos+1 .. sharp_pos]);
+    let result = if bytes.len() == mem_len
+    {
+        ctx.target.write_memory(mem_addr, &bytes)
+    }
+    else
+    {//Количество байт не совпадает с <len>
+        Err(0x01)
+    };
+    reply_result(pkt, result);
+}
+
+
+///"<addr>,<len>" в hex -> (addr, len). None - некорректные поля
+fn parse_addr_len(fields: &str) -> Option<(u64, usize)>
+{
+    let (addr, len) = fields.split_once(',')?;
+    Some((u64::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+
+///Заголовок X-пакета: (addr, len, позиция ':' в input_buf). None - некорректный пакет
+///$X<addr>,<len>:<bytes>#cs. Данные после ':' бинарные, поэтому разбирается только заголовок
+fn parse_x_header(input_buf: &[u8]) -> Option<(u64, usize, usize)>
+{
+    let x_pos = input_buf.iter().position(|&x| x == b'X')?; //Позиция знака 'X' для выделения поля адреса
+    let colon_pos = x_pos + input_buf[x_pos..].iter().position(|&x| x == b':')?; //Позиция знака ':' для выделения поля количества байт
+    if colon_pos + 3 >= input_buf.len()
+    {//Нет места для '#cs' после ':'
+        return None;
+    }
+    let (addr, len) = parse_addr_len(str::from_utf8(&input_buf[x_pos+1 .. colon_pos]).ok()?)?;
+    Some((addr, len, colon_pos))
+}
+
+
+///Разбор matchpoint-пакета: (type, addr, kind). None - некорректный пакет
+///$Z<type>,<addr>,<kind>[;cond_list...][;cmds:persist,cmd_list...] или $z<type>,<addr>,<kind>
+fn parse_matchpoint(data: &str) -> Option<(&str, u64, usize)>
+{
+    let mut fields = data.get(1..)?.split(',');
+    let z_type = fields.next()?;
+    let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let kind = usize::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?; //kind - до первой ';' (дальше опциональные параметры)
+    Some((z_type, addr, kind))
+}
+
+
+///Условия breakpoint из Z-пакета: ;X<len>,<bytecode>X<len>,<bytecode>... после kind (до ;cmds:)
+///None - некорректная запись условия
+fn parse_conditions(data: &str) -> Option<Vec<Vec<u8>>>
+{
+    match data.split(';').nth(1)
+    {
+        Some(conditions) if !conditions.starts_with("cmds:") => parse_expressions(conditions),
+        _ => Some(Vec::new()),
+    }
+}
+
+
+///Команды breakpoint из Z-пакета: ;cmds:<persist>,X<len>,<bytecode>X<len>,<bytecode>... (последняя часть)
+///Без cmds: - пустой список. None - некорректная запись команд
+fn parse_commands(data: &str) -> Option<(Vec<Vec<u8>>, bool)>
+{
+    let commands = match data.split(';').find_map(|part| part.strip_prefix("cmds:"))
+    {
+        Some(commands) => commands,
+        None => return Some((Vec::new(), false)),
+    };
+    let (persist, commands) = commands.split_at(commands.find(',')?);
+    let persist = match persist
+    {
+        "0" => false,
+        "1" => true,
+        _ => return None,
+    };
+    Some((parse_expressions(&commands[1..])?, persist))
+}
+
+
+///Снятие matchpoint
+///$z<type>,<addr>,<kind>
+pub fn cmd_remove_matchpoint(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let (z_type, addr, kind) = match parse_matchpoint(pkt.data.unwrap())
+    {
+        Some(v) => v,
+        None =>
+        {//Некорректный пакет
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+    log_println!("GDB-Server : Получена команда 'z'. addr = 0x{:x}. kind = {}", addr, kind);
+    match MatchpointType::from_z_type(z_type)
+    {
+        Some(mp_type) =>
+        {
+            log_println!("GDB-Server : Получена команда 'z{}' ({})", z_type, mp_type.name());
+            let result = ctx.target.remove_matchpoint(mp_type, addr, kind);
+            reply_result(pkt, result);
+        },
+        None =>
+        {
+            log_println!("GDB-Server : Unknown z-type: \'{}\'!", z_type);
+            pkt.responce("+$#00");
+            pkt.need_responce = Some(true);
+        },
+    }
+}
+
+
+///Установка matchpoint
+///$Z<type>,<addr>,<kind>[;X<len>,<cond>...][;cmds:<persist>,X<len>,<cmd>...]
+///Условия breakpoints (Z0, Z1) вычисляет цель, если поддерживает (ConditionalBreakpoints+). Z без условий делает breakpoint безусловным
+///Команды (BreakpointCommands+) так же заменяются каждым Z-пакетом
+pub fn cmd_insert_matchpoint(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    cmd_insert_matchpoint_with(pkt, ctx, &FeatureOverrides::default());
+}
+
+
+///'Z' с учетом переопределений features: условия отключенной ConditionalBreakpoints и команды отключенной BreakpointCommands игнорируются
+pub fn cmd_insert_matchpoint_with(pkt: &mut RspPacket, ctx: &mut CmdContext, overrides: &FeatureOverrides)
+{
+    let (z_type, addr, kind) = match parse_matchpoint(pkt.data.unwrap())
+    {
+        Some(v) => v,
+        None =>
+        {//Некорректный пакет
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+    log_println!("GDB-Server : Получена команда 'Z'. addr = 0x{:x}. kind = {}", addr, kind);
+    match MatchpointType::from_z_type(z_type)
+    {
+        Some(mp_type) =>
+        {
+            log_println!("GDB-Server : Получена команда 'Z{}' ({})", z_type, mp_type.name());
+            let breakpoint = mp_type == MatchpointType::SwBreakpoint || mp_type == MatchpointType::HwBreakpoint;
+            let conditions = match overrides.get("ConditionalBreakpoints")
+            {
+                Some(false) => Some(Vec::new()),
+                _ => parse_conditions(pkt.data.unwrap()),
+            };
+            let commands = match overrides.get("BreakpointCommands")
+            {
+                Some(false) => Some((Vec::new(), false)),
+                _ => parse_commands(pkt.data.unwrap()),
+            };
+            let result = match (conditions, commands)
+            {
+                (Some(conditions), Some((commands, persist))) => ctx.target.insert_matchpoint(mp_type, addr, kind).and_then(|_|
+                {
+                    if breakpoint && ctx.target.supports_conditions() {ctx.target.set_conditions(addr, conditions)} else {Ok(())}
+                }).and_then(|_|
+                {
+                    if breakpoint && ctx.target.supports_breakpoint_commands() {ctx.target.set_commands(addr, commands, persist)} else {Ok(())}
+                }),
+                _ => Err(0x01), //Некорректное условие или команды
+            };
+            reply_result(pkt, result);
+        },
+        None =>
+        {
+            log_println!("GDB-Server : Unknown Z-type: \'{}\'!", z_type);
+            pkt.responce("+$#00");
+            pkt.need_responce = Some(true);
+        },
+    }
+}
+
+
+///Features, которые сервер объявляет в ответ на qSupported, если включен обработчик соответствующей команды: (feature, команда)
+pub const SERVER_FEATURES: &[(&str, &str)] = &[
+    ("QStartNoAckMode+", "QStartNoAckMode"),
+    ("vContSupported+", "vCont;"),
+];
+
+///Features, которые сервер объявляет, только если их прислал клиент (и включен обработчик соответствующей команды): (feature, команда)
+///swbreak/hwbreak: причина останова на breakpoint в Stop Reply Packet
+pub const CLIENT_DEPENDENT_FEATURES: &[(&str, &str)] = &[
+    ("swbreak", "Z0"),
+    ("hwbreak", "Z1"),
+];
+
+
+///Features трассировки, которые сервер объявляет, если у цели есть эксперимент трассировки
+///ConditionalTracepoints - условия в QTDP, TracepointSource - QTDPsrc, EnableDisableTracepoints - QTEnable/QTDisable во время эксперимента,
+///QTBuffer:size - размер буфера
+pub const TRACE_FEATURES: &[&str] = &["ConditionalTracepoints", "TracepointSource", "EnableDisableTracepoints", "QTBuffer:size"];
+
+
+///Список features для ответа на qSupported: то, что сервер действительно реализует, с учетом переопределений
+pub fn server_features(ctx: &CmdContext, overrides: &FeatureOverrides) -> Vec<String>
+{
+    let mut features = vec![format!("PacketSize={:x}", overrides.packet_size.unwrap_or(ctx.session.packet_size))];
+    for &(feature, cmd) in SERVER_FEATURES
+    {
+        let name = &feature[..feature.len()-1];
+        if overrides.get(name).unwrap_or_else(|| ctx.registry.is_enabled(cmd))
+        {
+            features.push(feature.to_string());
+        }
+    }
+    for &(feature, cmd) in CLIENT_DEPENDENT_FEATURES
+    {
+        let negotiated = ctx.session.client_features.supports(feature) && ctx.registry.is_enabled(cmd);
+        if overrides.get(feature).unwrap_or(negotiated)
+        {
+            features.push(format!("{}+", feature));
+        }
+    }
+    //Non-stop режим: только если его поддерживает цель
+    if overrides.get("QNonStop").unwrap_or_else(|| ctx.registry.is_enabled("QNonStop:") && ctx.target.supports_non_stop())
+    {
+        features.push("QNonStop+".to_string());
+    }
+    //Условия breakpoints на стороне цели: только если цель их вычисляет
+    if overrides.get("ConditionalBreakpoints").unwrap_or_else(|| ctx.registry.is_enabled("Z0") && ctx.target.supports_conditions())
+    {
+        features.push("ConditionalBreakpoints+".to_string());
+    }
+    //Команды breakpoints (dprintf): только если цель их исполняет
+    if overrides.get("BreakpointCommands").unwrap_or_else(|| ctx.registry.is_enabled("Z0") && ctx.target.supports_breakpoint_commands())
+    {
+        features.push("BreakpointCommands+".to_string());
+    }
+    //Tracepoints: только если у цели есть эксперимент трассировки
+    let tracepoints = ctx.registry.is_enabled("QTDP:") && ctx.target.trace_experiment().is_some();
+    for feature in TRACE_FEATURES
+    {
+        if overrides.get(feature).unwrap_or(tracepoints)
+        {
+            features.push(format!("{}+", feature));
+        }
+    }
+    //Multiprocess extensions: только если их прислал клиент и у цели есть процессы
+    let multiprocess = ctx.session.client_features.supports("multiprocess") && ctx.registry.is_enabled("vAttach;") && !ctx.target.processes().is_empty();
+    if overrides.get("multiprocess").unwrap_or(multiprocess)
+    {
+        features.push("multiprocess+".to_string());
+    }
+    //Принудительно включенные features, которых нет в таблицах выше
+    for (name, enable) in &overrides.features
+    {
+        let known = SERVER_FEATURES.iter().any(|&(f, _)| f[..f.len()-1] == name[..]) || CLIENT_DEPENDENT_FEATURES.iter().any(|&(f, _)| f == name)
+            || TRACE_FEATURES.contains(&&name[..]) || name == "QNonStop" || name == "ConditionalBreakpoints"
+            || name == "BreakpointCommands" || name == "multiprocess";
+        if *enable && !known
+        {
+            features.push(format!("{}+", name));
+        }
+    }
+    features
+}
+
+
+///Согласование features
+///$qSupported[:gdbfeature[;gdbfeature]...]
+pub fn cmd_q_supported(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    cmd_q_supported_with(pkt, ctx, &FeatureOverrides::default());
+}
+
+
+///Согласование features с переопределениями (см. FeatureOverrides::apply)
+pub fn cmd_q_supported_with(pkt: &mut RspPacket, ctx: &mut CmdContext, overrides: &FeatureOverrides)
+{
+    log_println!("GDB-Server : Получена команда 'qSupported'");
+    let list = pkt.data.unwrap().split_once(':').map(|(_, list)| list).unwrap_or("");
+    ctx.session.client_features = ClientFeatures::parse(list); //Features клиента хранятся до конца подключения (или до следующего qSupported)
+    for (name, value) in ctx.session.client_features.iter()
+    {
+        log_println!("GDB-Server : qSupported, feature клиента {} : {:?}", name, value);
+    }
+    //'PacketSize=xx' обязательно.
+    //'QStartNoAckMode+' обязательно.
+    ctx.session.server_features = server_features(ctx, overrides);
+    pkt.responce_add_usd_cs( &ctx.session.server_features.join(";") );
+    //Добавить '+' перед уже сформированным RSP-ответом на qSupport (для работы без "set debug remote 1"). До включения no-acknowledgment режима
+    let tmp = pkt.responce.clone();
+    pkt.responce( &format!("+{}", tmp.unwrap()) );
+    pkt.need_responce = Some(true);
+}
+
+
+///Сколько потоков выдается в одном ответе на qfThreadInfo/qsThreadInfo
+pub const THREAD_INFO_CHUNK: usize = 16;
+
+
+///Начало списка потоков
+///$qfThreadInfo
+pub fn cmd_q_f_thread_info(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qfThreadInfo'");
+    ctx.session.thread_info = ctx.target.threads();
+    reply_thread_info(pkt, ctx);
+}
+
+
+///Продолжение списка потоков
+///$qsThreadInfo
+pub fn cmd_q_s_thread_info(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qsThreadInfo'");
+    reply_thread_info(pkt, ctx);
+}
+
+
+///Очередная часть списка потоков: "m<id>,<id>..." или 'l' - конец списка (у цели без потоков список сразу пустой)
+fn reply_thread_info(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let count = ctx.session.thread_info.len().min(THREAD_INFO_CHUNK);
+    if count == 0
+    {
+        pkt.responce_add_usd_cs("l");
+    }
+    else
+    {
+        let chunk: Vec<u64> = ctx.session.thread_info.drain(..count).collect();
+        let chunk: Vec<String> = chunk.into_iter().map(|tid| format_thread_id(ctx.session, tid)).collect();
+        pkt.responce_add_usd_cs(&format!("m{}", chunk.join(",")));
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Текущий поток
+///$qC
+pub fn cmd_q_c(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qC'");
+    //У цели без потоков - нулевой thread
+    pkt.responce_add_usd_cs(&format!("QC{}", format_thread_id(ctx.session, ctx.target.current_thread())));
+    pkt.need_responce = Some(true);
+}
+
+
+///Описание потока для 'info threads': текст в hex
+///$qThreadExtraInfo,<id>
+pub fn cmd_q_thread_extra_info(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let info = match ThreadId::parse(&pkt.data.unwrap()["qThreadExtraInfo,".len()..])
+    {
+        Some(ThreadId::Id(tid)) => ctx.target.thread_extra_info(tid),
+        _ =>
+        {//Некорректный пакет
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+    log_println!("GDB-Server : Получена команда 'qThreadExtraInfo'. {:?}", info);
+    match info
+    {
+        Some(text) => pkt.responce_add_usd_cs(&hex_encode(text.as_bytes())),
+        None => pkt.responce("+$#00"),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Выбор потока для последующих операций
+///$Hg<id> - регистры (и память), $Hc<id> - 'c'/'s'
+pub fn cmd_set_thread(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда 'H'. {}", data);
+    let result = match (data.get(1..2), data.get(2..).and_then(ThreadId::parse))
+    {
+        (Some("g"), Some(ThreadId::Id(tid))) => ctx.target.select_thread(tid),
+        (Some("g"), Some(ThreadId::Process(pid))) =>
+        {//Любой поток процесса: текущий, если он из этого процесса, иначе первый. Память и регистры - этого процесса
+            match process_thread(ctx, pid)
+            {
+                Some(tid) => ctx.target.select_thread(tid),
+                None => Err(0x01),
+            }
+        },
+        (Some("g"), Some(_)) => Ok(()), //Любой поток или все: остается текущий
+        (Some("c"), Some(thread)) =>
+        {
+            ctx.session.cont_thread = thread;
+            Ok(())
+        },
+        _ => Err(0x01),
+    };
+    reply_result(pkt, result);
+}
+
+
+///Жив ли поток
+///$T<id>
+pub fn cmd_thread_alive(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let alive = match ThreadId::parse(&pkt.data.unwrap()[1..])
+    {
+        Some(ThreadId::Id(tid)) => ctx.target.thread_alive(tid),
+        _ => false,
+    };
+    log_println!("GDB-Server : Получена команда 'T'. Поток жив: {}", alive);
+    reply_result(pkt, if alive {Ok(())} else {Err(0x01)});
+}
+
+
+pub fn cmd_q_attached(pkt: &mut RspPacket, _ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qAttached'");
+    //Запрос: GDB-server подключается к существующему процессу или создает новый процесс?
+    //Команда связана с остановкой (и перезапуском) цели
+    pkt.responce_add_usd_cs("0");//0: по команде (gdb) quit GDB пришлет 'vKill'. 1: по команде (gdb) quit GDB пришлет 'D'(Detach).
+    pkt.need_responce = Some(true);
+}
+
+
+pub fn cmd_q_symbol(pkt: &mut RspPacket, _ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qSymbol'");
+    //Информация о символах не нужна
+    pkt.responce("$OK#9a");
+    pkt.need_responce = Some(true);
+}
+
+
+pub fn cmd_q_offsets(pkt: &mut RspPacket, _ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'qOffsets'");
+    //Смещения секции при загрузке прошивки через GDB
+    pkt.responce_add_usd_cs("Text=0;Data=0;Bss=0");
+    pkt.need_responce = Some(true);
+}
+
+
+///Консольная команда 'monitor command'
+///$qRcmd,command
+pub fn cmd_q_rcmd(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    //$Otext можно использовать только с Stop Reply Packet и с qRcmd !
+    //При выводе output_text по команде $qRcmd: После $Otext обязательно должен быть $OK
+    let command = RspPacket::extract_monitor_cmd(pkt.data.unwrap().get(6..).unwrap_or("")); //Поле 'command' находится после ','
+    log_println!("GDB-Server : Получена команда 'qRcmd'. command = \'{}\'", command);
+    match ctx.target.monitor(&command)
+    {
+        Some(text)=>
+        {
+            pkt.text_add_usd_o_cs(&text);
+            log_println!("GDB-Server : '{}' monitor command", command);
+        },
+        None=>
+        {
+            let shown: String = command.chars().take(64).collect(); //Длинная команда не должна сделать O-пакет длиннее PacketSize
+            pkt.text_add_usd_o_cs( &(" GDB-Server message : Unknown monitor command \'".to_string() + &shown + "\'!\n") );
+            log_println!("GDB-Server : Unknown monitor command \'{}\'!", command);
+        },
+    }
+    pkt.responce("$OK#9a");
+    pkt.need_responce = Some(true);
+}
+
+
+pub fn cmd_start_no_ack_mode(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'QStartNoAckMode'");
+    //Дальше будем работать без подтверждений +/- (no-acknowledgment-режим)
+    ctx.session.no_ack_mode = true;
+    pkt.responce("+$OK#9a"); //'+' перед RSP-ответом на QStartNoAckMode (для работы без "set debug remote 1")
+    pkt.need_responce = Some(true);
+}
+
+
+///Запрос поддерживаемых vCont-action
+pub fn cmd_v_cont_query(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'vCont?'");
+    //GDB doesn't accept c without C and s without S. 't' нужен для non-stop режима
+    pkt.responce_add_usd_cs(if ctx.target.supports_non_stop() {"vCont;c;C;s;S;t"} else {"vCont;c;C;s;S"});
+    pkt.need_responce = Some(true);
+}
+
+
+///vCont-action: действие и поток (None - все потоки, к которым не относятся действия левее)
+///None - неизвестное действие, Some(Err) - некорректный thread-id
+fn parse_v_cont_action(text: &str) -> Option<Result<(ResumeAction, Option<ThreadId>), ()>>
+{
+    let (action, thread) = match text.split_once(':')
+    {
+        Some((action, thread)) => (action, Some(thread)),
+        None => (text, None),
+    };
+    let action = match action.get(..1)
+    {
+        Some("c") | Some("C") => ResumeAction::Continue, //Сигнал в C<sig>/S<sig> симулятору не передается
+        Some("s") | Some("S") => ResumeAction::Step,
+        Some("t") if action.len() == 1 => ResumeAction::Stop,
+        _ => return None,
+    };
+    Some(match thread.map(ThreadId::parse)
+    {
+        None => Ok((action, None)),
+        Some(Some(thread)) => Ok((action, Some(thread))),
+        Some(None) => Err(()),
+    })
+}
+
+
+///Команда к действию (vCont-action)
+///$vCont[;action[:thread-id]]...
+///Каждому потоку назначается самое левое подходящее действие, потоки без действия остаются остановленными
+///В non-stop режиме ответ OK сразу, остановы приходят уведомлениями %Stop; потоки без действия не меняют состояние
+pub fn cmd_v_cont(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'vCont;'");
+    let mut parsed = Vec::new();
+    for text in pkt.data.unwrap()[6..].split(';')
+    {
+        match parse_v_cont_action(text)
+        {
+            Some(Ok(action)) => parsed.push(action),
+            Some(Err(())) =>
+            {//Некорректный thread-id
+                reply_result(pkt, Err(0x01));
+                return;
+            },
+            None =>
+            {
+                log_println!("GDB-Server : Unknown vCont action: \'{}\'!", text);
+                pkt.responce("+$#00");
+                pkt.need_responce = Some(true);
+                return;
+            },
+        }
+    }
+    let actions: Vec<(u64, ResumeAction)> = target_threads(ctx).into_iter()
+        .filter_map(|tid| parsed.iter().find(|(_, thread)| thread.is_none_or(|t| t.matches(tid))).map(|&(action, _)| (tid, action)))
+        .collect();
+    log_println!("GDB-Server : vCont, actions {:?}", actions);
+    if ctx.session.non_stop
+    {
+        let mut result = Ok(());
+        for &(tid, action) in &actions
+        {
+            if action == ResumeAction::Stop
+            {
+                result = result.and(ctx.target.stop_thread(tid, StopReason::Signal(0)));
+            }
+        }
+        let resumed: Vec<(u64, ResumeAction)> = actions.into_iter().filter(|&(_, action)| action != ResumeAction::Stop).collect();
+        reply_result(pkt, result.and(ctx.target.start_threads(&resumed)));
+        return;
+    }
+    let actions: Vec<(u64, ResumeAction)> = actions.into_iter().filter(|&(_, action)| action != ResumeAction::Stop).collect();
+    resume_target(pkt, ctx, &actions, "vCont");
+}
+
+
+///Переключение all-stop/non-stop режима
+///$QNonStop:1 / $QNonStop:0
+pub fn cmd_non_stop(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда '{}'", data);
+    let result = match &data["QNonStop:".len()..]
+    {
+        "1" if ctx.target.supports_non_stop() =>
+        {
+            ctx.session.non_stop = true;
+            Ok(())
+        },
+        "0" =>
+        {//Все потоки останавливаются, очередь остановов больше не нужна
+            for tid in target_threads(ctx)
+            {
+                let _ = ctx.target.stop_thread(tid, StopReason::Signal(0));
+            }
+            ctx.target.take_stops();
+            ctx.session.non_stop = false;
+            ctx.session.stop_queue.clear();
+            ctx.session.notification_pending = false;
+            Ok(())
+        },
+        _ => Err(0x01),
+    };
+    reply_result(pkt, result);
+}
+
+
+///Non-stop: подтверждение уведомления %Stop. Ответ - следующий Stop Reply из очереди или OK, если очередь пуста
+///$vStopped
+pub fn cmd_v_stopped(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'vStopped'");
+    match ctx.session.stop_queue.pop_front()
+    {
+        Some(reply) => pkt.responce_add_usd_cs(&reply),
+        None =>
+        {
+            ctx.session.notification_pending = false;
+            pkt.responce("$OK#9a");
+        },
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Прерывание как по ^C. В non-stop режиме останавливается первый исполняющийся поток (Stop Reply придет уведомлением)
+///$vCtrlC
+pub fn cmd_v_ctrl_c(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'vCtrlC'");
+    let mut result = Ok(());
+    if ctx.session.non_stop
+    {
+        if let Some(tid) = target_threads(ctx).into_iter().find(|&tid| ctx.target.thread_stop(tid).is_none())
+        {
+            result = ctx.target.stop_thread(tid, StopReason::Signal(0x02));
+        }
+    }
+    reply_result(pkt, result); //All-stop: пакеты обрабатываются, только когда цель остановлена - прерывать нечего
+}
+
+
+///Non-stop: уведомление %Stop:<stop reply>, если у цели есть новые остановы и предыдущее уведомление уже выбрано через vStopped
+///Вызывается сервером после ответа на каждый пакет и между чтениями, пока потоки исполняются. Возвращает сообщение целиком (%...#cs)
+pub fn stop_notification(session: &mut Session, target: &mut dyn Target) -> Option<String>
+{
+    for (tid, stop) in target.take_stops()
+    {
+        let reply = thread_stop_reply(session, Some(tid), stop);
+        session.stop_queue.push_back(reply);
+    }
+    if session.notification_pending
+    {
+        return None;
+    }
+    let reply = session.stop_queue.pop_front()?;
+    session.notification_pending = true;
+    Some(format!("%{}", &make_packet(&format!("Stop:{}", reply))[1..]))
+}
+
+
+///Потоки цели. У цели без потоков - один неявный поток 0
+fn target_threads(ctx: &CmdContext) -> Vec<u64>
+{
+    let threads = ctx.target.threads();
+    if threads.is_empty() {vec![0]} else {threads}
+}
+
+
+///Поток процесса pid: текущий, если он из этого процесса, иначе первый. None - процесс не подключен
+fn process_thread(ctx: &CmdContext, pid: u64) -> Option<u64>
+{
+    let current = ctx.target.current_thread();
+    if ptid_parts(current).0 == pid && ctx.target.thread_alive(current)
+    {
+        return Some(current);
+    }
+    target_threads(ctx).into_iter().find(|&tid| ptid_parts(tid).0 == pid)
+}
+
+
+///Продолжить исполнение потоков до останова. Ответ: $Otext и Stop Reply Packet
+fn resume_target(pkt: &mut RspPacket, ctx: &mut CmdContext, actions: &[(u64, ResumeAction)], action: &str)
+{
+    if actions.is_empty()
+    {//Ни к одному потоку действие не относится
+        reply_result(pkt, Err(0x01));
+        return;
+    }
+    let step = actions.iter().any(|&(_, a)| a == ResumeAction::Step);
+    let stop = ctx.target.resume_threads(actions, ctx.cancel_flag); //Run simulation
+    if stop == StopReason::FileIo
+    {//Вызов semihosting: вместо Stop Reply - запрос File-I/O, исполнение продолжится после ответа $F
+        if let Some(request) = ctx.target.file_io_request()
+        {
+            log_println!("GDB-Server : File-I/O request '{}'", request);
+            ctx.session.file_io_resume = Some((actions.to_vec(), action.to_string()));
+            pkt.responce_add_usd_cs(&request);
+            pkt.need_responce = Some(true);
+            return;
+        }
+    }
+    let message = if stop == StopReason::Signal(0x02)
+    {//Было прерывание исполнения по ^C
+        " GDB-Server message : Interrupted execution by ^C.\n".to_string()
+    }
+    else if step
+    {
+        format!(" GDB-Server message : Halted due to step. ({}, s-action)\n + Any text message.\n", action)
+    }
+    else
+    {
+        //Перед Stop Reply Packet ещё можно ответить $Otext. $Otext можно использовать только с Stop Reply Packet и с qRcmd !
+        format!(" GDB-Server message : Halted due to breakpoint. ({}, c-action)\n + Any text message.\n", action)
+    };
+    pkt.console_add_usd_o_cs(&ctx.target.take_console_output()); //Вывод команд breakpoints (dprintf), еще не отправленный во время исполнения
+    pkt.console_add_usd_o_cs(&message);
+    pkt.responce_add_usd_cs(&stop_reply(ctx, stop)); //Stop-reply packet: T02 = SIGINT, T05 = SIGTRAP
+    ctx.cancel_flag.store(false, Ordering::SeqCst); //Сбросить признак прерывания по ^C
+
+    pkt.need_responce = Some(true);
+}
+
+
+///Продолжить исполнение (без vCont: GDB использует, если сервер не объявил vContSupported)
+///$c[addr]. Продолжают потоки, выбранные Hc (по умолчанию все)
+pub fn cmd_continue(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'c'");
+    let thread = ctx.session.cont_thread;
+    let actions: Vec<(u64, ResumeAction)> = target_threads(ctx).into_iter().filter(|&tid| thread.matches(tid)).map(|tid| (tid, ResumeAction::Continue)).collect();
+    resume_target(pkt, ctx, &actions, "c");
+}
+
+
+///Выполнить одну инструкцию (без vCont)
+///$s[addr]. Шаг выполняет поток, выбранный Hc (если выбраны все - текущий)
+pub fn cmd_step(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 's'");
+    let tid = match ctx.session.cont_thread
+    {
+        ThreadId::Id(tid) => tid,
+        ThreadId::Process(pid) => process_thread(ctx, pid).unwrap_or_else(|| ctx.target.current_thread()),
+        ThreadId::All | ThreadId::Any => ctx.target.current_thread(),
+    };
+    resume_target(pkt, ctx, &[(tid, ResumeAction::Step)], "s");
+}
+
+
+///Ответ GDB на запрос File-I/O
+///$F<retcode>[,errno[,C]][;attachment]. Исполнение продолжается с теми же действиями; 'C' - во время вызова был ^C
+pub fn cmd_file_io_reply(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда '{}'", data);
+    let mut fields = data[1..].split(';').next().unwrap_or("").split(',');
+    let retcode = fields.next().and_then(|r| match r.strip_prefix('-')
+    {
+        Some(r) => i64::from_str_radix(r, 16).ok().map(|r| -r),
+        None => i64::from_str_radix(r, 16).ok(),
+    });
+    let errno = fields.next().and_then(|e| u64::from_str_radix(e, 16).ok());
+    let interrupted = fields.next() == Some("C");
+    let (actions, action) = match (retcode, ctx.session.file_io_resume.take())
+    {
+        (Some(retcode), Some(resume)) if ctx.target.file_io_reply(retcode, errno).is_ok() => resume,
+        _ =>
+        {//Запроса не было или некорректный ответ
+            reply_result(pkt, Err(0x01));
+            return;
+        },
+    };
+    if interrupted
+    {//Вызов завершен, но дальше программа не исполняется
+        pkt.text_add_usd_o_cs(" GDB-Server message : Interrupted execution by ^C.\n");
+        pkt.responce_add_usd_cs(&stop_reply(ctx, StopReason::Signal(0x02)));
+        pkt.need_responce = Some(true);
+        return;
+    }
+    resume_target(pkt, ctx, &actions, &action);
+}
+
+
+///Завершение работы цели
+///$vKill[;pid]. Если подключены и другие процессы, от процесса pid сервер отключается, а сеанс продолжается
+pub fn cmd_v_kill(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'vKill'");
+    let pid = pkt.data.unwrap().strip_prefix("vKill;").and_then(|pid| u64::from_str_radix(pid, 16).ok());
+    if let Some(pid) = pid
+    {
+        let processes = ctx.target.processes();
+        if processes.len() > 1 && processes.contains(&pid)
+        {//Процесс симулятора не уничтожается: остается в списке для vAttach
+            reply_result(pkt, ctx.target.detach(pid));
+            return;
+        }
+    }
+    pkt.responce("$OK#9a");
+    pkt.need_responce = Some(true);
+    pkt.kill_flag = Some(true);
+}
+
+
+///Расширенный режим (target extended-remote): GDB может подключаться к процессам (vAttach)
+///$!
+pub fn cmd_extended_mode(pkt: &mut RspPacket, _ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда '!'");
+    pkt.responce("$OK#9a");
+    pkt.need_responce = Some(true);
+}
+
+
+///Подключение к процессу
+///$vAttach;pid. Ответ - Stop Reply Packet процесса (в non-stop режиме - OK и уведомление %Stop)
+pub fn cmd_v_attach(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда '{}'", data);
+    let stop = u64::from_str_radix(&data["vAttach;".len()..], 16).map_err(|_| 0x01)
+        .and_then(|pid| Ok((pid, ctx.target.attach(pid)?)));
+    let (pid, stop) = match stop
+    {
+        Ok(stop) => stop,
+        Err(code) =>
+        {
+            reply_result(pkt, Err(code));
+            return;
+        },
+    };
+    //Процесс становится текущим: GDB сразу читает его регистры
+    if let Some(tid) = process_thread(ctx, pid)
+    {
+        let _ = ctx.target.select_thread(tid);
+    }
+    let thread = if ctx.target.threads().is_empty() {None} else {Some(ctx.target.current_thread())};
+    let reply = thread_stop_reply(ctx.session, thread, stop);
+    if ctx.session.non_stop
+    {
+        ctx.session.stop_queue.push_back(reply);
+        pkt.responce("$OK#9a");
+    }
+    else
+    {
+        pkt.responce_add_usd_cs(&reply);
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Отключение от цели или от процесса
+///$D - от всех процессов, $D;pid - от процесса pid. У цели без процессов - только ответ OK
+pub fn cmd_detach(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let data = pkt.data.unwrap();
+    log_println!("GDB-Server : Получена команда '{}'", data);
+    let result = match data.strip_prefix("D;")
+    {
+        Some(pid) => u64::from_str_radix(pid, 16).map_err(|_| 0x01).and_then(|pid| ctx.target.detach(pid)),
+        None if data == "D" => ctx.target.processes().into_iter().try_for_each(|pid| ctx.target.detach(pid)),
+        None => Err(0x01),
+    };
+    reply_result(pkt, result);
+}
+
+
+
+///Результат vFile-операции: $F<result>[;<attachment>] или $F-1,<errno>
+///Без песочницы (--sandbox) - пустой ответ: GDB считает host I/O не поддерживаемым
+fn reply_v_file<F>(pkt: &mut RspPacket, ctx: &mut CmdContext, op: F)
+    where F: FnOnce(&mut HostIo) -> Result<(u64, Option<Vec<u8>>), u64>
+{
+    log_println!("GDB-Server : Получена команда '{}'", pkt.cmd_key());
+    match ctx.session.host_io.as_mut().map(op)
+    {
+        None => pkt.responce("+$#00"),
+        Some(Ok((result, None))) => pkt.responce_add_usd_cs(&format!("F{:x}", result)),
+        Some(Ok((result, Some(attachment)))) => pkt.responce_add_usd_cs_binary(&format!("F{:x};", result), &attachment),
+        Some(Err(errno)) => pkt.responce_add_usd_cs(&format!("F-1,{:x}", errno)),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Поля vFile-пакета после префикса команды: hex-числа и имена файлов в hex
+fn v_file_args<'a>(data: &'a str, prefix: &str) -> Vec<&'a str>
+{
+    data.strip_prefix(prefix).map_or(Vec::new(), |args| args.split(',').collect())
+}
+
+
+///Hex-число из поля vFile-пакета. Некорректное поле - EINVAL
+fn v_file_num(args: &[&str], index: usize) -> Result<u64, u64>
+{
+    args.get(index).and_then(|arg| u64::from_str_radix(arg, 16).ok()).ok_or(EINVAL)
+}
+
+
+///Имя файла из поля vFile-пакета (hex-байты). Некорректное поле - EINVAL
+fn v_file_path(args: &[&str], index: usize) -> Result<String, u64>
+{
+    args.get(index).and_then(|arg| hex_decode(arg)).and_then(|path| String::from_utf8(path).ok()).ok_or(EINVAL)
+}
+
+
+///Файловая система для следующих vFile-пакетов: процесс pid (0 - сам сервер)
+///$vFile:setfs:<pid>. У всех процессов одна песочница
+pub fn cmd_v_file_setfs(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:setfs:");
+    reply_v_file(pkt, ctx, |_| v_file_num(&args, 0).map(|_| (0, None)));
+}
+
+
+///Открыть файл песочницы
+///$vFile:open:<filename>,<flags>,<mode>. Ответ - $F<fd>
+pub fn cmd_v_file_open(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:open:");
+    reply_v_file(pkt, ctx, |host_io| host_io.open(&v_file_path(&args, 0)?, v_file_num(&args, 1)?, v_file_num(&args, 2)?).map(|fd| (fd, None)));
+}
+
+
+///Закрыть файл
+///$vFile:close:<fd>
+pub fn cmd_v_file_close(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:close:");
+    reply_v_file(pkt, ctx, |host_io| host_io.close(v_file_num(&args, 0)?).map(|_| (0, None)));
+}
+
+
+///Чтение файла
+///$vFile:pread:<fd>,<count>,<offset>. Ответ - $F<len>;<bytes> (экранированные как в X-пакете). Количество ограничено размером ответа
+pub fn cmd_v_file_pread(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:pread:");
+    let max_count = (pkt.max_responce_len() - 32) / 2; //Каждый байт может быть экранирован
+    reply_v_file(pkt, ctx, |host_io|
+    {
+        let count = (v_file_num(&args, 1)? as usize).min(max_count);
+        let data = host_io.pread(v_file_num(&args, 0)?, count, v_file_num(&args, 2)?)?;
+        Ok((data.len() as u64, Some(data)))
+    });
+}
+
+
+///Запись в файл
+///$vFile:pwrite:<fd>,<offset>,<bytes>. Данные бинарные (как в X-пакете), поэтому берутся из input_buf. Ответ - $F<количество записанных байт>
+pub fn cmd_v_file_pwrite(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let input_buf = ctx.input_buf;
+    let header = input_buf.iter().position(|&c| c == b'$').map_or(0, |usd_pos| usd_pos + 1 + "vFile:pwrite:".len());
+    let mut fields = input_buf[header .. input_buf.len() - 3].splitn(3, |&c| c == b',');
+    let fd = fields.next().and_then(|fd| str::from_utf8(fd).ok()).and_then(|fd| u64::from_str_radix(fd, 16).ok());
+    let offset = fields.next().and_then(|offset| str::from_utf8(offset).ok()).and_then(|offset| u64::from_str_radix(offset, 16).ok());
+    let data = fields.next().map(unescape_binary);
+    reply_v_file(pkt, ctx, |host_io| match (fd, offset, data)
+    {
+        (Some(fd), Some(offset), Some(data)) => host_io.pwrite(fd, offset, &data).map(|len| (len as u64, None)),
+        _ => Err(EINVAL),
+    });
+}
+
+
+///Удалить файл
+///$vFile:unlink:<filename>
+pub fn cmd_v_file_unlink(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:unlink:");
+    reply_v_file(pkt, ctx, |host_io| host_io.unlink(&v_file_path(&args, 0)?).map(|_| (0, None)));
+}
+
+
+///Содержимое символической ссылки
+///$vFile:readlink:<filename>. Ответ - $F<len>;<path>
+pub fn cmd_v_file_readlink(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:readlink:");
+    reply_v_file(pkt, ctx, |host_io|
+    {
+        let link = host_io.readlink(&v_file_path(&args, 0)?)?;
+        Ok((link.len() as u64, Some(link)))
+    });
+}
+
+
+///Информация об открытом файле
+///$vFile:fstat:<fd>. Ответ - $F<len>;<struct stat> (File-I/O, big-endian)
+pub fn cmd_v_file_fstat(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = v_file_args(pkt.data.unwrap_or(""), "vFile:fstat:");
+    reply_v_file(pkt, ctx, |host_io|
+    {
+        let stat = host_io.fstat(v_file_num(&args, 0)?)?;
+        Ok((stat.len() as u64, Some(stat)))
+    });
+}
+
+
+///Ответ на пакет трассировки
+///Цель без эксперимента трассировки - пустой ответ: GDB считает tracepoints не поддерживаемыми
+fn reply_trace<F>(pkt: &mut RspPacket, ctx: &mut CmdContext, op: F)
+    where F: FnOnce(&mut TraceExperiment, &mut Session) -> String
+{
+    log_println!("GDB-Server : Получена команда '{}'", pkt.cmd_key());
+    match ctx.target.trace_experiment_mut()
+    {
+        Some(trace) => pkt.responce_add_usd_cs(&op(trace, ctx.session)),
+        None => pkt.responce("+$#00"),
+    }
+    pkt.need_responce = Some(true);
+}
+
+
+///Аргументы пакета трассировки после префикса команды
+fn trace_args<'a>(data: Option<&'a str>, prefix: &str) -> &'a str
+{
+    data.and_then(|data| data.strip_prefix(prefix)).unwrap_or("")
+}
+
+
+///Ответ на пакет трассировки без данных: $OK или $E01
+fn trace_result(result: TargetResult<()>) -> String
+{
+    if result.is_ok() {"OK".to_string()} else {"E01".to_string()}
+}
+
+
+///Новый эксперимент: удалить tracepoints и кадры
+///$QTinit
+pub fn cmd_qt_init(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |trace, _|
+    {
+        trace.init();
+        "OK".to_string()
+    });
+}
+
+
+///Определение tracepoint или его действий (см. TraceExperiment::define)
+///$QTDP:<n>:<addr>:<E|D>:<step>:<pass>[:X<len>,<cond>][-] или $QTDP:-<n>:<addr>:[S]<actions>[-]
+pub fn cmd_qt_dp(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "QTDP:");
+    reply_trace(pkt, ctx, |trace, _| trace_result(trace.define(args)));
+}
+
+
+///Исходный текст tracepoint (команды actions, условие) для выгрузки в GDB
+///$QTDPsrc:<n>:<addr>:<type>:<start>:<len>:<hex text>
+pub fn cmd_qt_dp_src(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "QTDPsrc:");
+    reply_trace(pkt, ctx, |trace, _| trace_result(trace.define_source(args)));
+}
+
+
+///Включить tracepoint (в том числе во время эксперимента)
+///$QTEnable:<n>:<addr>
+pub fn cmd_qt_enable(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "QTEnable:");
+    reply_trace(pkt, ctx, |trace, _| trace_result(trace.enable(args, true)));
+}
+
+
+///Выключить tracepoint
+///$QTDisable:<n>:<addr>
+pub fn cmd_qt_disable(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "QTDisable:");
+    reply_trace(pkt, ctx, |trace, _| trace_result(trace.enable(args, false)));
+}
+
+
+///Параметры буфера трассировки
+///$QTBuffer:circular:<0|1> или $QTBuffer:size:<n>
+pub fn cmd_qt_buffer(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "QTBuffer:");
+    reply_trace(pkt, ctx, |trace, _| trace_result(trace.set_buffer(args)));
+}
+
+
+///Настройки эксперимента, которые принимаются и не влияют на него: переменные трассировки (QTDV),
+///read-only секции (QTro), трассировка после отключения GDB (QTDisconnected), заметки (QTNotes)
+pub fn cmd_qt_setting(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |_, _| "OK".to_string());
+}
+
+
+///Начать эксперимент: кадры собираются, когда цель проходит tracepoints
+///$QTStart
+pub fn cmd_qt_start(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |trace, _|
+    {
+        trace.start();
+        "OK".to_string()
+    });
+}
+
+
+///Остановить эксперимент. Кадры остаются для QTFrame
+///$QTStop
+pub fn cmd_qt_stop(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |trace, _|
+    {
+        trace.stop("tstop:0");
+        "OK".to_string()
+    });
+}
+
+
+///Состояние эксперимента
+///$qTStatus. Ответ - $T<running>;<stop reason>;tframes:..;tcreated:..;tfree:..;tsize:..;circular:..;disconn:0
+pub fn cmd_q_t_status(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |trace, _| trace.status());
+}
+
+
+///Состояние tracepoint
+///$qTP:<n>:<addr>. Ответ - $V<срабатывания>:<байт кадров в буфере>
+pub fn cmd_q_tp(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let args = trace_args(pkt.data, "qTP:");
+    reply_trace(pkt, ctx, |trace, _| trace.tracepoint_status(args).unwrap_or_else(|| "E01".to_string()));
+}
+
+
+///Выбор кадра трассировки: после него 'g', 'p', 'm' читают кадр
+///$QTFrame:<n>, $QTFrame:pc:<addr>, $QTFrame:tdp:<n>, $QTFrame:range:<start>:<end>, $QTFrame:outside:<start>:<end>
+///Ответ - $F<кадр>T<tracepoint> или $F-1 (кадр не найден). $QTFrame:ffffffff - вернуться к живой цели, ответ $OK
+pub fn cmd_qt_frame(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let query = trace_args(pkt.data, "QTFrame:");
+    reply_trace(pkt, ctx, |trace, _| match query
+    {
+        "ffffffff" | "-1" =>
+        {
+            trace.deselect();
+            "OK".to_string()
+        },
+        _ => match trace.select(query)
+        {
+            Ok(Some((frame, tracepoint))) => format!("F{:x}T{:x}", frame, tracepoint),
+            Ok(None) => "F-1".to_string(),
+            Err(_) => "E01".to_string(),
+        },
+    });
+}
+
+
+///Начало выгрузки tracepoints в GDB (после подключения к цели с идущим экспериментом)
+///$qTfP. Ответ - первая строка: T<n>:<addr>:<E|D>:<step>:<pass>, A/S<n>:<addr>:<действия>, Z<исходный текст>
+pub fn cmd_q_tf_p(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |trace, session|
+    {
+        session.trace_upload = trace.upload().into();
+        session.trace_upload.pop_front().unwrap_or_else(|| "l".to_string())
+    });
+}
+
+
+///Продолжение выгрузки tracepoints
+///$qTsP. Ответ - следующая строка или $l (конец списка)
+pub fn cmd_q_ts_p(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |_, session| session.trace_upload.pop_front().unwrap_or_else(|| "l".to_string()));
+}
+
+
+///Выгрузка переменных трассировки: переменных нет
+///$qTfV, $qTsV. Ответ - $l
+pub fn cmd_q_t_v(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    reply_trace(pkt, ctx, |_, _| "l".to_string());
+}
+
+
+///Кадры трассировки в формате tfile (так GDB сохраняет трассировку в файл на своей стороне: tsave)
+///$qTBuffer:<offset>,<len>. Ответ - hex или $l (смещение за концом буфера)
+pub fn cmd_q_t_buffer(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    let request = parse_addr_len(trace_args(pkt.data, "qTBuffer:"));
+    let max_len = (pkt.max_responce_len() - 4) / 2;
+    reply_trace(pkt, ctx, |trace, _|
+    {
+        let buffer = trace.buffer();
+        match request
+        {
+            Some((offset, _)) if offset >= buffer.len() as u64 => "l".to_string(),
+            Some((offset, len)) => hex_encode(&buffer[offset as usize .. buffer.len().min(offset as usize + len.min(max_len))]),
+            None => "E01".to_string(),
+        }
+    });
+}
+
+
+///Сохранить трассировку в файл на стороне цели (tsave -r): файл tfile в песочнице host I/O (--sandbox)
+///$QTSave:<hex filename>
+pub fn cmd_qt_save(pkt: &mut RspPacket, ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Получена команда 'QTSave'");
+    let name = hex_decode(trace_args(pkt.data, "QTSave:")).and_then(|name| String::from_utf8(name).ok());
+    let regs_size = ctx.target.read_registers().map_or(0, |regs| regs.len());
+    let file = match ctx.target.trace_experiment()
+    {
+        Some(trace) => trace.tfile(regs_size),
+        None =>
+        {
+            pkt.responce("+$#00");
+            pkt.need_responce = Some(true);
+            return;
+        },
+    };
+    let saved = match (name, ctx.session.host_io.as_mut())
+    {
+        (Some(name), Some(host_io)) => host_io.write_file(&name, &file).is_ok(),
+        _ => false, //Без песочницы сохранять некуда
+    };
+    reply_result(pkt, if saved {Ok(())} else {Err(0x01)});
+}
+
+
+///Тесты для обработчиков по умолчанию ================================================================================
+#[cfg(test)]
+mod test_cmd_handlers
+{
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use codec::make_packet;
+    use target::DummyTarget;
+    use sim::SimTarget;
+    use session::Session;
+    use config::FeatureOverrides;
+
+    fn dispatch(registry: &CmdRegistry, session: &mut Session, data: &str) -> String
+    {
+        let packet = make_packet(data);
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut target = DummyTarget::new(false);
+        let mut pkt = RspPacket::new(packet.as_bytes(), packet.len());
+        pkt.match_cmd(registry, packet.as_bytes(), &cancel_flag, &mut target, session);
+        pkt.responce.unwrap()
+    }
+
+    ///Данные ответа цели target на пакет (без Ack, '$' и контрольной суммы)
+    fn target_reply(registry: &CmdRegistry, session: &mut Session, target: &mut dyn Target, data: &str) -> String
+    {
+        let packet = make_packet(data);
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut pkt = RspPacket::new(packet.as_bytes(), packet.len());
+        pkt.match_cmd(registry, packet.as_bytes(), &cancel_flag, target, session);
+        let responce = pkt.responce.unwrap();
+        let packet = responce.trim_start_matches('+');
+        packet[1 .. packet.len()-3].to_string()
+    }
+
+    #[test]
+    fn test_q_supported()
+    {
+        let mut registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+
+        //Клиент без swbreak
+        assert_eq!("+$PacketSize=1000;QStartNoAckMode+;vContSupported+#3d", dispatch(&registry, &mut session, "qSupported"));
+        assert_eq!("$T05#b9", dispatch(&registry, &mut session, "vCont;c"));
+
+        //Клиент с swbreak+ и hwbreak+: features сохраняются в сессии, stop reply содержит причину останова
+        assert_eq!(
+            make_packet("PacketSize=1000;QStartNoAckMode+;vContSupported+;swbreak+;hwbreak+"),
+            dispatch(&registry, &mut session, "qSupported:multiprocess+;swbreak+;hwbreak+;xmlRegisters=i386")[1..].to_string());
+        assert!(session.client_features.supports("multiprocess"));
+        assert_eq!(Some("i386"), session.client_features.value("xmlRegisters"));
+        assert_eq!(make_packet("T05swbreak:;"), dispatch(&registry, &mut session, "vCont;c"));
+
+        //Отключенные команды не объявляются
+        registry.disable("QStartNoAckMode");
+        registry.disable("Z1");
+        assert_eq!(
+            make_packet("PacketSize=1000;vContSupported+;swbreak+"),
+            dispatch(&registry, &mut session, "qSupported:swbreak+;hwbreak+")[1..].to_string());
+    }
+
+
+    #[test]
+    fn test_feature_overrides()
+    {
+        let mut registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        let mut overrides = FeatureOverrides::default();
+        overrides.add("vContSupported-").unwrap();
+        overrides.add("swbreak+").unwrap();
+        overrides.add("multiprocess+").unwrap();
+        overrides.packet_size = Some(0x40);
+        overrides.apply(&mut registry);
+
+        //Клиент не присылал swbreak+, но feature включена принудительно
+        assert_eq!(
+            make_packet("PacketSize=40;QStartNoAckMode+;swbreak+;multiprocess+"),
+            dispatch(&registry, &mut session, "qSupported:vContSupported+")[1..].to_string());
+        //Команды отключенной feature получают пустой ответ, хотя обработчик есть
+        assert_eq!("+$#00", dispatch(&registry, &mut session, "vCont?"));
+        assert_eq!("+$#00", dispatch(&registry, &mut session, "vCont;c"));
+        //Вместо vCont GDB использует c/s
+        assert_eq!(make_packet("T05swbreak:;"), dispatch(&registry, &mut session, "c"));
+        assert_eq!("$T05#b9", dispatch(&registry, &mut session, "s"));
+    }
+
+
+    #[test]
+    fn test_disabled_feature_packets()
+    {
+        let mut registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        let mut overrides = FeatureOverrides::default();
+        for switch in &["QNonStop-", "TracepointSource-", "EnableDisableTracepoints-"]
+        {
+            overrides.add(switch).unwrap();
+        }
+        overrides.apply(&mut registry);
+        //Команды зарегистрированы под более длинными префиксами, чем название feature, но тоже отключены
+        for data in &["QNonStop:1", "vStopped", "vCtrlC", "QTDPsrc:1:80000000:at:0:4:6869", "QTEnable:1:80000000", "QTDisable:1:80000000"]
+        {
+            assert_eq!("+$#00", dispatch(&registry, &mut session, data), "{}", data);
+        }
+    }
+
+
+    #[test]
+    fn test_threads()
+    {
+        let registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        let mut target = SimTarget::with_harts(false, THREAD_INFO_CHUNK + 2);
+        let mut reply = |data: &str| target_reply(&registry, &mut session, &mut target, data);
+        //Список потоков по частям
+        assert_eq!("m1,2,3,4,5,6,7,8,9,a,b,c,d,e,f,10", reply("qfThreadInfo"));
+        assert_eq!("m11,12", reply("qsThreadInfo"));
+        assert_eq!("l", reply("qsThreadInfo"));
+        assert_eq!("QC1", reply("qC"));
+
+        assert_eq!("OK", reply("T12"));
+        assert_eq!("E01", reply("T13"));
+        assert_eq!("E01", reply("T-1"));
+        assert_eq!("OK", reply("Hg3"));
+        assert_eq!("QC3", reply("qC"));
+        assert_eq!("OK", reply("Hg0"));
+        assert_eq!("E01", reply("Hg13"));
+        assert_eq!("E01", reply("Hx1"));
+        assert_eq!(hex_encode(b"hart 2, pc 0x0, stopped: SIGINT"), reply("qThreadExtraInfo,3"));
+
+        //Шаг потока 2, остальные продолжают: останов на потоке 2
+        assert_eq!("T05thread:2;", reply("vCont;s:2;c"));
+        assert_eq!("QC2", reply("qC"));
+        assert_eq!(hex_encode(&4u64.to_le_bytes()), reply("p20"));
+        //Действие только для потока 5
+        assert_eq!("T05thread:5;", reply("vCont;c:5"));
+        assert_eq!("E01", reply("vCont;c:zz"));
+        assert_eq!("E01", reply("vCont;c:13"));
+        assert_eq!("", reply("vCont;x"));
+
+        //Hc выбирает поток для 's'
+        assert_eq!("OK", reply("Hc7"));
+        assert_eq!("T05thread:7;", reply("s"));
+        assert_eq!("OK", reply("Hc-1"));
+        assert_eq!("T05thread:7;", reply("s"));
+        assert_eq!("T05thread:1;", reply("c"));
+        assert_eq!("T05thread:1;", reply("?"));
+    }
+
+
+    #[test]
+    fn test_non_stop()
+    {
+        let registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        let mut target = SimTarget::with_harts(true, 3);
+        //Ответ на пакет и уведомление %Stop, отправляемое сервером после ответа
+        let mut reply = |data: &str|
+        {
+            let packet = target_reply(&registry, &mut session, &mut target, data);
+            let notification = if session.non_stop {stop_notification(&mut session, &mut target)} else {None};
+            (packet, notification.map(|n| n[1 .. n.len()-3].to_string()))
+        };
+        assert_eq!("vCont;c;C;s;S;t", reply("vCont?").0);
+        assert_eq!(("OK".to_string(), None), reply("QNonStop:1"));
+        //Все потоки остановлены: первый Stop Reply в ответе, остальные через vStopped
+        assert_eq!("T02thread:1;", reply("?").0);
+        assert_eq!("T02thread:2;", reply("vStopped").0);
+        assert_eq!("T02thread:3;", reply("vStopped").0);
+        assert_eq!("OK", reply("vStopped").0);
+
+        //Потоки исполняются, ответ OK сразу
+        assert_eq!(("OK".to_string(), None), reply("vCont;c"));
+        assert_eq!("OK", reply("?").0);
+        assert_eq!(("OK".to_string(), Some("Stop:T00thread:2;".to_string())), reply("vCont;t:2"));
+        //Регистры остановленного потока доступны, исполняющегося - нет
+        assert_eq!("OK", reply("Hg2").0);
+        assert!(!reply("g").0.starts_with('E'));
+        assert_eq!("OK", reply("Hg3").0);
+        assert_eq!("E01", reply("g").0);
+        //Пока уведомление не подтверждено до OK, новые остановы ждут в очереди
+        assert_eq!(("OK".to_string(), None), reply("vCtrlC"));
+        assert_eq!("T02thread:1;", reply("vStopped").0);
+        assert_eq!("OK", reply("vStopped").0);
+        assert_eq!(("OK".to_string(), Some("Stop:T05thread:2;".to_string())), reply("vCont;s:2"));
+        assert_eq!("OK", reply("vStopped").0);
+
+        //Возврат в all-stop: все потоки останавливаются
+        assert_eq!(("OK".to_string(), None), reply("QNonStop:0"));
+        assert_eq!("OK", reply("Hg3").0);
+        assert!(!reply("g").0.starts_with('E'));
+        assert_eq!("E01", reply("QNonStop:2").0);
+
+        //Без loop_run поток исполняется между пакетами (poll_threads) до breakpoint, а не останавливается сразу
+        let mut session = Session::new();
+        let mut target = SimTarget::with_harts(false, 2);
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, "Z0,80000020,4"));
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, &format!("P20={}", hex_encode(&0x8000_0000u64.to_le_bytes()))));
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, "QNonStop:1"));
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, "vCont;c:1"));
+        assert_eq!(None, stop_notification(&mut session, &mut target));
+        assert!(!target.poll_threads());
+        assert_eq!(Some(make_packet("Stop:T05thread:1;").replacen('$', "%", 1)), stop_notification(&mut session, &mut target));
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, "vStopped"));
+        assert_eq!("OK", target_reply(&registry, &mut session, &mut target, "Hg1"));
+        assert_eq!(hex_encode(&0x8000_0020u64.to_le_bytes()), target_reply(&registry, &mut session, &mut target, "p20"));
+
+        //Цель без non-stop режима
+        let mut session = Session::new();
+        assert_eq!(make_packet("vCont;c;C;s;S"), dispatch(&registry, &mut session, "vCont?"));
+        assert_eq!(make_packet("E01"), dispatch(&registry, &mut session, "QNonStop:1"));
+        assert!(!dispatch(&registry, &mut session, "qSupported").contains("QNonStop"));
+        assert_eq!("$OK#9a", dispatch(&registry, &mut session, "vCtrlC"));
+    }
+
+
+    #[test]
+    fn test_multiprocess()
+    {
+        use inferiors::Inferiors;
+        use target::ptid;
+
+        assert_eq!(Some(ThreadId::Id(ptid(2, 3))), ThreadId::parse("p2.3"));
+        assert_eq!(Some(ThreadId::Process(2)), ThreadId::parse("p2"));
+        assert_eq!(Some(ThreadId::Process(2)), ThreadId::parse("p2.-1"));
+        assert_eq!(Some(ThreadId::All), ThreadId::parse("p-1"));
+        assert_eq!(Some(ThreadId::Any), ThreadId::parse("p0.0"));
+        assert_eq!(None, ThreadId::parse("p-1.2"));
+        assert_eq!(None, ThreadId::parse("p1.x"));
+
+        let registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        let mut target = Inferiors::new(vec![SimTarget::with_harts(false, 2), SimTarget::new(false)]);
+        let mut reply = |data: &str| target_reply(&registry, &mut session, &mut target, data);
+        assert!(reply("qSupported:multiprocess+").ends_with(";multiprocess+"));
+        assert_eq!("OK", reply("!"));
+        assert_eq!("mp1.1,p1.2", reply("qfThreadInfo"));
+        assert_eq!("l", reply("qsThreadInfo"));
+        assert_eq!("QCp1.1", reply("qC"));
+
+        //Подключение ко второму процессу: Stop Reply с его потоком, память своя
+        assert_eq!("T02thread:p2.1;", reply("vAttach;2"));
+        assert_eq!("E01", reply("vAttach;2"));
+        assert_eq!("E01", reply("vAttach;7"));
+        assert_eq!("mp1.1,p1.2,p2.1", reply("qfThreadInfo"));
+        assert_eq!("OK", reply("X80000000,1:\x05"));
+        assert_eq!("05", reply("m80000000,1"));
+        assert_eq!("OK", reply("Hgp1.0"));
+        assert_eq!("QCp1.1", reply("qC"));
+        assert_eq!("00", reply("m80000000,1"));
+        assert_eq!("OK", reply("Tp2.1"));
+        assert_eq!("E01", reply("Tp2.2"));
+
+        //Действия по процессам: шаг во втором, остальные продолжают
+        assert_eq!("T05thread:p2.1;", reply("vCont;s:p2.1;c:p1.-1"));
+        assert_eq!("OK", reply("Hcp1"));
+        assert_eq!("T05thread:p1.1;", reply("s"));
+
+        //Отключение от процесса: его потоки больше не видны
+        assert_eq!("OK", reply("D;2"));
+        assert_eq!("E01", reply("D;2"));
+        assert_eq!("E01", reply("Tp2.1"));
+        assert_eq!("E01", reply("Hgp2.0"));
+        assert_eq!("mp1.1,p1.2", reply("qfThreadInfo"));
+        //vKill;pid при нескольких процессах - отключение, сеанс продолжается
+        assert_eq!("T05thread:p2.1;", reply("vAttach;2"));
+        assert_eq!("OK", reply("vKill;2"));
+        assert_eq!("mp1.1,p1.2", reply("qfThreadInfo"));
+        assert_eq!("OK", reply("D"));
+        assert_eq!("l", reply("qfThreadInfo"));
+
+        //Без multiprocess у клиента - номера потоков без pid
+        let mut session = Session::new();
+        let mut target = Inferiors::new(vec![SimTarget::new(false)]);
+        assert!(!target_reply(&registry, &mut session, &mut target, "qSupported").contains("multiprocess"));
+        assert_eq!(format!("thread:{:x};", ptid(1, 1)), thread_stop_reply(&session, Some(ptid(1, 1)), StopReason::Signal(0))[3..]);
+        //Цель без процессов: D отвечает OK
+        assert_eq!("$OK#9a", dispatch(&registry, &mut session, "D"));
+    }
+
+
+    #[test]
+    fn test_no_threads()
+    {
+        //Цель без потоков: один неявный поток 0, как до поддержки потоков
+        let registry = CmdRegistry::with_defaults();
+        let mut session = Session::new();
+        assert_eq!(make_packet("l"), dispatch(&registry, &mut session, "qfThreadInfo"));
+        assert_eq!(make_packet("l"), dispatch(&registry, &mut session, "qsThreadInfo"));
+        assert_eq!(make_packet("QC0"), dispatch(&registry, &mut session, "qC"));
+        assert_eq!("$OK#9a", dispatch(&registry, &mut session, "Hg0"));
+        assert_eq!("$OK#9a", dispatch(&registry, &mut session, "Hc-1"));
+        assert_eq!(make_packet("E01"), dispatch(&registry, &mut session, "Hg1"));
+        assert_eq!("+$#00", dispatch(&registry, &mut session, "qThreadExtraInfo,1"));
+        assert_eq!(make_packet("T05"), dispatch(&registry, &mut session, "vCont;s:1;c"));
+    }
+}
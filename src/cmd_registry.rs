@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+
+use gdb_server::RspPacket;
+use target::Target;
+use session::Session;
+use scripted::{ScriptedReplies, ScriptedReply};
+
+
+///Контекст обработки команды: всё, что нужно обработчику помимо самого RspPacket
+pub struct CmdContext<'c>
+{
+    pub input_buf: &'c [u8],                        // Исходный буфер с пакетом (для X-пакета, который содержит не только валидные utf-символы)
+    pub cancel_flag: &'c Arc<AtomicBool>,           // Признак прерывания исполнения по ^C
+    pub target: &'c mut dyn Target,                 // Отлаживаемая цель
+    pub session: &'c mut Session,                   // Состояние подключения (features клиента и т.п.)
+    pub registry: &'c CmdRegistry,                  // Реестр, из которого вызван обработчик (чтобы узнать, какие команды поддерживаются)
+}
+
+///Обработчик команды. Формирует responce (и при необходимости output_text) в RspPacket
+pub type CmdHandler = Box<dyn Fn(&mut RspPacket, &mut CmdContext) + Send + Sync>;
+
+
+///Реестр обработчиков команд
+///Обработчик ищется по самому длинному зарегистрированному префиксу данных пакета (например "qXfer:", "vFlash", "Z0", "m")
+///Если ни один префикс не подошел или префикс отключен - вызывается fallback (по умолчанию пустой ответ $#00)
+pub struct CmdRegistry
+{
+    handlers: Vec<(String, Option<CmdHandler>)>,    // Префикс и обработчик. None - префикс отключен (пустой ответ даже при наличии обработчика для более короткого префикса)
+    fallback: CmdHandler,                           // Обработчик неподдерживаемых команд
+    scripted: Option<Mutex<ScriptedReplies>>,       // Подмена ответов по файлу правил (проверяется до обработчиков)
+}
+
+
+impl CmdRegistry
+{
+    ///Пустой реестр: на любую команду пустой ответ
+    pub fn new() -> CmdRegistry
+    {
+        CmdRegistry{
+            handlers: Vec::new(),
+            fallback: Box::new(unsupported_cmd),
+            scripted: None,
+        }
+    }
+
+
+    ///Реестр с набором обработчиков по умолчанию (см. cmd_handlers)
+    pub fn with_defaults() -> CmdRegistry
+    {
+        let mut registry = CmdRegistry::new();
+        ::cmd_handlers::register_defaults(&mut registry);
+        registry
+    }
+
+
+    ///Зарегистрировать обработчик для префикса. Уже зарегистрированный (или отключенный) обработчик для этого префикса заменяется
+    pub fn register<F>(&mut self, prefix: &str, handler: F)
+        where F: Fn(&mut RspPacket, &mut CmdContext) + Send + Sync + 'static
+    {
+        self.set(prefix, Some(Box::new(handler)));
+    }
+
+
+    ///Отключить префикс: команды с этим префиксом получат пустой ответ
+    pub fn disable(&mut self, prefix: &str)
+    {
+        self.set(prefix, None);
+    }
+
+
+    ///Удалить префикс из реестра. Команды с этим префиксом будут искать обработчик по более короткому префиксу
+    pub fn remove(&mut self, prefix: &str)
+    {
+        self.handlers.retain(|(p, _)| p != prefix);
+    }
+
+
+    ///Заменить обработчик неподдерживаемых команд
+    pub fn set_fallback<F>(&mut self, handler: F)
+        where F: Fn(&mut RspPacket, &mut CmdContext) + Send + Sync + 'static
+    {
+        self.fallback = Box::new(handler);
+    }
+
+
+    ///Подменять ответы по правилам (--replies)
+    pub fn set_scripted(&mut self, replies: ScriptedReplies)
+    {
+        self.scripted = Some(Mutex::new(replies));
+    }
+
+
+    ///Ответ по правилам подмены для данных пакета (файл правил перечитывается, если изменился)
+    pub fn scripted_reply(&self, cmd: &str) -> Option<ScriptedReply>
+    {
+        let mut replies = self.scripted.as_ref()?.lock().unwrap_or_else(|e| e.into_inner());
+        match replies.reload_if_changed()
+        {
+            Ok(true) => println!("  Reply rules reloaded: {} rules\n", replies.rules().len()),
+            Ok(false) => (),
+            Err(e) => println!("  {}\n", e),
+        }
+        replies.find(cmd)
+    }
+
+
+    ///Зарегистрированные префиксы и признак их включенности
+    pub fn prefixes(&self) -> Vec<(&str, bool)>
+    {
+        self.handlers.iter().map(|(p, h)| (&p[..], h.is_some())).collect()
+    }
+
+
+    fn set(&mut self, prefix: &str, handler: Option<CmdHandler>)
+    {
+        match self.handlers.iter_mut().find(|(p, _)| p == prefix)
+        {
+            Some(entry) => entry.1 = handler,
+            None => self.handlers.push((prefix.to_string(), handler)),
+        }
+    }
+
+
+    ///Найти обработчик для данных пакета по самому длинному префиксу
+    ///None - если префикс не найден или отключен
+    pub fn find(&self, cmd: &str) -> Option<&CmdHandler>
+    {
+        self.handlers.iter()
+            .filter(|(p, _)| cmd.starts_with(&p[..]))
+            .max_by_key(|(p, _)| p.len())
+            .and_then(|(_, h)| h.as_ref())
+    }
+
+
+    ///Есть ли включенный обработчик для команды
+    pub fn is_enabled(&self, cmd: &str) -> bool
+    {
+        self.find(cmd).is_some()
+    }
+
+
+    ///Вызвать обработчик для пакета (или fallback)
+    pub fn dispatch(&self, pkt: &mut RspPacket, ctx: &mut CmdContext)
+    {
+        let cmd = pkt.cmd_key().to_string();
+        match self.find(&cmd)
+        {
+            Some(handler) => handler(pkt, ctx),
+            None => (self.fallback)(pkt, ctx),
+        }
+    }
+}
+
+
+impl Default for CmdRegistry
+{
+    fn default() -> CmdRegistry
+    {
+        CmdRegistry::with_defaults()
+    }
+}
+
+
+///Неподдерживаемые команды. Ответ от GDB-сервера должен быть: +$#00
+pub fn unsupported_cmd(pkt: &mut RspPacket, _ctx: &mut CmdContext)
+{
+    log_println!("GDB-Server : Unknown command \'{}\'!", pkt.cmd_key());
+    pkt.responce("+$#00");
+    pkt.need_responce = Some(true);
+}
+
+
+///Тесты для CmdRegistry ================================================================================
+#[cfg(test)]
+mod test_cmd_registry
+{
+    use super::*;
+    use target::DummyTarget;
+    use session::Session;
+    use codec::make_packet;
+    use gdb_server::PACKET_SIZE;
+
+    fn dispatch(registry: &CmdRegistry, packet: &str) -> Option<String>
+    {
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut target = DummyTarget::new(false);
+        let mut session = Session::new();
+        let mut pkt = RspPacket::new(packet.as_bytes(), packet.len());
+        let mut ctx = CmdContext{ input_buf: packet.as_bytes(), cancel_flag: &cancel_flag, target: &mut target, session: &mut session, registry };
+        registry.dispatch(&mut pkt, &mut ctx);
+        pkt.responce
+    }
+
+    #[test]
+    fn test_longest_prefix()
+    {
+        let mut registry = CmdRegistry::new();
+        registry.register("q", |pkt, _| pkt.responce("short"));
+        registry.register("qXfer:", |pkt, _| pkt.responce("long"));
+
+        assert_eq!(Some("long".to_string()), dispatch(&registry, "$qXfer:features:read:target.xml:0,fff#7d"));
+        assert_eq!(Some("short".to_string()), dispatch(&registry, "$qTStatus#49"));
+        //Нет подходящего префикса - пустой ответ
+        assert_eq!(Some("+$#00".to_string()), dispatch(&registry, "$vMustReplyEmpty#3a"));
+    }
+
+    #[test]
+    fn test_override_and_disable()
+    {
+        let mut registry = CmdRegistry::with_defaults();
+        assert_eq!(Some("$OK#9a".to_string()), dispatch(&registry, "$qSymbol::#5b"));
+
+        //Замена обработчика по умолчанию
+        registry.register("qSymbol", |pkt, _| pkt.responce_add_usd_cs("qSymbol:6d61696e"));
+        assert_eq!(Some("$qSymbol:6d61696e#2c".to_string()), dispatch(&registry, "$qSymbol::#5b"));
+
+        //Отключенный префикс перекрывает более короткий
+        registry.register("v", |pkt, _| pkt.responce("v"));
+        registry.disable("vKill");
+        assert_eq!(Some("+$#00".to_string()), dispatch(&registry, "$vKill;a410#33"));
+
+        //После удаления - снова более короткий префикс
+        registry.remove("vKill");
+        assert_eq!(Some("v".to_string()), dispatch(&registry, "$vKill;a410#33"));
+    }
+
+    #[test]
+    fn test_scripted_before_handlers()
+    {
+        let mut registry = CmdRegistry::with_defaults();
+        registry.set_scripted(ScriptedReplies::parse("[[reply]]\nmatch = \"qOffsets\"\nreply = \"Text=1000;Data=1000;Bss=1000\"\n\n\
+                                                      [[reply]]\nmatch = \"m*,*\"\nreply = \"{fill:00:{2}}\"\n").unwrap());
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut target = DummyTarget::new(false);
+        let mut session = Session::new();
+        let mut reply = |packet: &str|
+        {
+            let mut pkt = RspPacket::new(packet.as_bytes(), packet.len());
+            pkt.match_cmd(&registry, packet.as_bytes(), &cancel_flag, &mut target, &mut session);
+            pkt.responce
+        };
+        assert_eq!(Some(make_packet("Text=1000;Data=1000;Bss=1000")), reply("$qOffsets#4b"));
+        assert_eq!(Some(make_packet("00000000")), reply("$m10030000,4#b3"));
+        //Ответ длиннее пакета - обработчик из реестра (частичное чтение)
+        let long = reply("$m0,100000#00").unwrap();
+        assert!(long.len() <= PACKET_SIZE && long != make_packet(&"00".repeat(0x100000)));
+        assert_eq!(Some(make_packet("QC0")), reply("$qC#b4"));
+    }
+}
@@ -0,0 +1,342 @@
+use std::str;
+
+
+///Контрольная сумма RSP-пакета: сумма байт данных по модулю 256
+pub fn checksum(data: &[u8]) -> u8
+{
+    let mut checksum: u8 = 0;
+    for c in data
+    {
+        checksum = checksum.wrapping_add(*c);
+    }
+    checksum
+}
+
+
+///Обернуть данные в $ и #cs
+pub fn make_packet(data: &str) -> String
+{
+    format!("${}#{:02x}", data, checksum(data.as_bytes())) //cs: {:02x} - шестнадцатиричное u8 как строка и дополнить лидирующим нулем до двух цифр
+}
+
+
+///Длина первого целого сообщения в начале буфера:
+///одиночный символ ('+', '-', ^C) или пакет $<data>#cs (вместе с '+'/'-' перед ним, если есть)
+///None - сообщение еще не принято целиком
+pub fn frame_len(buf: &[u8]) -> Option<usize>
+{
+    let start = match buf.first()
+    {
+        None => return None,
+        Some(b'+') | Some(b'-') => 1,
+        Some(_) => 0,
+    };
+    match buf.get(start)
+    {
+        None => Some(1), //Одиночный acknowledgment
+        Some(b'$') | Some(b'%') =>
+        {//Пакет (или уведомление %...#cs) до первого '#' и еще два символа контрольной суммы. В бинарных данных '#' экранируется
+            match buf[start..].iter().position(|&c| c == b'#')
+            {
+                Some(pos) if buf.len() >= start + pos + 3 => Some(start + pos + 3),
+                _ => None,
+            }
+        },
+        Some(_) => Some(1), //Одиночный символ (acknowledgment или ^C)
+    }
+}
+
+
+///Байты в строку из двузначных hex-кодов
+pub fn hex_encode(bytes: &[u8]) -> String
+{
+    let mut result = String::with_capacity(bytes.len() * 2);
+    for b in bytes
+    {
+        result.push_str(&format!("{:02x}", b));
+    }
+    result
+}
+
+
+///Строка из двузначных hex-кодов в байты. None - если строка нечетной длины или содержит не hex-цифры
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>>
+{
+    if !hex.len().is_multiple_of(2)
+    {
+        return None;
+    }
+    hex.as_bytes().chunks(2)
+        .map(|pair| str::from_utf8(pair).ok().and_then(|s| u8::from_str_radix(s, 16).ok()))
+        .collect()
+}
+
+
+///Алфавит base64 (RFC 4648)
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+///Байты в base64 (с дополнением '=')
+pub fn base64_encode(bytes: &[u8]) -> String
+{
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3)
+    {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4
+        {
+            if i <= chunk.len()
+            {
+                result.push(char::from(BASE64_CHARS[(n >> (18 - 6 * i)) as usize & 0x3f]));
+            }
+            else
+            {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+
+///base64 в байты. None - если строка не base64
+pub fn base64_decode(text: &str) -> Option<Vec<u8>>
+{
+    let text = text.trim_end_matches('=');
+    let mut result = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes()
+    {
+        let value = BASE64_CHARS.iter().position(|&b| b == c)? as u32;
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8
+        {
+            bits -= 8;
+            result.push((acc >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+
+///Раскрыть escape-последовательности бинарных данных (X-пакет): '}' + (байт ^ 0x20)
+pub fn unescape_binary(data: &[u8]) -> Vec<u8>
+{
+    let mut result = Vec::with_capacity(data.len());
+    let mut escape = false;
+    for &b in data
+    {
+        if escape
+        {
+            result.push(b ^ 0x20);
+            escape = false;
+        }
+        else if b == b'}'
+        {
+            escape = true;
+        }
+        else
+        {
+            result.push(b);
+        }
+    }
+    result
+}
+
+
+///Экранировать бинарные данные для X-пакета: '#', '$', '}', '*' -> '}' + (байт ^ 0x20)
+pub fn escape_binary(data: &[u8]) -> Vec<u8>
+{
+    let mut result = Vec::with_capacity(data.len());
+    for &b in data
+    {
+        match b
+        {
+            b'#' | b'$' | b'}' | b'*' =>
+            {
+                result.push(b'}');
+                result.push(b ^ 0x20);
+            },
+            _ => result.push(b),
+        }
+    }
+    result
+}
+
+
+///CRC-32 в варианте GDB (qCRC, compare-sections): полином 0x04c11db7, биты без отражения, без итогового xor
+///crc - начальное значение (GDB начинает с 0xffffffff). Длинные данные считаются по частям: результат части - начальное значение следующей
+pub fn crc32(crc: u32, data: &[u8]) -> u32
+{
+    let mut crc = crc;
+    for &b in data
+    {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8
+        {
+            crc = if crc & 0x8000_0000 != 0 {(crc << 1) ^ 0x04c1_1db7} else {crc << 1};
+        }
+    }
+    crc
+}
+
+
+///Разобранная команда: название и аргументы
+///Название - это ключ команды в документации RSP: "m", "Z0", "qSupported", "vCont?", "vCont", "X" и т.п.
+#[derive(Debug, PartialEq)]
+pub struct Command<'a>
+{
+    pub name: &'a str,                              // Название команды
+    pub args: &'a [u8],                             // Всё, что после названия (включая разделитель ':' ',' ';')
+}
+
+
+impl<'a> Command<'a>
+{
+    ///Разобрать данные пакета (между '$' и '#')
+    pub fn parse(data: &'a [u8]) -> Command<'a>
+    {
+        let name_len = match data.first()
+        {
+            None => 0,
+            Some(b'q') | Some(b'Q') | Some(b'v') =>
+            {//Многосимвольное название до первого разделителя. '?' входит в название ("vCont?")
+                match data.iter().position(|&c| c == b':' || c == b',' || c == b';' || c == b'?')
+                {
+                    Some(pos) if data[pos] == b'?' => pos + 1,
+                    Some(pos) => pos,
+                    None => data.len(),
+                }
+            },
+            Some(b'Z') | Some(b'z') => if data.len() > 1 {2} else {1}, //Z<type>
+            Some(_) => 1,
+        };
+        //Название команды всегда ASCII. Если нет - название пустое, всё считается аргументами
+        match str::from_utf8(&data[..name_len])
+        {
+            Ok(name) => Command{ name, args: &data[name_len..] },
+            Err(_) => Command{ name: "", args: data },
+        }
+    }
+}
+
+
+///Краткое описание сообщения для вывода: "^C", "+", "[m] m1000,4", "[X] X1000,2:..." (бинарные данные экранированы)
+///Пакет с неверной контрольной суммой помечается "(bad checksum)"
+pub fn describe_frame(frame: &[u8]) -> String
+{
+    let start = match frame.iter().position(|&c| c == b'$' || c == b'%')
+    {
+        Some(start) => start,
+        None => return match frame
+        {
+            [0x03] => "^C".to_string(),
+            _ => String::from_utf8_lossy(frame).into_owned(),
+        },
+    };
+    let ack = String::from_utf8_lossy(&frame[..start]);
+    let sharp_pos = match frame[start..].iter().position(|&c| c == b'#')
+    {
+        Some(pos) => start + pos,
+        None => return format!("{}(incomplete) {:?}", ack, String::from_utf8_lossy(&frame[start..])),
+    };
+    let data = &frame[start+1 .. sharp_pos];
+    let cs = str::from_utf8(&frame[sharp_pos+1 ..]).ok().and_then(|cs| u8::from_str_radix(cs, 16).ok());
+    let notification = if frame[start] == b'%' {"%"} else {""};
+    let bad_cs = if cs == Some(checksum(data)) {""} else {" (bad checksum)"};
+    let text: String = data.iter().flat_map(|&c| std::ascii::escape_default(c)).map(char::from).collect();
+    format!("{}{}[{}] {}{}", ack, notification, Command::parse(data).name, text, bad_cs)
+}
+
+
+///Тесты для кодека ================================================================================
+#[cfg(test)]
+mod test_codec
+{
+    use super::*;
+
+    #[test]
+    fn test_make_packet()
+    {
+        assert_eq!("$OK#9a", make_packet("OK"));
+        assert_eq!("$#00", make_packet(""));
+        assert_eq!("$T02#b6", make_packet("T02"));
+    }
+
+    #[test]
+    fn test_frame_len()
+    {
+        assert_eq!(None, frame_len(b""));
+        assert_eq!(Some(1), frame_len(b"+"));
+        assert_eq!(Some(1), frame_len(b"\x03"));
+        assert_eq!(Some(1), frame_len(b"+\x03"));
+        assert_eq!(None, frame_len(b"$qSupported:swbre"));
+        assert_eq!(None, frame_len(b"+$OK#9"));
+        assert_eq!(Some(7), frame_len(b"+$OK#9a$T05#b9"));
+        assert_eq!(Some(4), frame_len(b"$#00+"));
+    }
+
+    #[test]
+    fn test_describe_frame()
+    {
+        assert_eq!("+[m] m1000,4", describe_frame(format!("+{}", make_packet("m1000,4")).as_bytes()));
+        assert_eq!("[O] OK (bad checksum)", describe_frame(b"$OK#00"));
+        assert_eq!("%[vStopped] vStopped", describe_frame(make_packet("vStopped").replace('$', "%").as_bytes()));
+        assert_eq!("[X] X0,1:}\\x03", describe_frame(b"$X0,1:}\x03#9f"));
+        assert_eq!("(incomplete) \"$T0\"", describe_frame(b"$T0"));
+        assert_eq!("^C", describe_frame(b"\x03"));
+        assert_eq!("-", describe_frame(b"-"));
+    }
+
+    #[test]
+    fn test_hex()
+    {
+        assert_eq!("7265736574", hex_encode(b"reset"));
+        assert_eq!(Some(b"reset".to_vec()), hex_decode("7265736574"));
+        assert_eq!(None, hex_decode("726"));
+        assert_eq!(None, hex_decode("zz"));
+    }
+
+    #[test]
+    fn test_crc32()
+    {
+        assert_eq!(0x0376_e6e7, crc32(0xffff_ffff, b"123456789"));
+        assert_eq!(0xffff_ffff, crc32(0xffff_ffff, b""));
+        assert_eq!(crc32(0xffff_ffff, b"123456789"), crc32(crc32(0xffff_ffff, b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn test_base64()
+    {
+        assert_eq!("", base64_encode(b""));
+        assert_eq!("Kw==", base64_encode(b"+"));
+        assert_eq!("JE9LIzlh", base64_encode(b"$OK#9a"));
+        assert_eq!("A/8=", base64_encode(&[0x03, 0xff]));
+        assert_eq!(Some(vec![0x03, 0xff]), base64_decode("A/8="));
+        assert_eq!(Some(b"$OK#9a".to_vec()), base64_decode("JE9LIzlh"));
+        assert_eq!(None, base64_decode("JE9L*"));
+    }
+
+    #[test]
+    fn test_unescape_binary()
+    {
+        let data = [0x01, b'#', b'$', b'}', b'*', 0x02];
+        assert_eq!(data.to_vec(), unescape_binary(&escape_binary(&data)));
+        assert_eq!(vec![0x01, b'#', b'$', b'}', 0x02], unescape_binary(&[0x01, b'}', 0x03, b'}', 0x04, b'}', 0x5d, 0x02]));
+    }
+
+    #[test]
+    fn test_command_parse()
+    {
+        assert_eq!(Command{ name: "qSupported", args: b":swbreak+" }, Command::parse(b"qSupported:swbreak+"));
+        assert_eq!(Command{ name: "vCont?", args: b"" }, Command::parse(b"vCont?"));
+        assert_eq!(Command{ name: "vCont", args: b";c" }, Command::parse(b"vCont;c"));
+        assert_eq!(Command{ name: "Z0", args: b",1000,4" }, Command::parse(b"Z0,1000,4"));
+        assert_eq!(Command{ name: "m", args: b"1000,4" }, Command::parse(b"m1000,4"));
+        assert_eq!(Command{ name: "qC", args: b"" }, Command::parse(b"qC"));
+        assert_eq!(Command{ name: "", args: b"" }, Command::parse(b""));
+    }
+}
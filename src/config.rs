@@ -0,0 +1,408 @@
+use gdb_server::{DEFAULT_ADDR, PACKET_SIZE};
+use cmd_registry::CmdRegistry;
+use cmd_handlers::{cmd_insert_matchpoint_with, cmd_q_supported_with};
+use target::{Target, DummyTarget};
+use sim::SimTarget;
+use inferiors::Inferiors;
+use host_io::HostIo;
+use mock::{MockTable, mock_registry};
+use fault::FaultInjector;
+use scripted::ScriptedReplies;
+
+
+///Команды, которые относятся к feature из qSupported (если название feature не совпадает с названием команды)
+///Для остальных features считается, что команда называется так же, как feature (например "qXfer:features:read", "QStartNoAckMode")
+///Отключаются именно зарегистрированные префиксы: более длинный префикс ("QNonStop:") выигрывает у отключенного короткого
+pub const FEATURE_PACKETS: &[(&str, &[&str])] = &[
+    ("vContSupported", &["vCont?", "vCont;"]),
+    ("QNonStop", &["QNonStop:", "vStopped", "vCtrlC"]),
+    ("TracepointSource", &["QTDPsrc:"]),
+    ("EnableDisableTracepoints", &["QTEnable:", "QTDisable:"]),
+    ("swbreak", &[]),                               // Только поле "swbreak:" в Stop Reply Packet
+    ("hwbreak", &[]),                               // Только поле "hwbreak:" в Stop Reply Packet
+    ("multiprocess", &[]),
+    ("error-message", &[]),
+];
+
+
+///Принудительное включение/отключение features, объявляемых в ответ на qSupported
+///Для экспериментов: как GDB обходится без vContSupported, без QStartNoAckMode, с маленьким PacketSize и т.п.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureOverrides
+{
+    pub packet_size: Option<usize>,                 // Объявляемый PacketSize (None - PACKET_SIZE)
+    pub features: Vec<(String, bool)>,              // feature и признак: true - объявлять всегда, false - не объявлять никогда
+}
+
+
+impl FeatureOverrides
+{
+    ///Разобрать переключатель вида "name+" (включить) или "name-" (отключить)
+    pub fn add(&mut self, switch: &str) -> Result<(), String>
+    {
+        if switch.len() < 2
+        {
+            return Err(format!("Некорректный переключатель feature \'{}\'", switch));
+        }
+        let (name, last) = switch.split_at(switch.len() - 1);
+        let enable = match last
+        {
+            "+" => true,
+            "-" => false,
+            _ => return Err(format!("Переключатель feature \'{}\' должен заканчиваться на '+' или '-'", switch)),
+        };
+        self.features.retain(|(n, _)| n != name);
+        self.features.push((name.to_string(), enable));
+        Ok(())
+    }
+
+
+    ///None - feature не переопределена. Some(true) - объявлять всегда. Some(false) - не объявлять никогда
+    pub fn get(&self, name: &str) -> Option<bool>
+    {
+        self.features.iter().find(|(n, _)| n == name).map(|&(_, enable)| enable)
+    }
+
+
+    ///Команды, относящиеся к feature
+    pub fn feature_packets(name: &str) -> Vec<&str>
+    {
+        match FEATURE_PACKETS.iter().find(|&&(feature, _)| feature == name)
+        {
+            Some(&(_, packets)) => packets.to_vec(),
+            None => vec![name],
+        }
+    }
+
+
+    ///Применить переопределения к реестру:
+    ///команды отключенных features получают пустой ответ (даже если есть обработчик), ответ на qSupported и разбор Z0/Z1 - с учетом переопределений
+    pub fn apply(&self, registry: &mut CmdRegistry)
+    {
+        for (name, enable) in &self.features
+        {
+            if !enable
+            {
+                for packet in FeatureOverrides::feature_packets(name)
+                {
+                    registry.disable(packet);
+                }
+            }
+        }
+        let overrides = self.clone();
+        registry.register("qSupported", move |pkt, ctx| cmd_q_supported_with(pkt, ctx, &overrides));
+        for prefix in &["Z0", "Z1"]
+        {//Условия и команды breakpoints разбираются с учетом переопределений (отключенные Z0/Z1 остаются отключенными)
+            if registry.is_enabled(prefix)
+            {
+                let overrides = self.clone();
+                registry.register(prefix, move |pkt, ctx| cmd_insert_matchpoint_with(pkt, ctx, &overrides));
+            }
+        }
+    }
+}
+
+
+///Отлаживаемая цель
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetKind
+{
+    Dummy,                                          // target::DummyTarget: фиксированные ответы
+    Sim,                                            // sim::SimTarget: регистры и память симулятора
+}
+
+
+///Настройки GDB-сервера из аргументов командной строки
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig
+{
+    pub addr: String,                               // Адрес, на котором GDB-сервер ожидает подключения
+    pub loop_run: bool,                             // Имитация исполнения в цикле (до ^C)
+    pub target: TargetKind,                         // Отлаживаемая цель
+    pub harts: usize,                               // Количество harts SimTarget (потоков для GDB)
+    pub inferiors: usize,                           // Количество процессов sim-цели (больше 1 - Inferiors, multiprocess)
+    pub packet_size: usize,                         // Размер RSP-пакета (PacketSize). От него зависит размер буферов приема
+    pub overrides: FeatureOverrides,                // Переопределения features для qSupported
+    pub record: Option<String>,                     // Файл транскрипта (JSON Lines), в который записывается весь обмен с GDB-клиентом
+    pub mock: Option<String>,                       // Транскрипт, по которому сервер отвечает вместо цели (mock::MockTable)
+    pub mock_default: String,                       // Ответ mock на команды, которых нет в транскрипте ("" - пустой ответ)
+    pub explain: bool,                              // Выводить пояснение к каждому пакету и ответу (explain::Explanation)
+    pub faults: Option<String>,                     // Файл правил внесения неисправностей в ответы (fault::FaultInjector)
+    pub replies: Option<String>,                    // Файл правил подмены ответов (scripted::ScriptedReplies)
+    pub sandbox: Option<String>,                    // Каталог-песочница для vFile-пакетов (host_io::HostIo). Без него host I/O не поддерживается
+}
+
+
+impl Default for ServerConfig
+{
+    fn default() -> ServerConfig
+    {
+        ServerConfig{
+            addr: DEFAULT_ADDR.to_string(),
+            loop_run: false,
+            target: TargetKind::Dummy,
+            harts: 1,
+            inferiors: 1,
+            packet_size: PACKET_SIZE,
+            overrides: FeatureOverrides::default(),
+            record: None,
+            mock: None,
+            mock_default: String::new(),
+            explain: false,
+            faults: None,
+            replies: None,
+            sandbox: None,
+        }
+    }
+}
+
+
+impl ServerConfig
+{
+    ///Разобрать аргументы командной строки (без имени программы)
+    pub fn from_args(args: &[String]) -> Result<ServerConfig, String>
+    {
+        let mut config = ServerConfig::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--loop" | "-l"=>
+                {
+                    println!("  Started with loop run simulation\n");
+                    config.loop_run = true;
+                },
+                "--addr"=>
+                {
+                    config.addr = next_value(&mut args, arg)?.to_string();
+                },
+                "--feature" | "-f"=>
+                {
+                    config.overrides.add(next_value(&mut args, arg)?)?;
+                },
+                "--target"=>
+                {
+                    config.target = match next_value(&mut args, arg)?
+                    {
+                        "dummy" => TargetKind::Dummy,
+                        "sim" => TargetKind::Sim,
+                        value => return Err(format!("Неизвестная цель \'{}\' (dummy или sim)", value)),
+                    };
+                },
+                "--harts"=>
+                {
+                    config.harts = parse_size(next_value(&mut args, arg)?)?;
+                    if config.harts == 0
+                    {
+                        return Err("Количество harts должно быть не меньше 1".to_string());
+                    }
+                },
+                "--inferiors"=>
+                {
+                    config.inferiors = parse_size(next_value(&mut args, arg)?)?;
+                    if config.inferiors == 0
+                    {
+                        return Err("Количество процессов должно быть не меньше 1".to_string());
+                    }
+                },
+                "--packet-size"=>
+                {
+                    let value = next_value(&mut args, arg)?;
+                    config.packet_size = parse_packet_size(value)?;
+                },
+                "--record"=>
+                {
+                    config.record = Some(next_value(&mut args, arg)?.to_string());
+                },
+                "--mock"=>
+                {
+                    config.mock = Some(next_value(&mut args, arg)?.to_string());
+                },
+                "--mock-default"=>
+                {
+                    config.mock_default = next_value(&mut args, arg)?.to_string();
+                },
+                "--explain"=>
+                {
+                    config.explain = true;
+                },
+                "--faults"=>
+                {
+                    config.faults = Some(next_value(&mut args, arg)?.to_string());
+                },
+                "--replies"=>
+                {
+                    config.replies = Some(next_value(&mut args, arg)?.to_string());
+                },
+                "--sandbox"=>
+                {
+                    config.sandbox = Some(next_value(&mut args, arg)?.to_string());
+                },
+                "--advertised-packet-size"=>
+                {
+                    let value = next_value(&mut args, arg)?;
+                    config.overrides.packet_size = Some(parse_size(value)?);
+                },
+                _=>
+                {
+                    println!("  Unknown argument {:?}\n", arg);
+                },
+            }
+        }
+        Ok(config)
+    }
+
+
+    ///Реестр обработчиков по умолчанию с примененными переопределениями features
+    ///С --mock: реестр, который отвечает по транскрипту (переопределения features не применяются, ответ на qSupported тоже из транскрипта)
+    ///С --replies: ответы по правилам проверяются раньше обработчиков (и раньше mock)
+    pub fn make_registry(&self) -> Result<CmdRegistry, String>
+    {
+        let mut registry = match self.mock
+        {
+            Some(ref path) => mock_registry(MockTable::load(path)?, &self.mock_default),
+            None =>
+            {
+                let mut registry = CmdRegistry::with_defaults();
+                self.overrides.apply(&mut registry);
+                registry
+            },
+        };
+        if let Some(ref path) = self.replies
+        {
+            registry.set_scripted(ScriptedReplies::load(path)?);
+        }
+        Ok(registry)
+    }
+
+
+    ///Правила внесения неисправностей (без --faults - пустые)
+    pub fn make_faults(&self) -> Result<FaultInjector, String>
+    {
+        match self.faults
+        {
+            Some(ref path) => FaultInjector::load(path),
+            None => Ok(FaultInjector::default()),
+        }
+    }
+
+
+    ///Файлы хоста для vFile-пакетов (без --sandbox - None)
+    pub fn make_host_io(&self) -> Result<Option<HostIo>, String>
+    {
+        match self.sandbox
+        {
+            Some(ref root) => HostIo::new(root).map(Some),
+            None => Ok(None),
+        }
+    }
+
+
+    ///Отлаживаемая цель по настройкам
+    pub fn make_target(&self) -> Box<dyn Target + Send>
+    {
+        match self.target
+        {
+            TargetKind::Dummy => Box::new(DummyTarget::new(self.loop_run)),
+            TargetKind::Sim if self.inferiors > 1 =>
+            {//Процессы со своей памятью и регистрами, у каждого - harts
+                let targets = (0..self.inferiors).map(|_| SimTarget::with_harts(self.loop_run, self.harts)).collect();
+                Box::new(Inferiors::new(targets))
+            },
+            TargetKind::Sim => Box::new(SimTarget::with_harts(self.loop_run, self.harts)),
+        }
+    }
+}
+
+
+///Значение аргумента командной строки
+pub fn next_value<'a, I>(args: &mut I, arg: &str) -> Result<&'a str, String>
+    where I: Iterator<Item = &'a String>
+{
+    args.next().map(|v| &v[..]).ok_or_else(|| format!("Не задано значение аргумента {}", arg))
+}
+
+
+///Размер: десятичное число или hex с префиксом 0x
+pub fn parse_size(value: &str) -> Result<usize, String>
+{
+    let parsed = if value.starts_with("0x") || value.starts_with("0X")
+    {
+        usize::from_str_radix(&value[2..], 16)
+    }
+    else
+    {
+        value.parse::<usize>()
+    };
+    parsed.map_err(|_| format!("Некорректный размер \'{}\'", value))
+}
+
+
+///Размер RSP-пакета. Должен вмещать хотя бы короткие ответы ("$T05swbreak:;#cs", "$E01#cs")
+pub fn parse_packet_size(value: &str) -> Result<usize, String>
+{
+    let size = parse_size(value)?;
+    if size < MIN_PACKET_SIZE
+    {
+        return Err(format!("Размер пакета {} меньше минимального {}", size, MIN_PACKET_SIZE));
+    }
+    Ok(size)
+}
+
+
+///Минимальный размер RSP-пакета
+pub const MIN_PACKET_SIZE: usize = 32;
+
+
+///Тесты для ServerConfig ================================================================================
+#[cfg(test)]
+mod test_config
+{
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String>
+    {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args()
+    {
+        let config = ServerConfig::from_args(&args(&["-l", "--feature", "vContSupported-", "-f", "swbreak+", "--advertised-packet-size", "0x40", "--packet-size", "1024", "--target", "sim", "--harts", "4", "--inferiors", "2", "--record", "session.jsonl", "--mock", "stub.jsonl", "--mock-default", "E01", "--explain", "--faults", "faults.toml", "--replies", "replies.toml", "--sandbox", "rootfs"])).unwrap();
+        assert!(config.loop_run);
+        assert_eq!(DEFAULT_ADDR, config.addr);
+        assert_eq!(1024, config.packet_size);
+        assert_eq!(TargetKind::Sim, config.target);
+        assert_eq!(4, config.harts);
+        assert_eq!(2, config.inferiors);
+        assert_eq!(Some(false), config.overrides.get("vContSupported"));
+        assert_eq!(Some(true), config.overrides.get("swbreak"));
+        assert_eq!(None, config.overrides.get("QStartNoAckMode"));
+        assert_eq!(Some(64), config.overrides.packet_size);
+        assert_eq!(Some("session.jsonl".to_string()), config.record);
+        assert_eq!((Some("stub.jsonl".to_string()), "E01".to_string()), (config.mock, config.mock_default));
+        assert!(config.explain);
+        assert_eq!(Some("faults.toml".to_string()), config.faults);
+        assert_eq!(Some("replies.toml".to_string()), config.replies);
+        assert_eq!(Some("rootfs".to_string()), config.sandbox);
+
+        assert!(ServerConfig::from_args(&args(&["--feature"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--feature", "swbreak"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--packet-size", "big"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--packet-size", "8"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--target", "qemu"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--harts", "0"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--inferiors", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_feature_packets()
+    {
+        assert_eq!(vec!["vCont?", "vCont;"], FeatureOverrides::feature_packets("vContSupported"));
+        assert_eq!(vec!["QStartNoAckMode"], FeatureOverrides::feature_packets("QStartNoAckMode"));
+        assert!(FeatureOverrides::feature_packets("swbreak").is_empty());
+        assert_eq!(vec!["QNonStop:", "vStopped", "vCtrlC"], FeatureOverrides::feature_packets("QNonStop"));
+        assert_eq!(vec!["QTEnable:", "QTDisable:"], FeatureOverrides::feature_packets("EnableDisableTracepoints"));
+    }
+}
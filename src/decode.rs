@@ -0,0 +1,871 @@
+use std::fs;
+use std::str;
+
+use codec::{Command, checksum, frame_len, hex_decode, unescape_binary};
+use config::next_value;
+use transcript::{Direction, packet_data};
+use trace::{TraceAction, parse_actions};
+use agent_expr::{parse_expressions, disassemble};
+
+
+///Имена регистров RISC-V по номерам GDB: x0..x31 (ABI-имена), pc, f0..f31, CSR
+pub fn register_name(num: usize) -> String
+{
+    const X_NAMES: [&str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+        "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+    ];
+    match num
+    {
+        0..=31 => X_NAMES[num].to_string(),
+        32 => "pc".to_string(),
+        33..=64 => format!("f{}", num - 33),
+        65..=4160 => match num - 65
+        {
+            0x001 => "fflags".to_string(),
+            0x002 => "frm".to_string(),
+            0x003 => "fcsr".to_string(),
+            0x300 => "mstatus".to_string(),
+            0x305 => "mtvec".to_string(),
+            0x341 => "mepc".to_string(),
+            0x342 => "mcause".to_string(),
+            0x343 => "mtval".to_string(),
+            csr => format!("csr{:#x}", csr),
+        },
+        4161 => "priv".to_string(),
+        _ => format!("reg{}", num),
+    }
+}
+
+
+///Название сигнала (номера сигналов GDB)
+pub fn signal_name(sig: u8) -> &'static str
+{
+    match sig
+    {
+        0 => "no signal",
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGEMT",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGBUS",
+        11 => "SIGSEGV",
+        15 => "SIGTERM",
+        _ => "signal",
+    }
+}
+
+
+///Значение little-endian из hex-строки (регистр в 'p', 'P', Stop Reply)
+fn le_value(hex: &str) -> Option<u64>
+{
+    let bytes = hex_decode(hex)?;
+    if bytes.is_empty() || bytes.len() > 8
+    {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+}
+
+
+fn hex_bytes(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+
+fn text(bytes: &[u8]) -> String
+{
+    format!("{:?}", String::from_utf8_lossy(bytes))
+}
+
+
+///"addr,len" -> (addr, len)
+fn addr_len(args: &str) -> Option<(u64, usize)>
+{
+    let (addr, len) = args.split_once(',')?;
+    Some((u64::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+
+///Значение поля регистра "<regnum>" из hex
+fn reg_field(num: &str) -> String
+{
+    match usize::from_str_radix(num, 16)
+    {
+        Ok(num) => format!("{} ({})", num, register_name(num)),
+        Err(_) => num.to_string(),
+    }
+}
+
+
+///Смысл пакета от GDB (данные между '$' и '#')
+pub fn explain_command(data: &[u8]) -> String
+{
+    let cmd = Command::parse(data);
+    let args = String::from_utf8_lossy(cmd.args).into_owned();
+    let args = &args[..];
+    match cmd.name
+    {
+        "?" => "query the reason the target halted".to_string(),
+        "g" => "read all general registers".to_string(),
+        "G" => format!("write all general registers ({} bytes)", args.len() / 2),
+        "p" => format!("read register {}", reg_field(args)),
+        "P" => match args.split_once('=')
+        {
+            Some((num, value)) => format!("write register {} = {}", reg_field(num), le_value(value).map(|v| format!("{:#x}", v)).unwrap_or_else(|| value.to_string())),
+            None => "write register (malformed)".to_string(),
+        },
+        "m" => match addr_len(args)
+        {
+            Some((addr, len)) => format!("read {} bytes of memory at {:#x}", len, addr),
+            None => "read memory (malformed)".to_string(),
+        },
+        "M" | "X" =>
+        {
+            let (header, payload) = match cmd.args.iter().position(|&c| c == b':')
+            {
+                Some(pos) => (&cmd.args[..pos], &cmd.args[pos+1 ..]),
+                None => (cmd.args, &[][..]),
+            };
+            let bytes = if cmd.name == "X" {unescape_binary(payload)} else {hex_decode(&String::from_utf8_lossy(payload)).unwrap_or_default()};
+            match addr_len(&String::from_utf8_lossy(header))
+            {
+                Some((addr, len)) => format!("write {} bytes of memory at {:#x}: {}", len, addr, hex_bytes(&bytes)),
+                None => "write memory (malformed)".to_string(),
+            }
+        },
+        "c" => if args.is_empty() {"continue".to_string()} else {format!("continue at {}", args)},
+        "s" => if args.is_empty() {"single step".to_string()} else {format!("single step at {}", args)},
+        "C" | "S" => format!("{} with signal {}", if cmd.name == "C" {"continue"} else {"step"}, args),
+        "H" => match args.split_at(args.len().min(1))
+        {
+            ("g", thread) => format!("set thread for register/memory operations: {}", thread),
+            ("c", thread) => format!("set thread for step/continue: {}", thread),
+            _ => format!("set thread {}", args),
+        },
+        "T" => format!("is thread {} alive?", args),
+        "k" | "vKill" => "kill the target".to_string(),
+        "D" => match args.strip_prefix(';')
+        {
+            Some(pid) => format!("detach from process {}", pid),
+            None => "detach".to_string(),
+        },
+        "!" => "use extended mode (attach, restart)".to_string(),
+        "F" =>
+        {
+            let mut fields = args.split(';').next().unwrap_or("").split(',');
+            let mut result = format!("File-I/O result {}", fields.next().unwrap_or(""));
+            if let Some(errno) = fields.next()
+            {
+                result += &format!(", errno {}", errno);
+            }
+            if fields.next() == Some("C")
+            {
+                result += ", interrupted by ^C";
+            }
+            result
+        },
+        "vAttach" => format!("attach to process {}", args.trim_start_matches(';')),
+        "vFile" => explain_v_file(cmd.args),
+        "Z0" | "Z1" | "Z2" | "Z3" | "Z4" | "z0" | "z1" | "z2" | "z3" | "z4" =>
+        {
+            let kind = ["software breakpoint", "hardware breakpoint", "write watchpoint", "read watchpoint", "access watchpoint"][(cmd.name.as_bytes()[1] - b'0') as usize];
+            let action = if cmd.name.starts_with('Z') {"insert"} else {"remove"};
+            let mut fields = args.trim_start_matches(',').split(',');
+            match (fields.next().and_then(|a| u64::from_str_radix(a, 16).ok()), fields.next())
+            {
+                (Some(addr), Some(size)) =>
+                {
+                    let size = size.split(';').next().unwrap_or(size);
+                    //Условия на стороне цели: ;X<len>,<bytecode>...
+                    let conditions: Vec<String> = args.split(';').nth(1).filter(|c| !c.starts_with("cmds:")).and_then(parse_expressions)
+                        .unwrap_or_default().iter().map(|c| format!("[{}]", disassemble(c))).collect();
+                    let text = if !conditions.is_empty()
+                    {
+                        format!("{} {} at {:#x} kind {} if {}", action, kind, addr, size, conditions.join(" or "))
+                    }
+                    else if cmd.name.ends_with('0') || cmd.name.ends_with('1')
+                    {//Для точек останова kind - длина инструкции (RISC-V: 2 - сжатая, 4 - обычная)
+                        format!("{} {} at {:#x} kind {} (instruction length)", action, kind, addr, size)
+                    }
+                    else
+                    {
+                        format!("{} {} at {:#x}, {} bytes", action, kind, addr, size)
+                    };
+                    //Команды на стороне цели: ;cmds:<persist>,X<len>,<bytecode>...
+                    match args.split(';').find_map(|part| part.strip_prefix("cmds:")).and_then(|c| c.split_once(','))
+                    {
+                        Some((persist, commands)) =>
+                        {
+                            let commands: Vec<String> = parse_expressions(commands).unwrap_or_default().iter().map(|c| format!("[{}]", disassemble(c))).collect();
+                            format!("{}, then run {}{}", text, commands.join(", "), if persist == "1" {" (persistent)"} else {""})
+                        },
+                        None => text,
+                    }
+                },
+                _ => format!("{} {}", action, kind),
+            }
+        },
+        "qCRC" => match addr_len(args.trim_start_matches(':'))
+        {
+            Some((addr, len)) => format!("CRC-32 of {} bytes of memory at {:#x}", len, addr),
+            None => "CRC-32 of memory (malformed)".to_string(),
+        },
+        "qSearch" =>
+        {//qSearch:memory:<addr>;<length>;<pattern>. Образец бинарный
+            let mut fields = cmd.args.strip_prefix(b":memory:").unwrap_or(cmd.args).splitn(3, |&c| c == b';');
+            let addr = fields.next().and_then(|addr| u64::from_str_radix(&String::from_utf8_lossy(addr), 16).ok());
+            let len = fields.next().and_then(|len| u64::from_str_radix(&String::from_utf8_lossy(len), 16).ok());
+            match (addr, len, fields.next())
+            {
+                (Some(addr), Some(len), Some(pattern)) => format!("search {} bytes of memory at {:#x} for {}", len, addr, hex_bytes(&unescape_binary(pattern))),
+                _ => "search memory (malformed)".to_string(),
+            }
+        },
+        "qRcmd" => match hex_decode(args.trim_start_matches(','))
+        {
+            Some(command) => format!("monitor command {}", text(&command)),
+            None => "monitor command (malformed)".to_string(),
+        },
+        "qSupported" => format!("negotiate features, GDB supports: {}", args.trim_start_matches(':').replace(';', " ")),
+        "QStartNoAckMode" => "stop sending +/- acknowledgments".to_string(),
+        "vCont?" => "which vCont actions are supported?".to_string(),
+        "vCont" => format!("resume: {}", args.trim_start_matches(';').split(';').map(vcont_action).collect::<Vec<_>>().join(", ")),
+        "vStopped" => "acknowledge the stop notification, next stop reply?".to_string(),
+        "vCtrlC" => "interrupt (non-stop)".to_string(),
+        "QNonStop" => if args.trim_start_matches(':') == "1" {"switch to non-stop mode".to_string()} else {"switch to all-stop mode".to_string()},
+        "vMustReplyEmpty" => "probe: unknown packets must get the empty reply".to_string(),
+        "qfThreadInfo" | "qsThreadInfo" => "list threads".to_string(),
+        "qC" => "current thread id".to_string(),
+        "qAttached" => "attached to an existing process or created a new one?".to_string(),
+        "qOffsets" => "section offsets of the loaded program".to_string(),
+        "qSymbol" => if args == "::" {"ready to look up symbols".to_string()} else {format!("symbol lookup {}", args)},
+        "qTStatus" => "is a trace experiment running?".to_string(),
+        "QTinit" => "delete tracepoints and trace frames".to_string(),
+        "QTDP" => explain_qtdp(args.trim_start_matches(':')),
+        "QTDPsrc" => format!("source text of tracepoint {}", args.trim_start_matches(':').split(':').next().unwrap_or("")),
+        "QTEnable" | "QTDisable" => format!("{} tracepoint {}", if cmd.name == "QTEnable" {"enable"} else {"disable"}, args.trim_start_matches(':').split(':').next().unwrap_or("")),
+        "QTBuffer" => format!("trace buffer setting {}", args.trim_start_matches(':').replace(':', " = ")),
+        "QTStart" => "start the trace experiment".to_string(),
+        "QTStop" => "stop the trace experiment".to_string(),
+        "QTFrame" => match args.trim_start_matches(':')
+        {
+            "ffffffff" | "-1" => "stop looking at trace frames, back to the live target".to_string(),
+            query => match query.split_once(':')
+            {
+                Some(("pc", addr)) => format!("select the next trace frame at pc 0x{}", addr),
+                Some(("tdp", tp)) => format!("select the next trace frame of tracepoint {}", tp),
+                Some(("range", range)) => format!("select the next trace frame with pc in 0x{}", range.replace(':', "..0x")),
+                Some(("outside", range)) => format!("select the next trace frame with pc outside 0x{}", range.replace(':', "..0x")),
+                _ => format!("select trace frame {}", query),
+            },
+        },
+        "qTP" => format!("hit count and buffer usage of tracepoint {}", args.trim_start_matches(':').split(':').next().unwrap_or("")),
+        "qTfP" | "qTsP" => "upload tracepoint definitions".to_string(),
+        "qTfV" | "qTsV" => "upload trace state variables".to_string(),
+        "qTBuffer" => match addr_len(args.trim_start_matches(':'))
+        {
+            Some((offset, len)) => format!("read {} bytes of the trace buffer at offset {}", len, offset),
+            None => "read the trace buffer (malformed)".to_string(),
+        },
+        "QTSave" => format!("save the trace to target file {}", hex_decode(args.trim_start_matches(':')).map_or_else(String::new, |name| text(&name))),
+        "qXfer" => format!("read object {}", args.trim_start_matches(':')),
+        "" => "unrecognized packet".to_string(),
+        name => format!("{} {}", name, args),
+    }
+}
+
+
+///QTDP: определение tracepoint или его действия (собираемые данные)
+fn explain_qtdp(args: &str) -> String
+{
+    let args = args.trim_end_matches('-');
+    let fields: Vec<&str> = args.splitn(3, ':').collect();
+    let addr = fields.get(1).and_then(|a| u64::from_str_radix(a, 16).ok()).unwrap_or(0);
+    match (args.strip_prefix('-'), fields.get(2))
+    {
+        (Some(number), Some(text)) =>
+        {
+            let number = number.split(':').next().unwrap_or("");
+            let (kind, text) = match text.strip_prefix('S')
+            {
+                Some(text) => ("while-stepping actions", text),
+                None => ("actions", &text[..]),
+            };
+            let actions: Vec<String> = parse_actions(text).unwrap_or_default().iter().map(|action| match *action
+            {
+                TraceAction::Registers => "collect registers".to_string(),
+                TraceAction::Memory(None, offset, len) => format!("collect {} bytes at {:#x}", len, offset),
+                TraceAction::Memory(Some(reg), offset, len) => format!("collect {} bytes at {}+{:#x}", len, register_name(reg), offset),
+                TraceAction::Expr(ref bytecode) => format!("evaluate [{}]", disassemble(bytecode)),
+            }).collect();
+            format!("{} of tracepoint {} at {:#x}: {}", kind, number, addr, actions.join(", "))
+        },
+        _ =>
+        {
+            let fields: Vec<&str> = args.split(':').collect();
+            let state = if fields.get(2) == Some(&"D") {"disabled"} else {"enabled"};
+            format!("define tracepoint {} at {:#x} ({}, while-stepping {}, pass count {})",
+                fields[0], addr, state, fields.get(3).unwrap_or(&"0"), fields.get(4).unwrap_or(&"0"))
+        },
+    }
+}
+
+
+///vFile:<op>:<args>. Имена файлов - hex, данные pwrite - бинарные
+fn explain_v_file(args: &[u8]) -> String
+{
+    let args = args.strip_prefix(b":").unwrap_or(args);
+    let (op, args) = match args.iter().position(|&c| c == b':')
+    {
+        Some(pos) => (String::from_utf8_lossy(&args[..pos]).into_owned(), &args[pos+1 ..]),
+        None => (String::from_utf8_lossy(args).into_owned(), &[][..]),
+    };
+    let fields: Vec<&[u8]> = args.splitn(3, |&c| c == b',').collect();
+    let num = |i: usize| fields.get(i).and_then(|f| str::from_utf8(f).ok()).and_then(|f| u64::from_str_radix(f, 16).ok()).unwrap_or(0);
+    let path = |i: usize| fields.get(i).and_then(|f| hex_decode(&String::from_utf8_lossy(f))).map_or_else(String::new, |p| text(&p));
+    match &op[..]
+    {
+        "setfs" if num(0) == 0 => "host I/O: use the filesystem of the server".to_string(),
+        "setfs" => format!("host I/O: use the filesystem of process {}", num(0)),
+        "open" => format!("host I/O: open {} flags {:#x} mode {:o}", path(0), num(1), num(2)),
+        "close" => format!("host I/O: close fd {}", num(0)),
+        "pread" => format!("host I/O: read {} bytes of fd {} at offset {}", num(1), num(0), num(2)),
+        "pwrite" => format!("host I/O: write {} bytes to fd {} at offset {}", fields.get(2).map_or(0, |d| unescape_binary(d).len()), num(0), num(1)),
+        "unlink" => format!("host I/O: delete {}", path(0)),
+        "readlink" => format!("host I/O: read symbolic link {}", path(0)),
+        "fstat" => format!("host I/O: stat of fd {}", num(0)),
+        op => format!("host I/O: {} {}", op, String::from_utf8_lossy(args)),
+    }
+}
+
+
+///Ответ на vFile: F<result>[;<attachment>] или F-1,<errno>
+fn explain_v_file_reply(data: &[u8]) -> String
+{
+    let (result, attachment) = match data.iter().position(|&c| c == b';')
+    {
+        Some(pos) => (String::from_utf8_lossy(&data[1..pos]).into_owned(), Some(unescape_binary(&data[pos+1 ..]))),
+        None => (String::from_utf8_lossy(data.get(1..).unwrap_or(&[])).into_owned(), None),
+    };
+    match (result.split_once(','), attachment)
+    {
+        (Some((_, errno)), _) => format!("host I/O error, errno {}", errno),
+        (None, Some(attachment)) => format!("host I/O result {}, data {}", result, text(&attachment)),
+        (None, None) => format!("host I/O result {}", result),
+    }
+}
+
+
+fn vcont_action(action: &str) -> String
+{
+    let (action, thread) = match action.split_once(':')
+    {
+        Some((action, thread)) => (action, format!(" thread {}", thread)),
+        None => (action, String::new()),
+    };
+    let name = match action.chars().next()
+    {
+        Some('c') => "continue".to_string(),
+        Some('s') => "step".to_string(),
+        Some('t') => "stop".to_string(),
+        Some('C') => format!("continue with signal {}", &action[1..]),
+        Some('S') => format!("step with signal {}", &action[1..]),
+        Some('r') => format!("step over range {}", &action[1..]),
+        _ => action.to_string(),
+    };
+    name + &thread
+}
+
+
+///Смысл ответа сервера (данные между '$' и '#') с учетом команды, на которую он отправлен
+pub fn explain_reply(data: &[u8], last_cmd: &str) -> String
+{
+    let reply = String::from_utf8_lossy(data).into_owned();
+    let reply = &reply[..];
+    if reply.is_empty()
+    {
+        return "empty reply: command not supported".to_string();
+    }
+    if reply == "OK"
+    {
+        return "success".to_string();
+    }
+    if reply.len() == 3 && reply.starts_with('E') && u8::from_str_radix(&reply[1..], 16).is_ok()
+    {
+        return format!("error {}", &reply[1..]);
+    }
+    if reply.starts_with('O') && last_cmd != "m" && last_cmd != "p" && last_cmd != "g"
+    {
+        if let Some(console) = hex_decode(&reply[1..])
+        {
+            return format!("console output {}", text(&console));
+        }
+    }
+    match last_cmd
+    {
+        "m" => match hex_decode(reply)
+        {
+            Some(bytes) => format!("memory: {}", hex_bytes(&bytes)),
+            None => reply.to_string(),
+        },
+        "p" => match le_value(reply)
+        {
+            Some(value) => format!("register value {:#x}", value),
+            None => reply.to_string(),
+        },
+        "g" => match hex_decode(reply)
+        {
+            Some(bytes) => bytes.chunks(8).enumerate()
+                .map(|(i, reg)| format!("{}={:#x}", register_name(i), reg.iter().rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b))))
+                .collect::<Vec<_>>().join(" "),
+            None => reply.to_string(),
+        },
+        "qSupported" => format!("server supports: {}", reply.replace(';', " ")),
+        "qRcmd" => reply.to_string(),
+        "qCRC" if reply.starts_with('C') => format!("CRC-32 0x{}", &reply[1..]),
+        "qSearch" => match reply.strip_prefix("1,")
+        {
+            Some(addr) => format!("pattern found at 0x{}", addr),
+            None if reply == "0" => "pattern not found".to_string(),
+            None => reply.to_string(),
+        },
+        "QTFrame" => match reply.strip_prefix('F').and_then(|frame| frame.split_once('T'))
+        {
+            Some((frame, tp)) => format!("trace frame {} of tracepoint {}", frame, tp),
+            None if reply == "F-1" => "no matching trace frame".to_string(),
+            None => reply.to_string(),
+        },
+        "qTStatus" if reply.starts_with('T') =>
+        {
+            let mut fields = reply[1..].split(';');
+            let running = if fields.next() == Some("1") {"trace experiment running"} else {"trace experiment not running"};
+            format!("{}, {}", running, fields.collect::<Vec<_>>().join(" "))
+        },
+        "vFile" if reply.starts_with('F') => explain_v_file_reply(data),
+        "c" | "s" | "C" | "S" | "vCont" | "F" if reply.starts_with('F') =>
+        {//Запрос File-I/O вместо Stop Reply: Fwrite,1,80001000,d -> write(1, 0x80001000, 13)
+            let mut fields = reply[1..].split(',');
+            let call = fields.next().unwrap_or("");
+            let args: Vec<String> = fields.map(|f| f.split('/').map(|v| u64::from_str_radix(v, 16).map(|v| format!("{:#x}", v)).unwrap_or_else(|_| v.to_string())).collect::<Vec<_>>().join("/")).collect();
+            format!("File-I/O request: {}({})", call, args.join(", "))
+        },
+        _ => match explain_stop_reply(reply)
+        {
+            Some(stop) => stop,
+            None => reply.to_string(),
+        },
+    }
+}
+
+
+///Разбор Stop Reply Packet: S<sig>, T<sig><n:r;...>, W<code>, X<sig>. None - это не Stop Reply
+pub fn explain_stop_reply(reply: &str) -> Option<String>
+{
+    let kind = reply.chars().next()?;
+    if !"STWX".contains(kind) || reply.len() < 3
+    {
+        return None;
+    }
+    let sig = u8::from_str_radix(reply.get(1..3)?, 16).ok()?;
+    let mut result = match kind
+    {
+        'W' => format!("process exited with code {}", sig),
+        'X' => format!("process terminated by {} ({})", signal_name(sig), sig),
+        _ => format!("stopped by {} ({})", signal_name(sig), sig),
+    };
+    for field in reply[3..].split(';').filter(|f| !f.is_empty())
+    {
+        let (name, value) = field.split_once(':').unwrap_or((field, ""));
+        let field = match name
+        {
+            "thread" => format!("thread {}", value),
+            "core" => format!("core {}", value),
+            "swbreak" => "software breakpoint".to_string(),
+            "hwbreak" => "hardware breakpoint".to_string(),
+            "watch" | "rwatch" | "awatch" => format!("{} at 0x{}", name, value),
+            "exec" | "fork" | "vfork" | "library" | "create" => name.to_string(),
+            num if usize::from_str_radix(num, 16).is_ok() => format!("{}={}", reg_field(num),
+                le_value(value).map(|v| format!("{:#x}", v)).unwrap_or_else(|| value.to_string())),
+            _ => field.to_string(),
+        };
+        result.push_str(", ");
+        result.push_str(&field);
+    }
+    Some(result)
+}
+
+
+///Одно сообщение из лога или захвата
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedFrame
+{
+    pub dir: Direction,                             // In - от GDB, Out - от сервера
+    pub raw: Vec<u8>,                               // Сообщение целиком: $<data>#cs, %<data>#cs, '+', '-', ^C
+}
+
+
+///Раскрыть escape-последовательности лога GDB: \\ \n \r \t \xHH \ooo
+pub fn unescape_log(text: &str) -> Vec<u8>
+{
+    let bytes = text.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len()
+    {
+        if bytes[i] != b'\\' || i + 1 == bytes.len()
+        {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let c = bytes[i + 1];
+        i += 2;
+        match c
+        {
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            b't' => result.push(b'\t'),
+            b'a' => result.push(0x07),
+            b'b' => result.push(0x08),
+            b'f' => result.push(0x0c),
+            b'v' => result.push(0x0b),
+            b'e' => result.push(0x1b),
+            b'x' =>
+            {
+                let end = (i..bytes.len().min(i + 2)).take_while(|&j| bytes[j].is_ascii_hexdigit()).last().map(|j| j + 1).unwrap_or(i);
+                match u8::from_str_radix(&text[i..end], 16)
+                {
+                    Ok(b) => result.push(b),
+                    Err(_) => result.extend_from_slice(b"\\x"),
+                }
+                i = end;
+            },
+            b'0'..=b'7' =>
+            {
+                let start = i - 1;
+                let end = (start..bytes.len().min(start + 3)).take_while(|&j| (b'0'..=b'7').contains(&bytes[j])).last().unwrap() + 1;
+                result.push(u32::from_str_radix(&text[start..end], 8).unwrap_or(0) as u8);
+                i = end;
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+
+///Пакет из данных (для "Packet received:", где в логе нет '$' и '#cs')
+fn frame_from_data(prefix: u8, data: &[u8]) -> Vec<u8>
+{
+    let mut raw = vec![prefix];
+    raw.extend_from_slice(data);
+    raw.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+    raw
+}
+
+
+///Похоже ли содержимое на лог "set debug remote 1"
+pub fn is_debug_log(content: &[u8]) -> bool
+{
+    let text = String::from_utf8_lossy(content);
+    text.contains("Sending packet:") || text.contains("Packet received:")
+}
+
+
+///Разобрать лог "set debug remote 1" (старый формат "Sending packet: $...#cs...Ack" и новый "[remote] Sending packet: ...")
+pub fn parse_debug_log(text: &str) -> Vec<DecodedFrame>
+{
+    let mut frames = Vec::new();
+    for line in text.lines()
+    {
+        if let Some(pos) = line.find("Sending packet: ")
+        {
+            let mut packet = &line[pos + "Sending packet: ".len() ..];
+            //Старый формат: после пакета "...Ack", "...Nak", "..."
+            let ack = packet.find("...").map(|dots| {
+                let ack = &packet[dots + 3 ..];
+                packet = &packet[..dots];
+                ack
+            });
+            frames.push(DecodedFrame{ dir: Direction::In, raw: unescape_log(packet) });
+            match ack
+            {
+                Some("Ack") => frames.push(DecodedFrame{ dir: Direction::Out, raw: b"+".to_vec() }),
+                Some("Nak") => frames.push(DecodedFrame{ dir: Direction::Out, raw: b"-".to_vec() }),
+                _ => (),
+            }
+        }
+        else if let Some(pos) = line.find("Packet received: ")
+        {
+            let data = unescape_log(&line[pos + "Packet received: ".len() ..]);
+            frames.push(DecodedFrame{ dir: Direction::Out, raw: frame_from_data(b'$', &data) });
+        }
+        else if let Some(pos) = line.find("Notification received: ")
+        {
+            let data = unescape_log(&line[pos + "Notification received: ".len() ..]);
+            frames.push(DecodedFrame{ dir: Direction::Out, raw: frame_from_data(b'%', &data) });
+        }
+        else if line.ends_with("Received Ack")
+        {
+            frames.push(DecodedFrame{ dir: Direction::Out, raw: b"+".to_vec() });
+        }
+        else if line.ends_with("Sending ack") || line.ends_with("Sending Ack")
+        {
+            frames.push(DecodedFrame{ dir: Direction::In, raw: b"+".to_vec() });
+        }
+    }
+    frames
+}
+
+
+///Разобрать захват байт. Направление неизвестно: если from не задано, считается, что пакеты GDB и сервера чередуются
+///(O-пакеты и уведомления '%' - от сервера, одиночные '+'/'-' - ответ на предыдущий пакет, ^C - от GDB)
+pub fn parse_raw_capture(bytes: &[u8], from: Option<Direction>) -> Vec<DecodedFrame>
+{
+    let mut frames = Vec::new();
+    let mut rest = bytes;
+    let mut next_packet = Direction::In;
+    let mut last = Direction::Out;
+    while !rest.is_empty()
+    {
+        let len = frame_len(rest).unwrap_or(rest.len());
+        let (frame, tail) = rest.split_at(len);
+        rest = tail;
+        //'+'/'-' перед пакетом - отдельное сообщение с другой стороны
+        let (ack, frame) = match frame.first()
+        {
+            Some(b'+') | Some(b'-') if frame.len() > 1 => (Some(&frame[..1]), &frame[1..]),
+            _ => (None, frame),
+        };
+        if let Some(ack) = ack
+        {
+            let dir = from.unwrap_or(if last == Direction::In {Direction::Out} else {Direction::In});
+            frames.push(DecodedFrame{ dir, raw: ack.to_vec() });
+        }
+        let dir = match (from, frame.first(), packet_data(frame))
+        {
+            (Some(from), _, _) => from,
+            (None, Some(0x03), _) => Direction::In,
+            (None, Some(b'%'), _) => Direction::Out,
+            (None, _, Some(data)) if data.starts_with(b"O") && data != b"OK" && hex_decode(&String::from_utf8_lossy(&data[1..])).is_some() => Direction::Out,
+            (None, _, Some(_)) =>
+            {
+                let dir = next_packet;
+                next_packet = if dir == Direction::In {Direction::Out} else {Direction::In};
+                dir
+            },
+            (None, _, None) => if last == Direction::In {Direction::Out} else {Direction::In},
+        };
+        last = dir;
+        frames.push(DecodedFrame{ dir, raw: frame.to_vec() });
+    }
+    frames
+}
+
+
+///Смысл сообщения. last_cmd - последняя команда GDB (для разбора ответа), обновляется
+pub fn explain_frame(frame: &DecodedFrame, last_cmd: &mut String) -> String
+{
+    match (frame.raw.first(), packet_data(&frame.raw))
+    {
+        (Some(0x03), _) => "interrupt (^C)".to_string(),
+        (Some(b'+'), None) => "ack: packet received correctly".to_string(),
+        (Some(b'-'), None) => "nak: packet corrupted, resend".to_string(),
+        (_, Some(data)) =>
+        {
+            let cs_ok = str::from_utf8(&frame.raw[frame.raw.len().saturating_sub(2) ..]).ok()
+                .and_then(|cs| u8::from_str_radix(cs, 16).ok()) == Some(checksum(data));
+            let meaning = if frame.raw.first() == Some(&b'%')
+            {
+                format!("notification: {}", explain_reply(data.splitn(2, |&c| c == b':').nth(1).unwrap_or(b""), ""))
+            }
+            else if frame.dir == Direction::In
+            {
+                *last_cmd = Command::parse(data).name.to_string();
+                explain_command(data)
+            }
+            else
+            {
+                explain_reply(data, last_cmd)
+            };
+            if cs_ok {meaning} else {meaning + " (bad checksum)"}
+        },
+        _ => "incomplete or unknown data".to_string(),
+    }
+}
+
+
+///Настройки режима decode
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeConfig
+{
+    pub path: String,
+    pub raw: Option<bool>,                          // None - определить формат по содержимому
+    pub from: Option<Direction>,                    // Для захвата байт одной стороны
+}
+
+
+impl DecodeConfig
+{
+    ///Разобрать аргументы режима decode (после "decode")
+    pub fn from_args(args: &[String]) -> Result<DecodeConfig, String>
+    {
+        let mut config = DecodeConfig{ path: String::new(), raw: None, from: None };
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--raw" => config.raw = Some(true),
+                "--log" => config.raw = Some(false),
+                "--from" =>
+                {
+                    config.from = match next_value(&mut args, arg)?
+                    {
+                        "gdb" => Some(Direction::In),
+                        "stub" => Some(Direction::Out),
+                        value => return Err(format!("Неизвестная сторона \'{}\' (gdb или stub)", value)),
+                    };
+                },
+                path if config.path.is_empty() && !path.starts_with("--") => config.path = path.to_string(),
+                _ => return Err(format!("Неизвестный аргумент режима decode {:?}", arg)),
+            }
+        }
+        if config.path.is_empty()
+        {
+            return Err("Не задан файл лога или захвата".to_string());
+        }
+        Ok(config)
+    }
+}
+
+
+///Режим decode: разобрать файл и вывести сообщения с их смыслом
+pub fn run_decode(config: &DecodeConfig) -> Result<(), String>
+{
+    let content = fs::read(&config.path).map_err(|e| format!("{}: {}", config.path, e))?;
+    let frames = if config.raw.unwrap_or_else(|| !is_debug_log(&content))
+    {
+        parse_raw_capture(&content, config.from)
+    }
+    else
+    {
+        parse_debug_log(&String::from_utf8_lossy(&content))
+    };
+    let mut last_cmd = String::new();
+    for frame in &frames
+    {
+        let arrow = if frame.dir == Direction::In {"GDB -> stub"} else {"stub -> GDB"};
+        let printable: String = frame.raw.iter().flat_map(|&c| std::ascii::escape_default(c)).map(char::from).collect();
+        println!("{} : {}", arrow, printable);
+        println!("              {}", explain_frame(frame, &mut last_cmd));
+    }
+    Ok(())
+}
+
+
+///Тесты для режима decode ================================================================================
+#[cfg(test)]
+mod test_decode
+{
+    use super::*;
+    use codec::make_packet;
+
+    #[test]
+    fn test_explain_command()
+    {
+        assert_eq!("read register 32 (pc)", explain_command(b"p20"));
+        assert_eq!("write register 10 (a0) = 0x1234", explain_command(b"Pa=3412000000000000"));
+        assert_eq!("read 4 bytes of memory at 0x80000000", explain_command(b"m80000000,4"));
+        assert_eq!("write 2 bytes of memory at 0x1000: 03 7d", explain_command(b"X1000,2:}#}]"));
+        assert_eq!("insert software breakpoint at 0x80000010 kind 4 (instruction length)", explain_command(b"Z0,80000010,4"));
+        assert_eq!("remove write watchpoint at 0x1000, 8 bytes", explain_command(b"z2,1000,8"));
+        assert_eq!("monitor command \"reset halt\"", explain_command(b"qRcmd,72657365742068616c74"));
+        assert_eq!("resume: step thread 1, continue", explain_command(b"vCont;s:1;c"));
+        assert_eq!("attach to process 2", explain_command(b"vAttach;2"));
+        assert_eq!("detach from process 2", explain_command(b"D;2"));
+        assert_eq!("host I/O: open \"/bin/app\" flags 0x0 mode 0", explain_command(b"vFile:open:2f62696e2f617070,0,0"));
+        assert_eq!("host I/O: write 2 bytes to fd 3 at offset 16", explain_command(b"vFile:pwrite:3,10,}#}]"));
+        assert_eq!("define tracepoint 1 at 0x80000008 (enabled, while-stepping 2, pass count 0)", explain_command(b"QTDP:1:80000008:E:2:0-"));
+        assert_eq!("while-stepping actions of tracepoint 1 at 0x80000008: collect registers, collect 4 bytes at a0+0x10",
+            explain_command(b"QTDP:-1:80000008:SRffffffffMa,10,4"));
+        assert_eq!("select the next trace frame with pc in 0x80000000..0x80000010", explain_command(b"QTFrame:range:80000000:80000010"));
+        assert_eq!("insert software breakpoint at 0x80000010 kind 4 if [reg 10, const8 5, equal, end]", explain_command(b"Z0,80000010,4;X7,26000a22051327"));
+        assert_eq!("insert software breakpoint at 0x80000010 kind 4 (instruction length), then run [reg 10, const8 0, const8 0, printf \"a0=%d\\n\" 1, end] (persistent)",
+            explain_command(b"Z0,80000010,4;cmds:1,X13,26000a220022003401000761303d25640a0027"));
+        assert_eq!("CRC-32 of 4096 bytes of memory at 0x80000000", explain_command(b"qCRC:80000000,1000"));
+        assert_eq!("search 256 bytes of memory at 0x80000000 for 12 23 7d", explain_command(b"qSearch:memory:80000000;100;\x12}\x03}]"));
+        assert_eq!("actions of tracepoint 1 at 0x100: evaluate [const8 16, const8 2, trace, end]", explain_command(b"QTDP:-1:100:X6,221022020c27"));
+    }
+
+    #[test]
+    fn test_explain_reply()
+    {
+        assert_eq!("console output \"ok\\n\"", explain_reply(b"O6f6b0a", "qRcmd"));
+        assert_eq!("success", explain_reply(b"OK", "qRcmd"));
+        assert_eq!("memory: 13 05 00 00", explain_reply(b"13050000", "m"));
+        assert_eq!("register value 0x80000000", explain_reply(b"0000008000000000", "p"));
+        assert_eq!("stopped by SIGTRAP (5), 32 (pc)=0x80000010, thread 1, software breakpoint", explain_reply(b"T0520:1000008000000000;thread:1;swbreak:;", "c"));
+        assert_eq!("process exited with code 0", explain_reply(b"W00", "c"));
+        assert_eq!("empty reply: command not supported", explain_reply(b"", "vMustReplyEmpty"));
+        assert_eq!("error 01", explain_reply(b"E01", "m"));
+        assert_eq!("File-I/O request: write(0x1, 0x80001000, 0xd)", explain_reply(b"Fwrite,1,80001000,d", "c"));
+        assert_eq!("File-I/O request: open(0x80002000/0x8, 0x601, 0x1a4)", explain_reply(b"Fopen,80002000/8,601,1a4", "F"));
+        assert_eq!("File-I/O result -1, errno 2", explain_command(b"F-1,2"));
+        assert_eq!("host I/O result 3, data \"hi}\"", explain_reply(b"F3;hi}]", "vFile"));
+        assert_eq!("host I/O error, errno 2", explain_reply(b"F-1,2", "vFile"));
+        assert_eq!("trace frame 2 of tracepoint 1", explain_reply(b"F2T1", "QTFrame"));
+        assert_eq!("no matching trace frame", explain_reply(b"F-1", "QTFrame"));
+        assert_eq!("CRC-32 0x0376e6e7", explain_reply(b"C0376e6e7", "qCRC"));
+        assert_eq!("pattern found at 0x80000010", explain_reply(b"1,80000010", "qSearch"));
+        assert_eq!("pattern not found", explain_reply(b"0", "qSearch"));
+        assert_eq!("trace experiment running, tnotrun:0 tframes:3", explain_reply(b"T1;tnotrun:0;tframes:3", "qTStatus"));
+    }
+
+    #[test]
+    fn test_parse_debug_log()
+    {
+        let log = "[remote] Sending packet: $m80000000,4#8d\n\
+                   [remote] Received Ack\n\
+                   [remote] Packet received: 13050000\n\
+                   Sending packet: $X0,1:\\003#00...Ack\n\
+                   Notification received: Stop:T05\n";
+        let frames = parse_debug_log(log);
+        assert_eq!(6, frames.len());
+        assert_eq!(DecodedFrame{ dir: Direction::In, raw: b"$m80000000,4#8d".to_vec() }, frames[0]);
+        assert_eq!(DecodedFrame{ dir: Direction::Out, raw: b"+".to_vec() }, frames[1]);
+        assert_eq!(make_packet("13050000").into_bytes(), frames[2].raw);
+        assert_eq!(b"$X0,1:\x03#00".to_vec(), frames[3].raw);
+        assert_eq!(b'%', frames[5].raw[0]);
+        assert_eq!(vec![0x5c, 0x0a, 0x41, 0x07], unescape_log("\\\\\\n\\x41\\a"));
+    }
+
+    #[test]
+    fn test_parse_raw_capture()
+    {
+        let capture = format!("{}+{}+{}{}\x03", make_packet("qRcmd,6869"), make_packet("O6869"), make_packet("OK"), make_packet("c"));
+        let frames = parse_raw_capture(capture.as_bytes(), None);
+        let dirs: Vec<Direction> = frames.iter().map(|f| f.dir).collect();
+        assert_eq!(vec![Direction::In, Direction::Out, Direction::Out, Direction::In, Direction::Out, Direction::In, Direction::In], dirs);
+        let mut last_cmd = String::new();
+        let meanings: Vec<String> = frames.iter().map(|f| explain_frame(f, &mut last_cmd)).collect();
+        assert_eq!("console output \"hi\"", meanings[2]);
+        assert_eq!("success", meanings[4]);
+        assert_eq!("interrupt (^C)", meanings[6]);
+    }
+
+    #[test]
+    fn test_explain_notification()
+    {
+        //Stop Reply в уведомлении - всё после "Stop:", вместе с полями после ':'
+        let frame = DecodedFrame{ dir: Direction::Out, raw: make_packet("Stop:T05swbreak:;thread:2;").replace('$', "%").into_bytes() };
+        let mut last_cmd = String::new();
+        assert_eq!("notification: stopped by SIGTRAP (5), software breakpoint, thread 2", explain_frame(&frame, &mut last_cmd));
+    }
+}
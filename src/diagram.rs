@@ -0,0 +1,443 @@
+use std::fmt::Write;
+
+use codec::{Command, hex_decode, unescape_binary};
+use config::{next_value, parse_size};
+use replay::{connection_records, split_frame, split_frames};
+use transcript::{Record, Direction, read_transcript};
+
+
+///Серия из стольких X/M-пакетов подряд сворачивается в одну стрелку
+pub const DEFAULT_BURST: usize = 4;
+///Максимальная длина подписи стрелки
+const MAX_LABEL: usize = 48;
+
+
+///Формат диаграммы
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagramFormat
+{
+    Mermaid,
+    PlantUml,
+}
+
+
+///Настройки режима diagram
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagramConfig
+{
+    pub path: String,                               // Файл транскрипта
+    pub format: DiagramFormat,
+    pub conn: Option<u64>,                          // Подключение (None - первое в транскрипте)
+    pub burst: usize,                               // Минимальная длина сворачиваемой серии X/M-пакетов (0 - не сворачивать)
+}
+
+
+impl DiagramConfig
+{
+    ///Разобрать аргументы режима diagram (после "diagram")
+    pub fn from_args(args: &[String]) -> Result<DiagramConfig, String>
+    {
+        let mut config = DiagramConfig{ path: String::new(), format: DiagramFormat::Mermaid, conn: None, burst: DEFAULT_BURST };
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--format"=>
+                {
+                    config.format = match next_value(&mut args, arg)?
+                    {
+                        "mermaid" => DiagramFormat::Mermaid,
+                        "plantuml" => DiagramFormat::PlantUml,
+                        value => return Err(format!("Неизвестный формат \'{}\' (mermaid или plantuml)", value)),
+                    };
+                },
+                "--conn"=>
+                {
+                    config.conn = Some(parse_size(next_value(&mut args, arg)?)? as u64);
+                },
+                "--burst"=>
+                {
+                    config.burst = parse_size(next_value(&mut args, arg)?)?;
+                },
+                path if config.path.is_empty() && !path.starts_with('-') => config.path = path.to_string(),
+                _ => return Err(format!("Неизвестный аргумент режима diagram {:?}", arg)),
+            }
+        }
+        if config.path.is_empty()
+        {
+            return Err("Не задан файл транскрипта".to_string());
+        }
+        Ok(config)
+    }
+}
+
+
+///Этап сеанса (заметка на диаграмме при смене этапа)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase
+{
+    Connect,                                        // Согласование features, начальные запросы
+    Load,                                           // Запись памяти и регистров
+    Inspect,                                        // Чтение памяти и регистров
+    Breakpoint,                                     // Установка/снятие точек останова
+    Continue,                                       // Исполнение, шаг, прерывание по ^C
+    Monitor,                                        // Команды monitor
+    Disconnect,                                     // vKill, k, D
+}
+
+
+impl Phase
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match *self
+        {
+            Phase::Connect => "connect",
+            Phase::Load => "load",
+            Phase::Inspect => "inspect",
+            Phase::Breakpoint => "breakpoint",
+            Phase::Continue => "continue",
+            Phase::Monitor => "monitor",
+            Phase::Disconnect => "disconnect",
+        }
+    }
+
+
+    ///Этап по команде. None - команда не меняет этап (остается текущий)
+    fn of(cmd: &str) -> Option<Phase>
+    {
+        match cmd
+        {
+            "qSupported" | "QStartNoAckMode" | "vMustReplyEmpty" | "qAttached" | "qOffsets" | "qSymbol" | "qXfer" => Some(Phase::Connect),
+            "X" | "M" | "G" | "P" => Some(Phase::Load),
+            "m" | "g" | "p" => Some(Phase::Inspect),
+            "c" | "s" | "C" | "S" | "vCont" | "^C" => Some(Phase::Continue),
+            "qRcmd" => Some(Phase::Monitor),
+            "vKill" | "k" | "D" => Some(Phase::Disconnect),
+            cmd if cmd.starts_with('Z') || cmd.starts_with('z') => Some(Phase::Breakpoint),
+            _ => None,
+        }
+    }
+}
+
+
+///Стрелка диаграммы
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arrow
+{
+    pub from_gdb: bool,
+    pub label: String,
+}
+
+
+///Элемент диаграммы: заметка об этапе или стрелка
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step
+{
+    Phase(Phase),
+    Arrow(Arrow),
+}
+
+
+///Пакет GDB (или ^C) и ответы на него
+struct Exchange
+{
+    cmd: String,
+    data: Vec<u8>,                                  // Данные пакета, для ^C - пусто
+    acked: Option<u8>,                              // acknowledgment сервера на пакет
+    replies: Vec<(Vec<u8>, Option<u8>)>,            // Данные ответов и acknowledgment GDB на каждый
+}
+
+
+///Разбить записи подключения на обмены: пакет GDB и все сообщения сервера до следующего пакета GDB
+fn exchanges(records: &[Record]) -> Vec<Exchange>
+{
+    let mut result: Vec<Exchange> = Vec::new();
+    for record in records
+    {
+        for frame in split_frames(&record.raw)
+        {
+            let (ack, data, _) = split_frame(frame);
+            match (record.dir, data)
+            {
+                (Direction::In, Some(data)) =>
+                {
+                    let cmd = Command::parse(data).name.to_string();
+                    result.push(Exchange{ cmd, data: data.to_vec(), acked: None, replies: Vec::new() });
+                },
+                (Direction::In, None) if ack == Some(0x03) =>
+                {
+                    result.push(Exchange{ cmd: "^C".to_string(), data: Vec::new(), acked: None, replies: Vec::new() });
+                },
+                (Direction::In, None) =>
+                {//acknowledgment GDB на последний ответ
+                    if let Some(reply) = result.last_mut().and_then(|e| e.replies.last_mut())
+                    {
+                        reply.1 = ack;
+                    }
+                },
+                (Direction::Out, data) =>
+                {
+                    if let Some(exchange) = result.last_mut()
+                    {
+                        if ack.is_some() && exchange.replies.is_empty()
+                        {
+                            exchange.acked = ack;
+                        }
+                        if let Some(data) = data
+                        {
+                            exchange.replies.push((data.to_vec(), None));
+                        }
+                    }
+                },
+            }
+        }
+    }
+    result
+}
+
+
+fn shorten(text: String) -> String
+{
+    if text.chars().count() <= MAX_LABEL
+    {
+        return text;
+    }
+    text.chars().take(MAX_LABEL - 3).collect::<String>() + "..."
+}
+
+
+fn with_ack(label: String, ack: Option<u8>) -> String
+{
+    match ack
+    {
+        Some(ack) => format!("{} ({})", label, char::from(ack)),
+        None => label,
+    }
+}
+
+
+///Подпись пакета GDB: X-данные заменяются их длиной
+fn command_label(exchange: &Exchange) -> String
+{
+    if exchange.cmd == "^C"
+    {
+        return "^C".to_string();
+    }
+    let label = match (&exchange.cmd[..], exchange.data.iter().position(|&c| c == b':'))
+    {
+        ("X", Some(pos)) => format!("{}: <{} bytes>", String::from_utf8_lossy(&exchange.data[..pos]), unescape_binary(&exchange.data[pos+1 ..]).len()),
+        _ => String::from_utf8_lossy(&exchange.data).into_owned(),
+    };
+    with_ack(shorten(label), exchange.acked)
+}
+
+
+///Подпись ответа: O-пакет - текст консоли
+fn reply_label(data: &[u8], last: bool) -> String
+{
+    if !last && data.first() == Some(&b'O') && data != b"OK"
+    {
+        if let Some(text) = hex_decode(&String::from_utf8_lossy(&data[1..]))
+        {
+            return shorten(format!("O {:?}", String::from_utf8_lossy(&text)));
+        }
+    }
+    if data.is_empty()
+    {
+        return "(empty)".to_string();
+    }
+    shorten(String::from_utf8_lossy(data).into_owned())
+}
+
+
+///Адрес и длина из X/M-пакета
+fn write_range(data: &[u8]) -> Option<(u64, u64)>
+{
+    let header = String::from_utf8_lossy(&data[1 .. data.iter().position(|&c| c == b':').unwrap_or(data.len())]).into_owned();
+    let (addr, len) = header.split_once(',')?;
+    Some((u64::from_str_radix(addr, 16).ok()?, u64::from_str_radix(len, 16).ok()?))
+}
+
+
+///Элементы диаграммы по записям одного подключения
+pub fn diagram_steps(records: &[Record], burst: usize) -> Vec<Step>
+{
+    let exchanges = exchanges(records);
+    let mut steps = Vec::new();
+    let mut phase = None;
+    let mut i = 0;
+    while i < exchanges.len()
+    {
+        let exchange = &exchanges[i];
+        let next_phase = Phase::of(&exchange.cmd).or(phase).unwrap_or(Phase::Connect);
+        if phase != Some(next_phase)
+        {
+            phase = Some(next_phase);
+            steps.push(Step::Phase(next_phase));
+        }
+
+        //Серия X/M-пакетов (load): одна стрелка с диапазоном адресов
+        let run = exchanges[i..].iter().take_while(|e| (e.cmd == "X" || e.cmd == "M") && e.replies.len() <= 1).count();
+        if burst > 0 && run >= burst
+        {
+            let series = &exchanges[i .. i+run];
+            let ranges: Vec<(u64, u64)> = series.iter().filter_map(|e| write_range(&e.data)).collect();
+            let start = ranges.iter().map(|r| r.0).min().unwrap_or(0);
+            let end = ranges.iter().map(|r| r.0 + r.1).max().unwrap_or(0);
+            let bytes: u64 = ranges.iter().map(|r| r.1).sum();
+            steps.push(Step::Arrow(Arrow{ from_gdb: true, label: format!("{} x {}: {:#x}..{:#x} ({} bytes)", exchange.cmd, run, start, end, bytes) }));
+            let ok = series.iter().filter(|e| e.replies.first().map(|r| &r.0[..]) == Some(b"OK")).count();
+            let label = if ok == run {format!("OK x {}", run)} else {format!("OK x {}, errors x {}", ok, run - ok)};
+            steps.push(Step::Arrow(Arrow{ from_gdb: false, label }));
+            i += run;
+            continue;
+        }
+
+        steps.push(Step::Arrow(Arrow{ from_gdb: true, label: command_label(exchange) }));
+        for (n, &(ref data, ack)) in exchange.replies.iter().enumerate()
+        {
+            let label = reply_label(data, n + 1 == exchange.replies.len());
+            steps.push(Step::Arrow(Arrow{ from_gdb: false, label: with_ack(label, ack) }));
+        }
+        i += 1;
+    }
+    steps
+}
+
+
+///Экранирование подписи Mermaid: '#' и ';' задаются кодами сущностей
+fn mermaid_escape(label: &str) -> String
+{
+    label.replace('#', "#35;").replace(';', "#59;").replace('<', "#lt;").replace('>', "#gt;")
+}
+
+
+///Текст диаграммы
+pub fn render(steps: &[Step], format: DiagramFormat) -> String
+{
+    let mut out = String::new();
+    match format
+    {
+        DiagramFormat::Mermaid =>
+        {
+            out.push_str("sequenceDiagram\n    participant GDB\n    participant Stub\n");
+            for step in steps
+            {
+                let _ = match *step
+                {
+                    Step::Phase(phase) => writeln!(out, "    Note over GDB,Stub: {}", phase.as_str()),
+                    Step::Arrow(ref arrow) if arrow.from_gdb => writeln!(out, "    GDB->>Stub: {}", mermaid_escape(&arrow.label)),
+                    Step::Arrow(ref arrow) => writeln!(out, "    Stub-->>GDB: {}", mermaid_escape(&arrow.label)),
+                };
+            }
+        },
+        DiagramFormat::PlantUml =>
+        {
+            out.push_str("@startuml\nparticipant GDB\nparticipant Stub\n");
+            for step in steps
+            {
+                let _ = match *step
+                {
+                    Step::Phase(phase) => writeln!(out, "== {} ==", phase.as_str()),
+                    Step::Arrow(ref arrow) if arrow.from_gdb => writeln!(out, "GDB -> Stub : {}", arrow.label.replace('\\', "\\\\")),
+                    Step::Arrow(ref arrow) => writeln!(out, "Stub --> GDB : {}", arrow.label.replace('\\', "\\\\")),
+                };
+            }
+            out.push_str("@enduml\n");
+        },
+    }
+    out
+}
+
+
+///Режим diagram: диаграмма последовательности по транскрипту
+pub fn run_diagram(config: &DiagramConfig) -> Result<String, String>
+{
+    let records = connection_records(read_transcript(&config.path)?, config.conn);
+    if records.is_empty()
+    {
+        return Err(format!("{}: нет записей для диаграммы", config.path));
+    }
+    Ok(render(&diagram_steps(&records, config.burst), config.format))
+}
+
+
+///Тесты для режима diagram ================================================================================
+#[cfg(test)]
+mod test_diagram
+{
+    use super::*;
+    use codec::make_packet;
+    use transcript::inbound_cmd;
+
+    fn rec(dir: Direction, raw: &[u8]) -> Record
+    {
+        Record{ ts_us: 0, dir, conn: 1, cmd: inbound_cmd(raw), data: None, raw: raw.to_vec() }
+    }
+
+    fn session() -> Vec<Record>
+    {
+        let mut records = vec![
+            rec(Direction::In, make_packet("qSupported:swbreak+").as_bytes()),
+            rec(Direction::Out, format!("+{}", make_packet("PacketSize=1000;swbreak+")).as_bytes()),
+            rec(Direction::In, b"+"),
+        ];
+        for n in 0..5
+        {
+            records.push(rec(Direction::In, format!("$X{:x},4:abcd#00", 0x80000000u64 + n * 4).as_bytes()));
+            records.push(rec(Direction::Out, format!("+{}", make_packet("OK")).as_bytes()));
+        }
+        records.extend(vec![
+            rec(Direction::In, make_packet("Z0,80000010,4").as_bytes()),
+            rec(Direction::Out, format!("+{}", make_packet("OK")).as_bytes()),
+            rec(Direction::In, make_packet("c").as_bytes()),
+            rec(Direction::Out, b"+"),
+            rec(Direction::In, b"\x03"),
+            rec(Direction::Out, make_packet("O6f6b0a").as_bytes()),
+            rec(Direction::Out, make_packet("T02").as_bytes()),
+            rec(Direction::In, b"+"),
+        ]);
+        records
+    }
+
+    fn arrow(from_gdb: bool, label: &str) -> Step
+    {
+        Step::Arrow(Arrow{ from_gdb, label: label.to_string() })
+    }
+
+    #[test]
+    fn test_diagram_steps()
+    {
+        assert_eq!(vec![
+            Step::Phase(Phase::Connect),
+            arrow(true, "qSupported:swbreak+ (+)"),
+            arrow(false, "PacketSize=1000;swbreak+ (+)"),
+            Step::Phase(Phase::Load),
+            arrow(true, "X x 5: 0x80000000..0x80000014 (20 bytes)"),
+            arrow(false, "OK x 5"),
+            Step::Phase(Phase::Breakpoint),
+            arrow(true, "Z0,80000010,4 (+)"),
+            arrow(false, "OK"),
+            Step::Phase(Phase::Continue),
+            arrow(true, "c (+)"),
+            arrow(true, "^C"),
+            arrow(false, "O \"ok\\n\""),
+            arrow(false, "T02 (+)"),
+        ], diagram_steps(&session(), DEFAULT_BURST));
+        //Без сворачивания: каждый X-пакет отдельно, бинарные данные заменены длиной
+        let steps = diagram_steps(&session(), 0);
+        assert_eq!(arrow(true, "X80000000,4: <4 bytes> (+)"), steps[4]);
+    }
+
+    #[test]
+    fn test_render()
+    {
+        let steps = vec![Step::Phase(Phase::Connect), arrow(true, "qSupported:a+;b+"), arrow(false, "OK")];
+        assert_eq!("sequenceDiagram\n    participant GDB\n    participant Stub\n    Note over GDB,Stub: connect\n    GDB->>Stub: qSupported:a+#59;b+\n    Stub-->>GDB: OK\n",
+            render(&steps, DiagramFormat::Mermaid));
+        assert_eq!("@startuml\nparticipant GDB\nparticipant Stub\n== connect ==\nGDB -> Stub : qSupported:a+;b+\nStub --> GDB : OK\n@enduml\n",
+            render(&steps, DiagramFormat::PlantUml));
+    }
+}
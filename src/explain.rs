@@ -0,0 +1,168 @@
+use std::fmt;
+
+use codec::{Command, frame_len};
+use decode::{explain_command, explain_reply};
+use transcript::packet_data;
+
+
+///Раздел описания протокола в руководстве GDB (приложение "Remote Serial Protocol")
+const SPEC_URL: &str = "https://sourceware.org/gdb/current/onlinedocs/gdb.html/";
+
+
+///Пояснение к принятому сообщению и ответу сервера (режим --explain)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation
+{
+    pub packet: String,                             // "Z0 = insert software breakpoint at 0x... kind 4 (instruction length)"
+    pub console: Vec<String>,                       // Пояснения к O-пакетам перед ответом
+    pub reply: Option<String>,                      // Ответ и почему он допустим. None - ответа нет
+    pub spec: String,                               // Ссылка на раздел руководства GDB
+}
+
+
+impl fmt::Display for Explanation
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        writeln!(f, "Explain : {}", self.packet)?;
+        for console in &self.console
+        {
+            writeln!(f, "          {}", console)?;
+        }
+        if let Some(ref reply) = self.reply
+        {
+            writeln!(f, "          {}", reply)?;
+        }
+        write!(f, "          spec : {}", self.spec)
+    }
+}
+
+
+///Раздел руководства GDB, в котором описана команда
+fn spec_page(name: &str) -> &'static str
+{
+    match name
+    {
+        "+" | "-" | "QStartNoAckMode" => "Packet-Acknowledgment.html",
+        "^C" | "vCtrlC" => "Interrupts.html",
+        "?" | "c" | "s" | "C" | "S" | "vCont" | "vCont?" | "vStopped" => "Stop-Reply-Packets.html",
+        "qXfer" => "General-Query-Packets.html",
+        "vFile" => "Host-I_002fO-Packets.html",
+        name if name.starts_with("qT") || name.starts_with("QT") => "Tracepoint-Packets.html",
+        name if name.starts_with('q') || name.starts_with('Q') => "General-Query-Packets.html",
+        _ => "Packets.html",
+    }
+}
+
+
+///Почему ответ сервера на команду допустим
+fn reply_reason(name: &str, reply: &[u8]) -> &'static str
+{
+    if name == "vMustReplyEmpty"
+    {
+        return "a stub must answer unknown packets with the empty reply, anything else confuses GDB";
+    }
+    if reply.is_empty()
+    {
+        return "the empty reply means the command is not supported; GDB falls back to other packets or reports an error";
+    }
+    if reply.len() == 3 && reply[0] == b'E'
+    {
+        return "Enn reports an error, GDB does not interpret the number";
+    }
+    match (name, reply)
+    {
+        ("QStartNoAckMode", b"OK") => "OK is still acknowledged with '+', after it neither side sends '+'/'-'",
+        ("vKill", b"OK") | ("k", b"OK") => "OK confirms the kill, then the connection is closed",
+        ("qSymbol", b"OK") => "OK: the stub does not need any symbol values",
+        ("qRcmd", b"OK") => "console output goes in O packets, the command result is OK",
+        (_, b"OK") => "OK confirms the command was executed",
+        ("?", _) | ("c", _) | ("s", _) | ("C", _) | ("S", _) | ("vCont", _) =>
+            "a stop reply is required: T<signal> reports why the target halted (O packets are allowed only before it)",
+        ("m", _) => "memory is sent as hex bytes in target order, a shorter reply means a partial read",
+        ("g", _) => "all registers as hex in target byte order, in the order of the target description",
+        ("p", _) => "register value as hex in target byte order (little-endian for RISC-V)",
+        ("qSupported", _) => "only features the stub implements are listed, PacketSize is in hex",
+        ("qAttached", _) => "'0': the stub created the process, so (gdb) quit sends vKill; '1' would mean an attached process and 'D'",
+        ("qOffsets", _) => "Text/Data/Bss offsets are 0: the program is loaded at its link addresses",
+        ("vCont?", _) => "the list of vCont actions the stub supports; without it GDB uses c/s",
+        ("qC", _) | ("qfThreadInfo", _) | ("qsThreadInfo", _) => "thread ids: 'm' starts a list, 'l' ends it, QC is the current thread",
+        ("qXfer", _) => "'m' - more data follows, 'l' - the last chunk",
+        ("qCRC", _) => "C<crc32>: GDB compares it with the CRC of the section in the file instead of reading the memory with 'm'",
+        ("qSearch", _) => "'1,<addr>' - address of the first match, '0' - no match; GDB does not read the memory itself",
+        _ => "reply format defined for this command",
+    }
+}
+
+
+///Пояснение к сообщению от GDB и ответу на него.
+///frame - принятое сообщение целиком, output_text - O-пакеты перед ответом, responce - ответ ("+$OK#9a")
+pub fn explain_exchange(frame: &[u8], output_text: Option<&str>, responce: Option<&str>) -> Explanation
+{
+    let (name, packet) = match (frame.first(), packet_data(frame))
+    {
+        (Some(0x03), _) => ("^C".to_string(), "^C = interrupt: GDB asks the running target to stop, the stop reply to 'c'/'vCont' reports SIGINT".to_string()),
+        (Some(b'+'), None) => ("+".to_string(), "+ = acknowledgment: the previous reply was received with a valid checksum".to_string()),
+        (Some(b'-'), None) => ("-".to_string(), "- = negative acknowledgment: the previous reply was corrupted and must be resent".to_string()),
+        (_, Some(data)) =>
+        {
+            let name = Command::parse(data).name.to_string();
+            let packet = format!("{} = {}", if name.is_empty() {"?"} else {&name[..]}, explain_command(data));
+            (name, packet)
+        },
+        _ => (String::new(), "incomplete or unknown data".to_string()),
+    };
+
+    let mut console = Vec::new();
+    let mut rest = output_text.unwrap_or("").as_bytes();
+    while let Some(len) = frame_len(rest)
+    {
+        if let Some(data) = packet_data(&rest[..len])
+        {
+            console.push(format!("O packet : {}", explain_reply(data, "")));
+        }
+        rest = &rest[len..];
+    }
+
+    let reply = responce.map(|responce| {
+        let ack = if responce.starts_with('+') {"'+' then "} else {""};
+        match packet_data(responce.trim_start_matches('+').as_bytes())
+        {
+            Some(data) => format!("reply {}{:?} : {}. Valid because {}", ack, String::from_utf8_lossy(data), explain_reply(data, &name), reply_reason(&name, data)),
+            None if name == "+" => "reply '+' : this server answers an acknowledgment with an acknowledgment".to_string(),
+            None => format!("reply {:?}", responce),
+        }
+    });
+
+    Explanation{ packet, console, reply, spec: format!("GDB manual, {}{}", SPEC_URL, spec_page(&name)) }
+}
+
+
+///Тесты для режима --explain ================================================================================
+#[cfg(test)]
+mod test_explain
+{
+    use super::*;
+    use codec::make_packet;
+
+    #[test]
+    fn test_explain_exchange()
+    {
+        let explanation = explain_exchange(make_packet("Z0,80000010,4").as_bytes(), None, Some("+$OK#9a"));
+        assert_eq!("Z0 = insert software breakpoint at 0x80000010 kind 4 (instruction length)", explanation.packet);
+        assert_eq!(Some("reply '+' then \"OK\" : success. Valid because OK confirms the command was executed".to_string()), explanation.reply);
+        assert!(explanation.spec.ends_with("/Packets.html"));
+
+        let explanation = explain_exchange(make_packet("qRcmd,6869").as_bytes(), Some(&make_packet("O6f6b0a")), Some(&make_packet("OK")));
+        assert_eq!(vec!["O packet : console output \"ok\\n\"".to_string()], explanation.console);
+        assert!(explanation.spec.ends_with("/General-Query-Packets.html"));
+
+        let explanation = explain_exchange(make_packet("vMustReplyEmpty").as_bytes(), None, Some("+$#00"));
+        assert!(explanation.reply.unwrap().contains("must answer unknown packets with the empty reply"));
+
+        let explanation = explain_exchange(b"\x03", None, None);
+        assert!(explanation.packet.starts_with("^C = interrupt"));
+        assert_eq!(None, explanation.reply);
+        assert!(explanation.spec.ends_with("/Interrupts.html"));
+    }
+}
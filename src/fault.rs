@@ -0,0 +1,255 @@
+use std::fmt;
+
+use codec::make_packet;
+use rules::{RuleTable, read_rules, parse_rules};
+
+
+///Stop Reply Packet, который по умолчанию отправляется без запроса (spurious-stop)
+pub const DEFAULT_SPURIOUS_STOP: &str = "T05";
+
+
+///Неисправность, вносимая в ответ GDB-сервера
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultAction
+{
+    CorruptChecksum,                                // Неверная контрольная сумма ответа (GDB должен ответить '-' и получить ответ повторно)
+    Drop,                                           // Ответ не отправляется
+    Delay(u64),                                     // Задержка ответа, мс
+    Duplicate,                                      // Ответ отправляется дважды
+    Error(u8),                                      // Вместо обработки команды ответ E<nn>
+    SpuriousStop(String),                           // После ответа - Stop Reply Packet без запроса
+}
+
+
+impl fmt::Display for FaultAction
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            FaultAction::CorruptChecksum => write!(f, "corrupt-checksum"),
+            FaultAction::Drop => write!(f, "drop"),
+            FaultAction::Delay(ms) => write!(f, "delay {} ms", ms),
+            FaultAction::Duplicate => write!(f, "duplicate"),
+            FaultAction::Error(code) => write!(f, "error E{:02x}", code),
+            FaultAction::SpuriousStop(reply) => write!(f, "spurious-stop {}", reply),
+        }
+    }
+}
+
+
+///Правило: к каким командам и как часто применять неисправность
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultRule
+{
+    pub cmd: String,                                // Название команды ("m", "Z0", "vCont;" ...). "*" - любая команда, "q*" - команды с префиксом
+    pub every: u64,                                 // Применять к каждому every-му пакету с этой командой (1 - к каждому)
+    pub action: FaultAction,
+}
+
+
+impl FaultRule
+{
+    ///Правило из таблицы [[fault]] файла правил
+    pub fn from_table(table: &RuleTable) -> Result<FaultRule, String>
+    {
+        let action = match table.require_str("action")?
+        {
+            "corrupt-checksum" => FaultAction::CorruptChecksum,
+            "drop" => FaultAction::Drop,
+            "delay" => FaultAction::Delay(table.get_uint("ms")?.ok_or_else(|| table.error("для delay не задано поле \'ms\'"))?),
+            "duplicate" => FaultAction::Duplicate,
+            "error" =>
+            {
+                let code = table.get_uint("code")?.unwrap_or(1);
+                if code > 0xff
+                {
+                    return Err(table.error(&format!("код ошибки {} больше 0xff", code)));
+                }
+                FaultAction::Error(code as u8)
+            },
+            "spurious-stop" => FaultAction::SpuriousStop(table.get_str("reply")?.unwrap_or(DEFAULT_SPURIOUS_STOP).to_string()),
+            action => return Err(table.error(&format!("неизвестное действие \'{}\' (corrupt-checksum, drop, delay, duplicate, error, spurious-stop)", action))),
+        };
+        let every = table.get_uint("every")?.unwrap_or(1);
+        if every == 0
+        {
+            return Err(table.error("every должно быть не меньше 1"));
+        }
+        Ok(FaultRule{ cmd: table.require_str("cmd")?.to_string(), every, action })
+    }
+
+
+    ///Подходит ли правило к команде пакета
+    pub fn matches(&self, cmd: &str) -> bool
+    {
+        match self.cmd.strip_suffix('*')
+        {
+            Some(prefix) => cmd.starts_with(prefix),
+            None => self.cmd == cmd,
+        }
+    }
+}
+
+
+///Правила внесения неисправностей (--faults) и счетчики пакетов по каждому правилу. Создается на каждое подключение
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultInjector
+{
+    rules: Vec<FaultRule>,
+    counters: Vec<u64>,                             // Число пакетов, подошедших к правилу
+}
+
+
+impl FaultInjector
+{
+    pub fn new(rules: Vec<FaultRule>) -> FaultInjector
+    {
+        let counters = vec![0; rules.len()];
+        FaultInjector{ rules, counters }
+    }
+
+
+    ///Правила из текста: таблицы [[fault]] (таблицы других видов пропускаются - файл может быть общим с другими правилами)
+    pub fn parse(text: &str) -> Result<FaultInjector, String>
+    {
+        FaultInjector::from_tables(&parse_rules(text)?)
+    }
+
+
+    ///Загрузить правила из файла
+    pub fn load(path: &str) -> Result<FaultInjector, String>
+    {
+        FaultInjector::from_tables(&read_rules(path)?).map_err(|e| format!("{}: {}", path, e))
+    }
+
+
+    fn from_tables(tables: &[RuleTable]) -> Result<FaultInjector, String>
+    {
+        let rules = tables.iter().filter(|t| t.kind == "fault").map(FaultRule::from_table).collect::<Result<Vec<_>, _>>()?;
+        Ok(FaultInjector::new(rules))
+    }
+
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.rules.is_empty()
+    }
+
+
+    ///Неисправности для очередного пакета с командой cmd (название из transcript::inbound_cmd)
+    pub fn next(&mut self, cmd: &str) -> Vec<FaultAction>
+    {
+        let mut actions = Vec::new();
+        for (rule, counter) in self.rules.iter().zip(self.counters.iter_mut())
+        {
+            if rule.matches(cmd)
+            {
+                *counter += 1;
+                if *counter % rule.every == 0
+                {
+                    actions.push(rule.action.clone());
+                }
+            }
+        }
+        actions
+    }
+}
+
+
+///Код ошибки, если среди неисправностей есть error (тогда команда не обрабатывается)
+pub fn error_code(actions: &[FaultAction]) -> Option<u8>
+{
+    actions.iter().find_map(|a| match a
+    {
+        FaultAction::Error(code) => Some(*code),
+        _ => None,
+    })
+}
+
+
+///Сообщения, которые надо отправить вместо ответа responce, и задержка перед ними
+///Acknowledgment перед пакетом сохраняется, испорченная контрольная сумма - инверсия правильной
+pub fn apply_faults(actions: &[FaultAction], responce: &str) -> (u64, Vec<String>)
+{
+    let mut delay = 0;
+    let mut message = responce.to_string();
+    let mut copies = 1;
+    let mut extra = Vec::new();
+    for action in actions
+    {
+        match action
+        {
+            FaultAction::CorruptChecksum => message = corrupt_checksum(&message),
+            FaultAction::Drop => copies = 0,
+            FaultAction::Delay(ms) => delay += ms,
+            FaultAction::Duplicate => if copies > 0 {copies = 2},
+            FaultAction::Error(_) => (), //Учитывается до обработки команды (error_code)
+            FaultAction::SpuriousStop(reply) => extra.push(make_packet(reply)),
+        }
+    }
+    let mut messages = vec![message; copies];
+    messages.extend(extra);
+    (delay, messages)
+}
+
+
+///Сообщение с инвертированной контрольной суммой. Одиночный acknowledgment не меняется
+pub fn corrupt_checksum(message: &str) -> String
+{
+    let bytes = message.as_bytes();
+    if bytes.len() < 4 || bytes[bytes.len()-3] != b'#' || !message.contains('$')
+    {
+        return message.to_string();
+    }
+    let sharp_pos = bytes.len() - 3;
+    let cs = u8::from_str_radix(&message[sharp_pos+1 ..], 16).unwrap_or(0);
+    format!("{}#{:02x}", &message[..sharp_pos], !cs)
+}
+
+
+///Тесты для внесения неисправностей ================================================================================
+#[cfg(test)]
+mod test_fault
+{
+    use super::*;
+
+    const RULES: &str = "[[fault]]\ncmd = \"m\"\naction = \"corrupt-checksum\"\nevery = 3\n\n\
+                         [[fault]]\ncmd = \"q*\"\naction = \"delay\"\nms = 200\n\n\
+                         [[fault]]\ncmd = \"g\"\naction = \"error\"\ncode = 0x0e\n\n\
+                         [[reply]]\nmatch = \"qOffsets\"\n\n\
+                         [[fault]]\ncmd = \"*\"\naction = \"spurious-stop\"\nevery = 4\n";
+
+    #[test]
+    fn test_next()
+    {
+        let mut faults = FaultInjector::parse(RULES).unwrap();
+        assert!(!faults.is_empty());
+        assert!(faults.next("m").is_empty());
+        assert!(faults.next("m").is_empty());
+        assert_eq!(vec![FaultAction::CorruptChecksum], faults.next("m"));
+        assert_eq!(vec![FaultAction::Delay(200), FaultAction::SpuriousStop("T05".to_string())], faults.next("qSupported"));
+        assert_eq!(Some(0x0e), error_code(&faults.next("g")));
+        assert_eq!(None, error_code(&faults.next("G")));
+        assert!(faults.next("Z0").is_empty());
+
+        assert!(FaultInjector::parse("[[fault]]\ncmd = \"m\"\naction = \"explode\"").is_err());
+        assert!(FaultInjector::parse("[[fault]]\ncmd = \"m\"\naction = \"delay\"").is_err());
+        assert!(FaultInjector::parse("[[fault]]\ncmd = \"m\"\naction = \"drop\"\nevery = 0").is_err());
+        assert!(FaultInjector::parse("[[fault]]\naction = \"drop\"").is_err());
+        assert!(FaultInjector::parse("[[fault]]\ncmd = \"m\"\naction = \"error\"\ncode = 256").is_err());
+    }
+
+    #[test]
+    fn test_apply_faults()
+    {
+        assert_eq!("$OK#65", corrupt_checksum("$OK#9a"));
+        assert_eq!("+$#ff", corrupt_checksum("+$#00"));
+        assert_eq!("+", corrupt_checksum("+"));
+
+        assert_eq!((0, vec!["$OK#9a".to_string()]), apply_faults(&[], "$OK#9a"));
+        assert_eq!((0, Vec::<String>::new()), apply_faults(&[FaultAction::Drop, FaultAction::Duplicate], "$OK#9a"));
+        assert_eq!((150, vec!["$OK#65".to_string(), "$OK#65".to_string(), "$T05#b9".to_string()]),
+            apply_faults(&[FaultAction::Delay(100), FaultAction::CorruptChecksum, FaultAction::Duplicate, FaultAction::Delay(50), FaultAction::SpuriousStop("T05".to_string())], "$OK#9a"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::str;
+use std::thread::spawn;
+
+use codec::{Command, frame_len};
+use cmd_registry::CmdRegistry;
+use config::ServerConfig;
+use decode::{explain_command, explain_reply};
+use gdb_server::{RspPacket, serve_connection, set_verbose};
+use target::{Target, DummyTarget};
+use sim::SimTarget;
+use transport::memory_pair;
+
+
+///Разбиение произвольных байт на сообщения: frame_len и RspPacket для каждого сообщения
+///Используется fuzz-целью "framer" (fuzz/fuzz_targets) и тестом регрессионного корпуса
+pub fn framer(data: &[u8])
+{
+    let mut rest = data;
+    while let Some(len) = frame_len(rest)
+    {
+        assert!(len > 0 && len <= rest.len());
+        let frame = &rest[..len];
+        let pkt = RspPacket::new(frame, len);
+        let _ = pkt.cmd_key();
+        rest = &rest[len..];
+    }
+}
+
+
+///Разбор команды: данные пакета (между '$' и '#') как их видят обработчики и decode
+pub fn command(data: &[u8])
+{
+    let cmd = Command::parse(data);
+    let _ = (cmd.name, cmd.args);
+    let _ = explain_command(data);
+    let _ = explain_reply(data, cmd.name);
+    if let Ok(text) = str::from_utf8(data)
+    {
+        let _ = RspPacket::extract_monitor_cmd(text);
+    }
+    //То же в виде пакета, в т.ч. с acknowledgment перед ним
+    for prefix in &[&b"$"[..], &b"+$"[..]]
+    {
+        let mut frame = prefix.to_vec();
+        frame.extend_from_slice(data);
+        frame.extend_from_slice(b"#00");
+        let pkt = RspPacket::new(&frame, frame.len());
+        let _ = pkt.cmd_key();
+    }
+}
+
+
+///Полная обработка: байты от "GDB" идут в serve_connection с обработчиками по умолчанию, сначала с DummyTarget, затем с SimTarget
+///Сервер должен ответить на все и завершиться, когда клиент закроет соединение
+pub fn dispatch(data: &[u8])
+{
+    dispatch_to(data, || DummyTarget::new(false));
+    dispatch_to(data, || SimTarget::new(false));
+}
+
+
+///Полная обработка с целью, которую создает make_target (в потоке сервера)
+fn dispatch_to<T: Target, F: FnOnce() -> T + Send + 'static>(data: &[u8], make_target: F)
+{
+    set_verbose(false);
+    let (mut client, server) = memory_pair();
+    let handle = spawn(move ||
+    {
+        serve_connection(server, &ServerConfig::default(), &CmdRegistry::with_defaults(), &mut make_target());
+    });
+    let _ = client.write_all(data); //Сервер мог уже завершиться по vKill
+    client.shutdown_write(); //Ответы сервера остаются в канале непрочитанными
+    handle.join().expect("server panicked");
+}
@@ -1,61 +1,107 @@
 use std::str;
-use std::io::Write;
+use std::io;
 use std::io::Read;
 use std::net::TcpListener;
 
-use std::thread::spawn;
-use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 
-use sim::run_sim;
+use codec::{checksum, frame_len, escape_binary, hex_encode};
+use cmd_registry::{CmdRegistry, CmdContext};
+use cmd_handlers::stop_notification;
+use target::Target;
+use session::Session;
+use config::ServerConfig;
+use transport::Transport;
+use transcript::{Direction, SharedTranscript, record, inbound_cmd, open_shared};
+use explain::explain_exchange;
+use fault::{FaultInjector, error_code, apply_faults};
+
+
+    ///PACKET_SIZE - Размер GDB-RSP-пакета в байтах по умолчанию ("PacketSize=PACKET_SIZE" в ответ на qSupported)
+    ///Размер должен вмещать все GPR регистры + символ 'G'. Задается при запуске (--packet-size), оптимальный размер можно подобрать режимом sweep
+    pub const PACKET_SIZE: usize = 4096;
+    ///BUF_SIZE - Размер буфера под TCP-пакет от GDB (чуть больше просто на всякий случай)
+    pub const BUF_SIZE: usize = PACKET_SIZE + BUF_RESERVE;
+    ///BUF_RESERVE - Запас буфера приема сверх размера пакета
+    pub const BUF_RESERVE: usize = 256;
 
 
-    ///PACKET_SIZE - Размер GDB-RSP-пакета в байтах ("PacketSize=PACKET_SIZE" в ответ на qSupported)
-    ///Размер должен вмещать все GPR регистры + символ 'G'
-    const PACKET_SIZE: usize = 4096; //Поподбирать оптимальный размер !!!
-    ///BUF_SIZE - Размер буфера под TCP-пакет от GDB (чуть больше просто на всякий случай)
-    const BUF_SIZE: usize = PACKET_SIZE + 256;
+///Признак технологического вывода (см. log_println!)
+static VERBOSE: AtomicBool = AtomicBool::new(true);
+
+///Включить/отключить технологический вывод
+pub fn set_verbose(verbose: bool)
+{
+    VERBOSE.store(verbose, Ordering::SeqCst);
+}
+
+pub fn is_verbose() -> bool
+{
+    VERBOSE.load(Ordering::Relaxed)
+}
+
 
 pub struct RspPacket<'a>
 {
     pub len: Option<usize>,                         // Длина принятого RSP-пакета
     pub data: Option<&'a str>,                      // Только данные <data> из RSP-пакета (между первым '$' и последним '#')
+    pub binary_key: Option<&'a str>,                // Ключ команды, если данные не валидная utf-строка: 'X' или начало данных до бинарной части ("vFile:pwrite:3,0,")
     pub first_cmd_symbol: Option<char>,             // Первый символ данных data[0]
     pub last_ack_sign: Option<char>,                // Acknowledgment '+' или '-' для предыдущего пакета (если есть). На случай, если no-acknowledgment режим еще не включен
     pub only_symb: Option<bool>,                    // Признак того, что это не пакет, а одиночный acknowledgment '+'/'-'
     pub cs: Option<&'a str>,                        // Контрольная сумма RSP-пакета
     pub need_responce: Option<bool>,                // Признак необходимости ответа. Без need_responce не обойтись т.к. в конструкторе заранее неизвостно, что будет содержать responce
     pub responce: Option<String>,                   // Ответный RSP-пакет
+    pub responce_binary: Option<Vec<u8>>,           // Ответный RSP-пакет с бинарными данными (vFile:pread). Отправляется вместо responce, в responce - он же для вывода
     pub output_text: Option<String>,                // Текстовое сообщение для вывода в GDB-консоль. Допустимо только с Stop Reply Packet и qRcmd !!
     pub kill_flag: Option<bool>,                    // Признак команды 'vKill'
+    pub packet_size: usize,                         // Максимальный размер RSP-пакета (PacketSize)
 }
 
 
 impl<'a> RspPacket<'a>
 {
-    ///Конструктор
+    ///Конструктор (размер пакета PACKET_SIZE)
     pub fn new(input_buf: &'a[u8], input_len: usize) -> RspPacket<'a>
     {
+        RspPacket::with_packet_size(input_buf, input_len, PACKET_SIZE)
+    }
+
+
+    ///Конструктор с заданным размером пакета
+    pub fn with_packet_size(input_buf: &'a[u8], input_len: usize, packet_size: usize) -> RspPacket<'a>
+    {
+        let input_len = input_len.min(input_buf.len()); //Длина не может быть больше буфера
         match input_len
         {
-            2...PACKET_SIZE => //Диапазоны в образцах включительные
+            2..=usize::MAX => //Диапазоны в образцах включительные
             { //if input_len > 1 : Пакет $data#cs, а не одиночный символ
-                let usd_pos = str::from_utf8(&input_buf[0..4]).unwrap() .find('$').unwrap(); //'$' должен быть 0м или 1м
-                let sharp_pos = input_len - 3; //str::from_utf8(&input_buf[0..input_len]).unwrap() .find('#').unwrap(); //Или .rfind() для быстроты            
+                let sharp_pos = input_len.saturating_sub(3); //str::from_utf8(&input_buf[0..input_len]).unwrap() .find('#').unwrap(); //Или .rfind() для быстроты            
+                let usd_pos = match input_buf[..2].iter().position(|&c| c == b'$') //'$' должен быть 0м или 1м
+                {
+                    Some(usd_pos) if input_len >= 4 && sharp_pos > usd_pos && input_buf[sharp_pos] == b'#' => usd_pos,
+                    _ => return RspPacket::ignored(input_len, packet_size), //Не пакет: мусор между пакетами, уведомление '%'
+                };
 
                 if char::from(input_buf[usd_pos+1]) == 'X'
                 { //X-пакет, который содержит не только валидные utf-символы
                     RspPacket{
                         len: Some(input_len),
                         data: None,
+                        binary_key: Some("X"),
                         first_cmd_symbol: Some( 'X' ),
                         last_ack_sign: if let 1 = usd_pos {Some(char::from(input_buf[0]))} else{None},
                         only_symb: Some(false),
                         cs: str::from_utf8(&input_buf[sharp_pos+1 .. sharp_pos+3]).ok(),
                         need_responce: Some(true), //Признак может быть сброшен в зависимости от пришедшей команды (только в случае, если это Пакет)
                         responce: None, //Ответ будет сформирован при необходимости
+                        responce_binary: None,
                         output_text: None,
                         kill_flag: Some(false),
+                        packet_size,
                     }
                 }
                 else
@@ -63,14 +109,17 @@ impl<'a> RspPacket<'a>
                     RspPacket{
                         len: Some(input_len),
                         data: str::from_utf8(&input_buf[usd_pos+1 .. sharp_pos]).ok(),
+                        binary_key: binary_key(&input_buf[usd_pos+1 .. sharp_pos]),
                         first_cmd_symbol: Some( char::from(input_buf[usd_pos+1]) ),
                         last_ack_sign: if let 1 = usd_pos {Some(char::from(input_buf[0]))} else{None},
                         only_symb: Some(false),
                         cs: str::from_utf8(&input_buf[sharp_pos+1 .. sharp_pos+3]).ok(),
                         need_responce: Some(true), //Признак может быть сброшен в зависимости от пришедшей команды (только в случае, если это Пакет)
                         responce: None, //Ответ будет сформирован при необходимости
+                        responce_binary: None,
                         output_text: None,
                         kill_flag: Some(false),
+                        packet_size,
                     }
                 }
             },
@@ -79,52 +128,65 @@ impl<'a> RspPacket<'a>
                 RspPacket{
                     len: Some(input_len),
                     data: None,
+                    binary_key: None,
                     first_cmd_symbol: None,
                     last_ack_sign: Some(char::from(input_buf[0])),
                     only_symb: Some(true),
                     cs: None,
                     need_responce: Some(true), //На '+' надо ответить '+'; На '-' надо повторить последний пакет; На Ctrl+C - Stop Reply Packet
                     responce: None, //Ответ будет сформирован при необходимости
+                    responce_binary: None,
                     output_text: None,
                     kill_flag: Some(false),
-                }
-            },
-            0 => 
-            { //Пустое сообщение (input_len = 0)
-                RspPacket{
-                    len: Some(0),
-                    data: None,
-                    first_cmd_symbol: None,
-                    last_ack_sign: None,
-                    only_symb: None,
-                    cs: None,
-                    need_responce: Some(false), //Игнорировать пустое сообщение
-                    responce: None,
-                    output_text: None,
-                    kill_flag: Some(false),
+                    packet_size,
                 }
             },
             _ =>
-            { //Такого не должно быть, так как input_len типа usize
-                panic!("Исключение в конструкторе структуры RspPacket: Некорректное значение input_len");
+            { //Пустое сообщение (input_len = 0)
+                RspPacket::ignored(0, packet_size)
             },
         }//match
     }
 
 
+    ///Сообщение, на которое не надо отвечать (пустое или не RSP-пакет)
+    fn ignored(len: usize, packet_size: usize) -> RspPacket<'a>
+    {
+        RspPacket{
+            len: Some(len),
+            data: None,
+            binary_key: None,
+            first_cmd_symbol: None,
+            last_ack_sign: None,
+            only_symb: None,
+            cs: None,
+            need_responce: Some(false), //Игнорировать сообщение
+            responce: None,
+            responce_binary: None,
+            output_text: None,
+            kill_flag: Some(false),
+            packet_size,
+        }
+    }
+
+
+    ///Максимальная длина ответа
+    ///PacketSize ограничивает пакеты от GDB к серверу, а ответы GDB принимает и длиннее (например 'g' при маленьком PacketSize)
+    ///Поэтому предел - большее из PacketSize и PACKET_SIZE: маленький PacketSize не укорачивает ответы меньше PACKET_SIZE
+    pub fn max_responce_len(&self) -> usize
+    {
+        self.packet_size.max(PACKET_SIZE)
+    }
+
+
     ///Сформировать ответный RSP-пакет: обернуть содержимое сообщения-ответа в $ и #cs
     ///Использовать только если необходимо. Ненужно например для одиночного Ack '+' или '-'
-    fn responce_add_usd_cs(& mut self, msg_str: &str)
+    pub fn responce_add_usd_cs(& mut self, msg_str: &str)
     {
         //Создание строки с выделением буфера. Это не должно ничего замедлить т.к. для одной команды ответ формируется максимум один раз.
-        self.responce = Some(String::with_capacity(PACKET_SIZE)); //Ответный RSP-пакет не должен быть длиннее PACKET_SIZE
+        self.responce = Some(String::with_capacity(self.packet_size)); //Ответный RSP-пакет не должен быть длиннее PACKET_SIZE
 
-        let mut checksum: u8 = 0;
-        for c in msg_str.as_bytes()
-        {
-            checksum = checksum.wrapping_add(*c);
-        }
-        self.responce = Some(format!("${}#{:02x}", msg_str, checksum)); //cs: {:02x} - шестнадцатиричное u8 как строка и дополнить лидирующим нулем до двух цифр
+        self.responce = Some(format!("${}#{:02x}", msg_str, checksum(msg_str.as_bytes()))); //cs: {:02x} - шестнадцатиричное u8 как строка и дополнить лидирующим нулем до двух цифр
         //Тут нельзя передать в responce срез &str на String. Т.к. время жизни String, возвращаемой из format!() ограничено вызывающей функцией
 
         let l = match self.responce //.unwrap() не работает: error[E0507]: cannot move out of borrowed content
@@ -133,20 +195,39 @@ impl<'a> RspPacket<'a>
                     None => panic!("RspPacket.responce = None"),
                 };
 
-        if l.len() > PACKET_SIZE //Длина уже с учетом $ и #cs. Длина строки в байтах
+        if l.len() > self.max_responce_len() //Длина уже с учетом $ и #cs. Длина строки в байтах
         {
-            panic!("Формирование ответного RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len() , PACKET_SIZE);
+            panic!("Формирование ответного RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len() , self.max_responce_len());
         }
     }
 
 
+    ///Сформировать ответный RSP-пакет с бинарными данными: $<msg_str><escaped data>#cs (vFile:pread - "F<count>;" и байты файла)
+    ///Бинарные данные экранируются как в X-пакете. Для вывода в responce байты, не являющиеся utf-символами, заменяются
+    pub fn responce_add_usd_cs_binary(& mut self, msg_str: &str, data: &[u8])
+    {
+        let mut payload = msg_str.as_bytes().to_vec();
+        payload.extend_from_slice(&escape_binary(data));
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&payload);
+        packet.extend_from_slice(format!("#{:02x}", checksum(&payload)).as_bytes());
+        if packet.len() > self.max_responce_len()
+        {
+            panic!("Формирование ответного RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", packet.len(), self.max_responce_len());
+        }
+        self.responce = Some(String::from_utf8_lossy(&packet).into_owned());
+        self.responce_binary = Some(packet);
+    }
+
+
     ///Сформировать Otext RSP-пакет: Сформировать строку из ASCII-кодов исходного сообщения (ASCII-код в виде двух Hex-цифр) и обернуть её в $O и #cs
     ///$O<console_output_text>#cs
-    fn text_add_usd_o_cs(& mut self, msg_str: &str)
+    pub fn text_add_usd_o_cs(& mut self, msg_str: &str)
     {
         //Создание строки с выделением буфера
-        self.output_text = Some(String::with_capacity(PACKET_SIZE)); //console_output_text RSP-пакет не должен быть длиннее PACKET_SIZE
-        let mut otext = String::with_capacity(PACKET_SIZE);
+        self.output_text = Some(String::with_capacity(self.packet_size)); //console_output_text RSP-пакет не должен быть длиннее PACKET_SIZE
+        let mut otext = String::with_capacity(self.packet_size);
 
         //Цикл для формирования строки otext, состоящих из ASCII-кодов символов строкового среза msg_str
         for c in msg_str.as_bytes()
@@ -156,14 +237,8 @@ impl<'a> RspPacket<'a>
 
         otext.insert(0, 'O'); //Добавить 'O' в начало otext
         //Строка otext будет длинее, чем исходный срез msg_str. Так как на каждый символ среза msg_str будет приходиться по два символа (ASCII-код в виде двух Hex-цифр) в строке otext
-        //И еще надо учесть 'O' в начале otext. Поэтому для подсчета cs для otext нужен отдельный цикл
-        let mut checksum: u8 = 0;
-        for c in otext.as_bytes()
-        {
-            checksum = checksum.wrapping_add(*c);
-        }
-
-        self.output_text = Some(format!("${}#{:02x}", otext, checksum));
+        //И еще надо учесть 'O' в начале otext. Поэтому cs считается по otext, а не по msg_str
+        self.output_text = Some(format!("${}#{:02x}", otext, checksum(otext.as_bytes())));
 
         let l = match self.output_text
                 {
@@ -171,9 +246,22 @@ impl<'a> RspPacket<'a>
                     None => panic!("RspPacket.output_text = None"),
                 };
 
-        if l.len() > PACKET_SIZE //Длина уже с учетом $O и #cs. Длина строки в байтах
+        if l.len() > self.max_responce_len() //Длина уже с учетом $O и #cs. Длина строки в байтах
+        {
+            panic!("Формирование output_text RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len() , self.max_responce_len());
+        }
+    }
+
+
+    ///Добавить $O-пакеты после уже сформированных в output_text (вывод команд breakpoints перед Stop Reply)
+    ///Длинный текст делится на несколько пакетов, каждый не длиннее PACKET_SIZE. Пустой текст ничего не добавляет
+    pub fn console_add_usd_o_cs(&mut self, msg_str: &str)
+    {
+        let mut output = self.output_text.take().unwrap_or_default();
+        output.push_str(&console_packets(msg_str, self.max_responce_len()));
+        if !output.is_empty()
         {
-            panic!("Формирование output_text RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len() , PACKET_SIZE);
+            self.output_text = Some(output);
         }
     }
 
@@ -188,18 +276,19 @@ impl<'a> RspPacket<'a>
                 //Получить строковый срез из подсреза(два u8);
                 //Получить само значение ASCII-кода, сохранить в u8;
                 //u8 привести к char и присоединить в конец String.
-    fn extract_monitor_cmd(cmd_str: &str) -> String
+    pub fn extract_monitor_cmd(cmd_str: &str) -> String
     {
         let mut result_cmd = String::with_capacity(PACKET_SIZE); //Создание строки с выделением буфера
-        let mut one_symb_ascii_str; //Строковый срез str. Двухзначный ASCII-код одного символа из начального среза cmd_str
-        let mut one_symb_ascii_u8: u8 = 0;
 
         let str_by_2_u8 = cmd_str.as_bytes().chunks(2); //Из среза cmd_str.as_bytes() сформировать вектор непересекающихся подсрезов по два u8
 
         for subslice in str_by_2_u8 //Итератор по подсрезам (по два u8)
         {
-            one_symb_ascii_str = str::from_utf8(&subslice).unwrap(); //Получение строкового среза из подсреза u8 (из двух u8)
-            one_symb_ascii_u8 = u8::from_str_radix(one_symb_ascii_str, 16).unwrap(); //Получить само значение ASCII-кода из его исходного представления в HEX виде
+            let one_symb_ascii_u8 = match str::from_utf8(subslice).ok().and_then(|one_symb_ascii_str| u8::from_str_radix(one_symb_ascii_str, 16).ok()) //Получение строкового среза из подсреза u8 (из двух u8). Двухзначный ASCII-код одного символа из начального среза cmd_str
+            {//Получить само значение ASCII-кода из его исходного представления в HEX виде
+                Some(code) => code,
+                None => break, //Не hex-цифры: команда обрезается
+            };
             result_cmd.push(char::from(one_symb_ascii_u8)); //Получить char из u8. И присоединить к результирующей строке String
         }
         result_cmd
@@ -208,9 +297,9 @@ impl<'a> RspPacket<'a>
 
     ///Сформировать ответ без $ и #cs например для одиночного Ack '+' или '-'
     ///Можно использовать для $OK#9a и для $#00 т.к. responce() быстрее, чем responce_add_usd_cs()
-    fn responce(& mut self, msg_str: &str)
+    pub fn responce(& mut self, msg_str: &str)
     {
-        self.responce = Some(String::with_capacity(PACKET_SIZE)); //Ответный RSP-пакет не должен быть длиннее PACKET_SIZE
+        self.responce = Some(String::with_capacity(self.packet_size)); //Ответный RSP-пакет не должен быть длиннее PACKET_SIZE
 
         self.responce = Some(msg_str.to_string());
 
@@ -220,545 +309,367 @@ impl<'a> RspPacket<'a>
                     None => panic!("RspPacket.responce = None"),
                 };
 
-        if l.len() > PACKET_SIZE //Вряд ли вообще будет такой длинный ответ без $ и #cs
+        if l.len() > self.max_responce_len() //Вряд ли вообще будет такой длинный ответ без $ и #cs
         {
-            panic!("Формирование ответного RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len(), PACKET_SIZE);
+            panic!("Формирование ответного RSP-пакета: пакет длиннее чем PACKET_SIZE. len = {}. PACKET_SIZE = {}", l.len(), self.max_responce_len());
         }
     }
 
 
-    ///Обработка полученной команды
-    fn match_cmd(&mut self, input_buf: &[u8], cancel_flag: &Arc<AtomicBool>)
+    ///Ключ для поиска обработчика команды: данные пакета (для X-пакета только 'X', для других бинарных - начало до бинарной части)
+    pub fn cmd_key(&self) -> &str
     {
-        match self.first_cmd_symbol.unwrap()
-        {
-            '?'=>
-            {
-                //Запрос состояния цели (причина останова)
-                //$?
-                println!("GDB-Server : Получена команда '?'");
-                //Stop-reply packet: Если цель остановлена (halt) - ответ T05 = SIGTRAP
-                //Stop-reply packet: Если цель прервана по ^C - ответ T02 = SIGINT
-                self.responce("$T02#b6"); //Stop-reply packet
-                self.need_responce = Some(true);
-            },
+        self.data.or(self.binary_key).unwrap_or("")
+    }
 
-            'g'=>
-            {
-                //Чтение всех регистров общего назначения
-                //$g
-                println!("GDB-Server : Получена команда 'g'");
-                //...
-                self.responce_add_usd_cs("ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100");
-                self.need_responce = Some(true);
-            },
 
-            'G'=>
+    ///Обработка полученной команды: ответ по правилам подмены (--replies) или поиск обработчика в реестре
+    pub fn match_cmd(&mut self, registry: &CmdRegistry, input_buf: &[u8], cancel_flag: &Arc<AtomicBool>, target: &mut dyn Target, session: &mut Session)
+    {
+        let scripted = registry.scripted_reply(self.cmd_key())
+            .filter(|r| r.reply.len() + 4 <= self.max_responce_len() && r.console.as_ref().is_none_or(|c| 2 * c.len() + 5 <= self.max_responce_len()));
+        if let Some(scripted) = scripted
+        {//Слишком длинный ответ по правилу не отправляется: команду обрабатывает реестр
+            log_println!("GDB-Server : Scripted reply for \'{}\'", self.cmd_key());
+            if let Some(console) = scripted.console
             {
-                //Запись всех регистров общего назначения
-                //$G<байты>
-                println!("GDB-Server : Получена команда 'G'");
-                //...
-                self.responce("$OK#9a");
-                self.need_responce = Some(true);
-            },
+                self.text_add_usd_o_cs(&console);
+            }
+            self.responce_add_usd_cs(&scripted.reply);
+            self.need_responce = Some(true);
+            return;
+        }
+        let mut ctx = CmdContext{
+            input_buf,
+            cancel_flag,
+            target,
+            session,
+            registry,
+        };
+        registry.dispatch(self, &mut ctx);
+    }
+}//impl RspPacket
 
-            'p'=>
-            {
-                //Чтение произвольного регистра
-                //$p<n>
-                println!("GDB-Server : Получена команда 'p'. Номер регистра {}", usize::from_str_radix(&self.data.unwrap()[1..], 16).unwrap());
-                //...
-                self.responce_add_usd_cs("7766554433221100"); //По RSP регистры передаются в little-endian
-                self.need_responce = Some(true);
-            },
 
-            'P'=>
-            {
-                //Запись произвольного регистра
-                //$P<n>=<байты>
-                let eq_pos = self.data.unwrap().find("=").unwrap(); //Позиция знака '=' для определения номера регистра
-                let reg_num = usize::from_str_radix(&self.data.unwrap()[1..eq_pos], 16).unwrap();
-                let reg_val = usize::from_str_radix(&self.data.unwrap()[eq_pos+1..], 16).unwrap(); //Значение в little-endian
-                println!("GDB-Server : Получена команда 'P'. Номер регистра {}. Значение = 0x{:016x}", reg_num, reg_val);
-                //...
-                self.responce("$OK#9a");
-                self.need_responce = Some(true);
-            },
+///$O-пакеты с текстом для консоли GDB, каждый не длиннее max_len. Пустой текст - пустая строка
+pub fn console_packets(text: &str, max_len: usize) -> String
+{
+    let chunk_len = (max_len - 5) / 2; //Без $O и #cs, на каждый байт - две Hex-цифры
+    let mut output = String::new();
+    for chunk in text.as_bytes().chunks(chunk_len)
+    {
+        let otext = format!("O{}", hex_encode(chunk));
+        output.push_str(&format!("${}#{:02x}", otext, checksum(otext.as_bytes())));
+    }
+    output
+}
 
-            'm'=>
-            {
-                //Чтение памяти
-                //$m<addr>,<len>
-                let comma_pos = self.data.unwrap().find(",").unwrap(); //Позиция знака ',' для определения адреса
-                let addr = usize::from_str_radix(&self.data.unwrap()[1..comma_pos], 16).unwrap();
-                let bytes_len = usize::from_str_radix(&self.data.unwrap()[comma_pos+1..], 16).unwrap();
-                println!("GDB-Server : Получена команда 'm'. Адрес = 0x{:x}. Количество байт для чтения = {}", addr, bytes_len);
-                //...
-                self.responce_add_usd_cs("33221100");
-                self.need_responce = Some(true);
-            },
 
-            'X'=>
-            {
-                //Запись в память
-                //$X<addr>,<len>:<bytes>
-                //Так как бинарные данные могут содержать не только валидные utf8-символы, то self.data == from_utf8(...).unwrap() использовать нельзя
-                //self.data.unwrap().find(",").unwrap() не работает, поэтому надо определять позиции символов не в self.data: Option<&'a str>, а в исходном input_buf: &[u8]
-                let x_pos = input_buf.iter().position(|&x| x == 0x58).unwrap(); //0x58 == 'X' //Позиция знака 'X' для выделения поля адреса
-                let comma_pos = input_buf.iter().position(|&x| x == 0x2c).unwrap(); //0x2c == ',' //Позиция знака ',' для выделения поля адреса
-                let colon_pos = input_buf.iter().position(|&x| x == 0x3a).unwrap(); //0x3a == ':' //Позиция знака ':' для выделения поля количества байт
-
-                let x_cmd = str::from_utf8(&input_buf[0..colon_pos]).unwrap(); //"$X<addr>,<len>". То есть начиная с начала input_buf (а не с 'X') и не включая ':'
-
-                let mem_addr = Some( usize::from_str_radix(&x_cmd[x_pos+1..comma_pos], 16).unwrap() );
-                let mem_len = Some( usize::from_str_radix(&x_cmd[comma_pos+1..], 16).unwrap() ); //Количество байт для записи
-                let mut ibuf_start_mem: Option<usize> = None;
-
-                println!("GDB-Server : Получена команда 'X'. Адрес = 0x{:x}. Количество байт для записи = {}.", mem_addr.unwrap(), mem_len.unwrap());
-                if mem_len.unwrap() == 0
-                {//Пробный пустой пакет "X<addr>,0:"
-                    ibuf_start_mem = None;
-                }
-                else
-                {
-                    ibuf_start_mem = Some(colon_pos+1); //bytes = &input_buf[colon_pos+1 .. colon_pos+1 + bytes_len];
-                }
-                self.responce("$OK#9a"); //Признак может быть изменен на $E01 при записи m.state.mem.set_u8(...)
-                self.need_responce = Some(true);
-            },
+///Команды с бинарными данными, обработчики которых берут данные из input_buf, а не из RspPacket.data (кроме 'X')
+pub const BINARY_PACKETS: &[&str] = &["vFile:pwrite:", "qSearch:memory:"];
 
-            'z'=>
-            {
-                //Снятие matchpoint
-                //$z<type>,<addr>,<kind>
-                let addr_pos = 3; //Позиция addr = Позиция первой ',' +1
-                let kind_pos = self.data.unwrap()[addr_pos..].find(",").unwrap() +1; //Позиция kind = Позиция второй ',' относительно addr_pos +1
-                let addr = usize::from_str_radix(&self.data.unwrap()[addr_pos..addr_pos+kind_pos-1], 16).unwrap();
-                let kind = usize::from_str_radix(&self.data.unwrap()[addr_pos+kind_pos..], 16).unwrap();
-                println!("GDB-Server : Получена команда 'z'. addr = 0x{:x}. kind = {}", addr, kind);
-
-                match &self.data.unwrap()[1..2] //type
-                {
-                    "0"=>
-                    {//software breakpoint
-                        println!("GDB-Server : Получена команда 'z0'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "1"=>
-                    {//hardware breakpoint
-                        println!("GDB-Server : Получена команда 'z1'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "2"=>
-                    {//write watchpoint
-                        println!("GDB-Server : Получена команда 'z2'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "3"=>
-                    {//read watchpoint
-                        println!("GDB-Server : Получена команда 'z3'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "4"=>
-                    {//access watch point
-                        println!("GDB-Server : Получена команда 'z4'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    _=>
-                    {
-                        println!("GDB-Server : Unknown z-type: \'{}\'!", &self.data.unwrap()[1..2]);
-                        self.responce("+$#00");
-                        self.need_responce = Some(true);
-                    },
-                }//match z-type
-            },
 
-            'Z'=>
-            {
-                //Установка matchpoint
-                //$Z<type>,<addr>,<kind>
-                let addr_pos = 3; //Позиция addr = Позиция первой ',' +1
-                let kind_pos = self.data.unwrap()[addr_pos..].find(",").unwrap() +1; //Позиция kind = Позиция второй ',' относительно addr_pos +1
-                let addr = usize::from_str_radix(&self.data.unwrap()[addr_pos..addr_pos+kind_pos-1], 16).unwrap();
-                let kind = usize::from_str_radix(&self.data.unwrap()[addr_pos+kind_pos..], 16).unwrap(); //Если будут опциональные параметры (...[;cond_list...][;cmds:persist,cmd_list...]), то так работать не будет. kind надо будет выделять не до конца, а до первой ';'
-                println!("GDB-Server : Получена команда 'Z'. addr = 0x{:x}. kind = {}", addr, kind);
-
-                match &self.data.unwrap()[1..2] //type
-                {
-                    "0"=>
-                    {//software breakpoint
-                        println!("GDB-Server : Получена команда 'Z0'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "1"=>
-                    {//hardware breakpoint
-                        println!("GDB-Server : Получена команда 'Z1'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "2"=>
-                    {//write watchpoint
-                        println!("GDB-Server : Получена команда 'Z2'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "3"=>
-                    {//read watchpoint
-                        println!("GDB-Server : Получена команда 'Z3'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    "4"=>
-                    {//access watch point
-                        println!("GDB-Server : Получена команда 'Z4'");
-                        //...
-                        self.responce("$OK#9a");
-                        self.need_responce = Some(true);
-                    },
-                    _=>
-                    {
-                        println!("GDB-Server : Unknown Z-type: \'{}\'!", &self.data.unwrap()[1..2]);
-                        self.responce("+$#00");
-                        self.need_responce = Some(true);
-                    },
-                }//match Z-type
-            },
+///Начало данных бинарного пакета до первого байта, не являющегося utf-символом. None - данные валидная utf-строка
+///или команда не из BINARY_PACKETS: остальные обработчики разбирают RspPacket.data и получат пустой ответ
+fn binary_key(data: &[u8]) -> Option<&str>
+{
+    let valid_len = str::from_utf8(data).err()?.valid_up_to();
+    str::from_utf8(&data[..valid_len]).ok().filter(|key| BINARY_PACKETS.iter().any(|packet| key.starts_with(packet)))
+}
 
-            'q'=>
-            {
-                //Пакеты q-запросов не очень большие, так что можно искать contains() по всему пакету (не только в начале)
-                if self.data.unwrap().contains("qSupported")
-                {
-                    println!("GDB-Server : Получена команда 'qSupported'");
-                    //'PacketSize=xx' обязательно.
-                    //'QStartNoAckMode+' обязательно.
-                    self.responce_add_usd_cs( &format!("PacketSize={:x};QStartNoAckMode+;vContSupported+", PACKET_SIZE) );
-                    //Добавить '+' перед уже сформированным RSP-ответом на qSupport (для работы без "set debug remote 1"). До включения no-acknowledgment режима
-                    let tmp = self.responce.clone();
-                    self.responce( &format!("+{}", tmp.unwrap()) );
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qfThreadInfo")
-                {
-                    println!("GDB-Server : Получена команда 'qfThreadInfo'");
-                    //'l' - Конец списка потоков
-                    self.responce_add_usd_cs("l");
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qC")
-                {
-                    println!("GDB-Server : Получена команда 'qC'");
-                    //Нулевой thread
-                    self.responce_add_usd_cs("QC0");
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qAttached")
-                {
-                    println!("GDB-Server : Получена команда 'qAttached'");
-                    //Запрос: GDB-server подключается к существующему процессу или создает новый процесс?
-                    //Команда связана с остановкой (и перезапуском) цели
-                    self.responce_add_usd_cs("0");//0: по команде (gdb) quit GDB пришлет 'vKill'. 1: по команде (gdb) quit GDB пришлет 'D'(Detach).
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qSymbol")
-                {
-                    println!("GDB-Server : Получена команда 'qSymbol'");
-                    //Информация о символах не нужна
-                    self.responce("$OK#9a");
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qOffsets")
-                {
-                    println!("GDB-Server : Получена команда 'qOffsets'");
-                    //Смещения секции при загрузке прошивки через GDB
-                    self.responce_add_usd_cs("Text=0;Data=0;Bss=0");
-                    self.need_responce = Some(true);
-                }
-                else if self.data.unwrap().contains("qRcmd")
-                {
-                    //Консольная команда 'monitor command'
-                    //$qRcmd,command
-                    //$Otext можно использовать только с Stop Reply Packet и с qRcmd !
-                    //При выводе output_text по команде $qRcmd: После $Otext обязательно должен быть $OK
-                    let command = RspPacket::extract_monitor_cmd(&self.data.unwrap()[6..]); //Поле 'command' находится после ','
-                    println!("GDB-Server : Получена команда 'qRcmd'. command = \'{}\'", command);
-                    match &command[..]
-                    {
-                        "reset init"=>
-                        {
-                            //...
-                            self.text_add_usd_o_cs(" GDB-Server message : 'reset init' monitor command.\n + Any text message.\n");
-                            println!("GDB-Server : 'reset init' monitor command");
-                        },
-                        "reset halt"=>
-                        {
-                            //...
-                            self.text_add_usd_o_cs(" GDB-Server message : 'reset halt' monitor command.\n + Any text message.\n");
-                            println!("GDB-Server : 'reset halt' monitor command");
-                        },
-                        _=>
-                        {
-                            self.text_add_usd_o_cs( &(" GDB-Server message : Unknown monitor command \'".to_string() + &command + "\'!\n") );
-                            println!("GDB-Server : Unknown monitor command \'{}\'!", command);
-                        },
-                    }//match command
-                    self.responce("$OK#9a");
-                    self.need_responce = Some(true);
-                }
-                else
-                {
-                    println!("GDB-Server : Unknown command: q-запрос \'{}\'!", &self.data.unwrap());
-                    //Неподдерживаемая команда (q-запрос)
-                    self.responce("+$#00");
-                    self.need_responce = Some(true);
-                }
-            },
 
-            'Q'=>
-            {
-                if self.data.unwrap().contains("QStartNoAckMode")
-                {
-                    println!("GDB-Server : Получена команда 'QStartNoAckMode'");
-                    //Дальше будем работать без подтверждений +/- (no-acknowledgment-режим)
-                    self.responce("+$OK#9a"); //'+' перед RSP-ответом на QStartNoAckMode (для работы без "set debug remote 1")
-                    self.need_responce = Some(true);
-                }
-                else
-                {
-                    println!("GDB-Server : Unknown command: Q-запрос \'{}\'!", &self.data.unwrap());
-                    //Неподдерживаемая команда (Q-запрос)
-                    self.responce("+$#00");
-                    self.need_responce = Some(true);
-                }
-            },
+///Адрес GDB-сервера по умолчанию
+pub const DEFAULT_ADDR: &str = "127.0.0.1:9999";
 
-            'v'=>
-            {
-                if self.data.unwrap().contains("vCont")
-                {
-                    match &self.data.unwrap()[0..6]
-                    {
-                        "vCont?"=> //if self.data.unwrap().contains("vCont?")
-                        {//Запрос поддерживаемых vCont-action
-                            println!("GDB-Server : Получена команда 'vCont?'");
-                            self.responce_add_usd_cs("vCont;c;C;s;S"); //GDB doesn't accept c without C and s without S
-                            self.need_responce = Some(true);
-                        }
-                        "vCont;"=>
-                        {//Команда к действию (vCont-action)
-                            println!("GDB-Server : Получена команда 'vCont;'");
-                            //Наверно для работы в единственном потоке можно ориентироваться на первое vCont-action ';s' или ';c'
-                            match &self.data.unwrap()[5..7]
-                            {
-                                ";c"=>
-                                {//continue action
-                                    println!("GDB-Server : vCont, c-action");
-                                    
-                                    run_sim(&cancel_flag).unwrap(); //Run simulation
-                                    if cancel_flag.load(Ordering::SeqCst)
-                                    {//Было прерывание исполнения по ^C
-                                        self.text_add_usd_o_cs(" GDB-Server message : Interrupted execution by ^C.\n");
-                                        self.responce("$T02#b6"); //Stop-reply packet: T02 = SIGINT
-                                    }
-                                    else
-                                    {
-                                        //Перед Stop Reply Packet ещё можно ответить $Otext. $Otext можно использовать только с Stop Reply Packet и с qRcmd !
-                                        self.text_add_usd_o_cs(" GDB-Server message : Halted due to breakpoint. (vCont, c-action)\n + Any text message.\n");
-                                        self.responce("$T05#b9"); //Stop-reply packet
-                                    }
-                                    cancel_flag.store(false, Ordering::SeqCst); //Сбросить признак прерывания по ^C
-
-                                    self.need_responce = Some(true);
-                                },
-                                ";s"=>
-                                {//step action
-                                    println!("GDB-Server : vCont, s-action");
-                                    //...
-                                    self.text_add_usd_o_cs(" GDB-Server message : Halted due to step. (vCont, s-action)\n + Any text message.\n");
-                                    self.responce("$T05#b9"); //Stop-reply packet
-                                    self.need_responce = Some(true);
-                                },
-                                _=>
-                                {
-                                    println!("GDB-Server : Unknown vCont action: \'{}\'!", &self.data.unwrap()[5..7]);
-                                    self.responce("+$#00");
-                                    self.need_responce = Some(true);
-                                },
-                            }//match vCont-action
-                        },
-                        _=>
-                        {
-                            println!("GDB-Server : Unknown vCont command \'{}\'!", &self.data.unwrap());
-                            self.responce("+$#00");
-                            self.need_responce = Some(true);
-                        },
-                    }//match vCont
-                }
-                else if self.data.unwrap().contains("vKill")
-                {
-                    println!("GDB-Server : Получена команда 'vKill'");
-                    self.responce("$OK#9a");
-                    self.need_responce = Some(true);
-                    self.kill_flag = Some(true);
-                }
-                else
-                {
-                    println!("GDB-Server : Unknown command: v-запрос \'{}\'!", &self.data.unwrap());
-                    //Неподдерживаемая команда (v-запрос)
-                    //Здесь же обрабатывается имитация неподдерживаемой команды: $vMustReplyEmpty#3a
-                    self.responce("+$#00");
-                    self.need_responce = Some(true);
-                }
-            },
 
-            _=>
-            {
-                println!("GDB-Server : Unknown command \'{}\'!", self.first_cmd_symbol.unwrap());
-                //Неподдерживаемые команды. Ответ от GDB-сервера должен быть: +$#00
-                self.responce("+$#00");
-                self.need_responce = Some(true);
-            },
-        }//match
-    }
-}//impl RspPacket
+///GDB-Сервер с набором обработчиков команд по умолчанию
+pub fn gdb_server(target: &mut dyn Target)
+{
+    gdb_server_with_registry(&ServerConfig::default(), &CmdRegistry::with_defaults(), target);
+}
 
 
-///GDB-Сервер
-pub fn gdb_server()
+///GDB-Сервер с заданными настройками и реестром обработчиков команд
+///Обслуживает подключения GDB-клиентов по одному до 'vKill'. После отключения GDB ждет следующего: цель (и persist-команды breakpoints) сохраняется
+pub fn gdb_server_with_registry(config: &ServerConfig, registry: &CmdRegistry, target: &mut dyn Target)
 {
-    let addr = "127.0.0.1:9999";
-    let listener = TcpListener::bind(addr).unwrap();
-    println!("Server listening at {}", addr);
-    let mut input_buf = [0x7Eu8; BUF_SIZE]; //Инициализация буфера символом '~'
-    let mut input_len = 0; //usize
+    let listener = TcpListener::bind(&config.addr[..]).unwrap();
+    println!("Server listening at {}", config.addr);
 
     for stream in listener.incoming() //stream типа TcpStream
     {
-        let mut stream = stream.unwrap();
-
-        //worker **********************************************************************
-            //Ждать приход ^C безусловно в отдельном потоке worker
-            let cancel_flag = Arc::new(AtomicBool::new(false)); //Потокобезопасный указатель типа Arc<AtomicBool> для основного потока
-            let worker_cancel_flag = cancel_flag.clone(); //Указатель для потока worker (указывает на тоже самое значение AtomicBool)
-            
-            let mut ctrlc_stream = stream.try_clone().expect("stream clone failed");
-
-            let _worker_handle = spawn(move ||
-            {//Замыкание. Ожидание прихода ^C
-                loop
+        let stream = match stream
+        {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if serve_connection(stream, config, registry, target)
+        {
+            break;
+        }
+        println!("GDB disconnected, waiting for the next connection at {}\n", config.addr);
+    }
+    drop(listener);
+    println!("Connection was killed!\n");
+}
+
+
+///Обслуживание одного подключения GDB-клиента: прием RSP-пакетов и ответы на них до 'vKill' или до отключения клиента
+///Возвращает true, если подключение завершено по vKill
+pub fn serve_connection<S: Transport>(mut stream: S, config: &ServerConfig, registry: &CmdRegistry, target: &mut dyn Target) -> bool
+{
+    let buf_size = config.packet_size + BUF_RESERVE;
+    let mut read_buf = vec![0x7Eu8; buf_size]; //Инициализация буфера символом '~'
+    let mut input_buf: Vec<u8> = Vec::with_capacity(buf_size); //Принятые, но еще не обработанные данные. Растет, если пакет пришел по частям
+    let mut session = Session::with_packet_size(config.packet_size); //Состояние подключения
+    session.host_io = config.make_host_io().unwrap_or_else(|e|
+    {
+        println!("  {}\n", e);
+        None
+    }); //Файлы хоста для vFile-пакетов (--sandbox). Открытые файлы закрываются вместе с подключением
+    let transcript = config.record.as_ref().and_then(|path| open_shared(path)); //Запись обмена в транскрипт (--record)
+    let mut faults = config.make_faults().unwrap_or_else(|e|
+    {
+        println!("  {}\n", e);
+        FaultInjector::default()
+    }); //Внесение неисправностей в ответы (--faults)
+    let mut last_packet: Option<String> = None; //Последний отправленный пакет (без неисправностей) - для повтора на '-'
+    let mut killed = false; //Подключение завершено по vKill
+
+    //Вывод команд breakpoints (dprintf) отправляется O-пакетами во время исполнения, а не вместе со Stop Reply
+    let console_stream = Mutex::new(stream.try_clone_transport().expect("stream clone failed"));
+    let console_transcript = transcript.clone();
+    let console_len = config.packet_size.max(PACKET_SIZE);
+    target.set_console(Some(Arc::new(move |text: &str|
+    {
+        let packets = console_packets(text, console_len);
+        if !packets.is_empty() && console_stream.lock().unwrap().write_all(packets.as_bytes()).is_ok()
+        {
+            record(&console_transcript, Direction::Out, "O", packets.as_bytes());
+        }
+    })));
+
+    //worker **********************************************************************
+        //Ждать приход ^C безусловно в отдельном потоке worker
+        let cancel_flag = Arc::new(AtomicBool::new(false)); //Потокобезопасный указатель типа Arc<AtomicBool> для основного потока
+        let worker_cancel_flag = cancel_flag.clone(); //Указатель для потока worker (указывает на тоже самое значение AtomicBool)
+
+        let mut ctrlc_stream = stream.try_clone_transport().expect("stream clone failed");
+        let ctrlc_transcript = transcript.clone();
+        let ctrlc_explain = config.explain;
+
+        let _worker_handle = spawn(move ||
+        {//Замыкание. Ожидание прихода ^C
+            let mut ctrlc_buf = vec![0u8; buf_size];
+            loop
+            {
+                let len = match ctrlc_stream.peek(&mut ctrlc_buf) //Принять данные без освобождения очереди чтения
+                    //То есть если приходит пакет не с ^C, то реальное чтение произойдет на следующей итерации основного цикла loop
+                    //А если приходит ^C, то выполняется ctrlc_stream.read(&mut ctrlc_buf) и очередь чтения освобождается
                 {
-                    let len = ctrlc_stream.peek(&mut input_buf).expect("peek failed"); //Принять данные без освобождения очереди чтения
-                        //То есть если приходит пакет не с ^C, то реальное чтение произойдет на следующей итерации основного цикла loop
-                        //А если приходит ^C, то выполняется ctrlc_stream.read(&mut ctrlc_buf) и очередь чтения освобождается
-                    if len == 1 && u8::from(input_buf[0]) == 0x03 //Первый символ == ^C == 0x03 ?
-                    {//Принят ^C
-                        worker_cancel_flag.store(true, Ordering::SeqCst);
-                        ctrlc_stream.read(&mut input_buf).expect("^C read failed"); //Освободить очередь чтения
-                        println!("  ^C\n");
+                    Err(ref e) if is_timeout(e) => continue, //Таймаут чтения TcpStream общий с основным потоком (non-stop)
+                    Ok(0) | Err(_) => break, //Соединение закрыто
+                    Ok(len) => len,
+                };
+                let acks = ctrlc_buf[..len].iter().take_while(|&&c| c == b'+').count(); //Подтверждения O-пакетов, отправленных во время исполнения
+                if len == acks + 1 && ctrlc_buf[acks] == 0x03 //Первый символ после '+' == ^C == 0x03 ?
+                {//Принят ^C
+                    worker_cancel_flag.store(true, Ordering::SeqCst);
+                    ctrlc_stream.read_exact(&mut ctrlc_buf[..len]).expect("^C read failed"); //Освободить очередь чтения
+                    record(&ctrlc_transcript, Direction::In, "^C", &ctrlc_buf[acks..len]);
+                    log_println!("  ^C\n");
+                    if ctrlc_explain
+                    {
+                        println!("{}\n", explain_exchange(&ctrlc_buf[acks..len], None, None));
                     }
-                }//Поток будет снят при выходе из main
-            });
-        //**********************************************************************
+                }
+            }
+        });
+    //**********************************************************************
 
-        loop
+    'connection: loop
+    {
+        //Дочитать данные, пока в input_buf не окажется целый пакет (или одиночный символ)
+        let input_len = loop
         {
-            input_len = stream.read(&mut input_buf).unwrap();
-            let mut rsp_pkt = RspPacket::new(&input_buf, input_len);
+            if let Some(len) = frame_len(&input_buf)
+            {
+                break len;
+            }
+            //Non-stop: пока потоки исполняются, чтение ждет недолго, а между чтениями цель исполняется дальше
+            let running = session.non_stop && target.poll_threads();
+            if session.non_stop && send_stop_notification(&mut stream, &mut session, target, &transcript).is_err()
+            {
+                break 'connection; //Клиент отключился
+            }
+            stream.set_poll_timeout(if running {Some(POLL_INTERVAL)} else {None});
+            match stream.read(&mut read_buf)
+            {
+                Err(ref e) if is_timeout(e) => continue,
+                Ok(0) | Err(_) =>
+                {//Клиент отключился
+                    log_println!("GDB-Server : Соединение закрыто клиентом");
+                    break 'connection;
+                },
+                Ok(len) => input_buf.extend_from_slice(&read_buf[..len]),
+            }
+            if input_buf.len() > buf_size
+            {//Пакет длиннее PacketSize (или '$' без '#'): GDB такие не отправляет, данные отбрасываются
+                log_println!("GDB-Server : Отброшено {} байт без конца пакета", input_buf.len());
+                input_buf.clear();
+            }
+        };
+        let frame: Vec<u8> = input_buf.drain(..input_len).collect();
+        let cmd = inbound_cmd(&frame);
+        record(&transcript, Direction::In, &cmd, &frame);
+        let mut rsp_pkt = RspPacket::with_packet_size(&frame, input_len, session.packet_size);
+        let fault_actions = if faults.is_empty() || rsp_pkt.only_symb != Some(false) {Vec::new()} else {faults.next(&cmd)};
+        for action in &fault_actions
+        {
+            log_println!("GDB-Server : Fault {} for \'{}\'", action, cmd);
+        }
 
-            if rsp_pkt.need_responce.unwrap()
-            {//Ответ требуется
-                if rsp_pkt.only_symb.unwrap()
-                {//acknowledgment '+'/'-'
-                    //На любой '+' надо ответить '+'. На '-' надо повторить последнее сообщение
-                    //Наверно при работе по TCP/IP не будет '-' (поэтому ответ на '-' пока не реализован)
-                    rsp_pkt.responce("+");
-                }
-                else
-                {//Пакет
-                    rsp_pkt.match_cmd(&input_buf, &cancel_flag);
+        if rsp_pkt.need_responce.unwrap()
+        {//Ответ требуется
+            if rsp_pkt.only_symb.unwrap()
+            {//acknowledgment '+'/'-'
+                //На любой '+' надо ответить '+'. На '-' надо повторить последнее сообщение
+                //По TCP/IP '-' приходит только на пакет с испорченной контрольной суммой (--faults)
+                match last_packet
+                {
+                    Some(ref packet) if rsp_pkt.last_ack_sign == Some('-') => rsp_pkt.responce(packet),
+                    _ => rsp_pkt.responce("+"),
                 }
             }
-            if !rsp_pkt.need_responce.unwrap()
-            {//Ответ не требуется. Отдельный if (а не else) т.к. изначальный признак need_responce может быть сброшен в зависимости от команды (только в случае, если это пакет)
+            else if let Some(code) = error_code(&fault_actions)
+            {//Неисправность: ошибка вместо обработки команды
+                rsp_pkt.responce_add_usd_cs(&format!("E{:02x}", code));
+            }
+            else
+            {//Пакет
+                rsp_pkt.match_cmd(registry, &frame, &cancel_flag, target, &mut session);
             }
+        }
+        //Если ответ не требуется - ничего не делать. Изначальный признак need_responce может быть сброшен в зависимости от команды (только в случае, если это пакет)
 
 
-                //Технологический вывод ======================================================================:
-                println!("len of src_packet: {}", rsp_pkt.len.unwrap()); //Длина пакета в буфере
-                //println!("Received Buffer: {}", str::from_utf8(&input_buf).unwrap()); //Буфер
-                if input_len > 1
-                { //Пакет
-                    println!("first_cmd_symbol: {}", &rsp_pkt.first_cmd_symbol.unwrap());
-                    if &rsp_pkt.first_cmd_symbol != &Some('X')
-                    {
-                        println!("data: {}", &rsp_pkt.data.unwrap());
-                    }
-                    println!("cs: {}", &rsp_pkt.cs.unwrap());
-                }
-                else if input_len == 1
-                { //acknowledgment, не пакет
-                    println!("only_symb: {}", &rsp_pkt.only_symb.unwrap());
-                    println!("symbol: {:?}", char::from(input_buf[0]));
-                }
-                else //input_len == 0
+            //Технологический вывод ======================================================================:
+            log_println!("len of src_packet: {}", rsp_pkt.len.unwrap()); //Длина пакета в буфере
+            //println!("Received Buffer: {}", str::from_utf8(&input_buf).unwrap()); //Буфер
+            if input_len > 1
+            { //Пакет
+                log_println!("first_cmd_symbol: {}", rsp_pkt.first_cmd_symbol.unwrap_or('?'));
+                if rsp_pkt.first_cmd_symbol != Some('X')
                 {
-                    println!("Получен пакет нулевой длины");
+                    log_println!("data: {}", rsp_pkt.data.unwrap_or("(not utf-8)"));
                 }
-                if rsp_pkt.need_responce.unwrap()
+                log_println!("cs: {}", rsp_pkt.cs.unwrap_or("(not utf-8)"));
+            }
+            else
+            { //acknowledgment, не пакет
+                log_println!("only_symb: {}", &rsp_pkt.only_symb.unwrap());
+                log_println!("symbol: {:?}", char::from(frame[0]));
+            }
+            if rsp_pkt.need_responce.unwrap()
+            {
+                let r = match rsp_pkt.responce //Сделано так, чтобы не было ошибки перемещения
                 {
-                    let r = match rsp_pkt.responce //Сделано так, чтобы не было ошибки перемещения
-                    {
-                        Some(ref v) => v,
-                        None => panic!("RspPacket.responce = None"),
-                    };
-                    println!("GDB-Server responce : {}", &r);
+                    Some(ref v) => v,
+                    None => panic!("RspPacket.responce = None"),
+                };
+                log_println!("GDB-Server responce : {}", &r);
 
-                    if rsp_pkt.output_text.is_some()
-                    {
-                        let r = match rsp_pkt.output_text
-                        {
-                            Some(ref v) => v,
-                            None => panic!("RspPacket.output_text = None"),
-                        };
-                        println!("output_text: {}", &r);
-                    }
+                if let Some(ref r) = rsp_pkt.output_text
+                {
+                    log_println!("output_text: {}", &r);
                 }
-                println!("{}\n", "#".repeat(80)); //Конец технологического вывода для принятого RSP-сообщения
+            }
+            log_println!("{}\n", "#".repeat(80)); //Конец технологического вывода для принятого RSP-сообщения
 
 
-            if rsp_pkt.need_responce.unwrap()
-            {//Ответ требуется
-                if rsp_pkt.output_text.is_some() //output_text обязательно перед responce
-                {//output_text может быть только в ответ на vCont и qRcmd
-                    stream.write(&rsp_pkt.output_text.unwrap().as_bytes()).unwrap();
+        if config.explain
+        {//Пояснение к пакету и ответу (--explain)
+            let responce = if rsp_pkt.need_responce.unwrap() {rsp_pkt.responce.as_deref()} else {None};
+            println!("{}\n", explain_exchange(&frame, rsp_pkt.output_text.as_deref(), responce));
+        }
+
+        if rsp_pkt.need_responce.unwrap()
+        {//Ответ требуется
+            let responce = rsp_pkt.responce.unwrap();
+            let binary = rsp_pkt.responce_binary.is_some();
+            let (delay, messages) = match rsp_pkt.responce_binary
+            {
+                Some(packet) => (0, vec![packet]), //Бинарный ответ (vFile) отправляется без неисправностей
+                None =>
+                {
+                    let (delay, messages) = apply_faults(&fault_actions, &responce);
+                    (delay, messages.into_iter().map(String::into_bytes).collect())
+                },
+            };
+            if delay > 0
+            {
+                sleep(Duration::from_millis(delay));
+            }
+            if let Some(output_text) = rsp_pkt.output_text //output_text обязательно перед responce
+            {//output_text может быть только в ответ на vCont и qRcmd
+                if stream.write_all(output_text.as_bytes()).is_err()
+                {
+                    break 'connection; //Клиент отключился
                 }
-                stream.write(&rsp_pkt.responce.unwrap().as_bytes()).unwrap(); //Ответ в TcpStream. Сделано в конце, чтобы не было ошибки перемещения
+                record(&transcript, Direction::Out, "O", output_text.as_bytes());
             }
-            if rsp_pkt.kill_flag.unwrap()
+            for message in &messages //Ответ (с неисправностями: испорченный, повторенный, пропущенный, с лишним Stop Reply)
             {
-                break;
+                if stream.write_all(message).is_err() //Ответ в TcpStream. Сделано в конце, чтобы не было ошибки перемещения
+                {
+                    log_println!("GDB-Server : Соединение закрыто клиентом");
+                    break 'connection;
+                }
+                record(&transcript, Direction::Out, &cmd, message);
+            }
+            if rsp_pkt.only_symb == Some(false)
+            {//Бинарный ответ в responce искажен заменой символов: на '-' повторять нечего
+                last_packet = if binary {None} else {Some(responce.trim_start_matches('+').to_string())};
             }
-        }//loop
-        break; //kill_flag
+        }
+        if session.non_stop && send_stop_notification(&mut stream, &mut session, target, &transcript).is_err()
+        {//Non-stop: остановы по vCont;t, шагу или vCtrlC сообщаются сразу после ответа
+            break 'connection; //Клиент отключился
+        }
+        if rsp_pkt.kill_flag.unwrap()
+        {
+            target.kill();
+            killed = true;
+            break;
+        }
+    }//loop
+    target.set_console(None);
+    target.disconnected(); //Снять breakpoints этого GDB (кроме persist-команд)
+    stream.shutdown(); //Закрыть соединение и для потока ожидания ^C
+    killed
+}
+
+
+///Non-stop: ожидание пакета GDB, пока потоки исполняются. Между ожиданиями цель исполняет следующую часть инструкций
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+
+///Чтение прервано таймаутом (set_poll_timeout), а не закрытием соединения
+fn is_timeout(e: &io::Error) -> bool
+{
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+
+///Non-stop: остановы потоков сообщаются уведомлением %Stop (без подтверждения '+'), когда GDB выбрал предыдущее через vStopped
+fn send_stop_notification<S: Transport>(stream: &mut S, session: &mut Session, target: &mut dyn Target, transcript: &Option<SharedTranscript>) -> io::Result<()>
+{
+    if let Some(notification) = stop_notification(session, target)
+    {
+        stream.write_all(notification.as_bytes())?;
+        record(transcript, Direction::Out, "%Stop", notification.as_bytes());
     }
-    drop(listener);
-    println!("Connection was killed!\n"); //Можно подключаться снова
+    Ok(())
 }
 
 
@@ -771,21 +682,22 @@ mod test_rsp_packet
     #[test]
     fn test_responce_add_usd_cs()
     {
-        ///Создание экземпляра
+        //Создание экземпляра
         let mut input_buf = [0x7Eu8; BUF_SIZE];
         input_buf[0] = b'+';
         let input_len = 1;
         let mut pkt = RspPacket::new(&input_buf, input_len);
 
-        ///Передается строковый литерал
+        //Передается строковый литерал
         pkt.responce_add_usd_cs("literal");
         assert_eq!(Some("$literal#ed".to_string()), pkt.responce, "Передается строковый литерал");
 
-        ///Передается ссылка на строку &String (строковый срез)
-        pkt.responce_add_usd_cs(&"string".to_string());
+        //Передается ссылка на строку &String (строковый срез)
+        let string = "string".to_string();
+        pkt.responce_add_usd_cs(&string);
         assert_eq!(Some("$string#97".to_string()), pkt.responce, "Передается ссылка на строку &String");
 
-        ///Еще литерал (OK : стандартный ответ)
+        //Еще литерал (OK : стандартный ответ)
         pkt.responce_add_usd_cs("OK");
         assert_eq!(Some("$OK#9a".to_string()), pkt.responce);
 
@@ -815,13 +727,13 @@ mod test_rsp_packet
     #[should_panic] //Тут желательно указать [should_panic(expected = "вид_паники")], но какое у него название?
     fn test_panic_responce_add_usd_cs()
     {
-        ///Создание экземпляра
+        //Создание экземпляра
         let mut input_buf = [0x7Eu8; BUF_SIZE];
         input_buf[0] = b'+';
         let input_len = 1;
         let mut pkt = RspPacket::new(&input_buf, input_len);
 
-        ///Строка размера PACKET_SIZE
+        //Строка размера PACKET_SIZE
         let mut s = String::new();
         loop
         {
@@ -839,25 +751,26 @@ mod test_rsp_packet
     #[test]
     fn test_responce()
     {
-        ///Создание экземпляра
+        //Создание экземпляра
         let mut input_buf = [0x7Eu8; BUF_SIZE];
         input_buf[0] = b'+';
         let input_len = 1;
         let mut pkt = RspPacket::new(&input_buf, input_len);
 
-        ///Передается строковый литерал
+        //Передается строковый литерал
         pkt.responce("literal");
         assert_eq!(Some("literal".to_string()), pkt.responce, "Передается строковый литерал");
 
-        ///Передается ссылка на строку &String (строковый срез)
-        pkt.responce(&"string".to_string());
+        //Передается ссылка на строку &String (строковый срез)
+        let string = "string".to_string();
+        pkt.responce(&string);
         assert_eq!(Some("string".to_string()), pkt.responce, "Передается ссылка на строку &String");
 
-        ///Еще литерал (+ : стандартный Acknowledgment)
+        //Еще литерал (+ : стандартный Acknowledgment)
         pkt.responce("+");
         assert_eq!(Some("+".to_string()), pkt.responce);
 
-        ///Еще литерал (OK : стандартный ответ)
+        //Еще литерал (OK : стандартный ответ)
         pkt.responce("$OK#9a");
         assert_eq!(Some("$OK#9a".to_string()), pkt.responce);
 
@@ -879,13 +792,13 @@ mod test_rsp_packet
     #[should_panic] //Тут желательно указать [should_panic(expected = "вид_паники")], но какое у него название?
     fn test_panic_responce()
     {
-        ///Создание экземпляра
+        //Создание экземпляра
         let mut input_buf = [0x7Eu8; BUF_SIZE];
         input_buf[0] = b'+';
         let input_len = 1;
         let mut pkt = RspPacket::new(&input_buf, input_len);
 
-        ///Строка размера PACKET_SIZE
+        //Строка размера PACKET_SIZE
         let mut s = String::new();
         loop
         {
@@ -898,4 +811,46 @@ mod test_rsp_packet
         s.push('p'); //s.len() > PACKET_SIZE
         pkt.responce(&s); //Паника
     }
+
+
+    #[test]
+    fn test_cmd_key()
+    {
+        //Бинарные данные: ключ - начало до бинарной части, только для команд, обработчики которых читают input_buf
+        let key = |frame: &[u8]| RspPacket::new(frame, frame.len()).cmd_key().to_string();
+        assert_eq!("X", key(b"$X0,1:\xff#00"));
+        assert_eq!("vFile:pwrite:3,0,", key(b"$vFile:pwrite:3,0,\xff#00"));
+        assert_eq!("qSearch:memory:0;10;", key(b"$qSearch:memory:0;10;\xff#00"));
+        //Остальные команды с не-utf данными - пустой ключ (пустой ответ), а не обработчик с data == None
+        assert_eq!("", key(b"$m\xff#00"));
+        assert_eq!("", key(b"$vCont;\xff#00"));
+        assert_eq!("m0,4", key(b"$m0,4#fd"));
+    }
+
+
+    #[test]
+    fn test_console_add_usd_o_cs()
+    {
+        let mut input_buf = [0x7Eu8; BUF_SIZE];
+        input_buf[0] = b'+';
+        let mut pkt = RspPacket::new(&input_buf, 1);
+
+        //Пустой текст - без O-пакетов
+        pkt.console_add_usd_o_cs("");
+        assert_eq!(None, pkt.output_text);
+
+        //Пакеты добавляются друг за другом
+        pkt.console_add_usd_o_cs("a");
+        pkt.console_add_usd_o_cs("b");
+        assert_eq!(Some("$O61#b6$O62#b7".to_string()), pkt.output_text);
+
+        //Длинный текст делится на пакеты не длиннее PACKET_SIZE
+        let mut pkt = RspPacket::new(&input_buf, 1);
+        pkt.console_add_usd_o_cs(&"x".repeat(PACKET_SIZE));
+        let output = pkt.output_text.unwrap();
+        let packets: Vec<&str> = output.split('$').skip(1).collect();
+        assert_eq!(3, packets.len());
+        assert!(packets.iter().all(|packet| packet.len() < PACKET_SIZE));
+        assert_eq!(2 * PACKET_SIZE + 3 * 4, packets.iter().map(|packet| packet.len()).sum::<usize>());
+    }
 }
@@ -0,0 +1,359 @@
+use std::fs::{self, File, OpenOptions, Metadata};
+use std::io::{self, Read, Write, Seek, SeekFrom, ErrorKind};
+use std::path::{Path, PathBuf};
+
+
+///Флаги open в File-I/O и vFile:open (не зависят от хоста GDB)
+pub const O_RDONLY: u64 = 0x0;
+pub const O_WRONLY: u64 = 0x1;
+pub const O_RDWR: u64 = 0x2;
+pub const O_APPEND: u64 = 0x8;
+pub const O_CREAT: u64 = 0x200;
+pub const O_TRUNC: u64 = 0x400;
+pub const O_EXCL: u64 = 0x800;
+
+///Значения errno в File-I/O
+pub const ENOENT: u64 = 2;
+pub const EBADF: u64 = 9;
+pub const EACCES: u64 = 13;
+pub const EEXIST: u64 = 17;
+pub const ENOTDIR: u64 = 20;
+pub const EISDIR: u64 = 21;
+pub const EINVAL: u64 = 22;
+pub const ENOSPC: u64 = 28;
+pub const EROFS: u64 = 30;
+pub const ENAMETOOLONG: u64 = 91;
+pub const EUNKNOWN: u64 = 9999;
+
+///Размер struct stat в File-I/O (vFile:fstat)
+pub const STAT_SIZE: usize = 64;
+
+
+///Файлы хоста для vFile-пакетов (remote get/put/delete, target:-пути): только внутри каталога-песочницы
+///Пути GDB отсчитываются от песочницы: "/bin/app" - это <root>/bin/app. Выйти из нее нельзя ни через "..", ни через символические ссылки
+#[derive(Debug)]
+pub struct HostIo
+{
+    root: PathBuf,                                  // Каталог-песочница (канонический путь)
+    files: Vec<Option<File>>,                       // Открытые файлы: fd - индекс. Места закрытых файлов используются снова
+}
+
+
+impl HostIo
+{
+    ///Песочница в существующем каталоге root
+    pub fn new(root: &str) -> Result<HostIo, String>
+    {
+        let root = fs::canonicalize(root).map_err(|e| format!("Каталог песочницы \'{}\': {}", root, e))?;
+        if !root.is_dir()
+        {
+            return Err(format!("Песочница \'{}\' - не каталог", root.display()));
+        }
+        Ok(HostIo{ root, files: Vec::new() })
+    }
+
+
+    ///Путь хоста для пути GDB. follow - разрешить символическую ссылку в последнем компоненте (open)
+    ///".." выше песочницы остается в ней (как в chroot), ссылка за пределы песочницы - EACCES
+    ///Висячая ссылка при follow - тоже EACCES: open с O_CREAT создал бы файл там, куда она ведет, а проверить это место нельзя
+    pub fn resolve(&self, path: &str, follow: bool) -> Result<PathBuf, u64>
+    {
+        let mut resolved = self.root.clone();
+        for part in path.split('/')
+        {
+            match part
+            {
+                "" | "." => (),
+                ".." => if resolved != self.root {resolved.pop();},
+                part => resolved.push(part),
+            }
+        }
+        let real = match (follow, resolved.file_name())
+        {
+            (_, None) => self.root.clone(),
+            (true, _) if resolved.exists() => fs::canonicalize(&resolved).map_err(errno)?,
+            (true, _) if fs::symlink_metadata(&resolved).is_ok() => return Err(EACCES),
+            (_, Some(name)) =>
+            {//Сам файл может не существовать (open с O_CREAT) или быть ссылкой (unlink, readlink): проверяется каталог
+                let parent = resolved.parent().unwrap_or(&self.root);
+                fs::canonicalize(parent).map_err(errno)?.join(name)
+            },
+        };
+        if real.starts_with(&self.root) {Ok(real)} else {Err(EACCES)}
+    }
+
+
+    ///vFile:open: открыть файл. Возвращает fd
+    ///От mode остаются только права доступа (без setuid/setgid/sticky). Каталог (и корень песочницы) не открывается: EISDIR
+    pub fn open(&mut self, path: &str, flags: u64, mode: u64) -> Result<u64, u64>
+    {
+        let path = self.resolve(path, true)?;
+        let mut options = OpenOptions::new();
+        match flags & 0x3
+        {
+            O_RDONLY => options.read(true),
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => return Err(EINVAL),
+        };
+        options.append(flags & O_APPEND != 0).truncate(flags & O_TRUNC != 0);
+        if flags & O_CREAT != 0
+        {
+            if flags & O_EXCL != 0 {options.create_new(true)} else {options.create(true)};
+        }
+        set_mode(&mut options, mode & 0o777);
+        let file = options.open(&path).map_err(errno)?;
+        if file.metadata().map_err(errno)?.is_dir()
+        {//Каталог на чтение открывается успешно, но pread/pwrite для него не имеют смысла
+            return Err(EISDIR);
+        }
+        let fd = match self.files.iter().position(Option::is_none)
+        {
+            Some(fd) => fd,
+            None =>
+            {
+                self.files.push(None);
+                self.files.len() - 1
+            },
+        };
+        self.files[fd] = Some(file);
+        Ok(fd as u64)
+    }
+
+
+    ///vFile:close
+    pub fn close(&mut self, fd: u64) -> Result<(), u64>
+    {
+        self.file(fd)?;
+        self.files[fd as usize] = None;
+        Ok(())
+    }
+
+
+    ///vFile:pread: до count байт со смещения offset (меньше - в конце файла)
+    pub fn pread(&mut self, fd: u64, count: usize, offset: u64) -> Result<Vec<u8>, u64>
+    {
+        let file = self.file(fd)?;
+        file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+        let mut data = Vec::with_capacity(count);
+        file.take(count as u64).read_to_end(&mut data).map_err(errno)?;
+        Ok(data)
+    }
+
+
+    ///vFile:pwrite: записать data со смещения offset. Возвращает количество записанных байт
+    pub fn pwrite(&mut self, fd: u64, offset: u64, data: &[u8]) -> Result<usize, u64>
+    {
+        let file = self.file(fd)?;
+        file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+        file.write_all(data).map_err(errno)?;
+        Ok(data.len())
+    }
+
+
+    ///Записать файл целиком (создается или перезаписывается): файл трассировки для QTSave
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), u64>
+    {
+        let fd = self.open(path, O_WRONLY | O_CREAT | O_TRUNC, 0o644)?;
+        let written = self.pwrite(fd, 0, data);
+        self.close(fd)?;
+        written.map(|_| ())
+    }
+
+
+    ///vFile:unlink: удалить файл (ссылка удаляется сама, а не файл, на который она указывает)
+    pub fn unlink(&self, path: &str) -> Result<(), u64>
+    {
+        let path = self.resolve(path, false)?;
+        if path == self.root
+        {
+            return Err(EISDIR);
+        }
+        fs::remove_file(path).map_err(errno)
+    }
+
+
+    ///vFile:readlink: содержимое символической ссылки. Абсолютный путь внутри песочницы - от ее корня
+    pub fn readlink(&self, path: &str) -> Result<Vec<u8>, u64>
+    {
+        let link = fs::read_link(self.resolve(path, false)?).map_err(errno)?;
+        let link = match link.strip_prefix(&self.root)
+        {
+            Ok(inside) => Path::new("/").join(inside),
+            Err(_) => link,
+        };
+        Ok(link.to_string_lossy().into_owned().into_bytes())
+    }
+
+
+    ///vFile:fstat: struct stat File-I/O (big-endian, STAT_SIZE байт)
+    pub fn fstat(&mut self, fd: u64) -> Result<Vec<u8>, u64>
+    {
+        let meta = self.file(fd)?.metadata().map_err(errno)?;
+        let (fields, sizes, times) = stat_fields(&meta);
+        let mut stat = Vec::with_capacity(STAT_SIZE);
+        stat.extend(fields.iter().flat_map(|&f| (f as u32).to_be_bytes())); //st_dev, st_ino, st_mode, st_nlink, st_uid, st_gid, st_rdev
+        stat.extend(sizes.iter().flat_map(|&s| s.to_be_bytes())); //st_size, st_blksize, st_blocks
+        stat.extend(times.iter().flat_map(|&t| (t as u32).to_be_bytes())); //st_atime, st_mtime, st_ctime
+        Ok(stat)
+    }
+
+
+    ///Открытый файл. Нет такого fd - EBADF
+    fn file(&mut self, fd: u64) -> Result<&mut File, u64>
+    {
+        self.files.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)
+    }
+}
+
+
+///errno File-I/O для ошибки ввода-вывода хоста
+pub fn errno(error: io::Error) -> u64
+{
+    match error.kind()
+    {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::PermissionDenied => EACCES,
+        ErrorKind::AlreadyExists => EEXIST,
+        ErrorKind::NotADirectory => ENOTDIR,
+        ErrorKind::IsADirectory => EISDIR,
+        ErrorKind::InvalidInput => EINVAL,
+        ErrorKind::InvalidFilename => ENAMETOOLONG,
+        ErrorKind::StorageFull => ENOSPC,
+        ErrorKind::ReadOnlyFilesystem => EROFS,
+        _ => EUNKNOWN,
+    }
+}
+
+
+#[cfg(unix)]
+fn set_mode(options: &mut OpenOptions, mode: u64)
+{
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(mode as u32);
+}
+
+#[cfg(not(unix))]
+fn set_mode(_options: &mut OpenOptions, _mode: u64)
+{
+}
+
+
+///Поля struct stat: 32-битные, размеры (64 бита) и времена
+#[cfg(unix)]
+fn stat_fields(meta: &Metadata) -> ([u64; 7], [u64; 3], [i64; 3])
+{
+    use std::os::unix::fs::MetadataExt;
+    ([meta.dev(), meta.ino(), meta.mode() as u64, meta.nlink(), meta.uid() as u64, meta.gid() as u64, meta.rdev()],
+     [meta.size(), meta.blksize(), meta.blocks()],
+     [meta.atime(), meta.mtime(), meta.ctime()])
+}
+
+#[cfg(not(unix))]
+fn stat_fields(meta: &Metadata) -> ([u64; 7], [u64; 3], [i64; 3])
+{//Без inode и владельцев: тип файла и права по атрибутам
+    use std::time::UNIX_EPOCH;
+    let mode = if meta.is_dir() {0o040755} else if meta.permissions().readonly() {0o100444} else {0o100644};
+    let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs() as i64);
+    ([0, 0, mode, 1, 0, 0, 0], [meta.len(), 512, meta.len().div_ceil(512)], [mtime, mtime, mtime])
+}
+
+
+///Тесты для HostIo ================================================================================
+#[cfg(test)]
+mod test_host_io
+{
+    use super::*;
+    use std::env;
+    use std::process;
+
+    ///Пустой временный каталог для песочницы
+    fn sandbox(name: &str) -> PathBuf
+    {
+        let dir = env::temp_dir().join(format!("host_io_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_files()
+    {
+        let dir = sandbox("files");
+        let mut host = HostIo::new(dir.to_str().unwrap()).unwrap();
+
+        let fd = host.open("/bin/app", O_WRONLY | O_CREAT | O_TRUNC, 0o644).unwrap();
+        assert_eq!(0, fd);
+        assert_eq!(Ok(5), host.pwrite(fd, 0, b"hello"));
+        assert_eq!(Ok(3), host.pwrite(fd, 5, b"!!!"));
+        assert_eq!(Ok(1), host.open("bin/app", O_RDONLY, 0));
+        assert_eq!(Ok(b"lo!!".to_vec()), host.pread(1, 4, 3));
+        assert_eq!(Ok(Vec::new()), host.pread(1, 4, 100));
+        let stat = host.fstat(1).unwrap();
+        assert_eq!(STAT_SIZE, stat.len());
+        assert_eq!(8u64.to_be_bytes(), stat[28..36]);
+        assert_eq!(Err(EEXIST), host.open("/bin/app", O_WRONLY | O_CREAT | O_EXCL, 0o644));
+        assert_eq!(Err(ENOENT), host.open("/missing", O_RDONLY, 0));
+        assert_eq!(Err(EINVAL), host.open("/bin/app", 0x3, 0));
+        assert_eq!(Err(EISDIR), host.open("/bin", O_RDONLY, 0));
+        assert_eq!(Err(EISDIR), host.open("", O_RDONLY, 0));
+        assert_eq!(Err(EISDIR), host.open("/", O_RDONLY, 0));
+
+        //Закрытый fd используется снова
+        assert_eq!(Ok(()), host.close(fd));
+        assert_eq!(Err(EBADF), host.close(fd));
+        assert_eq!(Err(EBADF), host.pread(fd, 1, 0));
+        assert_eq!(Ok(0), host.open("/bin/app", O_RDONLY, 0));
+
+        assert_eq!(Ok(()), host.unlink("/bin/app"));
+        assert_eq!(Err(ENOENT), host.unlink("/bin/app"));
+        assert!(!dir.join("bin/app").exists());
+
+        #[cfg(unix)]
+        {//setuid, setgid и sticky из mode не передаются
+            use std::os::unix::fs::PermissionsExt;
+            let fd = host.open("/bin/suid", O_WRONLY | O_CREAT, 0o7755).unwrap();
+            assert_eq!(Ok(()), host.close(fd));
+            assert_eq!(0, fs::metadata(dir.join("bin/suid")).unwrap().permissions().mode() & 0o7000);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox()
+    {
+        let dir = sandbox("sandbox");
+        let host = HostIo::new(dir.to_str().unwrap()).unwrap();
+        let root = fs::canonicalize(&dir).unwrap();
+        assert_eq!(Ok(root.join("passwd")), host.resolve("/../../passwd", true));
+        assert_eq!(Err(ENOENT), host.resolve("/../../etc/passwd", true));
+        assert_eq!(Ok(root.join("app")), host.resolve("bin/../app", true));
+        assert_eq!(Ok(root.clone()), host.resolve("/", true));
+        assert!(HostIo::new(dir.join("missing").to_str().unwrap()).is_err());
+
+        #[cfg(unix)]
+        {//Ссылки: наружу - EACCES, внутрь - путь от корня песочницы
+            use std::os::unix::fs::symlink;
+            symlink("/etc", dir.join("etc")).unwrap();
+            symlink(root.join("bin"), dir.join("lib")).unwrap();
+            assert_eq!(Err(EACCES), host.resolve("/etc/passwd", true));
+            assert_eq!(Ok(b"/etc".to_vec()), host.readlink("/etc"));
+            assert_eq!(Ok(b"/bin".to_vec()), host.readlink("/lib"));
+            assert_eq!(Ok(root.join("bin")), host.resolve("/lib", true));
+            assert_eq!(Err(EINVAL), host.readlink("/bin"));
+            assert_eq!(Ok(()), host.unlink("/etc"));
+            assert!(fs::metadata("/etc").is_ok());
+
+            //Висячая ссылка наружу: open с O_CREAT не создает файл вне песочницы, unlink удаляет саму ссылку
+            let outside = env::temp_dir().join(format!("host_io_outside_{}", process::id()));
+            let _ = fs::remove_file(&outside);
+            symlink(&outside, dir.join("out")).unwrap();
+            let mut host = host;
+            assert_eq!(Err(EACCES), host.open("/out", O_WRONLY | O_CREAT, 0o644));
+            assert_eq!(Err(EACCES), host.resolve("/out", true));
+            assert!(fs::symlink_metadata(&outside).is_err());
+            assert_eq!(Ok(()), host.unlink("/out"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,398 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use target::{Target, TargetResult, StopReason, MatchpointType, ResumeAction, ConsoleSink, ptid, ptid_parts};
+use trace::TraceExperiment;
+
+
+///Процесс (inferior) цели Inferiors
+pub struct Inferior<T: Target>
+{
+    pub pid: u64,                                   // Номер процесса (с 1)
+    pub target: T,                                  // Цель процесса: своя память, регистры и matchpoints
+    pub attached: bool,                             // GDB подключен к процессу
+}
+
+
+///Цель из нескольких процессов (multiprocess extensions): например, два ядра с разными прошивками
+///Каждый процесс - отдельная цель с потоками. Для GDB поток процесса - pPID.TID, для обработчиков - ptid(pid, tid)
+///Сначала GDB подключен только к первому процессу, к остальным - через vAttach
+pub struct Inferiors<T: Target>
+{
+    pub inferiors: Vec<Inferior<T>>,
+    current: usize,                                 // Индекс процесса текущего потока (Hg)
+}
+
+
+impl<T: Target> Inferiors<T>
+{
+    ///Процессы с номерами 1..=N. Подключен только первый
+    pub fn new(targets: Vec<T>) -> Inferiors<T>
+    {
+        assert!(!targets.is_empty(), "Inferiors without targets");
+        let inferiors = targets.into_iter().enumerate()
+            .map(|(index, target)| Inferior{ pid: index as u64 + 1, target, attached: index == 0 })
+            .collect();
+        Inferiors{ inferiors, current: 0 }
+    }
+
+
+    ///Индекс подключенного процесса
+    fn index(&self, pid: u64) -> Option<usize>
+    {
+        self.inferiors.iter().position(|inferior| inferior.pid == pid && inferior.attached)
+    }
+
+
+    ///Индекс процесса и номер потока внутри процесса
+    fn split(&self, tid: u64) -> Option<(usize, u64)>
+    {
+        let (pid, tid) = ptid_parts(tid);
+        Some((self.index(pid)?, tid))
+    }
+
+
+    ///Цель процесса текущего потока. Если GDB отключился от всех процессов - ошибка
+    fn current(&mut self) -> TargetResult<&mut T>
+    {
+        let inferior = &mut self.inferiors[self.current];
+        if inferior.attached {Ok(&mut inferior.target)} else {Err(0x01)}
+    }
+
+
+    ///Действия над потоками по процессам (номера потоков - внутри процесса), в порядке процессов
+    fn group(&self, actions: &[(u64, ResumeAction)]) -> Vec<(usize, Vec<(u64, ResumeAction)>)>
+    {
+        let mut groups: Vec<(usize, Vec<(u64, ResumeAction)>)> = Vec::new();
+        for &(tid, action) in actions
+        {
+            if let Some((index, tid)) = self.split(tid)
+            {
+                match groups.iter_mut().find(|(i, _)| *i == index)
+                {
+                    Some((_, group)) => group.push((tid, action)),
+                    None => groups.push((index, vec![(tid, action)])),
+                }
+            }
+        }
+        groups.sort_by_key(|&(index, _)| index);
+        groups
+    }
+}
+
+
+impl<T: Target> Target for Inferiors<T>
+{
+    fn read_registers(&mut self) -> TargetResult<Vec<u8>>
+    {
+        self.current()?.read_registers()
+    }
+
+    fn write_registers(&mut self, data: &[u8]) -> TargetResult<()>
+    {
+        self.current()?.write_registers(data)
+    }
+
+    fn read_register(&mut self, reg_num: usize) -> TargetResult<Vec<u8>>
+    {
+        self.current()?.read_register(reg_num)
+    }
+
+    fn write_register(&mut self, reg_num: usize, data: &[u8]) -> TargetResult<()>
+    {
+        self.current()?.write_register(reg_num, data)
+    }
+
+    fn read_memory(&mut self, addr: u64, len: usize) -> TargetResult<Vec<u8>>
+    {
+        self.current()?.read_memory(addr, len)
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> TargetResult<()>
+    {
+        self.current()?.write_memory(addr, data)
+    }
+
+    fn insert_matchpoint(&mut self, mp_type: MatchpointType, addr: u64, kind: usize) -> TargetResult<()>
+    {
+        self.current()?.insert_matchpoint(mp_type, addr, kind)
+    }
+
+    fn remove_matchpoint(&mut self, mp_type: MatchpointType, addr: u64, kind: usize) -> TargetResult<()>
+    {
+        self.current()?.remove_matchpoint(mp_type, addr, kind)
+    }
+
+    fn resume(&mut self, cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        let actions: Vec<(u64, ResumeAction)> = self.threads().into_iter().map(|tid| (tid, ResumeAction::Continue)).collect();
+        self.resume_threads(&actions, cancel_flag)
+    }
+
+    fn step(&mut self) -> StopReason
+    {
+        let tid = self.current_thread();
+        self.resume_threads(&[(tid, ResumeAction::Step)], &Arc::new(AtomicBool::new(false)))
+    }
+
+    fn threads(&self) -> Vec<u64>
+    {
+        self.inferiors.iter().filter(|inferior| inferior.attached)
+            .flat_map(|inferior| inferior.target.threads().into_iter().map(move |tid| ptid(inferior.pid, tid)))
+            .collect()
+    }
+
+    fn current_thread(&self) -> u64
+    {
+        let inferior = &self.inferiors[self.current];
+        ptid(inferior.pid, inferior.target.current_thread())
+    }
+
+    fn select_thread(&mut self, tid: u64) -> TargetResult<()>
+    {
+        let (index, tid) = self.split(tid).ok_or(0x01)?;
+        self.inferiors[index].target.select_thread(tid)?;
+        self.current = index;
+        Ok(())
+    }
+
+    fn thread_alive(&self, tid: u64) -> bool
+    {
+        self.split(tid).is_some_and(|(index, tid)| self.inferiors[index].target.thread_alive(tid))
+    }
+
+    fn thread_extra_info(&self, tid: u64) -> Option<String>
+    {
+        let (index, tid) = self.split(tid)?;
+        let info = self.inferiors[index].target.thread_extra_info(tid)?;
+        Some(format!("process {}, {}", self.inferiors[index].pid, info))
+    }
+
+    ///Все-стоп: процессы с действиями исполняются по очереди (сначала те, где есть шаг), останов - в первом из них
+    ///После прерывания по ^C остальные процессы тоже сразу останавливаются: cancel_flag уже установлен
+    fn resume_threads(&mut self, actions: &[(u64, ResumeAction)], cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        let mut groups = self.group(actions);
+        groups.sort_by_key(|(_, group)| !group.iter().any(|&(_, action)| action == ResumeAction::Step));
+        let mut first = None;
+        for (index, group) in groups
+        {
+            let stop = self.inferiors[index].target.resume_threads(&group, cancel_flag);
+            first = first.or(Some((index, stop)));
+        }
+        match first
+        {
+            Some((index, stop)) =>
+            {
+                self.current = index;
+                stop
+            },
+            None => self.stop_reason(),
+        }
+    }
+
+    fn stop_reason(&self) -> StopReason
+    {
+        self.inferiors[self.current].target.stop_reason()
+    }
+
+    fn thread_stop(&self, tid: u64) -> Option<StopReason>
+    {
+        let (index, tid) = self.split(tid)?;
+        self.inferiors[index].target.thread_stop(tid)
+    }
+
+    fn supports_non_stop(&self) -> bool
+    {
+        self.inferiors.iter().all(|inferior| inferior.target.supports_non_stop())
+    }
+
+    fn supports_conditions(&self) -> bool
+    {
+        self.inferiors.iter().all(|inferior| inferior.target.supports_conditions())
+    }
+
+    fn set_conditions(&mut self, addr: u64, conditions: Vec<Vec<u8>>) -> TargetResult<()>
+    {
+        self.current()?.set_conditions(addr, conditions)
+    }
+
+    fn supports_breakpoint_commands(&self) -> bool
+    {
+        self.inferiors.iter().all(|inferior| inferior.target.supports_breakpoint_commands())
+    }
+
+    fn set_commands(&mut self, addr: u64, commands: Vec<Vec<u8>>, persist: bool) -> TargetResult<()>
+    {
+        self.current()?.set_commands(addr, commands, persist)
+    }
+
+    fn set_console(&mut self, console: Option<ConsoleSink>)
+    {
+        for inferior in self.inferiors.iter_mut()
+        {
+            inferior.target.set_console(console.clone());
+        }
+    }
+
+    fn take_console_output(&mut self) -> String
+    {
+        self.inferiors.iter_mut().map(|inferior| inferior.target.take_console_output()).collect()
+    }
+
+    fn disconnected(&mut self)
+    {
+        for inferior in self.inferiors.iter_mut()
+        {
+            inferior.target.disconnected();
+        }
+    }
+
+    fn start_threads(&mut self, actions: &[(u64, ResumeAction)]) -> TargetResult<()>
+    {
+        if actions.iter().any(|&(tid, _)| self.split(tid).is_none())
+        {
+            return Err(0x01);
+        }
+        for (index, group) in self.group(actions)
+        {
+            self.inferiors[index].target.start_threads(&group)?;
+        }
+        Ok(())
+    }
+
+    fn stop_thread(&mut self, tid: u64, stop: StopReason) -> TargetResult<()>
+    {
+        let (index, tid) = self.split(tid).ok_or(0x01)?;
+        self.inferiors[index].target.stop_thread(tid, stop)
+    }
+
+    fn poll_threads(&mut self) -> bool
+    {
+        let mut running = false;
+        for inferior in self.inferiors.iter_mut()
+        {
+            running |= inferior.target.poll_threads();
+        }
+        running
+    }
+
+    fn take_stops(&mut self) -> Vec<(u64, StopReason)>
+    {
+        let mut stops = Vec::new();
+        for inferior in self.inferiors.iter_mut()
+        {
+            let pid = inferior.pid;
+            let taken = inferior.target.take_stops();
+            if inferior.attached
+            {//Остановы процессов, от которых GDB отключен, не сообщаются
+                stops.extend(taken.into_iter().map(|(tid, stop)| (ptid(pid, tid), stop)));
+            }
+        }
+        stops
+    }
+
+    fn file_io_request(&mut self) -> Option<String>
+    {
+        self.current().ok()?.file_io_request()
+    }
+
+    fn file_io_reply(&mut self, retcode: i64, errno: Option<u64>) -> TargetResult<()>
+    {
+        self.current()?.file_io_reply(retcode, errno)
+    }
+
+    fn processes(&self) -> Vec<u64>
+    {
+        self.inferiors.iter().filter(|inferior| inferior.attached).map(|inferior| inferior.pid).collect()
+    }
+
+    fn attach(&mut self, pid: u64) -> TargetResult<StopReason>
+    {
+        let inferior = self.inferiors.iter_mut().find(|inferior| inferior.pid == pid && !inferior.attached).ok_or(0x01)?;
+        inferior.attached = true;
+        Ok(inferior.target.stop_reason())
+    }
+
+    fn detach(&mut self, pid: u64) -> TargetResult<()>
+    {
+        let index = self.index(pid).ok_or(0x01)?;
+        self.inferiors[index].attached = false;
+        if index == self.current
+        {//Текущим становится первый оставшийся процесс
+            self.current = self.inferiors.iter().position(|inferior| inferior.attached).unwrap_or(index);
+        }
+        Ok(())
+    }
+
+    fn trace_experiment(&self) -> Option<&TraceExperiment>
+    {
+        self.inferiors[self.current].target.trace_experiment()
+    }
+
+    fn trace_experiment_mut(&mut self) -> Option<&mut TraceExperiment>
+    {
+        self.current().ok()?.trace_experiment_mut()
+    }
+
+    fn monitor(&mut self, command: &str) -> Option<String>
+    {
+        self.current().ok()?.monitor(command)
+    }
+
+    fn kill(&mut self)
+    {
+        for inferior in self.inferiors.iter_mut()
+        {
+            inferior.target.kill();
+        }
+    }
+}
+
+
+///Тесты для Inferiors ================================================================================
+#[cfg(test)]
+mod test_inferiors
+{
+    use super::*;
+    use sim::{SimTarget, PC_REG};
+
+    #[test]
+    fn test_processes()
+    {
+        let mut target = Inferiors::new(vec![SimTarget::with_harts(false, 2), SimTarget::new(false)]);
+        assert_eq!(vec![1], target.processes());
+        assert_eq!(vec![ptid(1, 1), ptid(1, 2)], target.threads());
+        assert_eq!(Err(0x01), target.select_thread(ptid(2, 1)));
+
+        //Подключение ко второму процессу: его потоки видны, память и регистры свои
+        assert_eq!(Ok(StopReason::Signal(0x02)), target.attach(2));
+        assert_eq!(Err(0x01), target.attach(2));
+        assert_eq!(Err(0x01), target.attach(3));
+        assert_eq!(vec![1, 2], target.processes());
+        target.write_memory(0x8000_0000, &[1, 2]).unwrap();
+        target.select_thread(ptid(2, 1)).unwrap();
+        assert_eq!(ptid(2, 1), target.current_thread());
+        assert_eq!(Ok(vec![0, 0]), target.read_memory(0x8000_0000, 2));
+        target.write_register(PC_REG, &0x100u64.to_le_bytes()).unwrap();
+        target.select_thread(ptid(1, 2)).unwrap();
+        assert_eq!(Ok(vec![1, 2]), target.read_memory(0x8000_0000, 2));
+        assert_eq!(Ok(vec![0; 8]), target.read_register(PC_REG));
+        assert_eq!(Some("process 2, hart 0, pc 0x100, stopped: SIGINT".to_string()), target.thread_extra_info(ptid(2, 1)));
+
+        //Шаг в процессе 2, продолжение в процессе 1: останов на шаге
+        let no_cancel = Arc::new(AtomicBool::new(false));
+        let stop = target.resume_threads(&[(ptid(1, 1), ResumeAction::Continue), (ptid(2, 1), ResumeAction::Step)], &no_cancel);
+        assert_eq!(StopReason::Signal(0x05), stop);
+        assert_eq!(ptid(2, 1), target.current_thread());
+        assert_eq!(Ok(0x104u64.to_le_bytes().to_vec()), target.read_register(PC_REG));
+
+        //Отключение: текущим становится оставшийся процесс
+        target.detach(2).unwrap();
+        assert_eq!(Err(0x01), target.detach(2));
+        assert_eq!(vec![1], target.processes());
+        assert_eq!(1, ptid_parts(target.current_thread()).0);
+        assert!(!target.thread_alive(ptid(2, 1)));
+        assert_eq!(Ok(vec![1, 2]), target.read_memory(0x8000_0000, 2));
+    }
+}
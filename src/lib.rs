@@ -0,0 +1,48 @@
+///Технологический вывод GDB-сервера (println!, который можно отключить через gdb_server::set_verbose(false), например для замеров в режиме sweep)
+#[macro_export]
+macro_rules! log_println
+{
+    ($($arg:tt)*) => { if $crate::gdb_server::is_verbose() { println!($($arg)*); } };
+}
+
+pub mod codec;
+pub mod target;
+pub mod sim;
+pub mod inferiors;
+pub mod gdb_server;
+pub mod cmd_registry;
+pub mod cmd_handlers;
+pub mod session;
+pub mod config;
+pub mod client;
+pub mod sweep;
+pub mod transcript;
+pub mod transport;
+pub mod replay;
+pub mod proxy;
+pub mod mock;
+pub mod decode;
+pub mod explain;
+pub mod diagram;
+pub mod fuzz;
+pub mod rules;
+pub mod fault;
+pub mod scripted;
+pub mod semihosting;
+pub mod host_io;
+pub mod agent_expr;
+pub mod trace;
+
+pub use codec::Command;
+pub use target::{Target, TargetResult, ConsoleSink, StopReason, MatchpointType, ThreadId, ResumeAction, DummyTarget, ptid, ptid_parts};
+pub use sim::SimTarget;
+pub use inferiors::Inferiors;
+pub use host_io::HostIo;
+pub use trace::TraceExperiment;
+pub use gdb_server::{RspPacket, gdb_server, gdb_server_with_registry, serve_connection};
+pub use cmd_registry::{CmdRegistry, CmdContext, CmdHandler};
+pub use session::{Session, ClientFeatures, FeatureValue};
+pub use config::{ServerConfig, FeatureOverrides, TargetKind};
+pub use client::RspClient;
+pub use transcript::{Transcript, Record, Direction};
+pub use transport::{Transport, MemoryStream, memory_pair};
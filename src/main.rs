@@ -1,13 +1,74 @@
-pub mod sim;
-use sim::*;
-pub mod gdb_server;
-use gdb_server::*;
+extern crate gdb_rsp_researcher;
+
+use gdb_rsp_researcher::{ServerConfig, gdb_server_with_registry};
+use gdb_rsp_researcher::sweep::{self, SweepConfig, run_sweep};
+use gdb_rsp_researcher::replay::{self, ReplayConfig, run_replay};
+use gdb_rsp_researcher::proxy::{ProxyConfig, run_proxy};
+use gdb_rsp_researcher::decode::{DecodeConfig, run_decode};
+use gdb_rsp_researcher::diagram::{DiagramConfig, run_diagram};
+
+
+///Сообщение об ошибке в аргументах и выход
+fn exit_with(message: &str) -> !
+{
+    println!("  {}\n", message);
+    std::process::exit(1);
+}
 
 
 fn main()
 {
-    parse_args();
-    gdb_server();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| &a[..])
+    {
+        Some("sweep") =>
+        {//Режим перебора размеров пакета
+            let config = SweepConfig::from_args(&args[1..]).unwrap_or_else(|e| exit_with(&e));
+            match run_sweep(&config)
+            {
+                Ok(results) => sweep::print_report(&config, &results),
+                Err(e) => exit_with(&format!("Sweep failed: {}", e)),
+            }
+            return;
+        },
+        Some("replay") =>
+        {//Воспроизведение транскрипта и сравнение ответов
+            let config = ReplayConfig::from_args(&args[1..]).unwrap_or_else(|e| exit_with(&e));
+            let report = run_replay(&config).unwrap_or_else(|e| exit_with(&e));
+            replay::print_report(&report);
+            std::process::exit(if report.differences.is_empty() {0} else {1});
+        },
+        Some("proxy") =>
+        {//Пересылка между GDB и другим RSP-сервером с разбором пакетов
+            let config = ProxyConfig::from_args(&args[1..]).unwrap_or_else(|e| exit_with(&e));
+            if let Err(e) = run_proxy(&config)
+            {
+                exit_with(&format!("Proxy failed: {}", e));
+            }
+            return;
+        },
+        Some("decode") =>
+        {//Разбор лога "set debug remote 1" или захвата байт
+            let config = DecodeConfig::from_args(&args[1..]).unwrap_or_else(|e| exit_with(&e));
+            run_decode(&config).unwrap_or_else(|e| exit_with(&e));
+            return;
+        },
+        Some("diagram") =>
+        {//Диаграмма последовательности по транскрипту
+            let config = DiagramConfig::from_args(&args[1..]).unwrap_or_else(|e| exit_with(&e));
+            print!("{}", run_diagram(&config).unwrap_or_else(|e| exit_with(&e)));
+            return;
+        },
+        _ => (),
+    }
+
+    let config = ServerConfig::from_args(&args).unwrap_or_else(|e| exit_with(&e));
+
+    let registry = config.make_registry().unwrap_or_else(|e| exit_with(&e));
+    config.make_faults().unwrap_or_else(|e| exit_with(&e)); //Ошибки в правилах - до подключения GDB (правила читаются заново на каждое подключение)
+    config.make_host_io().unwrap_or_else(|e| exit_with(&e)); //Каталог песочницы должен существовать
+    let mut target = config.make_target();
+    gdb_server_with_registry(&config, &registry, &mut *target);
 
     println!("End of execution!");
 }
@@ -0,0 +1,303 @@
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread::sleep;
+use std::time::Duration;
+
+use codec::make_packet;
+use cmd_registry::{CmdRegistry, CmdContext};
+use gdb_server::RspPacket;
+use transcript::{Record, Direction, read_transcript, packet_data};
+
+
+///Записанный ответ upstream на одну команду
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockReply
+{
+    pub ack: bool,                                  // Перед ответом был acknowledgment '+'
+    pub console: Vec<String>,                       // Данные O-пакетов перед ответом
+    pub reply: String,                              // Данные ответа (без $ и #cs)
+    pub after_interrupt: bool,                      // Ответ пришел только после ^C (Stop Reply на 'c'/'vCont;c' при исполнении в цикле)
+}
+
+
+///Таблица ответов, выученная из транскрипта: ключ команды и ответы в порядке записи
+///Ключ - данные пакета ("m80000000,4", "qXfer:features:read:target.xml:0,ffb", "vCont;c"). Для X-пакета - только "X<addr>,<len>"
+///Если команда записана несколько раз, ответы выдаются по очереди, последний повторяется
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockTable
+{
+    entries: Vec<(String, Vec<MockReply>)>,
+}
+
+
+///Ключ команды для таблицы по данным пакета
+pub fn mock_key(data: &[u8]) -> String
+{
+    let data = if data.first() == Some(&b'X')
+    {//Бинарные данные X-пакета в ключ не входят
+        &data[..data.iter().position(|&c| c == b':').unwrap_or(data.len())]
+    }
+    else
+    {
+        data
+    };
+    String::from_utf8_lossy(data).into_owned()
+}
+
+
+impl MockTable
+{
+    ///Выучить ответы из записей транскрипта (например записанного в режиме proxy)
+    ///Ответом на пакет GDB считаются все сообщения upstream до следующего пакета GDB (acknowledgment от GDB пропускаются)
+    pub fn learn(records: &[Record]) -> MockTable
+    {
+        let mut table = MockTable::default();
+        let mut current: Option<(String, MockReply)> = None;
+        let mut replies: Vec<String> = Vec::new();
+        for record in records
+        {
+            match (record.dir, packet_data(&record.raw))
+            {
+                (Direction::In, Some(data)) =>
+                {//Новая команда: сохранить ответ на предыдущую
+                    table.finish(current.take(), &mut replies);
+                    current = Some((mock_key(data), MockReply{ ack: false, console: Vec::new(), reply: String::new(), after_interrupt: false }));
+                },
+                (Direction::In, None) =>
+                {
+                    if let Some((_, ref mut reply)) = current
+                    {
+                        if record.raw.contains(&0x03) && replies.is_empty()
+                        {
+                            reply.after_interrupt = true;
+                        }
+                    }
+                },
+                (Direction::Out, data) =>
+                {
+                    if let Some((_, ref mut reply)) = current
+                    {
+                        if record.raw.first() == Some(&b'+') && replies.is_empty()
+                        {
+                            reply.ack = true;
+                        }
+                        if let Some(data) = data
+                        {
+                            replies.push(String::from_utf8_lossy(data).into_owned());
+                        }
+                    }
+                },
+            }
+        }
+        table.finish(current, &mut replies);
+        table
+    }
+
+
+    fn finish(&mut self, current: Option<(String, MockReply)>, replies: &mut Vec<String>)
+    {
+        if let Some((key, mut reply)) = current
+        {
+            if let Some(last) = replies.pop()
+            {
+                reply.console = mem::take(replies);
+                reply.reply = last;
+                match self.entries.iter_mut().find(|(k, _)| *k == key)
+                {
+                    Some(entry) => entry.1.push(reply),
+                    None => self.entries.push((key, vec![reply])),
+                }
+            }
+            replies.clear();
+        }
+    }
+
+
+    ///Загрузить транскрипт и выучить ответы
+    pub fn load(path: &str) -> Result<MockTable, String>
+    {
+        Ok(MockTable::learn(&read_transcript(path)?))
+    }
+
+
+    ///Ответ на n-е (с 0) обращение с ключом. Если обращений больше, чем записано, - последний записанный ответ
+    pub fn lookup(&self, key: &str, n: usize) -> Option<&MockReply>
+    {
+        self.entries.iter().find(|(k, _)| k == key).and_then(|(_, replies)| replies.get(n).or_else(|| replies.last()))
+    }
+
+
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+
+    ///Ключи и количество записанных ответов
+    pub fn keys(&self) -> Vec<(&str, usize)>
+    {
+        self.entries.iter().map(|(k, r)| (&k[..], r.len())).collect()
+    }
+}
+
+
+///Реестр, который отвечает только по таблице. Команды, которых нет в таблице, получают default_reply ("" - пустой ответ)
+pub fn mock_registry(table: MockTable, default_reply: &str) -> CmdRegistry
+{
+    let mut registry = CmdRegistry::new();
+    let counters: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+    let default_reply = default_reply.to_string();
+    registry.set_fallback(move |pkt, ctx|
+    {
+        let key = match packet_data(ctx.input_buf)
+        {
+            Some(data) => mock_key(data),
+            None => pkt.cmd_key().to_string(),
+        };
+        let n = {
+            let mut counters = counters.lock().unwrap();
+            match counters.iter_mut().find(|(k, _)| *k == key)
+            {
+                Some(counter) => { counter.1 += 1; counter.1 - 1 },
+                None => { counters.push((key.clone(), 1)); 0 },
+            }
+        };
+        match table.lookup(&key, n)
+        {
+            Some(reply) =>
+            {
+                log_println!("Mock : '{}' -> '{}'", key, reply.reply);
+                mock_reply(pkt, ctx, reply);
+            },
+            None =>
+            {
+                log_println!("Mock : '{}' is not in the table, default reply '{}'", key, default_reply);
+                pkt.responce(&make_packet(&default_reply));
+            },
+        }
+        pkt.kill_flag = Some(key == "vKill"); //Завершение сеанса как у настоящего сервера
+        pkt.need_responce = Some(true);
+    });
+    registry
+}
+
+
+fn mock_reply(pkt: &mut RspPacket, ctx: &mut CmdContext, reply: &MockReply)
+{
+    if reply.after_interrupt
+    {//Ответ был только после ^C: ждать ^C
+        while !ctx.cancel_flag.load(Ordering::SeqCst)
+        {
+            sleep(Duration::from_millis(10));
+        }
+        ctx.cancel_flag.store(false, Ordering::SeqCst);
+    }
+    let mut output = String::new();
+    if reply.ack
+    {
+        output.push('+');
+    }
+    for console in &reply.console
+    {
+        output.push_str(&make_packet(console));
+    }
+    if !output.is_empty()
+    {
+        pkt.output_text = Some(output);
+    }
+    pkt.responce = Some(make_packet(&reply.reply)); //Без проверки длины: записанный ответ мог быть длиннее PACKET_SIZE (у upstream другой PacketSize)
+}
+
+
+///Тесты для mock-цели ================================================================================
+#[cfg(test)]
+mod test_mock
+{
+    use super::*;
+    use std::thread::spawn;
+    use client::RspClient;
+    use config::ServerConfig;
+    use gdb_server::{serve_connection, set_verbose};
+    use target::DummyTarget;
+    use transcript::inbound_cmd;
+    use transport::memory_pair;
+
+    fn rec(dir: Direction, raw: &[u8]) -> Record
+    {
+        Record{ ts_us: 0, dir, conn: 1, cmd: inbound_cmd(raw), data: None, raw: raw.to_vec() }
+    }
+
+    fn upstream_session() -> Vec<Record>
+    {
+        vec![
+            rec(Direction::In, make_packet("m80000000,4").as_bytes()),
+            rec(Direction::Out, b"+"),
+            rec(Direction::Out, make_packet("13050000").as_bytes()),
+            rec(Direction::In, b"+"),
+            rec(Direction::In, make_packet("?").as_bytes()),
+            rec(Direction::Out, format!("+{}", make_packet("S05")).as_bytes()),
+            rec(Direction::In, make_packet("?").as_bytes()),
+            rec(Direction::Out, format!("+{}", make_packet("T0505:00;")).as_bytes()),
+            rec(Direction::In, b"$X80000000,2:}\x03\x01#00"),
+            rec(Direction::Out, format!("+{}", make_packet("OK")).as_bytes()),
+            rec(Direction::In, make_packet("qRcmd,7265736574").as_bytes()),
+            rec(Direction::Out, format!("+{}", make_packet("O6f6b0a")).as_bytes()),
+            rec(Direction::In, b"+"),
+            rec(Direction::Out, make_packet("OK").as_bytes()),
+            rec(Direction::In, make_packet("c").as_bytes()),
+            rec(Direction::Out, b"+"),
+            rec(Direction::In, b"\x03"),
+            rec(Direction::Out, make_packet("T02").as_bytes()),
+        ]
+    }
+
+    #[test]
+    fn test_learn()
+    {
+        let table = MockTable::learn(&upstream_session());
+        assert_eq!(vec![("m80000000,4", 1), ("?", 2), ("X80000000,2", 1), ("qRcmd,7265736574", 1), ("c", 1)], table.keys());
+        let m = table.lookup("m80000000,4", 0).unwrap();
+        assert!(m.ack);
+        assert_eq!("13050000", m.reply);
+        assert_eq!("T0505:00;", table.lookup("?", 1).unwrap().reply);
+        assert_eq!("T0505:00;", table.lookup("?", 5).unwrap().reply);
+        assert_eq!(vec!["O6f6b0a".to_string()], table.lookup("qRcmd,7265736574", 0).unwrap().console);
+        assert!(table.lookup("c", 0).unwrap().after_interrupt);
+        assert_eq!(None, table.lookup("g", 0));
+    }
+
+    #[test]
+    fn test_mock_registry()
+    {
+        set_verbose(false);
+        let (client, server) = memory_pair();
+        let handle = spawn(move ||
+        {
+            let registry = mock_registry(MockTable::learn(&upstream_session()), "E01");
+            serve_connection(server, &ServerConfig::default(), &registry, &mut DummyTarget::new(false));
+        });
+        let mut client = RspClient::new(client);
+        assert_eq!(Some(vec![0x13, 0x05, 0x00, 0x00]), client.read_memory(0x80000000, 4).unwrap());
+        assert_eq!("S05", client.request("?").unwrap().data);
+        assert_eq!("T0505:00;", client.request("?").unwrap().data);
+        assert_eq!("T0505:00;", client.request("?").unwrap().data);
+        assert_eq!("OK", client.write_memory(0x80000000, &[0x11, 0x22]).unwrap().data);
+        let reply = client.request("qRcmd,7265736574").unwrap();
+        assert_eq!(("OK", "ok\n"), (&reply.data[..], &reply.console[..]));
+        assert_eq!("E01", client.request("g").unwrap().data);
+
+        client.send_packet(b"c").unwrap();
+        sleep(Duration::from_millis(50)); //^C должен прийти отдельно, как от GDB
+        client.send_raw(b"\x03").unwrap();
+        assert_eq!(b"T02".to_vec(), client.read_packet().unwrap());
+        drop(client);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::spawn;
+
+use codec::{frame_len, describe_frame};
+use config::next_value;
+use gdb_server::DEFAULT_ADDR;
+use transcript::{SharedTranscript, Direction, record, inbound_cmd, packet_data, open_shared};
+
+
+///Адрес upstream RSP-сервера (OpenOCD, gdbstub QEMU, gdbserver ...)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Upstream
+{
+    Tcp(String),                                    // host:port
+    Unix(String),                                   // Путь к Unix-сокету
+}
+
+
+impl Upstream
+{
+    ///"host:port" или "unix:/path"
+    pub fn parse(text: &str) -> Upstream
+    {
+        if let Some(path) = text.strip_prefix("unix:")
+        {
+            Upstream::Unix(path.to_string())
+        }
+        else
+        {
+            Upstream::Tcp(text.to_string())
+        }
+    }
+
+
+    fn connect(&self) -> io::Result<Box<dyn Duplex>>
+    {
+        match *self
+        {
+            Upstream::Tcp(ref addr) => Ok(Box::new(TcpStream::connect(&addr[..])?)),
+            #[cfg(unix)]
+            Upstream::Unix(ref path) => Ok(Box::new(UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            Upstream::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets are not supported")),
+        }
+    }
+}
+
+
+///Соединение, которое можно читать и писать из разных потоков и закрыть из любого
+pub trait Duplex: Read + Write + Send
+{
+    fn try_clone_duplex(&self) -> io::Result<Box<dyn Duplex>>;
+    fn shutdown_both(&self);
+}
+
+
+impl Duplex for TcpStream
+{
+    fn try_clone_duplex(&self) -> io::Result<Box<dyn Duplex>>
+    {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown_both(&self)
+    {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+
+#[cfg(unix)]
+impl Duplex for UnixStream
+{
+    fn try_clone_duplex(&self) -> io::Result<Box<dyn Duplex>>
+    {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown_both(&self)
+    {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+
+///Настройки режима proxy
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyConfig
+{
+    pub addr: String,                               // Адрес, на котором proxy ожидает GDB
+    pub upstream: Upstream,
+    pub record: Option<String>,                     // Транскрипт: "in" - от GDB, "out" - от upstream
+}
+
+
+impl ProxyConfig
+{
+    ///Разобрать аргументы режима proxy (после "proxy")
+    pub fn from_args(args: &[String]) -> Result<ProxyConfig, String>
+    {
+        let mut addr = DEFAULT_ADDR.to_string();
+        let mut upstream = None;
+        let mut record = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--addr" => addr = next_value(&mut args, arg)?.to_string(),
+                "--upstream" => upstream = Some(Upstream::parse(next_value(&mut args, arg)?)),
+                "--record" => record = Some(next_value(&mut args, arg)?.to_string()),
+                _ => return Err(format!("Неизвестный аргумент режима proxy {:?}", arg)),
+            }
+        }
+        Ok(ProxyConfig{
+            addr,
+            upstream: upstream.ok_or_else(|| "Не задан --upstream (host:port или unix:/path)".to_string())?,
+            record,
+        })
+    }
+}
+
+
+///Состояние подключения, общее для обоих направлений
+struct ProxyState
+{
+    last_cmd: Mutex<String>,                        // Последняя команда GDB (для записи ответов upstream в транскрипт)
+    no_ack_requested: AtomicBool,                   // GDB отправил QStartNoAckMode, ответ еще не получен
+    no_ack_mode: AtomicBool,                        // Upstream ответил OK на QStartNoAckMode: acknowledgment больше не ожидаются
+    transcript: Option<SharedTranscript>,
+}
+
+
+///Режим proxy: принять подключение GDB, подключиться к upstream и пересылать трафик в обе стороны до отключения одной из сторон
+pub fn run_proxy(config: &ProxyConfig) -> io::Result<()>
+{
+    let listener = TcpListener::bind(&config.addr[..])?;
+    println!("Proxy listening at {} (upstream {:?})", config.addr, config.upstream);
+    let gdb = match listener.incoming().next()
+    {
+        Some(stream) => stream?,
+        None => return Ok(()),
+    };
+    gdb.set_nodelay(true)?;
+    let upstream = config.upstream.connect()?;
+    proxy_connection(Box::new(gdb), upstream, config.record.as_ref().and_then(|path| open_shared(path)));
+    println!("Connection was killed!\n");
+    Ok(())
+}
+
+
+///Пересылка между двумя соединениями. Возвращается, когда одна из сторон отключилась
+pub fn proxy_connection(gdb: Box<dyn Duplex>, upstream: Box<dyn Duplex>, transcript: Option<SharedTranscript>)
+{
+    let state = Arc::new(ProxyState{
+        last_cmd: Mutex::new(String::new()),
+        no_ack_requested: AtomicBool::new(false),
+        no_ack_mode: AtomicBool::new(false),
+        transcript,
+    });
+    let (gdb_reader, upstream_reader) = match (gdb.try_clone_duplex(), upstream.try_clone_duplex())
+    {
+        (Ok(g), Ok(u)) => (g, u),
+        _ =>
+        {
+            println!("Proxy : stream clone failed");
+            return;
+        },
+    };
+
+    let to_upstream_state = state.clone();
+    let to_upstream = spawn(move ||
+    {
+        let (mut from, mut to) = (gdb_reader, upstream);
+        let _ = pump(&mut *from, &mut *to, Direction::In, &to_upstream_state);
+        //GDB отключился: закрыть upstream, чтобы завершился встречный поток
+        to.shutdown_both();
+        from.shutdown_both();
+    });
+
+    let (mut from, mut to) = (upstream_reader, gdb);
+    let _ = pump(&mut *from, &mut *to, Direction::Out, &state);
+    to.shutdown_both();
+    from.shutdown_both();
+    let _ = to_upstream.join();
+}
+
+
+///Пересылка в одну сторону: сообщения пересылаются целиком, как только приняты (^C - сразу)
+fn pump(from: &mut dyn Duplex, to: &mut dyn Duplex, dir: Direction, state: &ProxyState) -> io::Result<()>
+{
+    let mut read_buf = vec![0u8; 64 * 1024];
+    let mut pending: Vec<u8> = Vec::new();
+    loop
+    {
+        let len = from.read(&mut read_buf)?;
+        if len == 0
+        {
+            to.write_all(&pending)?; //Неполное сообщение переслать как есть
+            return Ok(());
+        }
+        pending.extend_from_slice(&read_buf[..len]);
+        while let Some(frame_len) = frame_len(&pending)
+        {
+            let frame: Vec<u8> = pending.drain(..frame_len).collect();
+            to.write_all(&frame)?;
+            observe(&frame, dir, state);
+        }
+    }
+}
+
+
+///Вывод и запись в транскрипт пересланного сообщения, отслеживание no-ack режима
+fn observe(frame: &[u8], dir: Direction, state: &ProxyState)
+{
+    let cmd = match dir
+    {
+        Direction::In =>
+        {
+            let cmd = inbound_cmd(frame);
+            if packet_data(frame).is_some()
+            {
+                if cmd == "QStartNoAckMode"
+                {
+                    state.no_ack_requested.store(true, Ordering::SeqCst);
+                }
+                *state.last_cmd.lock().unwrap() = cmd.clone();
+            }
+            cmd
+        },
+        Direction::Out =>
+        {
+            if state.no_ack_requested.load(Ordering::SeqCst) && packet_data(frame).is_some()
+            {
+                state.no_ack_requested.store(false, Ordering::SeqCst);
+                if packet_data(frame) == Some(b"OK")
+                {
+                    state.no_ack_mode.store(true, Ordering::SeqCst);
+                    log_println!("Proxy : no-ack mode is on");
+                }
+            }
+            state.last_cmd.lock().unwrap().clone()
+        },
+    };
+
+    let arrow = if dir == Direction::In {"GDB -> stub"} else {"stub -> GDB"};
+    let unexpected_ack = state.no_ack_mode.load(Ordering::SeqCst) && (frame[0] == b'+' || frame[0] == b'-');
+    log_println!("{} : {}{}", arrow, describe_frame(frame), if unexpected_ack {" (ack in no-ack mode)"} else {""});
+    record(&state.transcript, dir, &cmd, frame);
+}
+
+
+///Тесты для режима proxy ================================================================================
+#[cfg(test)]
+mod test_proxy
+{
+    use super::*;
+    use client::RspClient;
+    use cmd_registry::CmdRegistry;
+    use config::ServerConfig;
+    use gdb_server::{serve_connection, set_verbose};
+    use sim::SimTarget;
+
+    #[test]
+    fn test_proxy_connection()
+    {
+        set_verbose(false);
+        //upstream: GDB-сервер с SimTarget
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        let server = spawn(move ||
+        {
+            let stream = upstream_listener.incoming().next().unwrap().unwrap();
+            serve_connection(stream, &ServerConfig::default(), &CmdRegistry::with_defaults(), &mut SimTarget::new(true));
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = spawn(move ||
+        {
+            let gdb = listener.incoming().next().unwrap().unwrap();
+            let upstream = Upstream::Tcp(upstream_addr.to_string()).connect().unwrap();
+            proxy_connection(Box::new(gdb), upstream, None);
+        });
+
+        let mut client = RspClient::new(TcpStream::connect(addr).unwrap());
+        assert!(client.handshake("swbreak+").unwrap().contains("QStartNoAckMode+"));
+        assert!(client.no_ack_mode);
+        client.write_memory(0x1000, b"#$}*").unwrap();
+        assert_eq!(Some(b"#$}*".to_vec()), client.read_memory(0x1000, 4).unwrap());
+
+        //Исполнение в цикле до ^C через proxy
+        client.send_packet(b"c").unwrap();
+        client.send_raw(b"\x03").unwrap();
+        let mut reply = client.read_packet().unwrap();
+        while reply[0] == b'O'
+        {//Вывод в консоль перед Stop Reply
+            reply = client.read_packet().unwrap();
+        }
+        assert_eq!(b"T02thread:1;".to_vec(), reply);
+
+        assert_eq!("OK", client.request("vKill").unwrap().data);
+        drop(client);
+        server.join().unwrap();
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn test_upstream_parse()
+    {
+        assert_eq!(Upstream::Tcp("localhost:3333".to_string()), Upstream::parse("localhost:3333"));
+        assert_eq!(Upstream::Unix("/tmp/qemu.sock".to_string()), Upstream::parse("unix:/tmp/qemu.sock"));
+    }
+}
@@ -0,0 +1,415 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::spawn;
+use std::time::Duration;
+
+use codec::frame_len;
+use config::{ServerConfig, next_value, parse_size};
+use gdb_server::{serve_connection, set_verbose};
+use transcript::{Record, Direction, read_transcript};
+use transport::memory_pair;
+
+
+///Время ожидания ответа сервера по умолчанию
+pub const DEFAULT_REPLAY_TIMEOUT_MS: u64 = 1000;
+
+
+///Транспорт между воспроизводящим клиентом и сервером
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayTransport
+{
+    Memory,                                         // Соединение в памяти (transport::MemoryStream)
+    Tcp,                                            // Настоящий сокет 127.0.0.1
+}
+
+
+///Настройки режима replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig
+{
+    pub path: String,                               // Файл транскрипта
+    pub transport: ReplayTransport,
+    pub conn: Option<u64>,                          // Воспроизводимое подключение (None - первое в транскрипте)
+    pub timeout: Duration,                          // Время ожидания ответа сервера
+    pub server: ServerConfig,                       // Настройки сервера, с которым сравниваются записанные ответы
+}
+
+
+impl ReplayConfig
+{
+    ///Разобрать аргументы режима replay (после "replay"): файл, свои аргументы, остальные - аргументы сервера
+    pub fn from_args(args: &[String]) -> Result<ReplayConfig, String>
+    {
+        let mut path = None;
+        let mut transport = ReplayTransport::Memory;
+        let mut conn = None;
+        let mut timeout = Duration::from_millis(DEFAULT_REPLAY_TIMEOUT_MS);
+        let mut server_args = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--transport"=>
+                {
+                    transport = match next_value(&mut args, arg)?
+                    {
+                        "memory" => ReplayTransport::Memory,
+                        "tcp" => ReplayTransport::Tcp,
+                        value => return Err(format!("Неизвестный транспорт \'{}\' (memory или tcp)", value)),
+                    };
+                },
+                "--conn"=>
+                {
+                    conn = Some(parse_size(next_value(&mut args, arg)?)? as u64);
+                },
+                "--timeout"=>
+                {
+                    timeout = Duration::from_millis(parse_size(next_value(&mut args, arg)?)? as u64);
+                },
+                _ if path.is_none() && !arg.starts_with('-') => path = Some(arg.clone()),
+                _ => server_args.push(arg.clone()),
+            }
+        }
+        Ok(ReplayConfig{
+            path: path.ok_or_else(|| "Не задан файл транскрипта".to_string())?,
+            transport,
+            conn,
+            timeout,
+            server: ServerConfig::from_args(&server_args)?,
+        })
+    }
+}
+
+
+///Вид расхождения ответа сервера с записанным
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffKind
+{
+    Ack,                                            // Отличается acknowledgment перед пакетом ('+', '-' или его отсутствие)
+    Payload,                                        // Отличаются данные пакета
+    Checksum,                                       // Данные совпадают, отличается контрольная сумма
+    Missing,                                        // Записанный ответ не получен
+    Extra,                                          // Получен ответ, которого нет в записи
+}
+
+
+///Расхождение одного сообщения
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference
+{
+    pub record: usize,                              // Номер записи транскрипта (с 1), на которую получен ответ
+    pub cmd: String,                                // Команда, на которую получен ответ
+    pub kind: DiffKind,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+
+impl fmt::Display for Difference
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "record {} ({}): {:?}: expected {:?}, got {:?}",
+            self.record, self.cmd, self.kind, String::from_utf8_lossy(&self.expected), String::from_utf8_lossy(&self.actual))
+    }
+}
+
+
+///Результат воспроизведения
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport
+{
+    pub sent: usize,                                // Отправлено записанных сообщений клиента
+    pub compared: usize,                            // Сравнено сообщений сервера
+    pub differences: Vec<Difference>,
+}
+
+
+///Части сообщения: acknowledgment, данные пакета, контрольная сумма
+pub fn split_frame(frame: &[u8]) -> (Option<u8>, Option<&[u8]>, Option<&[u8]>)
+{
+    match frame.iter().position(|&c| c == b'$' || c == b'%')
+    {
+        None => (frame.first().cloned(), None, None),
+        Some(start) =>
+        {
+            let ack = if start > 0 {Some(frame[0])} else {None};
+            match frame[start..].iter().position(|&c| c == b'#')
+            {
+                Some(pos) => (ack, Some(&frame[start+1 .. start+pos]), Some(&frame[start+pos+1 ..])),
+                None => (ack, Some(&frame[start+1 ..]), None),
+            }
+        },
+    }
+}
+
+
+///Разбить байты на сообщения (последнее может быть неполным)
+pub fn split_frames(mut bytes: &[u8]) -> Vec<&[u8]>
+{
+    let mut frames = Vec::new();
+    while !bytes.is_empty()
+    {
+        let len = frame_len(bytes).unwrap_or(bytes.len());
+        frames.push(&bytes[..len]);
+        bytes = &bytes[len..];
+    }
+    frames
+}
+
+
+///Сравнить записанное сообщение сервера с полученным. None - совпадают
+pub fn compare_frames(expected: &[u8], actual: &[u8]) -> Option<DiffKind>
+{
+    if expected == actual
+    {
+        return None;
+    }
+    let (e_ack, e_data, e_cs) = split_frame(expected);
+    let (a_ack, a_data, a_cs) = split_frame(actual);
+    if e_data != a_data
+    {
+        Some(if e_data.is_none() && a_data.is_none() {DiffKind::Ack} else {DiffKind::Payload})
+    }
+    else if e_ack != a_ack
+    {
+        Some(DiffKind::Ack)
+    }
+    else if e_cs != a_cs
+    {
+        Some(DiffKind::Checksum)
+    }
+    else
+    {
+        Some(DiffKind::Payload)
+    }
+}
+
+
+///Воспроизвести сообщения клиента и сравнить ответы сервера с записанными
+///После каждого записанного сообщения клиента ожидаются записанные за ним сообщения сервера (до следующего сообщения клиента)
+///Поток должен иметь таймаут чтения: если ответ не пришел, он считается отсутствующим
+pub fn replay_records<S: Read + Write>(stream: &mut S, records: &[Record]) -> io::Result<ReplayReport>
+{
+    let mut report = ReplayReport::default();
+    let mut i = 0;
+    while i < records.len()
+    {
+        let (record, cmd) = (i + 1, records[i].cmd.clone());
+        let mut expected = Vec::new();
+        if records[i].dir == Direction::In
+        {
+            stream.write_all(&records[i].raw)?;
+            stream.flush()?;
+            report.sent += 1;
+            i += 1;
+        }
+        while i < records.len() && records[i].dir == Direction::Out
+        {
+            expected.extend_from_slice(&records[i].raw);
+            i += 1;
+        }
+        //Если ответ не записан, всё равно подождать: вдруг сервер ответит (тогда это Extra)
+        let actual = receive(stream, if expected.is_empty() {usize::MAX} else {expected.len()})?;
+        compare_replies(&mut report, record, &cmd, &expected, &actual);
+    }
+    //Сообщения, которых нет в записи
+    let extra = receive(stream, usize::MAX)?;
+    compare_replies(&mut report, records.len(), "", &[], &extra);
+    Ok(report)
+}
+
+
+fn compare_replies(report: &mut ReplayReport, record: usize, cmd: &str, expected: &[u8], actual: &[u8])
+{
+    let expected = split_frames(expected);
+    let actual = split_frames(actual);
+    for n in 0..expected.len().max(actual.len())
+    {
+        let (e, a) = (expected.get(n).cloned().unwrap_or(&[]), actual.get(n).cloned().unwrap_or(&[]));
+        let kind = if e.is_empty() {Some(DiffKind::Extra)} else if a.is_empty() {Some(DiffKind::Missing)} else {compare_frames(e, a)};
+        if !e.is_empty()
+        {
+            report.compared += 1;
+        }
+        if let Some(kind) = kind
+        {
+            report.differences.push(Difference{ record, cmd: cmd.to_string(), kind, expected: e.to_vec(), actual: a.to_vec() });
+        }
+    }
+}
+
+
+///Принять не меньше len байт (и до конца сообщения) или всё, что пришло до таймаута
+fn receive<S: Read>(stream: &mut S, len: usize) -> io::Result<Vec<u8>>
+{
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    while received.len() < len || (!received.is_empty() && split_frames(&received).last().and_then(|f| frame_len(f)).is_none())
+    {
+        match stream.read(&mut buf)
+        {
+            Ok(0) => break,
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(received)
+}
+
+
+///Записи одного подключения из транскрипта (None - первого)
+pub fn connection_records(records: Vec<Record>, conn: Option<u64>) -> Vec<Record>
+{
+    let conn = match conn.or_else(|| records.first().map(|r| r.conn))
+    {
+        Some(conn) => conn,
+        None => return Vec::new(),
+    };
+    records.into_iter().filter(|r| r.conn == conn).collect()
+}
+
+
+///Режим replay: поднять сервер, воспроизвести транскрипт и сравнить ответы
+pub fn run_replay(config: &ReplayConfig) -> Result<ReplayReport, String>
+{
+    let records = connection_records(read_transcript(&config.path)?, config.conn);
+    if records.is_empty()
+    {
+        return Err(format!("{}: нет записей для воспроизведения", config.path));
+    }
+    set_verbose(false);
+    let server_config = ServerConfig{ record: None, ..config.server.clone() };
+    let registry = server_config.make_registry()?;
+    let report = match config.transport
+    {
+        ReplayTransport::Memory =>
+        {
+            let (mut client, server) = memory_pair();
+            client.set_read_timeout(Some(config.timeout));
+            let server = spawn(move ||
+            {
+                serve_connection(server, &server_config, &registry, &mut *server_config.make_target());
+            });
+            let report = replay_records(&mut client, &records);
+            drop(client);
+            let _ = server.join();
+            report
+        },
+        ReplayTransport::Tcp =>
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+            let addr = listener.local_addr().map_err(|e| e.to_string())?;
+            let server = spawn(move ||
+            {
+                if let Some(Ok(stream)) = listener.incoming().next()
+                {
+                    serve_connection(stream, &server_config, &registry, &mut *server_config.make_target());
+                }
+            });
+            let mut client = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+            client.set_read_timeout(Some(config.timeout)).map_err(|e| e.to_string())?;
+            let report = replay_records(&mut client, &records);
+            drop(client);
+            let _ = server.join();
+            report
+        },
+    };
+    report.map_err(|e| format!("Replay failed: {}", e))
+}
+
+
+///Вывод результата
+pub fn print_report(report: &ReplayReport)
+{
+    for diff in &report.differences
+    {
+        println!("  {}", diff);
+    }
+    println!("Replayed {} client messages, compared {} server messages, {} differences", report.sent, report.compared, report.differences.len());
+}
+
+
+///Тесты для режима replay ================================================================================
+#[cfg(test)]
+mod test_replay
+{
+    use super::*;
+    use codec::make_packet;
+    use transcript::inbound_cmd;
+
+    fn rec(dir: Direction, raw: &str) -> Record
+    {
+        Record{ ts_us: 0, dir, conn: 1, cmd: inbound_cmd(raw.as_bytes()), data: None, raw: raw.as_bytes().to_vec() }
+    }
+
+    fn replay(records: &[Record]) -> ReplayReport
+    {
+        let (mut client, server) = memory_pair();
+        client.set_read_timeout(Some(Duration::from_millis(200)));
+        let handle = spawn(move ||
+        {
+            let config = ServerConfig::default();
+            serve_connection(server, &config, &config.make_registry().unwrap(), &mut *config.make_target());
+        });
+        let report = replay_records(&mut client, records).unwrap();
+        drop(client);
+        handle.join().unwrap();
+        report
+    }
+
+    #[test]
+    fn test_replay_match()
+    {
+        set_verbose(false);
+        let records = vec![
+            rec(Direction::In, &make_packet("?")),
+            rec(Direction::Out, &make_packet("T02")),
+            rec(Direction::In, &format!("+{}", make_packet("m1000,4"))),
+            rec(Direction::Out, &make_packet("33221100")),
+            rec(Direction::In, "+"),
+            rec(Direction::Out, "+"),
+        ];
+        let report = replay(&records);
+        assert_eq!(Vec::<Difference>::new(), report.differences);
+        assert_eq!(3, report.sent);
+        assert_eq!(3, report.compared);
+    }
+
+    #[test]
+    fn test_replay_differences()
+    {
+        set_verbose(false);
+        let records = vec![
+            rec(Direction::In, &make_packet("?")),
+            rec(Direction::Out, &make_packet("T05")),                       // Payload
+            rec(Direction::In, &make_packet("m1000,4")),
+            rec(Direction::Out, &format!("+{}", make_packet("33221100"))),  // Ack
+            rec(Direction::In, &make_packet("vMustReplyEmpty")),
+            rec(Direction::Out, "+$#01"),                                   // Checksum
+            rec(Direction::In, &make_packet("QStartNoAckMode")),            // Extra: ответ не записан
+            rec(Direction::In, "+"),
+            rec(Direction::Out, "+"),
+            rec(Direction::In, &make_packet("vKill")),
+            rec(Direction::Out, &make_packet("OK")),
+            rec(Direction::Out, &make_packet("OK")),                        // Missing: второго ответа не будет
+        ];
+        let report = replay(&records);
+        let kinds: Vec<DiffKind> = report.differences.iter().map(|d| d.kind).collect();
+        assert_eq!(vec![DiffKind::Payload, DiffKind::Ack, DiffKind::Checksum, DiffKind::Extra, DiffKind::Missing], kinds);
+        assert_eq!(7, report.differences[3].record);
+    }
+
+    #[test]
+    fn test_split_frame()
+    {
+        assert_eq!((Some(b'+'), Some(&b"OK"[..]), Some(&b"9a"[..])), split_frame(b"+$OK#9a"));
+        assert_eq!((None, Some(&b""[..]), Some(&b"00"[..])), split_frame(b"$#00"));
+        assert_eq!((Some(b'+'), None, None), split_frame(b"+"));
+        assert_eq!(vec![&b"+$OK#9a"[..], &b"+$T0"[..]], split_frames(b"+$OK#9a+$T0"));
+    }
+}
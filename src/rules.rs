@@ -0,0 +1,243 @@
+use std::fs;
+
+
+///Значение в файле правил
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleValue
+{
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+
+///Одно правило: таблица [[kind]] файла правил и ее поля "key = value"
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleTable
+{
+    pub kind: String,                               // Название массива таблиц ("fault", "reply" ...)
+    pub line: usize,                                // Строка заголовка [[kind]] (для сообщений об ошибках)
+    pub fields: Vec<(String, RuleValue)>,
+}
+
+
+impl RuleTable
+{
+    pub fn get(&self, key: &str) -> Option<&RuleValue>
+    {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+
+    ///Строковое поле. Ошибка - если поле есть, но не строка
+    pub fn get_str(&self, key: &str) -> Result<Option<&str>, String>
+    {
+        match self.get(key)
+        {
+            None => Ok(None),
+            Some(RuleValue::Str(s)) => Ok(Some(s)),
+            Some(_) => Err(self.error(&format!("поле \'{}\' должно быть строкой", key))),
+        }
+    }
+
+
+    ///Обязательное строковое поле
+    pub fn require_str(&self, key: &str) -> Result<&str, String>
+    {
+        self.get_str(key)?.ok_or_else(|| self.error(&format!("не задано поле \'{}\'", key)))
+    }
+
+
+    ///Целое неотрицательное поле
+    pub fn get_uint(&self, key: &str) -> Result<Option<u64>, String>
+    {
+        match self.get(key)
+        {
+            None => Ok(None),
+            Some(&RuleValue::Int(n)) if n >= 0 => Ok(Some(n as u64)),
+            Some(_) => Err(self.error(&format!("поле \'{}\' должно быть неотрицательным числом", key))),
+        }
+    }
+
+
+    ///Ошибка в правиле со ссылкой на его строку
+    pub fn error(&self, message: &str) -> String
+    {
+        format!("строка {}: [[{}]]: {}", self.line, self.kind, message)
+    }
+}
+
+
+///Разобрать файл правил: подмножество TOML
+///Только массивы таблиц [[kind]] с полями "key = value", значения - строки ("..." или '...'), целые (в т.ч. 0x...) и true/false
+///Комментарии от '#' до конца строки
+pub fn parse_rules(text: &str) -> Result<Vec<RuleTable>, String>
+{
+    let mut tables: Vec<RuleTable> = Vec::new();
+    for (i, line) in text.lines().enumerate()
+    {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#')
+        {
+            continue;
+        }
+        if line.starts_with('[')
+        {
+            let header = strip_comment(line).trim();
+            if !(header.starts_with("[[") && header.ends_with("]]")) || header.len() < 5
+            {
+                return Err(format!("строка {}: ожидается заголовок [[name]]", number));
+            }
+            tables.push(RuleTable{ kind: header[2 .. header.len()-2].trim().to_string(), line: number, fields: Vec::new() });
+            continue;
+        }
+        let eq_pos = line.find('=').ok_or_else(|| format!("строка {}: ожидается \"key = value\"", number))?;
+        let key = line[..eq_pos].trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("строка {}: некорректное имя поля \'{}\'", number, key));
+        }
+        let value = parse_value(line[eq_pos+1 ..].trim()).map_err(|e| format!("строка {}: {}", number, e))?;
+        let table = tables.last_mut().ok_or_else(|| format!("строка {}: поле вне [[name]]", number))?;
+        if table.get(key).is_some()
+        {
+            return Err(format!("строка {}: поле \'{}\' задано повторно", number, key));
+        }
+        table.fields.push((key.to_string(), value));
+    }
+    Ok(tables)
+}
+
+
+///Прочитать и разобрать файл правил
+pub fn read_rules(path: &str) -> Result<Vec<RuleTable>, String>
+{
+    let text = fs::read_to_string(path).map_err(|e| format!("Не удалось прочитать {}: {}", path, e))?;
+    parse_rules(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+
+///Часть строки до комментария (вне строкового значения)
+fn strip_comment(text: &str) -> &str
+{
+    match text.find('#')
+    {
+        Some(pos) => &text[..pos],
+        None => text,
+    }
+}
+
+
+///Значение поля (с возможным комментарием после него)
+fn parse_value(text: &str) -> Result<RuleValue, String>
+{
+    let mut chars = text.char_indices();
+    let quote = match chars.next()
+    {
+        Some((_, c)) if c == '"' || c == '\'' => c,
+        Some(_) =>
+        {//Число или true/false
+            let word = strip_comment(text).trim();
+            return match word
+            {
+                "true" => Ok(RuleValue::Bool(true)),
+                "false" => Ok(RuleValue::Bool(false)),
+                _ => parse_int(word).map(RuleValue::Int).ok_or_else(|| format!("некорректное значение \'{}\'", word)),
+            };
+        },
+        None => return Err("не задано значение".to_string()),
+    };
+    let mut result = String::new();
+    loop
+    {
+        match chars.next()
+        {
+            None => return Err("незакрытая строка".to_string()),
+            Some((pos, c)) if c == quote =>
+            {
+                let rest = strip_comment(&text[pos+1 ..]).trim();
+                if !rest.is_empty()
+                {
+                    return Err(format!("лишние символы после строки \'{}\'", rest));
+                }
+                return Ok(RuleValue::Str(result));
+            },
+            Some((_, '\\')) if quote == '"' => match chars.next().map(|(_, c)| c)
+            {//В '...' escape-последовательностей нет
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('u') =>
+                {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let code = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("некорректный \\u{}", hex))?;
+                    result.push(code);
+                },
+                c => return Err(format!("неизвестная escape-последовательность \\{}", c.map(String::from).unwrap_or_default())),
+            },
+            Some((_, c)) => result.push(c),
+        }
+    }
+}
+
+
+///Целое: десятичное (в т.ч. отрицательное) или hex с префиксом 0x. Допускаются '_' между цифрами
+fn parse_int(word: &str) -> Option<i64>
+{
+    let digits = word.replace('_', "");
+    if digits.starts_with("0x") || digits.starts_with("0X")
+    {
+        i64::from_str_radix(&digits[2..], 16).ok()
+    }
+    else
+    {
+        digits.parse::<i64>().ok()
+    }
+}
+
+
+///Тесты для файла правил ================================================================================
+#[cfg(test)]
+mod test_rules
+{
+    use super::*;
+
+    #[test]
+    fn test_parse_rules()
+    {
+        let text = "# Правила\n\
+                    [[fault]]\n\
+                    cmd = \"m\"   # чтение памяти\n\
+                    every = 3\n\
+                    \n\
+                    [[ reply ]]\n\
+                    match = 'm10030000,8'\n\
+                    reply = \"E\\u0030\\\"1#\"\n\
+                    addr = 0x1003_0000\n\
+                    enabled = false\n";
+        let tables = parse_rules(text).unwrap();
+        assert_eq!(2, tables.len());
+        assert_eq!(("fault", 2), (&tables[0].kind[..], tables[0].line));
+        assert_eq!(Ok(Some("m")), tables[0].get_str("cmd"));
+        assert_eq!(Ok(Some(3)), tables[0].get_uint("every"));
+        assert_eq!(Ok(None), tables[0].get_uint("ms"));
+        assert_eq!("reply", tables[1].kind);
+        assert_eq!(Ok("m10030000,8"), tables[1].require_str("match"));
+        assert_eq!(Ok(Some("E0\"1#")), tables[1].get_str("reply"));
+        assert_eq!(Some(&RuleValue::Int(0x10030000)), tables[1].get("addr"));
+        assert_eq!(Some(&RuleValue::Bool(false)), tables[1].get("enabled"));
+        assert!(tables[1].get_str("addr").is_err());
+        assert!(tables[1].require_str("cmd").unwrap_err().starts_with("строка 6:"));
+
+        assert!(parse_rules("cmd = \"m\"").is_err());
+        assert!(parse_rules("[fault]").is_err());
+        assert!(parse_rules("[[fault]]\ncmd = \"m").is_err());
+        assert!(parse_rules("[[fault]]\ncmd = m").is_err());
+        assert!(parse_rules("[[fault]]\ncmd = \"m\" x").is_err());
+        assert!(parse_rules("[[fault]]\ncmd = \"m\"\ncmd = \"g\"").is_err());
+        assert!(parse_rules("[[fault]]\nevery = -1").unwrap()[0].get_uint("every").is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::fs;
+use std::time::SystemTime;
+
+use codec::hex_encode;
+use rules::{RuleTable, parse_rules};
+
+
+///Правило подмены ответа: таблица [[reply]] файла правил
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyRule
+{
+    pub pattern: String,                            // Шаблон данных пакета: '*' - любые символы (в т.ч. никаких), части под '*' доступны в ответе как {1}, {2} ...
+    pub reply: String,                              // Шаблон ответа (без $ и #cs)
+    pub console: Option<String>,                    // Текст в GDB-консоль (O-пакет) перед ответом. Шаблон, как и reply
+}
+
+
+impl ReplyRule
+{
+    ///Правило из таблицы [[reply]]. Шаблоны проверяются сразу, чтобы ошибка в них была видна при загрузке, а не на пакете от GDB
+    pub fn from_table(table: &RuleTable) -> Result<ReplyRule, String>
+    {
+        let rule = ReplyRule{
+            pattern: table.require_str("match")?.to_string(),
+            reply: table.require_str("reply")?.to_string(),
+            console: table.get_str("console")?.map(String::from),
+        };
+        let captures = vec![""; rule.pattern.matches('*').count()];
+        render_template(&rule.reply, &captures).map_err(|e| table.error(&format!("reply: {}", e)))?;
+        if let Some(ref console) = rule.console
+        {
+            render_template(console, &captures).map_err(|e| table.error(&format!("console: {}", e)))?;
+        }
+        Ok(rule)
+    }
+}
+
+
+///Ответ по правилу
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptedReply
+{
+    pub reply: String,
+    pub console: Option<String>,
+}
+
+
+///Подмена ответов по файлу правил (--replies): правила проверяются до обработчиков команд (RspPacket::match_cmd)
+///Файл перечитывается, если изменился (по времени изменения и размеру). Если новый файл с ошибкой - остаются прежние правила
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptedReplies
+{
+    path: Option<String>,
+    stamp: Option<(SystemTime, u64)>,               // Время изменения и размер загруженного файла
+    rules: Vec<ReplyRule>,
+}
+
+
+impl ScriptedReplies
+{
+    ///Правила из текста: таблицы [[reply]] (таблицы других видов пропускаются - файл может быть общим, например с [[fault]])
+    pub fn parse(text: &str) -> Result<ScriptedReplies, String>
+    {
+        let rules = parse_rules(text)?.iter().filter(|t| t.kind == "reply").map(ReplyRule::from_table).collect::<Result<Vec<_>, _>>()?;
+        Ok(ScriptedReplies{ path: None, stamp: None, rules })
+    }
+
+
+    ///Загрузить правила из файла и следить за его изменением
+    pub fn load(path: &str) -> Result<ScriptedReplies, String>
+    {
+        let stamp = file_stamp(path);
+        let text = fs::read_to_string(path).map_err(|e| format!("Не удалось прочитать {}: {}", path, e))?;
+        let mut replies = ScriptedReplies::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        replies.path = Some(path.to_string());
+        replies.stamp = stamp;
+        Ok(replies)
+    }
+
+
+    pub fn rules(&self) -> &[ReplyRule]
+    {
+        &self.rules
+    }
+
+
+    ///Перечитать файл, если он изменился. Ok(true) - правила обновлены
+    pub fn reload_if_changed(&mut self) -> Result<bool, String>
+    {
+        let path = match self.path
+        {
+            Some(ref path) => path.clone(),
+            None => return Ok(false),
+        };
+        let stamp = file_stamp(&path);
+        if stamp == self.stamp
+        {
+            return Ok(false);
+        }
+        self.stamp = stamp; //Ошибка в файле выводится один раз, а не на каждый пакет
+        let reloaded = ScriptedReplies::load(&path)?;
+        self.rules = reloaded.rules;
+        Ok(true)
+    }
+
+
+    ///Ответ на пакет с данными data по первому подходящему правилу
+    pub fn find(&self, data: &str) -> Option<ScriptedReply>
+    {
+        self.rules.iter().find_map(|rule|
+        {
+            let captures = glob_match(&rule.pattern, data)?;
+            Some(ScriptedReply{
+                reply: render_template(&rule.reply, &captures).ok()?,
+                console: match rule.console
+                {
+                    Some(ref console) => Some(render_template(console, &captures).ok()?),
+                    None => None,
+                },
+            })
+        })
+    }
+}
+
+
+fn file_stamp(path: &str) -> Option<(SystemTime, u64)>
+{
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+
+///Сопоставить текст с шаблоном, в котором '*' - любая последовательность символов
+///Возвращает части текста под каждой '*' (None - не подходит). При нескольких вариантах '*' берет как можно меньше
+pub fn glob_match<'t>(pattern: &str, text: &'t str) -> Option<Vec<&'t str>>
+{
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = text.strip_prefix(first)?;
+    let mut pos = text.len() - rest.len();
+    let mut captures = Vec::new();
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate()
+    {
+        let found = if i == parts.len() - 1
+        {//Последняя часть - в конце текста
+            if rest.ends_with(part) {Some(rest.len() - part.len())} else {None}
+        }
+        else
+        {
+            rest.find(part)
+        }?;
+        captures.push(&text[pos .. pos+found]);
+        pos += found + part.len();
+        rest = &text[pos..];
+    }
+    if parts.is_empty() && !rest.is_empty()
+    {
+        return None;
+    }
+    Some(captures)
+}
+
+
+///Ответ по шаблону:
+///{1}, {2} ... - части данных пакета под '*' шаблона match;
+///{hex:text} - text в hex (для qRcmd и O-пакетов), внутри можно использовать {N};
+///{fill:HH:len} - байт HH, повторенный len раз (len - hex, как в m-пакете, не больше FILL_MAX), например "{fill:00:{2}}" на "m*,*";
+///{{ и }} - символы '{' и '}'
+pub fn render_template(template: &str, captures: &[&str]) -> Result<String, String>
+{
+    let mut result = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((pos, c)) = chars.next()
+    {
+        match c
+        {
+            '{' if chars.peek().map(|&(_, c)| c) == Some('{') => { chars.next(); result.push('{'); },
+            '}' if chars.peek().map(|&(_, c)| c) == Some('}') => { chars.next(); result.push('}'); },
+            '{' =>
+            {//Найти парную '}' с учетом вложенных {N}
+                let mut depth = 1;
+                let mut end = None;
+                for (p, c) in chars.by_ref()
+                {
+                    match c
+                    {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0
+                    {
+                        end = Some(p);
+                        break;
+                    }
+                }
+                let end = end.ok_or_else(|| format!("незакрытая '{{' в позиции {}", pos))?;
+                result.push_str(&render_placeholder(&template[pos+1 .. end], captures)?);
+            },
+            '}' => return Err(format!("'}}' без '{{' в позиции {}", pos)),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+
+///Наибольшая длина {fill} в байтах
+pub const FILL_MAX: usize = 0x10_0000;
+
+
+fn render_placeholder(body: &str, captures: &[&str]) -> Result<String, String>
+{
+    if let Some(text) = body.strip_prefix("hex:")
+    {
+        return Ok(hex_encode(render_template(text, captures)?.as_bytes()));
+    }
+    if let Some(args) = body.strip_prefix("fill:")
+    {
+        let (byte, len) = args.split_once(':').ok_or_else(|| format!("ожидается {{fill:HH:len}} вместо {{{}}}", body))?;
+        let byte = u8::from_str_radix(byte, 16).map_err(|_| format!("некорректный байт \'{}\' в {{fill}}", byte))?;
+        let len = render_template(len, captures)?;
+        //При проверке шаблона на загрузке {N} пустые: длина 0
+        let len = if len.is_empty() {0} else {usize::from_str_radix(&len, 16).map_err(|_| format!("некорректная длина \'{}\' в {{fill}}", len))?};
+        if len > FILL_MAX
+        {//Длину присылает клиент: ответ такой длины все равно не поместится в пакет
+            return Err(format!("длина {:x} в {{fill}} больше {:x}", len, FILL_MAX));
+        }
+        return Ok(format!("{:02x}", byte).repeat(len));
+    }
+    let index: usize = body.parse().map_err(|_| format!("неизвестная подстановка {{{}}}", body))?;
+    match index.checked_sub(1).and_then(|i| captures.get(i))
+    {
+        Some(capture) => Ok(capture.to_string()),
+        None => Err(format!("{{{}}}: в шаблоне match {} '*'", index, captures.len())),
+    }
+}
+
+
+///Тесты для подмены ответов ================================================================================
+#[cfg(test)]
+mod test_scripted
+{
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_glob_match()
+    {
+        assert_eq!(Some(vec![]), glob_match("qOffsets", "qOffsets"));
+        assert_eq!(None, glob_match("qOffsets", "qOffsetsX"));
+        assert_eq!(None, glob_match("qC", "qOffsets"));
+        assert_eq!(Some(vec!["10030000", "8"]), glob_match("m*,*", "m10030000,8"));
+        assert_eq!(Some(vec!["", "ff"]), glob_match("m*1000*", "m1000ff"));
+        assert_eq!(Some(vec!["a,b"]), glob_match("m*,c", "ma,b,c"));
+        assert_eq!(Some(vec!["Rcmd,7265"]), glob_match("q*", "qRcmd,7265"));
+        assert_eq!(None, glob_match("m*,8", "m1000,80"));
+    }
+
+    #[test]
+    fn test_render_template()
+    {
+        let captures = ["10030000", "4"];
+        assert_eq!(Ok("Text=1000;Data=1000;Bss=1000".to_string()), render_template("Text=1000;Data=1000;Bss=1000", &[]));
+        assert_eq!(Ok("addr 10030000 {len 4}".to_string()), render_template("addr {1} {{len {2}}}", &captures));
+        assert_eq!(Ok("00000000".to_string()), render_template("{fill:00:{2}}", &captures));
+        assert_eq!(Ok("68692031303033303030300a".to_string()), render_template("{hex:hi {1}\n}", &captures));
+        assert!(render_template("{3}", &captures).is_err());
+        assert!(render_template("{0}", &captures).is_err());
+        assert!(render_template("{1", &captures).is_err());
+        assert!(render_template("1}", &captures).is_err());
+        assert!(render_template("{upper:1}", &captures).is_err());
+        assert!(render_template("{fill:zz:4}", &captures).is_err());
+        assert_eq!(2 * FILL_MAX, render_template(&format!("{{fill:00:{:x}}}", FILL_MAX), &captures).unwrap().len());
+        assert!(render_template(&format!("{{fill:00:{:x}}}", FILL_MAX + 1), &captures).is_err());
+    }
+
+    #[test]
+    fn test_find()
+    {
+        let replies = ScriptedReplies::parse("[[reply]]\nmatch = \"m10030000,8\"\nreply = \"0102030405060708\"\n\n\
+                                              [[fault]]\ncmd = \"m\"\n\n\
+                                              [[reply]]\nmatch = \"m*,*\"\nreply = \"{fill:ab:{2}}\"\n\n\
+                                              [[reply]]\nmatch = \"qRcmd,*\"\nreply = \"OK\"\nconsole = \"monitor {1}\\n\"\n").unwrap();
+        assert_eq!(3, replies.rules().len());
+        assert_eq!(Some("0102030405060708".to_string()), replies.find("m10030000,8").map(|r| r.reply));
+        assert_eq!(Some("abab".to_string()), replies.find("m0,2").map(|r| r.reply));
+        assert_eq!(Some(ScriptedReply{ reply: "OK".to_string(), console: Some("monitor 7265\n".to_string()) }), replies.find("qRcmd,7265"));
+        assert_eq!(None, replies.find("qOffsets"));
+        //Длина не hex: правило пропускается
+        assert_eq!(None, replies.find("m0,zz"));
+        //Огромная длина от клиента: правило пропускается, а не выделяется память под ответ
+        assert_eq!(None, replies.find("m1000,ffffffffffff"));
+
+        assert!(ScriptedReplies::parse("[[reply]]\nmatch = \"m*\"\nreply = \"{2}\"").is_err());
+        assert!(ScriptedReplies::parse("[[reply]]\nmatch = \"m*\"").is_err());
+    }
+
+    #[test]
+    fn test_reload_if_changed()
+    {
+        let path = env::temp_dir().join(format!("gdb-rsp-replies-{}.toml", std::process::id()));
+        let path_str = path.to_string_lossy().into_owned();
+        fs::write(&path, "[[reply]]\nmatch = \"qOffsets\"\nreply = \"Text=0\"\n").unwrap();
+        let mut replies = ScriptedReplies::load(&path_str).unwrap();
+        assert_eq!(Ok(false), replies.reload_if_changed());
+        assert_eq!(Some("Text=0".to_string()), replies.find("qOffsets").map(|r| r.reply));
+
+        sleep(Duration::from_millis(10));
+        fs::write(&path, "[[reply]]\nmatch = \"qOffsets\"\nreply = \"Text=1000\"\n").unwrap();
+        assert_eq!(Ok(true), replies.reload_if_changed());
+        assert_eq!(Some("Text=1000".to_string()), replies.find("qOffsets").map(|r| r.reply));
+
+        //Файл с ошибкой: прежние правила остаются
+        fs::write(&path, "[[reply]]\nmatch = \"qOffsets\"\n").unwrap();
+        assert!(replies.reload_if_changed().is_err());
+        assert_eq!(Ok(false), replies.reload_if_changed());
+        assert_eq!(Some("Text=1000".to_string()), replies.find("qOffsets").map(|r| r.reply));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+use target::{Target, TargetResult};
+use host_io::{O_RDONLY, O_WRONLY, O_RDWR, O_APPEND, O_CREAT, O_TRUNC};
+
+
+///Последовательность инструкций вызова semihosting RISC-V: slli x0, x0, 0x1f; ebreak; srai x0, x0, 7
+pub const SLLI: u32 = 0x01f0_1013;
+pub const EBREAK: u32 = 0x0010_0073;
+pub const SRAI: u32 = 0x4070_5013;
+
+///Номера операций semihosting (a0)
+pub const SYS_OPEN: u64 = 0x01;
+pub const SYS_CLOSE: u64 = 0x02;
+pub const SYS_WRITEC: u64 = 0x03;
+pub const SYS_WRITE0: u64 = 0x04;
+pub const SYS_WRITE: u64 = 0x05;
+pub const SYS_READ: u64 = 0x06;
+pub const SYS_ISTTY: u64 = 0x09;
+pub const SYS_SEEK: u64 = 0x0a;
+pub const SYS_REMOVE: u64 = 0x0e;
+pub const SYS_RENAME: u64 = 0x0f;
+pub const SYS_SYSTEM: u64 = 0x12;
+pub const SYS_ERRNO: u64 = 0x13;
+
+///Права создаваемого файла: 0644
+const CREATE_MODE: u64 = 0o644;
+///Ограничение длины строки SYS_WRITE0: строка без '\0' не должна читаться до конца памяти
+const WRITE0_MAX: usize = 4096;
+///Ограничение длины имени файла (и команды SYS_SYSTEM) вместе с '\0', как PATH_MAX
+const PATH_MAX: u64 = 4096;
+
+
+///Результат вызова semihosting
+#[derive(Debug, Clone, PartialEq)]
+pub enum SemihostingCall
+{
+    FileIo(String),                                 // Запрос к GDB без '$' и '#cs': "Fwrite,1,80001000,d". Результат - после ответа $F
+    Done(u64),                                      // Вызов выполнен без GDB (или не поддерживается): значение для a0
+}
+
+
+///Semihosting программы цели: вызовы превращаются в запросы File-I/O к GDB ("F"-пакеты)
+///Параметры вызова - блок слов XLEN (8 байт для RV64) по адресу из a1, результат - в a0
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Semihosting
+{
+    pending: Option<(u64, u64)>,                    // Вызов, ожидающий ответа GDB: (операция, длина для SYS_WRITE/SYS_READ)
+    errno: u64,                                     // errno последнего ответа GDB (SYS_ERRNO)
+}
+
+
+impl Semihosting
+{
+    ///Вызов операции op с параметром param (a1). Параметры и строки читаются из памяти цели
+    pub fn call(&mut self, op: u64, param: u64, target: &mut dyn Target) -> TargetResult<SemihostingCall>
+    {
+        let request = match op
+        {
+            SYS_OPEN =>
+            {
+                let [name, mode, len] = words(target, param)?;
+                if len == 3 && target.read_memory(name, 3)? == b":tt"
+                {//Консоль: r - stdin, w - stdout, a - stderr
+                    return Ok(SemihostingCall::Done(mode / 4));
+                }
+                let flags = match mode / 4
+                {
+                    0 => if mode & 2 == 0 {O_RDONLY} else {O_RDWR},
+                    1 => (if mode & 2 == 0 {O_WRONLY} else {O_RDWR}) | O_CREAT | O_TRUNC,
+                    _ => (if mode & 2 == 0 {O_WRONLY} else {O_RDWR}) | O_CREAT | O_APPEND,
+                };
+                let Some(len) = name_len(len) else {return Ok(SemihostingCall::Done(u64::MAX))};
+                format!("Fopen,{:x}/{:x},{:x},{:x}", name, len, flags, CREATE_MODE)
+            },
+            SYS_CLOSE => format!("Fclose,{:x}", words::<1>(target, param)?[0]),
+            SYS_WRITEC => format!("Fwrite,1,{:x},1", param),
+            SYS_WRITE0 =>
+            {
+                let text = target.read_memory(param, WRITE0_MAX)?;
+                match text.iter().position(|&c| c == 0)
+                {
+                    Some(0) => return Ok(SemihostingCall::Done(0)),
+                    Some(len) => format!("Fwrite,1,{:x},{:x}", param, len),
+                    None => format!("Fwrite,1,{:x},{:x}", param, WRITE0_MAX),
+                }
+            },
+            SYS_WRITE | SYS_READ =>
+            {
+                let [fd, buf, len] = words(target, param)?;
+                self.pending = Some((op, len));
+                return Ok(SemihostingCall::FileIo(format!("F{},{:x},{:x},{:x}", if op == SYS_WRITE {"write"} else {"read"}, fd, buf, len)));
+            },
+            SYS_ISTTY => format!("Fisatty,{:x}", words::<1>(target, param)?[0]),
+            SYS_SEEK =>
+            {
+                let [fd, pos] = words(target, param)?;
+                format!("Flseek,{:x},{:x},0", fd, pos) //SEEK_SET
+            },
+            SYS_REMOVE =>
+            {
+                let [name, len] = words(target, param)?;
+                let Some(len) = name_len(len) else {return Ok(SemihostingCall::Done(u64::MAX))};
+                format!("Funlink,{:x}/{:x}", name, len)
+            },
+            SYS_RENAME =>
+            {
+                let [old, old_len, new, new_len] = words(target, param)?;
+                let (Some(old_len), Some(new_len)) = (name_len(old_len), name_len(new_len)) else {return Ok(SemihostingCall::Done(u64::MAX))};
+                format!("Frename,{:x}/{:x},{:x}/{:x}", old, old_len, new, new_len)
+            },
+            SYS_SYSTEM =>
+            {
+                let [command, len] = words(target, param)?;
+                let Some(len) = name_len(len) else {return Ok(SemihostingCall::Done(u64::MAX))};
+                format!("Fsystem,{:x}/{:x}", command, len)
+            },
+            SYS_ERRNO => return Ok(SemihostingCall::Done(self.errno)),
+            _ => return Ok(SemihostingCall::Done(u64::MAX)), //-1: операция не поддерживается
+        };
+        self.pending = Some((op, 0));
+        Ok(SemihostingCall::FileIo(request))
+    }
+
+
+    ///Ответ GDB на запрос: retcode и errno из $F<retcode>[,errno]. Возвращает значение для a0 (None - запроса не было)
+    pub fn reply(&mut self, retcode: i64, errno: Option<u64>) -> Option<u64>
+    {
+        let (op, len) = self.pending.take()?;
+        if let Some(errno) = errno
+        {
+            self.errno = errno;
+        }
+        Some(match op
+        {
+            //Semihosting возвращает число НЕ записанных (не прочитанных) байт
+            SYS_WRITE | SYS_READ if retcode >= 0 => len.saturating_sub(retcode as u64),
+            SYS_WRITE | SYS_READ => len,
+            SYS_SEEK | SYS_CLOSE | SYS_REMOVE | SYS_RENAME if retcode >= 0 => 0,
+            _ => retcode as u64,
+        })
+    }
+
+
+    ///Вызов ждет ответа GDB
+    pub fn is_pending(&self) -> bool
+    {
+        self.pending.is_some()
+    }
+}
+
+
+///Начало последовательности вызова semihosting по коду трех инструкций (little-endian)
+pub fn is_semihosting_call(code: &[u8]) -> bool
+{
+    let word = |i: usize| code.get(i*4 .. i*4+4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    word(0) == Some(SLLI) && word(1) == Some(EBREAK) && word(2) == Some(SRAI)
+}
+
+
+///Длина строки для запроса File-I/O - вместе с '\0'. None - длина из блока параметров больше PATH_MAX (результат вызова -1)
+fn name_len(len: u64) -> Option<u64>
+{
+    len.checked_add(1).filter(|&len| len <= PATH_MAX)
+}
+
+
+///N слов блока параметров (RV64: 8 байт, little-endian)
+fn words<const N: usize>(target: &mut dyn Target, addr: u64) -> TargetResult<[u64; N]>
+{
+    let bytes = target.read_memory(addr, N * 8)?;
+    let mut words = [0u64; N];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(8))
+    {
+        let mut le = [0u8; 8];
+        le[..chunk.len()].copy_from_slice(chunk);
+        *word = u64::from_le_bytes(le);
+    }
+    Ok(words)
+}
+
+
+///Тесты для Semihosting ================================================================================
+#[cfg(test)]
+mod test_semihosting
+{
+    use super::*;
+    use sim::SimTarget;
+
+    fn block(target: &mut SimTarget, addr: u64, words: &[u64])
+    {
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        target.write_memory(addr, &bytes).unwrap();
+    }
+
+    #[test]
+    fn test_calls()
+    {
+        let mut target = SimTarget::new(false);
+        let mut semihosting = Semihosting::default();
+        target.write_memory(0x2000, b"log.txt\0:tt\0hello\0").unwrap();
+
+        block(&mut target, 0x1000, &[0x2000, 4, 7]); //"w"
+        assert_eq!(Ok(SemihostingCall::FileIo("Fopen,2000/8,601,1a4".to_string())), semihosting.call(SYS_OPEN, 0x1000, &mut target));
+        assert!(semihosting.is_pending());
+        assert_eq!(Some(3), semihosting.reply(3, None));
+        assert_eq!(None, semihosting.reply(3, None));
+        block(&mut target, 0x1000, &[0x2000, 9, 7]); //"ab"
+        assert_eq!(Ok(SemihostingCall::FileIo("Fopen,2000/8,209,1a4".to_string())), semihosting.call(SYS_OPEN, 0x1000, &mut target));
+        semihosting.reply(-1, Some(2));
+        assert_eq!(Ok(SemihostingCall::Done(2)), semihosting.call(SYS_ERRNO, 0, &mut target));
+        block(&mut target, 0x1000, &[0x2008, 8, 3]); //":tt" "a" - stderr
+        assert_eq!(Ok(SemihostingCall::Done(2)), semihosting.call(SYS_OPEN, 0x1000, &mut target));
+
+        //SYS_WRITE: результат - число незаписанных байт
+        block(&mut target, 0x1000, &[3, 0x200c, 5]);
+        assert_eq!(Ok(SemihostingCall::FileIo("Fwrite,3,200c,5".to_string())), semihosting.call(SYS_WRITE, 0x1000, &mut target));
+        assert_eq!(Some(2), semihosting.reply(3, None));
+        assert_eq!(Ok(SemihostingCall::FileIo("Fwrite,1,200c,5".to_string())), semihosting.call(SYS_WRITE0, 0x200c, &mut target));
+        assert_eq!(Some(5), semihosting.reply(5, None));
+        assert_eq!(Ok(SemihostingCall::FileIo("Fwrite,1,200c,1".to_string())), semihosting.call(SYS_WRITEC, 0x200c, &mut target));
+        block(&mut target, 0x1000, &[3]);
+        assert_eq!(Ok(SemihostingCall::FileIo("Fclose,3".to_string())), semihosting.call(SYS_CLOSE, 0x1000, &mut target));
+        assert_eq!(Some(0), semihosting.reply(0, None));
+        block(&mut target, 0x1000, &[0x2000, 7, 0x200c, 5]);
+        assert_eq!(Ok(SemihostingCall::FileIo("Frename,2000/8,200c/6".to_string())), semihosting.call(SYS_RENAME, 0x1000, &mut target));
+        assert_eq!(Ok(SemihostingCall::Done(u64::MAX)), semihosting.call(0x30, 0x1000, &mut target));
+    }
+
+    #[test]
+    fn test_name_length()
+    {
+        let mut target = SimTarget::new(false);
+        let mut semihosting = Semihosting::default();
+        target.write_memory(0x2000, b":tt\0").unwrap();
+
+        //Длина из памяти цели не читается целиком и не переполняется: результат -1 без запроса к GDB
+        for len in [u64::MAX, 1 << 40, PATH_MAX]
+        {
+            block(&mut target, 0x1000, &[0x2000, 0, len]);
+            assert_eq!(Ok(SemihostingCall::Done(u64::MAX)), semihosting.call(SYS_OPEN, 0x1000, &mut target));
+            block(&mut target, 0x1000, &[0x2000, len]);
+            assert_eq!(Ok(SemihostingCall::Done(u64::MAX)), semihosting.call(SYS_REMOVE, 0x1000, &mut target));
+            assert_eq!(Ok(SemihostingCall::Done(u64::MAX)), semihosting.call(SYS_SYSTEM, 0x1000, &mut target));
+            block(&mut target, 0x1000, &[0x2000, 3, 0x2000, len]);
+            assert_eq!(Ok(SemihostingCall::Done(u64::MAX)), semihosting.call(SYS_RENAME, 0x1000, &mut target));
+        }
+        assert!(!semihosting.is_pending());
+
+        block(&mut target, 0x1000, &[0x2000, 0, PATH_MAX - 1]);
+        assert_eq!(Ok(SemihostingCall::FileIo(format!("Fopen,2000/{:x},0,1a4", PATH_MAX))), semihosting.call(SYS_OPEN, 0x1000, &mut target));
+    }
+
+    #[test]
+    fn test_sequence()
+    {
+        let code: Vec<u8> = [SLLI, EBREAK, SRAI].iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert!(is_semihosting_call(&code));
+        assert!(!is_semihosting_call(&code[4..]));
+        assert!(!is_semihosting_call(&[0; 12]));
+    }
+}
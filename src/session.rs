@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+
+use gdb_server::PACKET_SIZE;
+use target::{ThreadId, ResumeAction};
+use host_io::HostIo;
+
+
+///Значение feature из qSupported
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureValue
+{
+    Supported,                                      // name+
+    Unsupported,                                    // name-
+    Query,                                          // name?
+    Value(String),                                  // name=value
+}
+
+
+///Features, которые GDB-клиент прислал в qSupported (в порядке получения)
+///$qSupported:multiprocess+;swbreak+;hwbreak+;qRelocInsn+;xmlRegisters=i386;...
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientFeatures
+{
+    features: Vec<(String, FeatureValue)>,
+}
+
+
+impl ClientFeatures
+{
+    ///Разобрать список features (то, что после "qSupported:")
+    pub fn parse(list: &str) -> ClientFeatures
+    {
+        let mut features = Vec::new();
+        for item in list.split(';').filter(|item| !item.is_empty())
+        {
+            let feature = if let Some(eq_pos) = item.find('=')
+            {
+                (item[..eq_pos].to_string(), FeatureValue::Value(item[eq_pos+1..].to_string()))
+            }
+            else
+            {
+                let (name, last) = item.split_at(item.char_indices().last().map_or(0, |(pos, _)| pos));
+                match last
+                {
+                    "+" => (name.to_string(), FeatureValue::Supported),
+                    "-" => (name.to_string(), FeatureValue::Unsupported),
+                    "?" => (name.to_string(), FeatureValue::Query),
+                    _ => (item.to_string(), FeatureValue::Supported), //Без суффикса - считать поддерживаемой
+                }
+            };
+            if !feature.0.is_empty()
+            {//Некорректный элемент без названия ("+", "=1") пропускается
+                features.push(feature);
+            }
+        }
+        ClientFeatures{ features }
+    }
+
+
+    ///Значение feature. None - если клиент её не присылал
+    pub fn get(&self, name: &str) -> Option<&FeatureValue>
+    {
+        self.features.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+
+    ///Клиент поддерживает feature (name+)
+    pub fn supports(&self, name: &str) -> bool
+    {
+        self.get(name) == Some(&FeatureValue::Supported)
+    }
+
+
+    ///Значение feature вида name=value
+    pub fn value(&self, name: &str) -> Option<&str>
+    {
+        match self.get(name)
+        {
+            Some(FeatureValue::Value(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, FeatureValue)>
+    {
+        self.features.iter()
+    }
+}
+
+
+///Состояние одного подключения GDB-клиента
+#[derive(Debug)]
+pub struct Session
+{
+    pub packet_size: usize,                         // Размер RSP-пакета (PacketSize) для этого подключения
+    pub client_features: ClientFeatures,            // Features из последнего qSupported
+    pub server_features: Vec<String>,               // Features, объявленные сервером в ответ на последний qSupported ("PacketSize=1000", "swbreak+", ...)
+    pub no_ack_mode: bool,                          // Включен no-acknowledgment режим (QStartNoAckMode)
+    pub thread_info: Vec<u64>,                      // Потоки, еще не выданные в ответ на qfThreadInfo/qsThreadInfo
+    pub cont_thread: ThreadId,                      // Поток для 'c'/'s' (Hc)
+    pub non_stop: bool,                             // Включен non-stop режим (QNonStop:1)
+    pub stop_queue: VecDeque<String>,               // Non-stop: Stop Reply, которые GDB еще не забрал (%Stop и vStopped)
+    pub notification_pending: bool,                 // Non-stop: отправлено уведомление %Stop, очередь еще не выбрана через vStopped до OK
+    pub file_io_resume: Option<(Vec<(u64, ResumeAction)>, String)>, // Действия и команда (c, s, vCont), прерванные запросом File-I/O: продолжаются после $F
+    pub host_io: Option<HostIo>,                    // Файлы хоста для vFile-пакетов (--sandbox). None - host I/O не поддерживается
+    pub trace_upload: VecDeque<String>,             // Строки tracepoints, еще не выданные в ответ на qTfP/qTsP
+}
+
+
+impl Session
+{
+    pub fn new() -> Session
+    {
+        Session::with_packet_size(PACKET_SIZE)
+    }
+
+
+    pub fn with_packet_size(packet_size: usize) -> Session
+    {
+        Session{
+            packet_size,
+            client_features: ClientFeatures::default(),
+            server_features: Vec::new(),
+            no_ack_mode: false,
+            thread_info: Vec::new(),
+            cont_thread: ThreadId::All,
+            non_stop: false,
+            stop_queue: VecDeque::new(),
+            notification_pending: false,
+            file_io_resume: None,
+            host_io: None,
+            trace_upload: VecDeque::new(),
+        }
+    }
+
+
+    ///Сервер объявил поддержку feature (name+) в ответ на qSupported
+    pub fn server_supports(&self, name: &str) -> bool
+    {
+        self.server_features.iter().any(|f| f.len() == name.len() + 1 && f.starts_with(name) && f.ends_with('+'))
+    }
+
+
+    ///Согласованы multiprocess extensions: thread-id в ответах сервера - pPID.TID
+    pub fn multiprocess(&self) -> bool
+    {
+        self.client_features.supports("multiprocess") && self.server_supports("multiprocess")
+    }
+}
+
+
+impl Default for Session
+{
+    fn default() -> Session
+    {
+        Session::new()
+    }
+}
+
+
+///Тесты для ClientFeatures ================================================================================
+#[cfg(test)]
+mod test_session
+{
+    use super::*;
+
+    #[test]
+    fn test_client_features_parse()
+    {
+        let f = ClientFeatures::parse("multiprocess+;swbreak+;hwbreak-;qRelocInsn+;xmlRegisters=i386;fork-events?");
+        assert!(f.supports("multiprocess"));
+        assert!(f.supports("swbreak"));
+        assert!(!f.supports("hwbreak"));
+        assert_eq!(Some(&FeatureValue::Unsupported), f.get("hwbreak"));
+        assert_eq!(Some(&FeatureValue::Query), f.get("fork-events"));
+        assert_eq!(Some("i386"), f.value("xmlRegisters"));
+        assert_eq!(None, f.get("error-message"));
+        assert_eq!(6, f.iter().count());
+
+        //Пустой список (qSupported без параметров)
+        assert_eq!(0, ClientFeatures::parse("").iter().count());
+        //Не-ASCII символ в конце и элементы без названия
+        let f = ClientFeatures::parse("é;swbreaké+;+;=1");
+        assert!(f.supports("é"));
+        assert!(f.supports("swbreaké"));
+        assert_eq!(2, f.iter().count());
+    }
+}
@@ -1,43 +1,739 @@
+use std::collections::HashMap;
+use std::mem;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use target::{Target, TargetResult, StopReason, MatchpointType, ResumeAction, ConsoleSink};
+use decode::signal_name;
+use semihosting::{Semihosting, SemihostingCall, is_semihosting_call};
+use trace::TraceExperiment;
+use agent_expr::{condition_holds, eval};
 
-pub fn parse_args() -> bool
+
+///Run simulation
+///loop_run: имитация исполнения в цикле до прерывания по ^C (cancel_flag). Иначе - мгновенный останов
+pub fn run_sim(cancel_flag: &Arc<AtomicBool>, loop_run: bool)
 {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1
+    if loop_run
     {
-        match &args[1][..]
+        loop
         {
-            "--loop" | "-l"=>
-            {
-                println!("  Started with loop run simulation\n");
-                true
-            },
-            _=>
+            if cancel_flag.load(Ordering::SeqCst)
             {
-                println!("  Unknown argument {:?}\n", args[1]);
-                false
+                break
             }
         }
     }
-    else
+}
+
+
+///Количество регистров, которые передаются в 'g'/'G': x0..x31 и pc (RV64)
+pub const REG_COUNT: usize = 33;
+///Номер регистра pc
+pub const PC_REG: usize = 32;
+///Размер регистра в байтах
+pub const REG_SIZE: usize = 8;
+///Размер страницы памяти симулятора
+pub const PAGE_SIZE: usize = 4096;
+///Регистры a0, a1: операция и параметр вызова semihosting, a0 - результат
+pub const A0_REG: usize = 10;
+pub const A1_REG: usize = 11;
+///Продолжение до breakpoint без loop_run: больше стольких инструкций подряд не исполняется, дальше - как без breakpoints
+pub const TRACE_RUN_LIMIT: usize = 0x10_0000;
+///Инструкции исполняются частями такой длины: между частями проверяется ^C, в non-stop - принимаются пакеты GDB
+pub const RUN_SLICE: usize = 0x1000;
+
+
+///Один hart симулятора (для GDB - поток с номером index + 1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hart
+{
+    pub regs: [u64; REG_COUNT],                     // x0..x31, pc
+    pub stop: StopReason,                           // Причина последнего останова этого hart (Signal(0) - остановлен вместе с другим hart)
+    pub running: bool,                              // Hart исполняется (только в non-stop режиме)
+    pub executed: usize,                            // Non-stop: исполнено инструкций с последнего продолжения
+}
+
+
+impl Hart
+{
+    fn new() -> Hart
     {
-        false
+        Hart{ regs: [0; REG_COUNT], stop: StopReason::Signal(0x02), running: false, executed: 0 }
     }
 }
 
-pub fn run_sim(cancel_flag: &Arc<AtomicBool>) -> Result<(),()>
-{//Run simulation
-    if parse_args() == true
+
+///Цель-симулятор: регистры и память хранятся (запись и последующее чтение возвращают записанное)
+///Без breakpoints исполнение имитируется так же, как в DummyTarget: цикл до ^C или мгновенный останов
+///Несколько harts с общей памятью видны GDB как потоки; регистры у каждого hart свои
+///В non-stop режиме продолжающие harts исполняются по частям между пакетами GDB (poll_threads) так же, как в all-stop:
+///до breakpoint, а без loop_run - не дольше TRACE_RUN_LIMIT инструкций. Без breakpoints hart с loop_run исполняется до vCont;t или vCtrlC
+///All-stop: если продолжающий hart стоит на последовательности semihosting (slli/ebreak/srai), вызов выполняется до исполнения:
+///запрос File-I/O к GDB или результат сразу в a0. Несколько вызовов подряд выполняются по очереди
+///Если установлены breakpoints или идет эксперимент трассировки, all-stop продолжение исполняет инструкции подряд (pc += 4)
+///до breakpoint, на каждой проходятся tracepoints. Шаг тоже проходит tracepoint по адресу до шага
+///Breakpoint с условиями останавливает hart, только если истинно хотя бы одно из них
+///Breakpoint с командами (dprintf) не останавливает hart: команды исполняются, в all-stop вывод printf отправляется через set_console
+///после каждой части исполнения (RUN_SLICE), в non-stop и без set_console - копится до take_console_output
+///После отключения GDB остаются только breakpoints с persist-командами
+pub struct SimTarget
+{
+    pub loop_run: bool,                             // Имитация исполнения в цикле (до ^C)
+    pub harts: Vec<Hart>,                           // Harts (потоки 1..=N)
+    current: usize,                                 // Индекс hart для операций с регистрами (Hg, поток последнего останова)
+    pages: HashMap<u64, Vec<u8>>,                   // Страницы памяти по номеру страницы. Память, в которую не писали, читается нулями
+    breakpoints: Vec<(MatchpointType, u64, usize)>, // Установленные matchpoints
+    conditions: Vec<(u64, Vec<Vec<u8>>)>,           // Условия breakpoints по адресу (байткод агентских выражений)
+    commands: Vec<(u64, Vec<Vec<u8>>, bool)>,       // Команды breakpoints по адресу и признак persist
+    console: String,                                // Вывод команд breakpoints, еще не выданный take_console_output
+    console_sink: Option<ConsoleSink>,              // Куда отправлять вывод во время all-stop исполнения (set_console)
+    stops: Vec<(u64, StopReason)>,                  // Non-stop: остановы, еще не выданные take_stops
+    semihosting: Semihosting,                       // Вызовы semihosting
+    file_io: Option<String>,                        // Запрос File-I/O, еще не выданный file_io_request
+    trace: TraceExperiment,                         // Tracepoints и кадры трассировки
+}
+
+
+impl SimTarget
+{
+    pub fn new(loop_run: bool) -> SimTarget
+    {
+        SimTarget::with_harts(loop_run, 1)
+    }
+
+
+    ///Симулятор с несколькими harts (не меньше одного)
+    pub fn with_harts(loop_run: bool, hart_count: usize) -> SimTarget
+    {
+        SimTarget{
+            loop_run,
+            harts: vec![Hart::new(); hart_count.max(1)],
+            current: 0,
+            pages: HashMap::new(),
+            breakpoints: Vec::new(),
+            conditions: Vec::new(),
+            commands: Vec::new(),
+            console: String::new(),
+            console_sink: None,
+            stops: Vec::new(),
+            semihosting: Semihosting::default(),
+            file_io: None,
+            trace: TraceExperiment::default(),
+        }
+    }
+
+
+    ///Установленные matchpoints
+    pub fn matchpoints(&self) -> &[(MatchpointType, u64, usize)]
+    {
+        &self.breakpoints
+    }
+
+
+    ///Регистры текущего hart
+    pub fn regs(&self) -> &[u64; REG_COUNT]
+    {
+        &self.harts[self.current].regs
+    }
+
+
+    ///Регистры исполняющегося hart недоступны
+    fn check_halted(&self) -> TargetResult<()>
+    {
+        if self.harts[self.current].running {Err(0x01)} else {Ok(())}
+    }
+
+
+    ///Останов hart в non-stop режиме
+    fn halt(&mut self, index: usize, stop: StopReason)
+    {
+        self.harts[index].running = false;
+        self.harts[index].stop = stop;
+        self.stops.push((index as u64 + 1, stop));
+    }
+
+
+    ///Индекс hart по номеру потока
+    fn hart_index(&self, tid: u64) -> Option<usize>
+    {
+        let index = (tid as usize).checked_sub(1)?;
+        if index < self.harts.len() {Some(index)} else {None}
+    }
+
+
+    ///Вызовы semihosting с pc hart, пока они идут подряд. Вызов, требующий GDB, оставляет pc на ebreak
+    ///Возвращает true, если hart ждет ответа GDB на запрос File-I/O
+    fn run_semihosting(&mut self, index: usize) -> bool
     {
         loop
+        {
+            let pc = self.harts[index].regs[PC_REG];
+            let code: Vec<u8> = (0..12).map(|i| self.byte(pc.wrapping_add(i))).collect();
+            if !is_semihosting_call(&code)
+            {
+                return false;
+            }
+            self.harts[index].regs[PC_REG] = pc.wrapping_add(4); //ebreak
+            let (op, param) = (self.harts[index].regs[A0_REG], self.harts[index].regs[A1_REG]);
+            let mut semihosting = mem::take(&mut self.semihosting);
+            let call = semihosting.call(op, param, self);
+            self.semihosting = semihosting;
+            let result = match call
+            {
+                Ok(SemihostingCall::FileIo(request)) =>
+                {
+                    self.file_io = Some(request);
+                    return true;
+                },
+                Ok(SemihostingCall::Done(result)) => result,
+                Err(_) => u64::MAX,
+            };
+            self.harts[index].regs[A0_REG] = result;
+            self.harts[index].regs[PC_REG] = pc.wrapping_add(12);
+        }
+    }
+
+
+    ///Tracepoints по адресу pc hart (регистры кадра - этого hart)
+    fn trace_pass(&mut self, index: usize)
+    {
+        if !self.trace.is_running()
+        {
+            return;
+        }
+        let current = mem::replace(&mut self.current, index);
+        let mut trace = mem::take(&mut self.trace);
+        trace.pass(self.harts[index].regs[PC_REG], self);
+        self.trace = trace;
+        self.current = current;
+    }
+
+
+    ///Условия breakpoint по адресу pc (регистры - этого hart). Без условий - останов всегда
+    fn breakpoint_condition(&mut self, index: usize, pc: u64) -> bool
+    {
+        let conditions = match self.conditions.iter().find(|&&(addr, _)| addr == pc)
+        {
+            Some((_, conditions)) => conditions.clone(),
+            None => return true,
+        };
+        let current = mem::replace(&mut self.current, index);
+        let holds = conditions.iter().any(|condition| condition_holds(condition, self));
+        self.current = current;
+        holds
+    }
+
+
+    ///Команды breakpoint по адресу pc (регистры - этого hart). Ошибки вычисления пропускаются
+    ///Возвращает false, если команд нет и hart должен остановиться
+    fn breakpoint_commands(&mut self, index: usize, pc: u64) -> bool
+    {
+        let commands = match self.commands.iter().find(|&&(addr, _, _)| addr == pc)
+        {
+            Some((_, commands, _)) => commands.clone(),
+            None => return false,
+        };
+        let current = mem::replace(&mut self.current, index);
+        for command in &commands
+        {
+            if let Ok(evaluation) = eval(command, self)
+            {
+                self.console.push_str(&evaluation.output);
+            }
+        }
+        self.current = current;
+        true
+    }
+
+
+    ///Инструкции hart исполняются подряд до breakpoint: с loop_run - до ^C, без loop_run - не больше TRACE_RUN_LIMIT
+    ///^C проверяется, а вывод команд breakpoints отправляется между частями. Возвращает true, если hart остановился на breakpoint (pc - его адрес)
+    fn run_straight(&mut self, index: usize, cancel_flag: &Arc<AtomicBool>) -> bool
+    {
+        let mut executed = 0;
+        while self.loop_run || executed < TRACE_RUN_LIMIT
         {
             if cancel_flag.load(Ordering::SeqCst)
             {
-                break
+                return false;
+            }
+            let stopped = self.run_slice(index, RUN_SLICE);
+            if let Some(ref sink) = self.console_sink
+            {
+                if !self.console.is_empty()
+                {
+                    sink(&mem::take(&mut self.console));
+                }
+            }
+            if stopped
+            {
+                return true;
+            }
+            executed += RUN_SLICE;
+        }
+        false
+    }
+
+
+    ///Не больше steps инструкций hart подряд: на breakpoints проверяются условия и исполняются команды, проходятся tracepoints
+    ///Возвращает true, если hart остановился на breakpoint (pc - его адрес)
+    fn run_slice(&mut self, index: usize, steps: usize) -> bool
+    {
+        for _ in 0..steps
+        {
+            let pc = self.harts[index].regs[PC_REG];
+            if self.is_breakpoint(pc) && self.breakpoint_condition(index, pc) && !self.breakpoint_commands(index, pc)
+            {
+                return true;
             }
+            self.trace_pass(index);
+            self.harts[index].regs[PC_REG] = pc.wrapping_add(4);
         }
+        false
+    }
+
+
+    ///Адреса программных и аппаратных breakpoints (без watchpoints)
+    fn breakpoint_addrs(&self) -> impl Iterator<Item = u64> + '_
+    {
+        self.breakpoints.iter().filter(|&&(mp_type, _, _)| mp_type == MatchpointType::SwBreakpoint || mp_type == MatchpointType::HwBreakpoint).map(|&(_, addr, _)| addr)
+    }
+
+
+    fn is_breakpoint(&self, pc: u64) -> bool
+    {
+        self.breakpoint_addrs().any(|addr| addr == pc)
+    }
+
+
+    fn byte(&self, addr: u64) -> u8
+    {
+        match self.pages.get(&(addr / PAGE_SIZE as u64))
+        {
+            Some(page) => page[(addr % PAGE_SIZE as u64) as usize],
+            None => 0,
+        }
+    }
+
+
+    fn set_byte(&mut self, addr: u64, value: u8)
+    {
+        let page = self.pages.entry(addr / PAGE_SIZE as u64).or_insert_with(|| vec![0; PAGE_SIZE]);
+        page[(addr % PAGE_SIZE as u64) as usize] = value;
+    }
+}
+
+
+impl Target for SimTarget
+{
+    fn read_registers(&mut self) -> TargetResult<Vec<u8>>
+    {
+        self.check_halted()?;
+        let mut regs = Vec::with_capacity(REG_COUNT * REG_SIZE);
+        for reg in self.regs().iter()
+        {
+            regs.extend_from_slice(&reg.to_le_bytes());
+        }
+        Ok(regs)
+    }
+
+    fn write_registers(&mut self, data: &[u8]) -> TargetResult<()>
+    {
+        self.check_halted()?;
+        if data.len() != REG_COUNT * REG_SIZE
+        {
+            return Err(0x01);
+        }
+        for (reg, bytes) in self.harts[self.current].regs.iter_mut().zip(data.chunks(REG_SIZE))
+        {
+            let mut le = [0u8; REG_SIZE];
+            le.copy_from_slice(bytes);
+            *reg = u64::from_le_bytes(le);
+        }
+        Ok(())
+    }
+
+    fn read_register(&mut self, reg_num: usize) -> TargetResult<Vec<u8>>
+    {
+        self.check_halted()?;
+        match self.regs().get(reg_num)
+        {
+            Some(reg) => Ok(reg.to_le_bytes().to_vec()),
+            None => Err(0x01),
+        }
+    }
+
+    fn write_register(&mut self, reg_num: usize, data: &[u8]) -> TargetResult<()>
+    {
+        self.check_halted()?;
+        if reg_num >= REG_COUNT || data.len() != REG_SIZE
+        {
+            return Err(0x01);
+        }
+        let mut le = [0u8; REG_SIZE];
+        le.copy_from_slice(data);
+        self.harts[self.current].regs[reg_num] = u64::from_le_bytes(le);
+        Ok(())
+    }
+
+    fn read_memory(&mut self, addr: u64, len: usize) -> TargetResult<Vec<u8>>
+    {
+        Ok((0..len as u64).map(|i| self.byte(addr.wrapping_add(i))).collect())
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> TargetResult<()>
+    {
+        for (i, &b) in data.iter().enumerate()
+        {
+            self.set_byte(addr.wrapping_add(i as u64), b);
+        }
+        Ok(())
+    }
+
+    fn insert_matchpoint(&mut self, mp_type: MatchpointType, addr: u64, kind: usize) -> TargetResult<()>
+    {
+        if !self.breakpoints.contains(&(mp_type, addr, kind))
+        {
+            self.breakpoints.push((mp_type, addr, kind));
+        }
+        Ok(())
+    }
+
+    fn remove_matchpoint(&mut self, mp_type: MatchpointType, addr: u64, kind: usize) -> TargetResult<()>
+    {
+        self.breakpoints.retain(|&bp| bp != (mp_type, addr, kind));
+        if !self.is_breakpoint(addr)
+        {
+            self.conditions.retain(|&(cond_addr, _)| cond_addr != addr);
+            self.commands.retain(|&(cmd_addr, _, _)| cmd_addr != addr);
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        let actions: Vec<(u64, ResumeAction)> = self.threads().into_iter().map(|tid| (tid, ResumeAction::Continue)).collect();
+        self.resume_threads(&actions, cancel_flag)
+    }
+
+    fn step(&mut self) -> StopReason
+    {
+        let tid = self.current_thread();
+        self.resume_threads(&[(tid, ResumeAction::Step)], &Arc::new(AtomicBool::new(false)))
+    }
+
+    fn threads(&self) -> Vec<u64>
+    {
+        (1 ..= self.harts.len() as u64).collect()
+    }
+
+    fn current_thread(&self) -> u64
+    {
+        self.current as u64 + 1
+    }
+
+    fn select_thread(&mut self, tid: u64) -> TargetResult<()>
+    {
+        self.current = self.hart_index(tid).ok_or(0x01)?;
+        Ok(())
+    }
+
+    fn thread_extra_info(&self, tid: u64) -> Option<String>
+    {
+        let index = self.hart_index(tid)?;
+        let hart = &self.harts[index];
+        if hart.running
+        {
+            return Some(format!("hart {}, running", index));
+        }
+        Some(format!("hart {}, pc 0x{:x}, stopped: {}", index, hart.regs[PC_REG], signal_name(hart.stop.signal())))
+    }
+
+    ///Все-стоп: шаг выполняется сразу, поэтому если есть s-действия, останов - на первом из шагающих harts
+    ///Иначе исполнение (цикл до ^C или мгновенный останов на breakpoint) и останов на первом из продолжающих harts
+    ///Остальные harts с действием останавливаются вместе с ним (Signal(0))
+    fn resume_threads(&mut self, actions: &[(u64, ResumeAction)], cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        let actions: Vec<(u64, ResumeAction)> = actions.iter().cloned().filter(|&(_, action)| action != ResumeAction::Stop).collect();
+        let mut stopped = None;
+        for &(tid, action) in &actions
+        {
+            if let (Some(index), ResumeAction::Step) = (self.hart_index(tid), action)
+            {
+                self.trace_pass(index);
+                let pc = &mut self.harts[index].regs[PC_REG];
+                *pc = pc.wrapping_add(4);
+                self.harts[index].stop = StopReason::Signal(0x05);
+                stopped = stopped.or(Some(index));
+            }
+        }
+        let (index, stop) = match stopped
+        {
+            Some(index) => (index, StopReason::Signal(0x05)),
+            None =>
+            {
+                let first = actions.iter().find_map(|&(tid, _)| self.hart_index(tid)).unwrap_or(self.current);
+                if self.run_semihosting(first)
+                {//Исполнение продолжится после ответа GDB на запрос File-I/O
+                    self.current = first;
+                    self.harts[first].stop = StopReason::FileIo;
+                    return StopReason::FileIo;
+                }
+                let straight = self.trace.is_running() || self.breakpoint_addrs().next().is_some();
+                if !(straight && self.run_straight(first, cancel_flag))
+                {
+                    run_sim(cancel_flag, self.loop_run);
+                }
+                let stop = if cancel_flag.load(Ordering::SeqCst)
+                {//Было прерывание исполнения по ^C
+                    StopReason::Signal(0x02)
+                }
+                else
+                {//Останов по breakpoint
+                    StopReason::SwBreakpoint
+                };
+                (first, stop)
+            },
+        };
+        for &(tid, _) in &actions
+        {
+            if let Some(other) = self.hart_index(tid)
+            {
+                self.harts[other].stop = if other == index {stop} else {StopReason::Signal(0)};
+            }
+        }
+        self.current = index;
+        stop
+    }
+
+    fn stop_reason(&self) -> StopReason
+    {
+        self.harts[self.current].stop
+    }
+
+    fn thread_stop(&self, tid: u64) -> Option<StopReason>
+    {
+        let hart = &self.harts[self.hart_index(tid)?];
+        if hart.running {None} else {Some(hart.stop)}
+    }
+
+    fn supports_non_stop(&self) -> bool
+    {
+        true
+    }
+
+    fn supports_conditions(&self) -> bool
+    {
+        true
+    }
+
+    fn set_conditions(&mut self, addr: u64, conditions: Vec<Vec<u8>>) -> TargetResult<()>
+    {
+        self.conditions.retain(|&(cond_addr, _)| cond_addr != addr);
+        if !conditions.is_empty()
+        {
+            self.conditions.push((addr, conditions));
+        }
+        Ok(())
+    }
+
+    fn supports_breakpoint_commands(&self) -> bool
+    {
+        true
+    }
+
+    fn set_commands(&mut self, addr: u64, commands: Vec<Vec<u8>>, persist: bool) -> TargetResult<()>
+    {
+        self.commands.retain(|&(cmd_addr, _, _)| cmd_addr != addr);
+        if !commands.is_empty()
+        {
+            self.commands.push((addr, commands, persist));
+        }
+        Ok(())
+    }
+
+    fn set_console(&mut self, console: Option<ConsoleSink>)
+    {
+        self.console_sink = console;
+    }
+
+    fn take_console_output(&mut self) -> String
+    {
+        mem::take(&mut self.console)
+    }
+
+    fn disconnected(&mut self)
+    {
+        self.commands.retain(|&(_, _, persist)| persist);
+        let commands = &self.commands;
+        self.breakpoints.retain(|&(mp_type, addr, _)|
+        {
+            (mp_type == MatchpointType::SwBreakpoint || mp_type == MatchpointType::HwBreakpoint) && commands.iter().any(|&(cmd_addr, _, _)| cmd_addr == addr)
+        });
+        let breakpoints = &self.breakpoints;
+        self.conditions.retain(|&(cond_addr, _)| breakpoints.iter().any(|&(_, addr, _)| addr == cond_addr));
+        self.console.clear();
+    }
+
+    fn file_io_request(&mut self) -> Option<String>
+    {
+        self.file_io.take()
+    }
+
+    fn file_io_reply(&mut self, retcode: i64, errno: Option<u64>) -> TargetResult<()>
+    {
+        let result = self.semihosting.reply(retcode, errno).ok_or(0x01)?;
+        let regs = &mut self.harts[self.current].regs;
+        regs[A0_REG] = result;
+        regs[PC_REG] = regs[PC_REG].wrapping_add(8); //После srai
+        Ok(())
+    }
+
+    fn start_threads(&mut self, actions: &[(u64, ResumeAction)]) -> TargetResult<()>
+    {
+        for &(tid, action) in actions
+        {
+            let index = self.hart_index(tid).ok_or(0x01)?;
+            match action
+            {
+                ResumeAction::Continue =>
+                {//Исполнение - в poll_threads
+                    self.harts[index].running = true;
+                    self.harts[index].executed = 0;
+                },
+                ResumeAction::Step =>
+                {
+                    self.trace_pass(index);
+                    let pc = &mut self.harts[index].regs[PC_REG];
+                    *pc = pc.wrapping_add(4);
+                    self.halt(index, StopReason::Signal(0x05));
+                },
+                ResumeAction::Stop => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_thread(&mut self, tid: u64, stop: StopReason) -> TargetResult<()>
+    {
+        let index = self.hart_index(tid).ok_or(0x01)?;
+        if self.harts[index].running
+        {
+            self.halt(index, stop);
+        }
+        Ok(())
+    }
+
+    fn poll_threads(&mut self) -> bool
+    {
+        let straight = self.trace.is_running() || self.breakpoint_addrs().next().is_some();
+        for index in 0..self.harts.len()
+        {
+            if !self.harts[index].running
+            {
+                continue;
+            }
+            if straight && self.run_slice(index, RUN_SLICE)
+            {
+                self.halt(index, StopReason::SwBreakpoint);
+                continue;
+            }
+            self.harts[index].executed += if straight {RUN_SLICE} else {TRACE_RUN_LIMIT};
+            if !self.loop_run && self.harts[index].executed >= TRACE_RUN_LIMIT
+            {//Как в all-stop: без breakpoints останов сразу, с breakpoints - после TRACE_RUN_LIMIT инструкций
+                self.halt(index, StopReason::SwBreakpoint);
+            }
+        }
+        self.harts.iter().any(|hart| hart.running)
+    }
+
+    fn take_stops(&mut self) -> Vec<(u64, StopReason)>
+    {
+        mem::take(&mut self.stops)
+    }
+
+    fn trace_experiment(&self) -> Option<&TraceExperiment>
+    {
+        Some(&self.trace)
+    }
+
+    fn trace_experiment_mut(&mut self) -> Option<&mut TraceExperiment>
+    {
+        Some(&mut self.trace)
+    }
+
+    fn monitor(&mut self, command: &str) -> Option<String>
+    {
+        match command
+        {
+            "reset init" | "reset halt" =>
+            {
+                for hart in self.harts.iter_mut()
+                {
+                    hart.regs = [0; REG_COUNT];
+                }
+                Some(format!(" GDB-Server message : '{}' monitor command. Registers are reset.\n", command))
+            },
+            _ => None,
+        }
+    }
+}
+
+
+///Тесты для SimTarget ================================================================================
+#[cfg(test)]
+mod test_sim_target
+{
+    use super::*;
+
+    #[test]
+    fn test_registers_and_memory()
+    {
+        let mut sim = SimTarget::new(false);
+
+        //Память, в которую не писали, читается нулями. Запись через границу страницы
+        assert_eq!(Ok(vec![0, 0]), sim.read_memory(0x1000, 2));
+        sim.write_memory(PAGE_SIZE as u64 - 2, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(Ok(vec![0, 1, 2, 3, 4, 0]), sim.read_memory(PAGE_SIZE as u64 - 3, 6));
+
+        //Регистры в little-endian
+        sim.write_register(PC_REG, &[0x00, 0x00, 0x01, 0x10, 0, 0, 0, 0]).unwrap();
+        assert_eq!(0x10010000, sim.regs()[PC_REG]);
+        assert_eq!(REG_COUNT * REG_SIZE, sim.read_registers().unwrap().len());
+        assert_eq!(Err(0x01), sim.write_register(REG_COUNT, &[0; REG_SIZE]));
+        assert_eq!(Err(0x01), sim.write_registers(&[0; 4]));
+    }
+
+    #[test]
+    fn test_harts()
+    {
+        let mut sim = SimTarget::with_harts(false, 3);
+        let no_cancel = Arc::new(AtomicBool::new(false));
+        assert_eq!(vec![1, 2, 3], sim.threads());
+        assert!(sim.thread_alive(3) && !sim.thread_alive(4) && !sim.thread_alive(0));
+
+        //Регистры у каждого hart свои, память общая
+        sim.select_thread(2).unwrap();
+        sim.write_register(PC_REG, &0x8000_0100u64.to_le_bytes()).unwrap();
+        sim.write_memory(0x8000_0000, &[1, 2]).unwrap();
+        sim.select_thread(1).unwrap();
+        assert_eq!(Ok(vec![0; REG_SIZE]), sim.read_register(PC_REG));
+        assert_eq!(Ok(vec![1, 2]), sim.read_memory(0x8000_0000, 2));
+        assert_eq!(Err(0x01), sim.select_thread(4));
+
+        //Шаг hart 2, остальные продолжают: останов на hart 2
+        let stop = sim.resume_threads(&[(2, ResumeAction::Step), (1, ResumeAction::Continue), (3, ResumeAction::Continue)], &no_cancel);
+        assert_eq!(StopReason::Signal(0x05), stop);
+        assert_eq!(2, sim.current_thread());
+        assert_eq!(0x8000_0104, sim.regs()[PC_REG]);
+        assert_eq!(StopReason::Signal(0), sim.harts[0].stop);
+        assert_eq!(Some("hart 1, pc 0x80000104, stopped: SIGTRAP".to_string()), sim.thread_extra_info(2));
+        assert_eq!(None, sim.thread_extra_info(9));
+
+        //Продолжение только hart 3
+        assert_eq!(StopReason::SwBreakpoint, sim.resume_threads(&[(3, ResumeAction::Continue)], &no_cancel));
+        assert_eq!((3, StopReason::SwBreakpoint), (sim.current_thread(), sim.stop_reason()));
+        assert_eq!(StopReason::Signal(0x05), sim.harts[1].stop);
     }
-    Ok(())
 }
@@ -0,0 +1,396 @@
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use client::RspClient;
+use codec::{escape_binary, crc32};
+use cmd_registry::CmdRegistry;
+use config::{ServerConfig, next_value, parse_size, parse_packet_size};
+use gdb_server::{serve_connection, set_verbose};
+use sim::SimTarget;
+
+
+///Размеры пакета для перебора по умолчанию
+pub const DEFAULT_SWEEP_SIZES: &[usize] = &[64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768];
+///Объем передаваемых данных по умолчанию
+pub const DEFAULT_SWEEP_BYTES: usize = 1024 * 1024;
+///Адрес памяти симулятора, с которого выполняется загрузка/выгрузка
+pub const SWEEP_ADDR: u64 = 0x8000_0000;
+
+
+///Нагрузка для измерения
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload
+{
+    Load,                                           // Загрузка программы в память: X-пакеты (как 'load' в GDB)
+    Dump,                                           // Выгрузка памяти: m-пакеты (как 'dump memory' в GDB)
+    Verify,                                         // Сверка памяти: qCRC (как 'compare-sections' в GDB), для сравнения - m-пакеты
+    Find,                                           // Поиск в памяти: qSearch:memory (как 'find' в GDB), для сравнения - m-пакеты
+}
+
+
+///Настройки режима sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepConfig
+{
+    pub workload: Workload,
+    pub total_bytes: usize,                         // Объем данных для каждого размера пакета
+    pub sizes: Vec<usize>,                          // Перебираемые размеры пакета (PacketSize)
+}
+
+
+impl Default for SweepConfig
+{
+    fn default() -> SweepConfig
+    {
+        SweepConfig{
+            workload: Workload::Load,
+            total_bytes: DEFAULT_SWEEP_BYTES,
+            sizes: DEFAULT_SWEEP_SIZES.to_vec(),
+        }
+    }
+}
+
+
+impl SweepConfig
+{
+    ///Разобрать аргументы режима sweep (после "sweep")
+    pub fn from_args(args: &[String]) -> Result<SweepConfig, String>
+    {
+        let mut config = SweepConfig::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next()
+        {
+            match &arg[..]
+            {
+                "--workload"=>
+                {
+                    config.workload = match next_value(&mut args, arg)?
+                    {
+                        "load" => Workload::Load,
+                        "dump" => Workload::Dump,
+                        "verify" => Workload::Verify,
+                        "find" => Workload::Find,
+                        value => return Err(format!("Неизвестная нагрузка \'{}\' (load, dump, verify или find)", value)),
+                    };
+                },
+                "--bytes"=>
+                {
+                    config.total_bytes = parse_size(next_value(&mut args, arg)?)?;
+                },
+                "--sizes"=>
+                {
+                    config.sizes = next_value(&mut args, arg)?.split(',').map(parse_packet_size).collect::<Result<_, _>>()?;
+                },
+                _=>
+                {
+                    return Err(format!("Неизвестный аргумент режима sweep {:?}", arg));
+                },
+            }
+        }
+        Ok(config)
+    }
+}
+
+
+///Результат измерения для одного размера пакета
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepResult
+{
+    pub packet_size: usize,
+    pub packets: usize,                             // Пакетов в обе стороны (без acknowledgment)
+    pub wire_bytes: usize,                          // Байт в обе стороны (вместе с $, #cs, экранированием и acknowledgment)
+    pub elapsed: Duration,
+    pub fallback: Option<(usize, usize)>,           // Verify, Find: пакеты и байты того же результата через m-пакеты (как GDB без qCRC/qSearch)
+}
+
+
+impl SweepResult
+{
+    ///Пропускная способность по полезным данным, KB/s
+    pub fn throughput(&self, total_bytes: usize) -> f64
+    {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {total_bytes as f64 / 1024.0 / secs} else {0.0}
+    }
+
+    ///Сколько байт на линии сэкономлено по сравнению с m-пакетами, %
+    pub fn saved(&self) -> Option<f64>
+    {
+        self.fallback.map(|(_, wire_bytes)| 100.0 * (1.0 - self.wire_bytes as f64 / wire_bytes as f64))
+    }
+}
+
+
+///Перебрать размеры пакета: для каждого поднять GDB-сервер с SimTarget и прогнать через него нагрузку
+pub fn run_sweep(config: &SweepConfig) -> io::Result<Vec<SweepResult>>
+{
+    set_verbose(false); //Технологический вывод на каждый пакет исказит измерение
+    config.sizes.iter().map(|&size| measure(size, config.workload, config.total_bytes)).collect()
+}
+
+
+///Измерение для одного размера пакета
+pub fn measure(packet_size: usize, workload: Workload, total_bytes: usize) -> io::Result<SweepResult>
+{
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = spawn(move ||
+    {
+        if let Some(Ok(stream)) = listener.incoming().next()
+        {
+            let config = ServerConfig{ packet_size, ..ServerConfig::default() };
+            serve_connection(stream, &config, &CmdRegistry::with_defaults(), &mut SimTarget::new(false));
+        }
+    });
+
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let mut client = RspClient::new(stream);
+    client.handshake("swbreak+;hwbreak+")?;
+    if workload == Workload::Verify || workload == Workload::Find
+    {//Сверяется и ищется загруженная программа
+        load(&mut client, packet_size, total_bytes)?;
+    }
+    client.stats = Default::default(); //Считать только саму нагрузку
+
+    let start = Instant::now();
+    let expected = match workload
+    {
+        Workload::Load => load(&mut client, packet_size, total_bytes).map(|_| None)?,
+        Workload::Dump => dump(&mut client, packet_size, total_bytes).map(|_| None)?,
+        Workload::Verify => Some(verify(&mut client, total_bytes)?),
+        Workload::Find => Some(find(&mut client, total_bytes)?),
+    };
+    let elapsed = start.elapsed();
+    let stats = client.stats;
+
+    let fallback = match expected
+    {
+        Some(expected) =>
+        {//Тот же результат без qCRC/qSearch: память читается m-пакетами, CRC и поиск - на стороне GDB
+            client.stats = Default::default();
+            let result = fallback(&mut client, packet_size, workload, total_bytes)?;
+            if result != expected
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {:#x} differs from {:#x} by 'm' packets", workload, expected, result)));
+            }
+            Some((client.stats.packets_sent + client.stats.packets_received, client.stats.bytes_sent + client.stats.bytes_received))
+        },
+        None => None,
+    };
+
+    client.request("vKill")?;
+    drop(client);
+    let _ = server.join();
+
+    Ok(SweepResult{
+        packet_size,
+        packets: stats.packets_sent + stats.packets_received,
+        wire_bytes: stats.bytes_sent + stats.bytes_received,
+        elapsed,
+        fallback,
+    })
+}
+
+
+///Загрузка псевдослучайных данных X-пакетами, каждый пакет вмещает сколько позволяет PacketSize с учетом экранирования
+fn load(client: &mut RspClient<TcpStream>, packet_size: usize, total_bytes: usize) -> io::Result<()>
+{
+    let data = test_data(total_bytes);
+    let mut offset = 0;
+    while offset < total_bytes
+    {
+        let addr = SWEEP_ADDR + offset as u64;
+        //$X<addr>,<len>:<data>#cs. <len> не длиннее, чем hex от packet_size
+        let header_len = format!("X{:x},{:x}:", addr, packet_size).len();
+        let mut budget = packet_size.saturating_sub(header_len + 4);
+        let mut len = 0;
+        for &b in &data[offset..]
+        {
+            let cost = escape_binary(&[b]).len();
+            if cost > budget
+            {
+                break;
+            }
+            budget -= cost;
+            len += 1;
+        }
+        if len == 0
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("packet size {} is too small for X packet", packet_size)));
+        }
+        expect_ok(client.write_memory(addr, &data[offset .. offset+len])?.data)?;
+        offset += len;
+    }
+    Ok(())
+}
+
+
+///Выгрузка памяти m-пакетами: ответ $<hex>#cs не длиннее PacketSize
+fn dump(client: &mut RspClient<TcpStream>, packet_size: usize, total_bytes: usize) -> io::Result<()>
+{
+    let chunk = (packet_size.saturating_sub(4) / 2).max(1);
+    let mut offset = 0;
+    while offset < total_bytes
+    {
+        let len = chunk.min(total_bytes - offset);
+        if client.read_memory(SWEEP_ADDR + offset as u64, len)?.is_none()
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad 'm' reply"));
+        }
+        offset += len;
+    }
+    Ok(())
+}
+
+
+///CRC-32 загруженной памяти одним qCRC-пакетом
+fn verify(client: &mut RspClient<TcpStream>, total_bytes: usize) -> io::Result<u64>
+{
+    let reply = client.request(&format!("qCRC:{:x},{:x}", SWEEP_ADDR, total_bytes))?.data;
+    reply.strip_prefix('C').and_then(|crc| u64::from_str_radix(crc, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad 'qCRC' reply {:?}", reply)))
+}
+
+
+///Поиск последних байт загруженной памяти одним qSearch:memory-пакетом. Образец бинарный, как данные X-пакета
+fn find(client: &mut RspClient<TcpStream>, total_bytes: usize) -> io::Result<u64>
+{
+    let mut packet = format!("qSearch:memory:{:x};{:x};", SWEEP_ADDR, total_bytes).into_bytes();
+    packet.extend_from_slice(&escape_binary(search_pattern(&test_data(total_bytes))));
+    let reply = client.request_bytes(&packet)?.data;
+    reply.strip_prefix("1,").and_then(|addr| u64::from_str_radix(addr, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad 'qSearch:memory' reply {:?}", reply)))
+}
+
+
+///Образец для поиска: последние 8 байт данных
+fn search_pattern(data: &[u8]) -> &[u8]
+{
+    &data[data.len().saturating_sub(8)..]
+}
+
+
+///Verify и Find без qCRC/qSearch (как GDB на пустой ответ): память читается m-пакетами, как в dump
+///Find прекращает чтение после первого совпадения
+fn fallback(client: &mut RspClient<TcpStream>, packet_size: usize, workload: Workload, total_bytes: usize) -> io::Result<u64>
+{
+    let pattern = search_pattern(&test_data(total_bytes)).to_vec();
+    let chunk = (packet_size.saturating_sub(4) / 2).max(1);
+    let mut memory = Vec::with_capacity(total_bytes);
+    while memory.len() < total_bytes
+    {
+        let len = chunk.min(total_bytes - memory.len());
+        match client.read_memory(SWEEP_ADDR + memory.len() as u64, len)?
+        {
+            Some(bytes) => memory.extend_from_slice(&bytes),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad 'm' reply")),
+        }
+        if workload == Workload::Find
+        {//Совпадение может начинаться до новых байт, но не раньше чем за pattern.len() - 1 до них
+            let start = memory.len().saturating_sub(len + pattern.len() - 1);
+            if let Some(pos) = memory[start..].windows(pattern.len()).position(|w| w == &pattern[..])
+            {
+                return Ok(SWEEP_ADDR + (start + pos) as u64);
+            }
+        }
+    }
+    match workload
+    {
+        Workload::Find => Err(io::Error::new(io::ErrorKind::InvalidData, "pattern not found by 'm' packets")),
+        _ => Ok(crc32(0xffff_ffff, &memory) as u64),
+    }
+}
+
+
+fn expect_ok(reply: String) -> io::Result<()>
+{
+    if reply == "OK" {Ok(())} else {Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply {:?}", reply)))}
+}
+
+
+///Детерминированные псевдослучайные данные (xorshift). Встречаются и байты, требующие экранирования
+pub fn test_data(len: usize) -> Vec<u8>
+{
+    let mut state: u32 = 0x2545_f491;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect()
+}
+
+
+///Таблица результатов
+pub fn print_report(config: &SweepConfig, results: &[SweepResult])
+{
+    println!("Workload: {:?}, {} bytes\n", config.workload, config.total_bytes);
+    if config.workload == Workload::Verify || config.workload == Workload::Find
+    {//Трафик qCRC/qSearch и того же результата через m-пакеты
+        println!("{:>11} {:>9} {:>12} {:>12} {:>15} {:>15} {:>9}", "PacketSize", "Packets", "Wire bytes", "Time, ms", "'m' packets", "'m' wire bytes", "Saved, %");
+        for r in results
+        {
+            let (packets, wire_bytes) = r.fallback.unwrap_or_default();
+            println!("{:>11} {:>9} {:>12} {:>12.1} {:>15} {:>15} {:>9.2}", r.packet_size, r.packets, r.wire_bytes, r.elapsed.as_secs_f64() * 1000.0,
+                packets, wire_bytes, r.saved().unwrap_or(0.0));
+        }
+        println!();
+        return;
+    }
+    println!("{:>11} {:>9} {:>12} {:>12} {:>12}", "PacketSize", "Packets", "Wire bytes", "Time, ms", "KB/s");
+    for r in results
+    {
+        println!("{:>11} {:>9} {:>12} {:>12.1} {:>12.1}", r.packet_size, r.packets, r.wire_bytes, r.elapsed.as_secs_f64() * 1000.0, r.throughput(config.total_bytes));
+    }
+    println!();
+}
+
+
+///Тесты для режима sweep ================================================================================
+#[cfg(test)]
+mod test_sweep
+{
+    use super::*;
+
+    #[test]
+    fn test_measure()
+    {
+        set_verbose(false);
+        let small = measure(64, Workload::Load, 4096).unwrap();
+        let big = measure(1024, Workload::Load, 4096).unwrap();
+        assert!(small.packets > big.packets);
+
+        let dump = measure(256, Workload::Dump, 1000).unwrap();
+        assert_eq!(2 * 1000_usize.div_ceil(126), dump.packets);
+        assert_eq!(None, dump.fallback);
+    }
+
+    #[test]
+    fn test_measure_fallback()
+    {
+        set_verbose(false);
+        //Один запрос и ответ вместо чтения всей памяти m-пакетами
+        let verify = measure(256, Workload::Verify, 4096).unwrap();
+        assert_eq!(2, verify.packets);
+        assert_eq!(Some(2 * 4096_usize.div_ceil(126)), verify.fallback.map(|(packets, _)| packets));
+        assert!(verify.saved().unwrap() > 90.0);
+
+        let find = measure(256, Workload::Find, 4096).unwrap();
+        assert_eq!(2, find.packets);
+        assert!(find.saved().unwrap() > 90.0);
+    }
+
+    #[test]
+    fn test_sweep_args()
+    {
+        let args: Vec<String> = ["--workload", "dump", "--bytes", "0x1000", "--sizes", "64,512"].iter().map(|s| s.to_string()).collect();
+        let config = SweepConfig::from_args(&args).unwrap();
+        assert_eq!(Workload::Dump, config.workload);
+        assert_eq!(4096, config.total_bytes);
+        assert_eq!(vec![64, 512], config.sizes);
+        assert!(SweepConfig::from_args(&["--sizes".to_string(), "8".to_string()]).is_err());
+    }
+}
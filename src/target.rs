@@ -0,0 +1,479 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sim::run_sim;
+use trace::TraceExperiment;
+
+
+///Результат операции над целью. Ошибка - номер ошибки для ответа "E<nn>"
+pub type TargetResult<T> = Result<T, u8>;
+
+///Получатель вывода команд breakpoints во время исполнения (сервер отправляет его GDB в O-пакетах)
+pub type ConsoleSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+
+///Причина останова цели (для Stop Reply Packet)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason
+{
+    Signal(u8),                                     // Останов по сигналу: T<signal>. 0x05 = SIGTRAP, 0x02 = SIGINT
+    SwBreakpoint,                                   // Останов на программном breakpoint (SIGTRAP)
+    HwBreakpoint,                                   // Останов на аппаратном breakpoint (SIGTRAP)
+    FileIo,                                         // Вызов semihosting: программа ждет ответа GDB на запрос File-I/O (Target::file_io_request)
+}
+
+
+impl StopReason
+{
+    ///Данные Stop Reply Packet (без $ и #cs)
+    pub fn reply(&self) -> String
+    {
+        format!("T{:02x}", self.signal())
+    }
+
+
+    ///Номер сигнала останова
+    pub fn signal(&self) -> u8
+    {
+        match *self
+        {
+            StopReason::Signal(sig) => sig,
+            StopReason::SwBreakpoint | StopReason::HwBreakpoint | StopReason::FileIo => 0x05, //SIGTRAP
+        }
+    }
+}
+
+
+///Тип matchpoint из Z/z-пакета
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchpointType
+{
+    SwBreakpoint,                                   // Z0
+    HwBreakpoint,                                   // Z1
+    WriteWatchpoint,                                // Z2
+    ReadWatchpoint,                                 // Z3
+    AccessWatchpoint,                               // Z4
+}
+
+
+impl MatchpointType
+{
+    ///Тип matchpoint по полю <type> Z/z-пакета
+    pub fn from_z_type(z_type: &str) -> Option<MatchpointType>
+    {
+        match z_type
+        {
+            "0" => Some(MatchpointType::SwBreakpoint),
+            "1" => Some(MatchpointType::HwBreakpoint),
+            "2" => Some(MatchpointType::WriteWatchpoint),
+            "3" => Some(MatchpointType::ReadWatchpoint),
+            "4" => Some(MatchpointType::AccessWatchpoint),
+            _ => None,
+        }
+    }
+
+
+    ///Название типа matchpoint для вывода
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            MatchpointType::SwBreakpoint => "software breakpoint",
+            MatchpointType::HwBreakpoint => "hardware breakpoint",
+            MatchpointType::WriteWatchpoint => "write watchpoint",
+            MatchpointType::ReadWatchpoint => "read watchpoint",
+            MatchpointType::AccessWatchpoint => "access watchpoint",
+        }
+    }
+}
+
+
+///Номер потока цели с процессами: процесс pid, поток tid внутри процесса (multiprocess: thread-id pPID.TID)
+pub fn ptid(pid: u64, tid: u64) -> u64
+{
+    pid << 32 | (tid & 0xffff_ffff)
+}
+
+
+///Процесс и номер потока внутри процесса по номеру потока цели. У цели без процессов pid = 0
+pub fn ptid_parts(tid: u64) -> (u64, u64)
+{
+    (tid >> 32, tid & 0xffff_ffff)
+}
+
+
+///Поток в RSP-пакетах (H, T, vCont): конкретный поток, любой (0), все (-1) или все потоки процесса (multiprocess)
+///Номера потоков в RSP - hex, начиная с 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadId
+{
+    All,                                            // -1, p-1
+    Any,                                            // 0, p0
+    Process(u64),                                   // pPID, pPID.-1, pPID.0
+    Id(u64),                                        // TID, pPID.TID (номер потока цели, см. ptid)
+}
+
+
+impl ThreadId
+{
+    ///Разобрать thread-id: "-1", "0", номер в hex или pPID[.TID]. None - некорректный thread-id
+    pub fn parse(text: &str) -> Option<ThreadId>
+    {
+        if let Some(text) = text.strip_prefix('p')
+        {
+            let (pid, tid) = match text.split_once('.')
+            {
+                Some((pid, tid)) => (pid, tid),
+                None => (text, "-1"),
+            };
+            return match (pid, tid)
+            {
+                ("-1", "-1") => Some(ThreadId::All),
+                ("-1", _) => None,
+                ("0", _) => Some(ThreadId::Any),
+                (_, "-1") | (_, "0") => u64::from_str_radix(pid, 16).ok().map(ThreadId::Process),
+                _ => match (u64::from_str_radix(pid, 16), u64::from_str_radix(tid, 16))
+                {
+                    (Ok(pid), Ok(tid)) if pid >> 32 == 0 && tid >> 32 == 0 => Some(ThreadId::Id(ptid(pid, tid))),
+                    _ => None,
+                },
+            };
+        }
+        match text
+        {
+            "-1" => Some(ThreadId::All),
+            "0" => Some(ThreadId::Any),
+            _ => u64::from_str_radix(text, 16).ok().map(ThreadId::Id),
+        }
+    }
+
+
+    ///Подходит ли поток tid под этот thread-id
+    pub fn matches(&self, tid: u64) -> bool
+    {
+        match *self
+        {
+            ThreadId::All | ThreadId::Any => true,
+            ThreadId::Process(pid) => ptid_parts(tid).0 == pid,
+            ThreadId::Id(id) => id == tid,
+        }
+    }
+}
+
+
+///Действие над потоком при продолжении исполнения (vCont)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumeAction
+{
+    Continue,                                       // c, C<sig>
+    Step,                                           // s, S<sig>
+    Stop,                                           // t (только в non-stop режиме)
+}
+
+
+///Абстракция отлаживаемой цели (симулятора)
+///Обработчики команд обращаются к цели только через этот трейт. Регистры и память передаются в том порядке байт, в котором их ожидает GDB (little-endian)
+pub trait Target
+{
+    ///Чтение всех регистров общего назначения ('g')
+    fn read_registers(&mut self) -> TargetResult<Vec<u8>>;
+
+    ///Запись всех регистров общего назначения ('G')
+    fn write_registers(&mut self, data: &[u8]) -> TargetResult<()>;
+
+    ///Чтение произвольного регистра ('p')
+    fn read_register(&mut self, reg_num: usize) -> TargetResult<Vec<u8>>;
+
+    ///Запись произвольного регистра ('P')
+    fn write_register(&mut self, reg_num: usize, data: &[u8]) -> TargetResult<()>;
+
+    ///Чтение памяти ('m')
+    fn read_memory(&mut self, addr: u64, len: usize) -> TargetResult<Vec<u8>>;
+
+    ///Запись в память ('X')
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> TargetResult<()>;
+
+    ///Установка matchpoint ('Z')
+    fn insert_matchpoint(&mut self, _mp_type: MatchpointType, _addr: u64, _kind: usize) -> TargetResult<()>
+    {
+        Ok(())
+    }
+
+    ///Снятие matchpoint ('z')
+    fn remove_matchpoint(&mut self, _mp_type: MatchpointType, _addr: u64, _kind: usize) -> TargetResult<()>
+    {
+        Ok(())
+    }
+
+    ///Продолжить исполнение до останова или до прерывания по ^C (cancel_flag)
+    fn resume(&mut self, cancel_flag: &Arc<AtomicBool>) -> StopReason;
+
+    ///Выполнить одну инструкцию
+    fn step(&mut self) -> StopReason;
+
+    ///Потоки цели (например harts). Пустой список - цель без потоков: один неявный поток с номером 0
+    ///У цели с процессами - потоки всех подключенных процессов, номера составные (ptid)
+    fn threads(&self) -> Vec<u64>
+    {
+        Vec::new()
+    }
+
+    ///Поток, к которому относятся операции с регистрами (qC). После останова - поток, на котором произошел останов
+    fn current_thread(&self) -> u64
+    {
+        0
+    }
+
+    ///Выбрать поток для операций с регистрами ('Hg')
+    fn select_thread(&mut self, tid: u64) -> TargetResult<()>
+    {
+        if tid == self.current_thread() {Ok(())} else {Err(0x01)}
+    }
+
+    ///Жив ли поток ('T')
+    fn thread_alive(&self, tid: u64) -> bool
+    {
+        self.threads().contains(&tid)
+    }
+
+    ///Описание состояния потока для 'info threads' (qThreadExtraInfo). None - нет описания
+    fn thread_extra_info(&self, _tid: u64) -> Option<String>
+    {
+        None
+    }
+
+    ///Продолжить исполнение с отдельным действием для каждого потока (vCont). Потоки без действия остаются остановленными
+    ///Возвращает причину останова; поток, на котором он произошел, становится текущим (current_thread)
+    fn resume_threads(&mut self, actions: &[(u64, ResumeAction)], cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        if actions.iter().any(|&(_, action)| action == ResumeAction::Step)
+        {
+            self.step()
+        }
+        else
+        {
+            self.resume(cancel_flag)
+        }
+    }
+
+    ///Причина последнего останова ('?')
+    fn stop_reason(&self) -> StopReason
+    {
+        StopReason::Signal(0x02)
+    }
+
+    ///Причина останова потока. None - поток исполняется (non-stop)
+    fn thread_stop(&self, _tid: u64) -> Option<StopReason>
+    {
+        Some(self.stop_reason())
+    }
+
+    ///Поддерживает ли цель non-stop режим (QNonStop:1): потоки исполняются, пока GDB работает с другими
+    fn supports_non_stop(&self) -> bool
+    {
+        false
+    }
+
+    ///Цель сама вычисляет условия breakpoints (ConditionalBreakpoints+): останов, только если истинно хотя бы одно условие
+    fn supports_conditions(&self) -> bool
+    {
+        false
+    }
+
+    ///Условия breakpoint по адресу addr (байткод агентских выражений). Пустой список - безусловный breakpoint
+    ///Заменяет прежние условия: GDB присылает Z-пакет заново при каждом изменении условия
+    fn set_conditions(&mut self, _addr: u64, _conditions: Vec<Vec<u8>>) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Цель сама исполняет команды breakpoints (BreakpointCommands+): на breakpoint с командами hart не останавливается
+    fn supports_breakpoint_commands(&self) -> bool
+    {
+        false
+    }
+
+    ///Команды breakpoint по адресу addr (байткод агентских выражений, например printf для dprintf). Пустой список снимает команды
+    ///persist - команды остаются после отключения GDB
+    fn set_commands(&mut self, _addr: u64, _commands: Vec<Vec<u8>>, _persist: bool) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Куда отправлять вывод команд breakpoints сразу во время all-stop исполнения. None - копить до take_console_output
+    fn set_console(&mut self, _console: Option<ConsoleSink>)
+    {
+    }
+
+    ///Вывод команд breakpoints (printf) с прошлого вызова, еще не отправленный через set_console. Сервер отправляет его GDB в O-пакетах
+    fn take_console_output(&mut self) -> String
+    {
+        String::new()
+    }
+
+    ///GDB отключился: цель снимает то, что GDB не просил сохранить (breakpoints без persist-команд)
+    fn disconnected(&mut self)
+    {
+    }
+
+    ///Non-stop: запустить потоки (c/s-действия) и сразу вернуться. Исполнение - в poll_threads, остановы сообщаются через take_stops
+    fn start_threads(&mut self, _actions: &[(u64, ResumeAction)]) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Non-stop: остановить исполняющийся поток (vCont;t - Signal(0), vCtrlC - Signal(0x02)). Уже остановленный поток не меняется
+    fn stop_thread(&mut self, _tid: u64, _stop: StopReason) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Non-stop: исполнить следующую часть инструкций исполняющихся потоков. Остановы сообщаются через take_stops
+    ///Сервер вызывает, пока ждет пакеты GDB. Возвращает true, если потоки еще исполняются
+    fn poll_threads(&mut self) -> bool
+    {
+        false
+    }
+
+    ///Non-stop: остановы потоков, произошедшие после предыдущего вызова
+    fn take_stops(&mut self) -> Vec<(u64, StopReason)>
+    {
+        Vec::new()
+    }
+
+    ///Запрос File-I/O, на котором остановилось исполнение (StopReason::FileIo): "Fwrite,1,80001000,d" (без '$' и '#cs')
+    ///Запрос отдается один раз
+    fn file_io_request(&mut self) -> Option<String>
+    {
+        None
+    }
+
+    ///Ответ GDB на запрос File-I/O ($F<retcode>[,errno]). После него исполнение продолжается (resume_threads)
+    fn file_io_reply(&mut self, _retcode: i64, _errno: Option<u64>) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Процессы (inferiors), к которым подключен GDB. Пустой список - цель без процессов (multiprocess не объявляется)
+    ///Память, регистры и matchpoints относятся к процессу текущего потока (Hg)
+    fn processes(&self) -> Vec<u64>
+    {
+        Vec::new()
+    }
+
+    ///Подключиться к процессу (vAttach). Возвращает причину останова процесса
+    fn attach(&mut self, _pid: u64) -> TargetResult<StopReason>
+    {
+        Err(0x01)
+    }
+
+    ///Отключиться от процесса (D;pid). Процесс продолжает работу без GDB
+    fn detach(&mut self, _pid: u64) -> TargetResult<()>
+    {
+        Err(0x01)
+    }
+
+    ///Эксперимент трассировки (tracepoints, QTDP/QTStart/QTFrame). None - цель без трассировки (пустой ответ)
+    fn trace_experiment(&self) -> Option<&TraceExperiment>
+    {
+        None
+    }
+
+    fn trace_experiment_mut(&mut self) -> Option<&mut TraceExperiment>
+    {
+        None
+    }
+
+    ///Консольная команда 'monitor command'. Возвращает текст для вывода в GDB-консоль или None, если команда неизвестна
+    fn monitor(&mut self, _command: &str) -> Option<String>
+    {
+        None
+    }
+
+    ///Завершение работы цели ('vKill')
+    fn kill(&mut self)
+    {
+    }
+}
+
+
+///Цель-заглушка: на все запросы возвращает фиксированные значения, исполнение имитируется циклом в sim::run_sim
+pub struct DummyTarget
+{
+    pub loop_run: bool,                             // Имитация исполнения в цикле (до ^C)
+}
+
+
+impl DummyTarget
+{
+    pub fn new(loop_run: bool) -> DummyTarget
+    {
+        DummyTarget{ loop_run }
+    }
+}
+
+
+impl Target for DummyTarget
+{
+    fn read_registers(&mut self) -> TargetResult<Vec<u8>>
+    {
+        let mut regs = Vec::with_capacity(256);
+        for _ in 0..16
+        {
+            regs.extend_from_slice(&[0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00]);
+        }
+        Ok(regs)
+    }
+
+    fn write_registers(&mut self, _data: &[u8]) -> TargetResult<()>
+    {
+        Ok(())
+    }
+
+    fn read_register(&mut self, _reg_num: usize) -> TargetResult<Vec<u8>>
+    {
+        Ok(vec![0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00]) //По RSP регистры передаются в little-endian
+    }
+
+    fn write_register(&mut self, _reg_num: usize, _data: &[u8]) -> TargetResult<()>
+    {
+        Ok(())
+    }
+
+    fn read_memory(&mut self, _addr: u64, _len: usize) -> TargetResult<Vec<u8>>
+    {
+        Ok(vec![0x33, 0x22, 0x11, 0x00])
+    }
+
+    fn write_memory(&mut self, _addr: u64, _data: &[u8]) -> TargetResult<()>
+    {
+        Ok(())
+    }
+
+    fn resume(&mut self, cancel_flag: &Arc<AtomicBool>) -> StopReason
+    {
+        run_sim(cancel_flag, self.loop_run); //Run simulation
+        if cancel_flag.load(Ordering::SeqCst)
+        {//Было прерывание исполнения по ^C
+            StopReason::Signal(0x02) //SIGINT
+        }
+        else
+        {//Останов по breakpoint
+            StopReason::SwBreakpoint
+        }
+    }
+
+    fn step(&mut self) -> StopReason
+    {
+        StopReason::Signal(0x05)
+    }
+
+    fn monitor(&mut self, command: &str) -> Option<String>
+    {
+        match command
+        {
+            "reset init" | "reset halt" => Some(format!(" GDB-Server message : '{}' monitor command.\n + Any text message.\n", command)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,676 @@
+use target::{Target, TargetResult};
+use codec::{hex_encode, hex_decode};
+use agent_expr::eval;
+
+
+///Размер буфера трассировки по умолчанию (QTBuffer:size:-1)
+pub const DEFAULT_BUFFER_SIZE: usize = 0x10000;
+///Заголовок файла трассировки (tfile): "\x7fTRACE0\n"
+pub const TFILE_MAGIC: &[u8] = b"\x7fTRACE0\n";
+
+
+///Действие tracepoint: что собирается в кадр
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceAction
+{
+    Registers,                                      // R<mask>: все регистры (блок 'g')
+    Memory(Option<usize>, u64, usize),              // M<basereg>,<offset>,<len>: память по адресу offset (basereg -1) или <basereg> + offset
+    Expr(Vec<u8>),                                  // X<len>,<bytecode>: агентское выражение
+}
+
+
+///Tracepoint: адрес и действия. Номер у GDB общий для всех адресов (locations) одного tracepoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tracepoint
+{
+    pub number: u64,
+    pub addr: u64,
+    pub enabled: bool,
+    pub step_count: u64,                            // while-stepping: количество шагов после срабатывания, на каждом - кадр step_actions
+    pub pass_count: u64,                            // Эксперимент останавливается после стольких срабатываний (0 - не останавливается)
+    pub condition: Option<Vec<u8>>,                 // Условие (агентское выражение из :X<len>,<bytecode>): кадр собирается, если оно не 0
+    pub actions: Vec<TraceAction>,
+    pub step_actions: Vec<TraceAction>,
+    pub hits: u64,                                  // Срабатываний в текущем эксперименте
+    definition: Vec<String>,                        // Действия и исходный текст для qTfP/qTsP и tfile: "A...", "S...", "Z..."
+}
+
+
+///Кадр трассировки: данные, собранные при прохождении tracepoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame
+{
+    pub tracepoint: u64,                            // Номер tracepoint
+    pub pc: u64,                                    // Адрес tracepoint или шага while-stepping
+    pub registers: Option<Vec<u8>>,                 // Блок регистров как в 'g' (None - не собирались)
+    pub memory: Vec<(u64, Vec<u8>)>,                // Собранные участки памяти
+}
+
+
+impl TraceFrame
+{
+    ///Память кадра. None - хотя бы один байт не собирался
+    pub fn read_memory(&self, addr: u64, len: usize) -> Option<Vec<u8>>
+    {
+        (0..len as u64).map(|i| addr.wrapping_add(i)).map(|a|
+        {
+            self.memory.iter().find(|(start, bytes)| a.wrapping_sub(*start) < bytes.len() as u64).map(|(start, bytes)| bytes[(a - start) as usize])
+        }).collect()
+    }
+
+
+    ///Кадр в формате tfile (little-endian, как у цели): <tracepoint u16><size u32>, блоки 'R'<регистры> и 'M'<addr u64><len u16><байты>
+    pub fn encode(&self) -> Vec<u8>
+    {
+        let mut blocks = Vec::new();
+        if let Some(ref regs) = self.registers
+        {
+            blocks.push(b'R');
+            blocks.extend_from_slice(regs);
+        }
+        for (addr, bytes) in &self.memory
+        {
+            blocks.push(b'M');
+            blocks.extend_from_slice(&addr.to_le_bytes());
+            blocks.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+            blocks.extend_from_slice(bytes);
+        }
+        let mut frame = (self.tracepoint as u16).to_le_bytes().to_vec();
+        frame.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        frame.extend(blocks);
+        frame
+    }
+}
+
+
+///Эксперимент трассировки: tracepoints, буфер кадров, состояние для qTStatus и выбранный кадр (QTFrame)
+///Кадры собирает цель, когда проходит адрес tracepoint при идущем эксперименте (pass)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceExperiment
+{
+    pub tracepoints: Vec<Tracepoint>,
+    pub frames: Vec<TraceFrame>,                    // Буфер кадров (номер кадра - индекс)
+    running: bool,
+    stop_reason: String,                            // Причина останова для qTStatus: "tnotrun:0", "tstop:0", "tfull:0", "tpasscount:<n>", "terror:<hex text>:<n>"
+    created: usize,                                 // Создано кадров в эксперименте (в кольцевом буфере старые удаляются)
+    used: usize,                                    // Занято байт буфера (кадры в формате tfile)
+    pub buffer_size: usize,
+    pub circular: bool,                             // Кольцевой буфер: при заполнении удаляются старые кадры, иначе эксперимент останавливается
+    selected: Option<usize>,                        // Выбранный кадр: 'g', 'p', 'm' читают из него
+    stepping: Option<(usize, u64)>,                 // while-stepping: индекс tracepoint и оставшееся количество шагов
+}
+
+
+impl Default for TraceExperiment
+{
+    fn default() -> TraceExperiment
+    {
+        TraceExperiment{
+            tracepoints: Vec::new(),
+            frames: Vec::new(),
+            running: false,
+            stop_reason: "tnotrun:0".to_string(),
+            created: 0,
+            used: 0,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            circular: false,
+            selected: None,
+            stepping: None,
+        }
+    }
+}
+
+
+impl TraceExperiment
+{
+    ///QTinit: удалить tracepoints и кадры (размер буфера сохраняется)
+    pub fn init(&mut self)
+    {
+        *self = TraceExperiment{ buffer_size: self.buffer_size, circular: self.circular, ..TraceExperiment::default() };
+    }
+
+
+    ///QTDP: определение tracepoint или его действия
+    ///<n>:<addr>:<E|D>:<step>:<pass>[:F<len>][:X<len>,<cond>][-] - tracepoint
+    ///-<n>:<addr>:[S]<actions>[-] - действия ('S' - начало while-stepping, следующие действия тоже while-stepping)
+    pub fn define(&mut self, args: &str) -> TargetResult<()>
+    {
+        let args = args.strip_suffix('-').unwrap_or(args); //'-': будут еще действия
+        if let Some(args) = args.strip_prefix('-')
+        {
+            let mut fields = args.splitn(3, ':');
+            let (number, addr) = (hex(fields.next())?, hex(fields.next())?);
+            let text = fields.next().ok_or(0x01)?;
+            let tracepoint = self.tracepoints.iter_mut().find(|tp| tp.number == number && tp.addr == addr).ok_or(0x01)?;
+            let stepping = text.starts_with('S') || tracepoint.definition.iter().any(|line| line.starts_with('S'));
+            let text = text.strip_prefix('S').unwrap_or(text);
+            let actions = parse_actions(text).ok_or(0x01)?;
+            if stepping
+            {
+                tracepoint.step_actions.extend(actions);
+            }
+            else
+            {
+                tracepoint.actions.extend(actions);
+            }
+            tracepoint.definition.push(format!("{}{:x}:{:016x}:{}", if stepping {'S'} else {'A'}, number, addr, text));
+            return Ok(());
+        }
+        let fields: Vec<&str> = args.split(':').collect();
+        if fields.len() < 5 || (fields[2] != "E" && fields[2] != "D")
+        {
+            return Err(0x01);
+        }
+        let mut condition = None;
+        for field in &fields[5..]
+        {//F<len> (быстрый tracepoint) исполняется как обычный
+            if let Some(cond) = field.strip_prefix('X')
+            {
+                let (len, bytecode) = cond.split_once(',').ok_or(0x01)?;
+                condition = Some(hex_decode(bytecode).filter(|b| Some(b.len() as u64) == u64::from_str_radix(len, 16).ok()).ok_or(0x01)?);
+            }
+        }
+        let tracepoint = Tracepoint{
+            number: hex(fields.first().copied())?,
+            addr: hex(fields.get(1).copied())?,
+            enabled: fields[2] == "E",
+            step_count: hex(fields.get(3).copied())?,
+            pass_count: hex(fields.get(4).copied())?,
+            condition,
+            actions: Vec::new(),
+            step_actions: Vec::new(),
+            hits: 0,
+            definition: Vec::new(),
+        };
+        self.tracepoints.retain(|tp| tp.number != tracepoint.number || tp.addr != tracepoint.addr);
+        self.tracepoints.push(tracepoint);
+        Ok(())
+    }
+
+
+    ///QTDPsrc:<n>:<addr>:<type>:<start>:<len>:<hex text> - исходный текст tracepoint (команды, условие) для qTfP/qTsP
+    pub fn define_source(&mut self, args: &str) -> TargetResult<()>
+    {
+        let mut fields = args.splitn(3, ':');
+        let (number, addr) = (hex(fields.next())?, hex(fields.next())?);
+        let tracepoint = self.tracepoints.iter_mut().find(|tp| tp.number == number && tp.addr == addr).ok_or(0x01)?;
+        tracepoint.definition.push(format!("Z{}", args));
+        Ok(())
+    }
+
+
+    ///QTEnable/QTDisable:<n>:<addr>
+    pub fn enable(&mut self, args: &str, enabled: bool) -> TargetResult<()>
+    {
+        let mut fields = args.splitn(2, ':');
+        let (number, addr) = (hex(fields.next())?, hex(fields.next())?);
+        let tracepoint = self.tracepoints.iter_mut().find(|tp| tp.number == number && tp.addr == addr).ok_or(0x01)?;
+        tracepoint.enabled = enabled;
+        Ok(())
+    }
+
+
+    ///QTStart: начать эксперимент с пустым буфером
+    pub fn start(&mut self)
+    {
+        for tracepoint in self.tracepoints.iter_mut()
+        {
+            tracepoint.hits = 0;
+        }
+        self.frames.clear();
+        self.created = 0;
+        self.used = 0;
+        self.selected = None;
+        self.stepping = None;
+        self.running = true;
+        self.stop_reason = "tnotrun:0".to_string();
+    }
+
+
+    ///Остановить эксперимент (QTStop - "tstop:0"). Кадры сохраняются
+    pub fn stop(&mut self, reason: &str)
+    {
+        if self.running
+        {
+            self.running = false;
+            self.stepping = None;
+            self.stop_reason = reason.to_string();
+        }
+    }
+
+
+    pub fn is_running(&self) -> bool
+    {
+        self.running
+    }
+
+
+    ///Ответ на qTStatus: T<running>;<stop reason>;tframes:..;tcreated:..;tfree:..;tsize:..;circular:..;disconn:0
+    pub fn status(&self) -> String
+    {
+        format!("T{};{};tframes:{:x};tcreated:{:x};tfree:{:x};tsize:{:x};circular:{};disconn:0",
+            u8::from(self.running), self.stop_reason, self.frames.len(), self.created, self.buffer_size.saturating_sub(self.used), self.buffer_size, u8::from(self.circular))
+    }
+
+
+    ///qTP:<n>:<addr> - V<срабатывания>:<байт кадров в буфере>
+    pub fn tracepoint_status(&self, args: &str) -> Option<String>
+    {
+        let mut fields = args.splitn(2, ':');
+        let (number, addr) = (hex(fields.next()).ok()?, hex(fields.next()).ok()?);
+        let tracepoint = self.tracepoints.iter().find(|tp| tp.number == number && tp.addr == addr)?;
+        let usage: usize = self.frames.iter().filter(|frame| frame.tracepoint == number).map(|frame| frame.encode().len()).sum();
+        Some(format!("V{:x}:{:x}", tracepoint.hits, usage))
+    }
+
+
+    ///QTBuffer:circular:<0|1> или QTBuffer:size:<n> (-1 - размер по умолчанию)
+    pub fn set_buffer(&mut self, args: &str) -> TargetResult<()>
+    {
+        match args.split_once(':')
+        {
+            Some(("circular", value)) => self.circular = hex(Some(value))? != 0,
+            Some(("size", "-1")) => self.buffer_size = DEFAULT_BUFFER_SIZE,
+            Some(("size", value)) => self.buffer_size = hex(Some(value))? as usize,
+            _ => return Err(0x01),
+        }
+        Ok(())
+    }
+
+
+    ///QTFrame: выбрать кадр. <n> - по номеру, pc:<addr>, tdp:<n>, range:<start>:<end>, outside:<start>:<end> - следующий после выбранного
+    ///Возвращает (кадр, tracepoint). Кадр не найден - None, выбранного кадра нет
+    pub fn select(&mut self, query: &str) -> TargetResult<Option<(usize, u64)>>
+    {
+        let from = self.selected.map_or(0, |index| index + 1);
+        let next = |test: &dyn Fn(&TraceFrame) -> bool| self.frames.iter().enumerate().skip(from).find(|(_, frame)| test(frame)).map(|(index, _)| index);
+        let found = match query.split_once(':')
+        {
+            Some(("pc", addr)) =>
+            {
+                let addr = hex(Some(addr))?;
+                next(&|frame| frame.pc == addr)
+            },
+            Some(("tdp", number)) =>
+            {
+                let number = hex(Some(number))?;
+                next(&|frame| frame.tracepoint == number)
+            },
+            Some((kind @ ("range" | "outside"), bounds)) =>
+            {
+                let (start, end) = bounds.split_once(':').ok_or(0x01)?;
+                let (start, end) = (hex(Some(start))?, hex(Some(end))?);
+                let inside = kind == "range";
+                next(&|frame| (start <= frame.pc && frame.pc <= end) == inside)
+            },
+            Some(_) => return Err(0x01),
+            None => Some(hex(Some(query))? as usize).filter(|&index| index < self.frames.len()),
+        };
+        self.selected = found;
+        Ok(found.map(|index| (index, self.frames[index].tracepoint)))
+    }
+
+
+    ///QTFrame:-1 - вернуться к живой цели
+    pub fn deselect(&mut self)
+    {
+        self.selected = None;
+    }
+
+
+    pub fn selected_frame(&self) -> Option<&TraceFrame>
+    {
+        self.frames.get(self.selected?)
+    }
+
+
+    ///Строки tracepoints для qTfP/qTsP и tfile: T<n>:<addr>:<E|D>:<step>:<pass>[:X<len>,<cond>], затем действия и исходный текст
+    pub fn upload(&self) -> Vec<String>
+    {
+        let mut lines = Vec::new();
+        for tp in &self.tracepoints
+        {
+            let mut line = format!("T{:x}:{:016x}:{}:{:x}:{:x}", tp.number, tp.addr, if tp.enabled {'E'} else {'D'}, tp.step_count, tp.pass_count);
+            if let Some(ref cond) = tp.condition
+            {
+                line += &format!(":X{:x},{}", cond.len(), hex_encode(cond));
+            }
+            lines.push(line);
+            lines.extend(tp.definition.iter().cloned());
+        }
+        lines
+    }
+
+
+    ///Кадры в формате tfile (qTBuffer)
+    pub fn buffer(&self) -> Vec<u8>
+    {
+        self.frames.iter().flat_map(|frame| frame.encode()).collect()
+    }
+
+
+    ///Файл трассировки (QTSave, формат tfile): заголовок, состояние, tracepoints, кадры и признак конца (кадр tracepoint 0)
+    ///regs_size - размер блока регистров ('g')
+    pub fn tfile(&self, regs_size: usize) -> Vec<u8>
+    {
+        let mut file = TFILE_MAGIC.to_vec();
+        file.extend_from_slice(format!("R {:x}\nstatus {}\n", regs_size, &self.status()[1..]).as_bytes());
+        for line in self.upload()
+        {
+            file.extend_from_slice(format!("tp {}\n", line).as_bytes());
+        }
+        file.push(b'\n');
+        file.extend(self.buffer());
+        file.extend_from_slice(&[0; 6]);
+        file
+    }
+
+
+    ///Цель исполняет инструкцию по адресу pc: кадры tracepoints этого адреса и очередного шага while-stepping
+    ///Данные читаются через Target (регистры - текущего потока)
+    pub fn pass(&mut self, pc: u64, target: &mut dyn Target)
+    {
+        if let Some((index, steps)) = self.stepping.take()
+        {
+            let frame = collect(self.tracepoints[index].number, pc, &self.tracepoints[index].step_actions, target);
+            self.record(frame);
+            if steps > 1 && self.running
+            {
+                self.stepping = Some((index, steps - 1));
+            }
+        }
+        for index in 0..self.tracepoints.len()
+        {
+            let tp = &self.tracepoints[index];
+            if !self.running || !tp.enabled || tp.addr != pc
+            {
+                continue;
+            }
+            if let Some(condition) = tp.condition.clone()
+            {//Ложное условие - tracepoint не срабатывает, ошибка вычисления останавливает эксперимент
+                let number = tp.number;
+                match eval(&condition, target)
+                {
+                    Ok(result) if result.value.unwrap_or(0) == 0 => continue,
+                    Ok(_) => (),
+                    Err(error) =>
+                    {
+                        self.stop(&format!("terror:{}:{:x}", hex_encode(error.as_bytes()), number));
+                        continue;
+                    },
+                }
+            }
+            let tp = &self.tracepoints[index];
+            let frame = collect(tp.number, pc, &tp.actions, target);
+            self.record(frame);
+            let tp = &mut self.tracepoints[index];
+            tp.hits += 1;
+            if tp.step_count > 0 && self.running
+            {
+                self.stepping = Some((index, tp.step_count));
+            }
+            if tp.pass_count > 0 && tp.hits >= tp.pass_count
+            {
+                let reason = format!("tpasscount:{:x}", tp.number);
+                self.stop(&reason);
+            }
+        }
+    }
+
+
+    ///Кадр в буфер. Места нет: в кольцевом буфере удаляются старые кадры, иначе эксперимент останавливается ("tfull:0")
+    fn record(&mut self, frame: TraceFrame)
+    {
+        let size = frame.encode().len();
+        while self.used + size > self.buffer_size
+        {
+            if !self.circular || self.frames.is_empty()
+            {
+                self.stop("tfull:0");
+                return;
+            }
+            let oldest = self.frames.remove(0);
+            self.used -= oldest.encode().len();
+            self.selected = None;
+        }
+        self.used += size;
+        self.created += 1;
+        self.frames.push(frame);
+    }
+}
+
+
+///Сбор кадра по действиям. Недоступные регистры и память не попадают в кадр
+fn collect(tracepoint: u64, pc: u64, actions: &[TraceAction], target: &mut dyn Target) -> TraceFrame
+{
+    let mut frame = TraceFrame{ tracepoint, pc, registers: None, memory: Vec::new() };
+    for action in actions
+    {
+        match *action
+        {
+            TraceAction::Registers => frame.registers = target.read_registers().ok(),
+            TraceAction::Memory(basereg, offset, len) =>
+            {
+                let base = match basereg
+                {
+                    Some(reg) => match target.read_register(reg)
+                    {
+                        Ok(value) => value.iter().rev().fold(0u64, |acc, &b| (acc << 8) | u64::from(b)), //little-endian
+                        Err(_) => continue,
+                    },
+                    None => 0,
+                };
+                let addr = base.wrapping_add(offset);
+                if let Ok(bytes) = target.read_memory(addr, len.min(u16::MAX as usize))
+                {
+                    frame.memory.push((addr, bytes));
+                }
+            },
+            TraceAction::Expr(ref bytecode) =>
+            {//Память, собранная операциями trace выражения. Ошибка вычисления - ничего не собрано
+                if let Ok(result) = eval(bytecode, target)
+                {
+                    frame.memory.extend(result.memory);
+                }
+            },
+        }
+    }
+    frame
+}
+
+
+///Действия из строки QTDP: R<mask>, M<basereg>,<offset>,<len>, X<len>,<bytecode> подряд
+pub fn parse_actions(text: &str) -> Option<Vec<TraceAction>>
+{
+    let mut actions = Vec::new();
+    let mut rest = text;
+    while let Some(kind) = rest.chars().next()
+    {
+        rest = &rest[kind.len_utf8()..];
+        let action = match kind
+        {
+            'R' =>
+            {
+                hex_run(&mut rest)?;
+                TraceAction::Registers
+            },
+            'M' =>
+            {
+                let basereg = match rest.strip_prefix("-1")
+                {
+                    Some(after) =>
+                    {
+                        rest = after;
+                        None
+                    },
+                    None => Some(hex_run(&mut rest)? as usize),
+                };
+                rest = rest.strip_prefix(',')?;
+                let offset = hex_run(&mut rest)?;
+                rest = rest.strip_prefix(',')?;
+                TraceAction::Memory(basereg, offset, hex_run(&mut rest)? as usize)
+            },
+            'X' =>
+            {
+                let len = hex_run(&mut rest)? as usize;
+                rest = rest.strip_prefix(',')?;
+                let hex_len = len.checked_mul(2)?;
+                let bytecode = hex_decode(rest.get(..hex_len)?)?;
+                rest = &rest[hex_len..];
+                TraceAction::Expr(bytecode)
+            },
+            _ => return None,
+        };
+        actions.push(action);
+    }
+    Some(actions)
+}
+
+
+///Hex-число в начале строки (строка сдвигается за него)
+fn hex_run(text: &mut &str) -> Option<u64>
+{
+    let len = text.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(text.len());
+    let value = u64::from_str_radix(&text[..len], 16).ok()?;
+    *text = &text[len..];
+    Some(value)
+}
+
+
+///Hex-поле пакета
+fn hex(field: Option<&str>) -> TargetResult<u64>
+{
+    field.and_then(|f| u64::from_str_radix(f, 16).ok()).ok_or(0x01)
+}
+
+
+///Тесты для TraceExperiment ================================================================================
+#[cfg(test)]
+mod test_trace
+{
+    use super::*;
+    use sim::{SimTarget, PC_REG, A0_REG};
+
+    #[test]
+    fn test_actions()
+    {
+        assert_eq!(Some(vec![TraceAction::Registers, TraceAction::Memory(None, 0x8000_1000, 0x10), TraceAction::Memory(Some(2), 0x10, 8)]),
+            parse_actions("R1fffffffffM-1,80001000,10M2,10,8"));
+        assert_eq!(Some(vec![TraceAction::Expr(vec![0x22, 0x07, 0x27])]), parse_actions("X3,220727"));
+        assert_eq!(None, parse_actions("M-1,80001000"));
+        assert_eq!(None, parse_actions("Q1"));
+        //Не-ASCII действие и длина выражения, удвоение которой переполняется
+        assert_eq!(None, parse_actions("é"));
+        assert_eq!(None, parse_actions("R1é"));
+        assert_eq!(None, parse_actions("X8000000000000000,"));
+    }
+
+    #[test]
+    fn test_experiment()
+    {
+        let mut target = SimTarget::new(false);
+        let mut trace = TraceExperiment::default();
+        target.write_memory(0x8000_1000, b"data").unwrap();
+        target.write_register(A0_REG, &0x8000_1000u64.to_le_bytes()).unwrap();
+
+        trace.define("1:0000000080000010:E:2:0-").unwrap();
+        trace.define("-1:0000000080000010:RffffffffM-1,80001000,4-").unwrap();
+        trace.define("-1:0000000080000010:SMa,0,2").unwrap();
+        trace.define("2:0000000080000020:E:0:2").unwrap();
+        trace.define_source("1:0000000080000010:cmd:0:7:636f6c6c656374").unwrap();
+        assert!(trace.define("-3:0000000080000030:R1").is_err());
+        assert_eq!(vec!["T1:0000000080000010:E:2:0", "A1:0000000080000010:RffffffffM-1,80001000,4", "S1:0000000080000010:Ma,0,2",
+            "Z1:0000000080000010:cmd:0:7:636f6c6c656374", "T2:0000000080000020:E:0:2"], trace.upload());
+
+        //Проход адресов подряд: кадр tracepoint 1, два шага while-stepping, кадр tracepoint 2
+        trace.pass(0x8000_0010, &mut target);
+        assert!(trace.frames.is_empty());
+        trace.start();
+        for pc in (0x8000_000cu64 .. 0x8000_0024).step_by(4)
+        {
+            target.write_register(PC_REG, &pc.to_le_bytes()).unwrap();
+            trace.pass(pc, &mut target);
+        }
+        assert_eq!(4, trace.frames.len());
+        assert_eq!(Some(b"data".to_vec()), trace.frames[0].read_memory(0x8000_1000, 4));
+        assert_eq!(None, trace.frames[0].read_memory(0x8000_1000, 5));
+        assert_eq!(Some(0x8000_0010u64.to_le_bytes().to_vec()), trace.frames[0].registers.as_ref().map(|r| r[PC_REG * 8 ..].to_vec()));
+        assert_eq!((0x8000_0014, None, Some(b"da".to_vec())), (trace.frames[1].pc, trace.frames[1].registers.clone(), trace.frames[1].read_memory(0x8000_1000, 2)));
+        assert_eq!((2, 0x8000_0020), (trace.frames[3].tracepoint, trace.frames[3].pc));
+        assert!(trace.status().starts_with("T1;tnotrun:0;tframes:4;tcreated:4;"));
+
+        //Выбор кадров
+        assert_eq!(Ok(Some((1, 1))), trace.select("1"));
+        assert_eq!(Ok(Some((3, 2))), trace.select("tdp:2"));
+        assert_eq!(Ok(None), trace.select("tdp:2"));
+        assert_eq!(Ok(Some((1, 1))), trace.select("range:80000014:80000018"));
+        assert_eq!(Ok(Some((3, 2))), trace.select("outside:80000010:80000018"));
+        assert_eq!(Ok(None), trace.select("pc:80000010")); //Поиск - после выбранного кадра, не найден - кадр не выбран
+        assert_eq!(Ok(Some((0, 1))), trace.select("pc:80000010"));
+        assert_eq!(0x8000_0010, trace.selected_frame().unwrap().pc);
+        trace.deselect();
+        assert_eq!(None, trace.selected_frame());
+
+        //Второе срабатывание tracepoint 2 - pass count
+        trace.pass(0x8000_0020, &mut target);
+        assert!(!trace.is_running());
+        assert!(trace.status().starts_with("T0;tpasscount:2;tframes:5;"));
+        assert_eq!(Some("V2:c".to_string()), trace.tracepoint_status("2:0000000080000020"));
+    }
+
+    #[test]
+    fn test_conditions()
+    {
+        let mut target = SimTarget::new(false);
+        let mut trace = TraceExperiment::default();
+        target.write_memory(0x8000_1000, b"data").unwrap();
+        //Условие a0 == 1, действие - выражение trace 0x80001000,2
+        trace.define("1:100:E:0:0:X7,26000a22011327-").unwrap();
+        trace.define("-1:100:X9,248000100022020c27").unwrap();
+        trace.define("2:200:E:0:0:X1,01").unwrap();
+        trace.start();
+        trace.pass(0x100, &mut target);
+        assert!(trace.frames.is_empty());
+        target.write_register(A0_REG, &1u64.to_le_bytes()).unwrap();
+        trace.pass(0x100, &mut target);
+        assert_eq!(vec![(0x8000_1000, b"da".to_vec())], trace.frames[0].memory);
+
+        //Ошибка вычисления условия останавливает эксперимент
+        trace.pass(0x200, &mut target);
+        assert!(trace.status().starts_with(&format!("T0;terror:{}:2;tframes:1;", hex_encode("Плавающая точка не поддерживается".as_bytes()))));
+    }
+
+    #[test]
+    fn test_buffer()
+    {
+        let mut target = SimTarget::new(false);
+        let mut trace = TraceExperiment::default();
+        trace.define("1:100:E:0:0-").unwrap();
+        trace.define("-1:100:M-1,0,10").unwrap();
+        trace.set_buffer("size:40").unwrap();
+        trace.start();
+        for _ in 0..3
+        {
+            trace.pass(0x100, &mut target);
+        }
+        //Кадр - 6 байт заголовка и 27 байт блока памяти: второй не помещается
+        assert_eq!(1, trace.frames.len());
+        assert!(trace.status().starts_with("T0;tfull:0;tframes:1;tcreated:1;tfree:1f;tsize:40;circular:0"));
+
+        trace.set_buffer("circular:1").unwrap();
+        trace.start();
+        for _ in 0..3
+        {
+            trace.pass(0x100, &mut target);
+        }
+        assert!(trace.status().starts_with("T1;tnotrun:0;tframes:1;tcreated:3;"));
+        trace.stop("tstop:0");
+
+        let file = trace.tfile(0x108);
+        assert!(file.starts_with(b"\x7fTRACE0\nR 108\nstatus 0;tstop:0;tframes:1;tcreated:3;tfree:1f;tsize:40;circular:1;disconn:0\ntp T1:0000000000000100:E:0:0\ntp A1:0000000000000100:M-1,0,10\n\n"));
+        assert_eq!(&[1, 0, 27, 0, 0, 0, b'M'], &file[file.len() - 6 - 33 .. file.len() - 6 - 26]);
+        assert_eq!(&[0; 6], &file[file.len() - 6 ..]);
+        trace.init();
+        assert!(trace.tracepoints.is_empty() && trace.frames.is_empty() && trace.circular);
+    }
+}
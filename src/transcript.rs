@@ -0,0 +1,392 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::iter::Peekable;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use codec::{Command, base64_encode, base64_decode};
+
+
+///Направление передачи (относительно GDB-сервера)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction
+{
+    In,                                             // От GDB-клиента к серверу
+    Out,                                            // От сервера к GDB-клиенту
+}
+
+
+impl Direction
+{
+    pub fn parse(text: &str) -> Option<Direction>
+    {
+        match text
+        {
+            "in" => Some(Direction::In),
+            "out" => Some(Direction::Out),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str
+    {
+        match *self
+        {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+
+///Одна запись транскрипта: одна последовательность байт, принятая или отправленная целиком
+///Формат строки JSON Lines:
+///{"ts_us":1700000000123456,"dir":"in","conn":1,"cmd":"qSupported","data":"qSupported:swbreak+","raw":"JHFTdXBw..."}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record
+{
+    pub ts_us: u64,                                 // Время в микросекундах от UNIX_EPOCH
+    pub dir: Direction,
+    pub conn: u64,                                  // Номер подключения
+    pub cmd: String,                                // Команда: название RSP-команды ("m", "vCont;", "X"), "+", "-", "^C". Для ответа - команда, на которую он отправлен
+    pub data: Option<String>,                       // Данные пакета (между '$' и '#') как текст. Для бинарных данных не точны, точны только raw
+    pub raw: Vec<u8>,                               // Байты как есть (в файле - base64)
+}
+
+
+impl Record
+{
+    ///Строка JSON (без '\n')
+    pub fn to_json(&self) -> String
+    {
+        let data = match self.data
+        {
+            Some(ref data) => format!(",\"data\":{}", json_string(data)),
+            None => String::new(),
+        };
+        format!("{{\"ts_us\":{},\"dir\":\"{}\",\"conn\":{},\"cmd\":{}{},\"raw\":\"{}\"}}",
+            self.ts_us, self.dir.as_str(), self.conn, json_string(&self.cmd), data, base64_encode(&self.raw))
+    }
+
+
+    ///Разобрать строку транскрипта
+    pub fn from_json(line: &str) -> Result<Record, String>
+    {
+        let fields = parse_json_object(line)?;
+        let get = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| &v[..]);
+        let field = |name: &str| get(name).ok_or_else(|| format!("Нет поля \'{}\'", name));
+        let number = |name: &str| field(name).and_then(|v| v.parse::<u64>().map_err(|_| format!("Поле \'{}\' не число", name)));
+        Ok(Record{
+            ts_us: number("ts_us")?,
+            dir: Direction::parse(field("dir")?).ok_or_else(|| "Поле 'dir' должно быть \"in\" или \"out\"".to_string())?,
+            conn: number("conn")?,
+            cmd: field("cmd")?.to_string(),
+            data: get("data").map(|v| v.to_string()),
+            raw: base64_decode(field("raw")?).ok_or_else(|| "Поле 'raw' не base64".to_string())?,
+        })
+    }
+}
+
+
+///Прочитать транскрипт из файла JSON Lines. Пустые строки пропускаются
+pub fn read_transcript(path: &str) -> Result<Vec<Record>, String>
+{
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty())
+    {
+        records.push(Record::from_json(line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?);
+    }
+    Ok(records)
+}
+
+
+///Разобрать плоский JSON-объект, значения которого строки или числа: {"name":"value","n":1}
+///Значения возвращаются как текст (строки без кавычек и с раскрытыми escape-последовательностями)
+pub fn parse_json_object(text: &str) -> Result<Vec<(String, String)>, String>
+{
+    let mut chars = text.trim().chars().peekable();
+    let mut fields = Vec::new();
+    if chars.next() != Some('{')
+    {
+        return Err("Ожидается '{'".to_string());
+    }
+    loop
+    {
+        skip_spaces(&mut chars);
+        match chars.peek()
+        {
+            Some('}') if fields.is_empty() => { chars.next(); break; },
+            Some('"') => (),
+            _ => return Err("Ожидается имя поля".to_string()),
+        }
+        let name = parse_json_string(&mut chars)?;
+        skip_spaces(&mut chars);
+        if chars.next() != Some(':')
+        {
+            return Err(format!("Ожидается ':' после \'{}\'", name));
+        }
+        skip_spaces(&mut chars);
+        let value = if chars.peek() == Some(&'"')
+        {
+            parse_json_string(&mut chars)?
+        }
+        else
+        {//Число (или true/false/null как текст)
+            let mut value = String::new();
+            while let Some(&c) = chars.peek()
+            {
+                if c == ',' || c == '}' || c.is_whitespace()
+                {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+            value
+        };
+        fields.push((name, value));
+        skip_spaces(&mut chars);
+        match chars.next()
+        {
+            Some(',') => continue,
+            Some('}') => break,
+            _ => return Err("Ожидается ',' или '}'".to_string()),
+        }
+    }
+    Ok(fields)
+}
+
+
+fn skip_spaces<I: Iterator<Item = char>>(chars: &mut Peekable<I>)
+{
+    while chars.peek().is_some_and(|c| c.is_whitespace())
+    {
+        chars.next();
+    }
+}
+
+
+///JSON-строка в кавычках (курсор на открывающей кавычке)
+fn parse_json_string<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<String, String>
+{
+    chars.next(); //'"'
+    let mut result = String::new();
+    loop
+    {
+        match chars.next()
+        {
+            None => return Err("Незакрытая строка".to_string()),
+            Some('"') => return Ok(result),
+            Some('\\') => match chars.next()
+            {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('b') => result.push('\u{8}'),
+                Some('f') => result.push('\u{c}'),
+                Some('u') =>
+                {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("Некорректный \\u{}", hex))?;
+                    result.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                },
+                Some(c) => result.push(c), // \" \\ \/
+                None => return Err("Незакрытая строка".to_string()),
+            },
+            Some(c) => result.push(c),
+        }
+    }
+}
+
+
+///Строка в JSON-строку в кавычках
+pub fn json_string(text: &str) -> String
+{
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars()
+    {
+        match c
+        {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+
+///Данные пакета $<data>#cs (или %<data>#cs) из принятого/отправленного сообщения. None - если это не пакет
+pub fn packet_data(raw: &[u8]) -> Option<&[u8]>
+{
+    let start = raw.iter().position(|&c| c == b'$' || c == b'%')?;
+    let end = raw[start..].iter().position(|&c| c == b'#')? + start;
+    Some(&raw[start+1 .. end])
+}
+
+
+///Команда принятого от GDB сообщения: название RSP-команды или одиночный символ
+pub fn inbound_cmd(raw: &[u8]) -> String
+{
+    match packet_data(raw)
+    {
+        Some(data) => Command::parse(data).name.to_string(),
+        None => match raw.first()
+        {
+            Some(0x03) => "^C".to_string(),
+            Some(&c) => char::from(c).to_string(),
+            None => String::new(),
+        },
+    }
+}
+
+
+///Номер следующего подключения (общий для всех транскриптов процесса)
+static NEXT_CONN: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_conn_id() -> u64
+{
+    NEXT_CONN.fetch_add(1, Ordering::SeqCst)
+}
+
+
+///Время в микросекундах от UNIX_EPOCH
+pub fn timestamp_us() -> u64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0)
+}
+
+
+///Запись транскрипта одного подключения в файл JSON Lines (файл дополняется, каждое подключение - со своим conn)
+pub struct Transcript
+{
+    out: Box<dyn Write + Send>,
+    conn: u64,
+}
+
+
+///Транскрипт, в который пишут основной поток и поток ожидания ^C
+pub type SharedTranscript = Arc<Mutex<Transcript>>;
+
+
+impl Transcript
+{
+    ///Открыть файл на дополнение
+    pub fn open(path: &str, conn: u64) -> io::Result<Transcript>
+    {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Transcript::new(Box::new(file), conn))
+    }
+
+
+    pub fn new(out: Box<dyn Write + Send>, conn: u64) -> Transcript
+    {
+        Transcript{ out, conn }
+    }
+
+
+    pub fn conn(&self) -> u64
+    {
+        self.conn
+    }
+
+
+    ///Записать принятое/отправленное сообщение. Ошибка записи не прерывает сеанс отладки, только выводится
+    pub fn record(&mut self, dir: Direction, cmd: &str, raw: &[u8])
+    {
+        let record = Record{
+            ts_us: timestamp_us(),
+            dir,
+            conn: self.conn,
+            cmd: cmd.to_string(),
+            data: packet_data(raw).map(|data| String::from_utf8_lossy(data).into_owned()),
+            raw: raw.to_vec(),
+        };
+        let line = record.to_json() + "\n";
+        if let Err(e) = self.out.write_all(line.as_bytes()).and_then(|_| self.out.flush())
+        {
+            println!("Transcript write failed: {}", e);
+        }
+    }
+}
+
+
+///Транскрипт нового подключения (номер подключения - следующий). Если файл не открывается, сеанс продолжается без записи
+pub fn open_shared(path: &str) -> Option<SharedTranscript>
+{
+    match Transcript::open(path, next_conn_id())
+    {
+        Ok(transcript) => Some(Arc::new(Mutex::new(transcript))),
+        Err(e) =>
+        {
+            println!("Transcript {} can not be opened: {}", path, e);
+            None
+        },
+    }
+}
+
+
+///Записать сообщение в транскрипт, если запись включена
+pub fn record(transcript: &Option<SharedTranscript>, dir: Direction, cmd: &str, raw: &[u8])
+{
+    if let Some(ref transcript) = *transcript
+    {
+        if let Ok(mut transcript) = transcript.lock()
+        {
+            transcript.record(dir, cmd, raw);
+        }
+    }
+}
+
+
+///Тесты для транскрипта ================================================================================
+#[cfg(test)]
+mod test_transcript
+{
+    use super::*;
+
+    #[test]
+    fn test_record_to_json()
+    {
+        let record = Record{
+            ts_us: 1700000000123456,
+            dir: Direction::In,
+            conn: 3,
+            cmd: "qSupported".to_string(),
+            data: Some("qSupported:swbreak+".to_string()),
+            raw: b"$qSupported:swbreak+#d1".to_vec(),
+        };
+        assert_eq!("{\"ts_us\":1700000000123456,\"dir\":\"in\",\"conn\":3,\"cmd\":\"qSupported\",\"data\":\"qSupported:swbreak+\",\"raw\":\"JHFTdXBwb3J0ZWQ6c3dicmVhaysjZDE=\"}", record.to_json());
+
+        assert_eq!("\"a\\\"\\\\\\n\\u0003\"", json_string("a\"\\\n\x03"));
+
+        //Обратно из JSON
+        assert_eq!(Ok(record.clone()), Record::from_json(&record.to_json()));
+        let record = Record{ cmd: "a\"\\\n\x03".to_string(), data: None, ..record };
+        assert_eq!(Ok(record.clone()), Record::from_json(&record.to_json()));
+        assert!(Record::from_json("{\"ts_us\":1}").is_err());
+        assert!(Record::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_inbound_cmd()
+    {
+        assert_eq!("m", inbound_cmd(b"+$m1000,4#8d"));
+        assert_eq!("vCont?", inbound_cmd(b"$vCont?#49"));
+        assert_eq!("+", inbound_cmd(b"+"));
+        assert_eq!("^C", inbound_cmd(b"\x03"));
+        assert_eq!(Some(&b"OK"[..]), packet_data(b"+$OK#9a"));
+        assert_eq!(None, packet_data(b"+"));
+    }
+}
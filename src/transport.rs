@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
+
+
+///Транспорт, поверх которого работает GDB-сервер (serve_connection)
+///Кроме чтения и записи нужен второй независимый дескриптор для потока ожидания ^C и чтение без освобождения очереди (peek)
+pub trait Transport: Read + Write + Send
+{
+    ///Еще один дескриптор того же соединения
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>>;
+
+    ///Принять данные без освобождения очереди чтения. Ok(0) - соединение закрыто
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    ///Закрыть соединение для всех дескрипторов (после vKill или отключения клиента)
+    fn shutdown(&self);
+
+    ///Ограничить ожидание в read (None - ждать без ограничения). По истечении read возвращает ошибку WouldBlock или TimedOut
+    fn set_poll_timeout(&mut self, timeout: Option<Duration>);
+}
+
+
+impl Transport for TcpStream
+{
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>>
+    {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        TcpStream::peek(self, buf)
+    }
+
+    fn shutdown(&self)
+    {
+        let _ = TcpStream::shutdown(self, Shutdown::Both);
+    }
+
+    fn set_poll_timeout(&mut self, timeout: Option<Duration>)
+    {//Таймаут у сокета общий для всех дескрипторов
+        let _ = TcpStream::set_read_timeout(self, timeout);
+    }
+}
+
+
+///Однонаправленный канал в памяти
+#[derive(Default)]
+struct Pipe
+{
+    state: Mutex<(VecDeque<u8>, bool)>,             // Данные и признак закрытия
+    cond: Condvar,
+}
+
+
+impl Pipe
+{
+    fn close(&self)
+    {
+        self.state.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+
+///Конец соединения в памяти. Когда все дескрипторы конца закрыты, второй конец читает Ok(0)
+struct Endpoint
+{
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+}
+
+
+impl Drop for Endpoint
+{
+    fn drop(&mut self)
+    {
+        self.tx.close();
+        self.rx.close();
+    }
+}
+
+
+///Соединение в памяти (для replay и тестов без сокетов). Создается парой: memory_pair()
+#[derive(Clone)]
+pub struct MemoryStream
+{
+    endpoint: Arc<Endpoint>,
+    read_timeout: Option<Duration>,                 // Как у TcpStream::set_read_timeout. None - ждать без ограничения
+}
+
+
+///Пара соединенных концов: записанное в один читается из другого
+pub fn memory_pair() -> (MemoryStream, MemoryStream)
+{
+    let a = Arc::new(Pipe::default());
+    let b = Arc::new(Pipe::default());
+    (
+        MemoryStream{ endpoint: Arc::new(Endpoint{ rx: a.clone(), tx: b.clone() }), read_timeout: None },
+        MemoryStream{ endpoint: Arc::new(Endpoint{ rx: b, tx: a }), read_timeout: None },
+    )
+}
+
+
+impl MemoryStream
+{
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>)
+    {
+        self.read_timeout = timeout;
+    }
+
+
+    ///Закрыть только направление записи (как TcpStream::shutdown(Shutdown::Write)): второй конец прочитает Ok(0) после уже записанных данных
+    pub fn shutdown_write(&self)
+    {
+        self.endpoint.tx.close();
+    }
+
+
+    ///Дождаться данных (или закрытия) и скопировать их в buf. consume - освободить очередь чтения
+    fn receive(&self, buf: &mut [u8], consume: bool) -> io::Result<usize>
+    {
+        let pipe = &self.endpoint.rx;
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = pipe.state.lock().unwrap();
+        while state.0.is_empty() && !state.1
+        {
+            state = match deadline
+            {
+                None => pipe.cond.wait(state).unwrap(),
+                Some(deadline) =>
+                {
+                    let now = Instant::now();
+                    if now >= deadline
+                    {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+                    }
+                    pipe.cond.wait_timeout(state, deadline - now).unwrap().0
+                },
+            };
+        }
+        let len = buf.len().min(state.0.len());
+        for (dst, src) in buf.iter_mut().zip(state.0.iter())
+        {
+            *dst = *src;
+        }
+        if consume
+        {
+            state.0.drain(..len);
+        }
+        Ok(len)
+    }
+}
+
+
+impl Read for MemoryStream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.receive(buf, true)
+    }
+}
+
+
+impl Write for MemoryStream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let pipe = &self.endpoint.tx;
+        let mut state = pipe.state.lock().unwrap();
+        if state.1
+        {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "memory stream closed"));
+        }
+        state.0.extend(buf);
+        pipe.cond.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+
+impl Transport for MemoryStream
+{
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>>
+    {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.receive(buf, false)
+    }
+
+    fn shutdown(&self)
+    {//Уже записанные данные второй конец еще прочитает
+        self.endpoint.tx.close();
+        self.endpoint.rx.close();
+    }
+
+    fn set_poll_timeout(&mut self, timeout: Option<Duration>)
+    {
+        self.read_timeout = timeout;
+    }
+}
+
+
+///Тесты для транспорта в памяти ================================================================================
+#[cfg(test)]
+mod test_transport
+{
+    use super::*;
+
+    #[test]
+    fn test_memory_pair()
+    {
+        let (mut a, mut b) = memory_pair();
+        a.write_all(b"$OK#9a").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(6, b.peek(&mut buf).unwrap());
+        assert_eq!(3, b.read(&mut buf[..3]).unwrap());
+        assert_eq!(b"$OK", &buf[..3]);
+        assert_eq!(3, b.read(&mut buf).unwrap());
+
+        //Нет данных: ожидание с таймаутом
+        b.set_read_timeout(Some(Duration::from_millis(10)));
+        assert_eq!(io::ErrorKind::TimedOut, b.read(&mut buf).unwrap_err().kind());
+
+        //Закрыт второй конец (со всеми дескрипторами)
+        let a_clone = a.try_clone_transport().unwrap();
+        drop(a);
+        drop(a_clone);
+        assert_eq!(0, b.read(&mut buf).unwrap());
+        assert!(b.write(b"+").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//!Команды breakpoints на стороне цели (dprintf): вывод printf приходит в O-пакетах, исполнение продолжается
+//!persist-команды остаются после отключения GDB
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::thread::spawn;
+
+use gdb_rsp_researcher::{CmdRegistry, RspClient, ServerConfig, SimTarget, MemoryStream, serve_connection};
+use gdb_rsp_researcher::codec::hex_encode;
+use gdb_rsp_researcher::sim::{PC_REG, A0_REG};
+
+use common::{connect, start, start_target, finish};
+
+
+///printf "a0=%d\n", a0
+const PRINT_A0: &str = "X13,26000a220022003401000761303d25640a0027";
+///printf "hit\n"
+const PRINT_HIT: &str = "XE,22002200340000056869740a0027";
+
+
+///a0 = value, pc = 0x80000000, 'c'. Возвращает вывод в консоль и pc останова
+fn run(client: &mut RspClient<MemoryStream>, a0: u64) -> (String, u64)
+{
+    assert_eq!("OK", client.write_register(A0_REG, &a0.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    let reply = client.request("c").unwrap();
+    assert!(reply.data.starts_with("T05"));
+    let pc = client.read_register(PC_REG).unwrap().unwrap();
+    (reply.console, u64::from_le_bytes([pc[0], pc[1], pc[2], pc[3], pc[4], pc[5], pc[6], pc[7]]))
+}
+
+
+#[test]
+fn test_breakpoint_commands()
+{
+    let (first, first_server) = connect();
+    let (second, second_server) = connect();
+    let handle = spawn(move ||
+    {//Оба подключения - к одной цели
+        let mut target = SimTarget::new(false);
+        serve_connection(first_server, &ServerConfig::default(), &CmdRegistry::with_defaults(), &mut target);
+        serve_connection(second_server, &ServerConfig::default(), &CmdRegistry::with_defaults(), &mut target);
+    });
+    let mut client = RspClient::new(first);
+    assert!(client.handshake("").unwrap().contains(";ConditionalBreakpoints+;BreakpointCommands+"));
+
+    //dprintf на 0x80000008, dprintf с условием a0 == 5 и persist на 0x80000010, обычный breakpoint на 0x80000020
+    assert_eq!("OK", client.request(&format!("Z0,80000008,4;cmds:0,{}", PRINT_A0)).unwrap().data);
+    assert_eq!("OK", client.request(&format!("Z0,80000010,4;X7,26000a22051327;cmds:1,{}", PRINT_HIT)).unwrap().data);
+    assert_eq!("OK", client.request("Z0,80000020,4").unwrap().data);
+    let (console, pc) = run(&mut client, 5);
+    assert!(console.starts_with("a0=5\nhit\n GDB-Server message : Halted due to breakpoint."), "{:?}", console);
+    assert_eq!(0x8000_0020, pc);
+    let (console, pc) = run(&mut client, 3);
+    assert!(console.starts_with("a0=3\n GDB-Server message"), "{:?}", console);
+    assert_eq!(0x8000_0020, pc);
+
+    //Z0 без cmds снимает команды: breakpoint снова останавливает
+    assert_eq!("OK", client.request("Z0,80000008,4").unwrap().data);
+    assert_eq!(0x8000_0008, run(&mut client, 5).1);
+    assert_eq!("OK", client.request(&format!("Z0,80000008,4;cmds:0,{}", PRINT_A0)).unwrap().data);
+    assert_eq!("E01", client.request(&format!("Z0,80000030,4;cmds:2,{}", PRINT_HIT)).unwrap().data);
+    assert_eq!("E01", client.request("Z0,80000030,4;cmds:1,X2,27").unwrap().data);
+
+    //После отключения GDB остается только dprintf с persist: новый GDB останавливается на своем breakpoint после него
+    drop(client);
+    let mut client = RspClient::new(second);
+    client.handshake("").unwrap();
+    assert_eq!("OK", client.request("Z0,80000040,4").unwrap().data);
+    assert_eq!("OK", client.write_register(A0_REG, &5u64.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    let reply = client.request("c").unwrap();
+    assert_eq!("T05thread:1;", reply.data);
+    assert!(reply.console.starts_with("hit\n GDB-Server message : Halted due to breakpoint."), "{:?}", reply.console);
+    assert_eq!(Some(0x8000_0040u64.to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_breakpoint_commands_disabled()
+{
+    let config = ServerConfig::from_args(&["-f".to_string(), "BreakpointCommands-".to_string()]).unwrap();
+    let (mut client, handle) = start_target(config, SimTarget::new(false));
+    assert!(!client.handshake("").unwrap().contains("BreakpointCommands+"));
+
+    //Feature отключена: команды игнорируются, breakpoint останавливает без вывода
+    assert_eq!("OK", client.request(&format!("Z0,80000008,4;cmds:0,{}", PRINT_A0)).unwrap().data);
+    let (console, pc) = run(&mut client, 5);
+    assert!(!console.contains("a0=5"), "{:?}", console);
+    assert_eq!(0x8000_0008, pc);
+    assert_eq!("OK", client.request("Z0,80000008,4;cmds:1,X2,27").unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_breakpoint_commands_output_while_running()
+{
+    let (mut client, handle) = start(true); //Без handshake: режим подтверждений, '+' на O-пакет приходит перед ^C
+
+    //С --loop исполнение идет до ^C, а вывод dprintf приходит O-пакетом, пока цель еще исполняется
+    assert_eq!("OK", client.request(&format!("Z0,80000008,4;cmds:0,{}", PRINT_A0)).unwrap().data);
+    assert_eq!("OK", client.write_register(A0_REG, &5u64.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    client.send_packet(b"c").unwrap();
+    assert_eq!(format!("O{}", hex_encode(b"a0=5\n")).into_bytes(), client.read_packet().unwrap());
+    client.interrupt().unwrap();
+    let reply = client.read_packet().unwrap();
+    let reply = if reply.starts_with(b"O") {client.read_packet().unwrap()} else {reply};
+    assert!(reply.starts_with(b"T02"), "{:?}", String::from_utf8_lossy(&reply));
+    finish(client, handle);
+}
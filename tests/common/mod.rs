@@ -0,0 +1,63 @@
+//!Общая обвязка интеграционных тестов: сервер в отдельном потоке поверх транспорта в памяти и клиент, подключенный к нему
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use gdb_rsp_researcher::{MemoryStream, RspClient, ServerConfig, SimTarget, Target, memory_pair, serve_connection};
+use gdb_rsp_researcher::gdb_server::set_verbose;
+
+
+///Соединение в памяти: (конец клиента, конец сервера)
+pub fn connect() -> (MemoryStream, MemoryStream)
+{
+    set_verbose(false);
+    let (mut client, server) = memory_pair();
+    client.set_read_timeout(Some(Duration::from_secs(5))); //Сервер, который не ответил, - ошибка теста, а не зависание
+    (client, server)
+}
+
+
+///Сервер с SimTarget и настройками по умолчанию
+pub fn start(loop_run: bool) -> (RspClient<MemoryStream>, JoinHandle<()>)
+{
+    start_target(ServerConfig::default(), SimTarget::new(loop_run))
+}
+
+
+///Сервер с целью target и набором команд из config (переопределения -f, --mock, --replies)
+pub fn start_target<T: Target + Send + 'static>(config: ServerConfig, mut target: T) -> (RspClient<MemoryStream>, JoinHandle<()>)
+{
+    let registry = config.make_registry().unwrap();
+    let (client, server) = connect();
+    let handle = spawn(move ||
+    {
+        serve_connection(server, &config, &registry, &mut target);
+    });
+    (RspClient::new(client), handle)
+}
+
+
+///Завершить сеанс: на vKill сервер отвечает OK и закрывает соединение
+pub fn finish(mut client: RspClient<MemoryStream>, handle: JoinHandle<()>)
+{
+    assert_eq!("OK", client.kill().unwrap().data);
+    handle.join().unwrap();
+    assert_eq!(io::ErrorKind::UnexpectedEof, client.read_packet().unwrap_err().kind());
+}
+
+
+///Пустой временный каталог для песочницы vFile и настройки сервера с ней
+pub fn sandbox(name: &str) -> (PathBuf, ServerConfig)
+{
+    let dir = env::temp_dir().join(format!("{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = ServerConfig{ sandbox: Some(dir.to_str().unwrap().to_string()), ..ServerConfig::default() };
+    (dir, config)
+}
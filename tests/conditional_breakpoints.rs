@@ -0,0 +1,68 @@
+//!Условия breakpoints на стороне цели: симулятор вычисляет агентские выражения и останавливается, только если условие истинно
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use gdb_rsp_researcher::{RspClient, ServerConfig, SimTarget, MemoryStream};
+use gdb_rsp_researcher::sim::{PC_REG, A0_REG};
+
+use common::{start, start_target, finish};
+
+
+///a0 = value, pc = 0x80000000, 'c'. Возвращает pc останова
+fn run(client: &mut RspClient<MemoryStream>, a0: u64) -> u64
+{
+    assert_eq!("OK", client.write_register(A0_REG, &a0.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    assert!(client.request("c").unwrap().data.starts_with("T05"));
+    let pc = client.read_register(PC_REG).unwrap().unwrap();
+    u64::from_le_bytes([pc[0], pc[1], pc[2], pc[3], pc[4], pc[5], pc[6], pc[7]])
+}
+
+
+#[test]
+fn test_conditional_breakpoints()
+{
+    let (mut client, handle) = start(false);
+    assert!(client.handshake("").unwrap().contains(";ConditionalBreakpoints+"));
+
+    //Breakpoint с условием a0 == 5 или a0 == 7 и безусловный breakpoint за ним
+    assert_eq!("OK", client.request("Z0,80000010,4;X7,26000a22051327X7,26000a22071327").unwrap().data);
+    assert_eq!("OK", client.request("Z0,80000020,4").unwrap().data);
+    assert_eq!(0x8000_0020, run(&mut client, 0));
+    assert_eq!(0x8000_0010, run(&mut client, 5));
+    assert_eq!(0x8000_0010, run(&mut client, 7));
+
+    //Z0 без условий делает breakpoint безусловным, после z0 условия снимаются
+    assert_eq!("OK", client.request("Z0,80000010,4").unwrap().data);
+    assert_eq!(0x8000_0010, run(&mut client, 0));
+    assert_eq!("OK", client.request("Z0,80000010,4;X3,220027").unwrap().data);
+    assert_eq!(0x8000_0020, run(&mut client, 0));
+    assert_eq!("OK", client.request("z0,80000010,4").unwrap().data);
+    assert_eq!("OK", client.request("Z0,80000010,4").unwrap().data);
+    assert_eq!(0x8000_0010, run(&mut client, 0));
+
+    //Ошибка вычисления условия - останов
+    assert_eq!("OK", client.request("Z0,80000010,4;X1,01").unwrap().data);
+    assert_eq!(0x8000_0010, run(&mut client, 0));
+    assert_eq!("E01", client.request("Z0,80000010,4;X7,2600").unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_conditional_breakpoints_disabled()
+{
+    let config = ServerConfig::from_args(&["-f".to_string(), "ConditionalBreakpoints-".to_string()]).unwrap();
+    let (mut client, handle) = start_target(config, SimTarget::new(false));
+    assert!(!client.handshake("").unwrap().contains("ConditionalBreakpoints+"));
+
+    //Feature отключена: условия игнорируются, breakpoint безусловный
+    assert_eq!("OK", client.request("Z0,80000010,4;X7,26000a22051327").unwrap().data);
+    assert_eq!("OK", client.request("Z0,80000020,4").unwrap().data);
+    assert_eq!(0x8000_0010, run(&mut client, 0));
+    assert_eq!("OK", client.request("Z0,80000010,4;X7,2600").unwrap().data);
+    assert_eq!(0x8000_0010, run(&mut client, 0));
+    finish(client, handle);
+}
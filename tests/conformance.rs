@@ -0,0 +1,307 @@
+//!Проверка GDB-сервера на соответствие описанию RSP (приложение "Remote Serial Protocol" руководства GDB)
+//!Сервер работает в том же процессе поверх транспорта в памяти, диалоги ведет RspClient
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use gdb_rsp_researcher::{Inferiors, MemoryStream, RspClient, ServerConfig, SimTarget};
+use gdb_rsp_researcher::codec::{hex_encode, hex_decode};
+
+use common::{start, start_target, finish};
+
+
+///Число регистров SimTarget в ответе на 'g' (x0..x31, pc) и размер регистра
+const REG_COUNT: usize = 33;
+const REG_SIZE: usize = 8;
+const PC_REG: usize = 32;
+
+
+///Stop Reply Packet: S<sig>, T<sig>[n:r;]..., W<code>, X<sig>
+fn assert_stop_reply(reply: &[u8], signal: u8)
+{
+    let text = String::from_utf8_lossy(reply);
+    assert!(text.len() >= 3 && "STWX".contains(&text[..1]), "not a stop reply: {:?}", text);
+    assert_eq!(Some(signal), u8::from_str_radix(&text[1..3], 16).ok(), "signal in {:?}", text);
+    if text.starts_with('T')
+    {//Поля n:r; разделены ';' и завершаются ';'
+        let fields = &text[3..];
+        assert!(fields.is_empty() || fields.ends_with(';'), "fields in {:?}", text);
+        assert!(fields.split(';').filter(|f| !f.is_empty()).all(|f| f.contains(':')), "fields in {:?}", text);
+    }
+}
+
+
+///Пакет, который пришел после ответа (O-пакеты перед Stop Reply пропускаются)
+fn read_stop_reply(client: &mut RspClient<MemoryStream>) -> Vec<u8>
+{
+    loop
+    {
+        let reply = client.read_packet().unwrap();
+        if reply.first() != Some(&b'O') || reply == b"OK"
+        {
+            return reply;
+        }
+        assert!(hex_decode(&String::from_utf8_lossy(&reply[1..])).is_some(), "O packet is not hex: {:?}", reply);
+    }
+}
+
+
+#[test]
+fn test_handshake_and_no_ack_mode()
+{
+    let (mut client, handle) = start(false);
+    //До no-ack режима на каждый пакет отвечают '+'
+    let supported = client.request("qSupported:multiprocess+;swbreak+;hwbreak+").unwrap().data;
+    assert_eq!(1, client.stats.acks_received);
+    let features: Vec<&str> = supported.split(';').collect();
+    let packet_size = features.iter().find(|f| f.starts_with("PacketSize=")).expect("PacketSize is mandatory");
+    assert!(usize::from_str_radix(&packet_size["PacketSize=".len()..], 16).unwrap() >= 32);
+    assert!(features.contains(&"QStartNoAckMode+"));
+    assert!(features.contains(&"swbreak+"));
+    assert!(!features.contains(&"multiprocess+"), "features the server does not implement must not be advertised");
+
+    //OK на QStartNoAckMode еще подтверждается '+', после него acknowledgment нет
+    client.send_packet(b"QStartNoAckMode").unwrap();
+    assert_eq!(b"OK".to_vec(), client.read_packet().unwrap());
+    assert_eq!(2, client.stats.acks_received);
+    client.no_ack_mode = true;
+    assert_eq!("m1", client.request("qfThreadInfo").unwrap().data);
+    assert_eq!("l", client.request("qsThreadInfo").unwrap().data);
+    assert_eq!("QC1", client.request("qC").unwrap().data);
+    assert_eq!(2, client.stats.acks_received);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_unknown_packets_get_empty_reply()
+{
+    let (mut client, handle) = start(false);
+    assert_eq!("", client.request("vMustReplyEmpty").unwrap().data);
+    assert_eq!("", client.request("qThisPacketDoesNotExist").unwrap().data);
+    assert_eq!("", client.request("Z7,1000,4").unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_register_round_trip()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("swbreak+").unwrap();
+
+    //'g': все регистры в hex, по REG_SIZE байт в порядке цели
+    let regs = hex_decode(&client.request("g").unwrap().data).unwrap();
+    assert_eq!(REG_COUNT * REG_SIZE, regs.len());
+
+    let value = 0x8000_1234u64.to_le_bytes();
+    assert_eq!("OK", client.write_register(10, &value).unwrap().data);
+    assert_eq!(Some(value.to_vec()), client.read_register(10).unwrap());
+
+    let mut regs: Vec<u8> = (0..REG_COUNT * REG_SIZE).map(|i| i as u8).collect();
+    assert_eq!("OK", client.request(&format!("G{}", hex_encode(&regs))).unwrap().data);
+    assert_eq!(Some(regs.clone()), hex_decode(&client.request("g").unwrap().data));
+    assert_eq!(Some(regs[PC_REG * REG_SIZE ..].to_vec()), client.read_register(PC_REG).unwrap());
+
+    //Ошибки - Enn
+    regs.truncate(REG_SIZE);
+    assert!(client.request(&format!("G{}", hex_encode(&regs))).unwrap().data.starts_with('E'));
+    assert!(client.request("p1000").unwrap().data.starts_with('E'));
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_memory_round_trip()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("").unwrap();
+    //Бинарные данные с символами, которые надо экранировать: '#', '$', '}', '*'
+    let data: Vec<u8> = (0..=255u8).collect();
+    assert_eq!("OK", client.write_memory(0x8000_0000, &data).unwrap().data);
+    assert_eq!(Some(data), client.read_memory(0x8000_0000, 256).unwrap());
+    //Пробный пакет нулевой длины: GDB так проверяет поддержку X
+    assert_eq!("OK", client.request("X80000000,0:").unwrap().data);
+    //Память читается байтами в hex: 2 символа на байт
+    assert_eq!(2 * 16, client.request("m90000000,10").unwrap().data.len());
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_breakpoint_and_continue()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("swbreak+").unwrap();
+    assert_eq!("OK", client.matchpoint(true, 0, 0x8000_0010, 4).unwrap().data);
+    assert_eq!("OK", client.matchpoint(true, 2, 0x8000_1000, 8).unwrap().data);
+
+    //Останов на breakpoint: T05 (SIGTRAP) с полем swbreak, т.к. GDB прислал swbreak+
+    client.send_packet(b"c").unwrap();
+    let reply = read_stop_reply(&mut client);
+    assert_stop_reply(&reply, 5);
+    assert!(String::from_utf8_lossy(&reply).contains("swbreak:;"));
+    //'?' повторяет причину последнего останова
+    assert_eq!(reply, client.request("?").unwrap().data.into_bytes());
+
+    assert_eq!("OK", client.matchpoint(false, 0, 0x8000_0010, 4).unwrap().data);
+    assert_eq!("OK", client.matchpoint(false, 2, 0x8000_1000, 8).unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_step()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("").unwrap();
+    let pc = 0x8000_0000u64;
+    assert_eq!("OK", client.write_register(PC_REG, &pc.to_le_bytes()).unwrap().data);
+    client.send_packet(b"s").unwrap();
+    assert_stop_reply(&read_stop_reply(&mut client), 5);
+    assert_eq!(Some((pc + 4).to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+
+    //'t' - симулятор поддерживает non-stop режим
+    assert_eq!("vCont;c;C;s;S;t", client.request("vCont?").unwrap().data);
+    client.send_packet(b"vCont;s:0").unwrap();
+    assert_stop_reply(&read_stop_reply(&mut client), 5);
+    assert_eq!(Some((pc + 8).to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_continue_and_interrupt()
+{
+    let (mut client, handle) = start(true);
+    client.handshake("").unwrap();
+    client.send_packet(b"c").unwrap();
+    sleep(Duration::from_millis(50)); //^C приходит отдельно от пакета, как от GDB
+    client.interrupt().unwrap();
+    //Прерывание по ^C: T02 (SIGINT)
+    assert_stop_reply(&read_stop_reply(&mut client), 2);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_monitor_command()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("").unwrap();
+    //Вывод monitor-команды - в O-пакетах, результат - OK
+    let reply = client.request("qRcmd,72657365742068616c74").unwrap(); //"reset halt"
+    assert_eq!("OK", reply.data);
+    assert!(!reply.console.is_empty());
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_threads()
+{
+    let (mut client, handle) = start_target(ServerConfig::default(), SimTarget::with_harts(false, 2));
+    client.handshake("").unwrap();
+    //Список потоков: 'm' с номерами через ',', 'l' - конец
+    assert_eq!("m1,2", client.request("qfThreadInfo").unwrap().data);
+    assert_eq!("l", client.request("qsThreadInfo").unwrap().data);
+    assert_eq!("OK", client.request("T2").unwrap().data);
+    assert!(client.request("T3").unwrap().data.starts_with('E'));
+
+    //Регистры у каждого потока свои
+    assert_eq!("OK", client.request("Hg2").unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.request("Hg1").unwrap().data);
+    assert_eq!(Some(vec![0; REG_SIZE]), client.read_register(PC_REG).unwrap());
+    let info = hex_decode(&client.request("qThreadExtraInfo,2").unwrap().data).unwrap();
+    assert!(String::from_utf8_lossy(&info).contains("pc 0x80000000"));
+
+    //Stop Reply с полем thread: поток, на котором произошел останов
+    client.send_packet(b"vCont;s:2;c").unwrap();
+    let reply = read_stop_reply(&mut client);
+    assert_stop_reply(&reply, 5);
+    assert!(String::from_utf8_lossy(&reply).contains("thread:2;"));
+    assert_eq!("QC2", client.request("qC").unwrap().data);
+    assert_eq!(Some(0x8000_0004u64.to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_multiprocess()
+{
+    let (mut client, handle) = start_target(ServerConfig::default(), Inferiors::new(vec![SimTarget::new(false), SimTarget::with_harts(false, 2)]));
+    //Multiprocess объявляется, только если его прислал GDB
+    assert!(client.handshake("multiprocess+").unwrap().contains("multiprocess+"));
+    assert_eq!("OK", client.request("!").unwrap().data);
+    assert_eq!("QCp1.1", client.request("qC").unwrap().data);
+    //vAttach: Stop Reply с thread-id pPID.TID
+    client.send_packet(b"vAttach;2").unwrap();
+    let reply = read_stop_reply(&mut client);
+    assert!(String::from_utf8_lossy(&reply).contains("thread:p2.1;"));
+    assert_eq!("mp1.1,p2.1,p2.2", client.request("qfThreadInfo").unwrap().data);
+
+    //У каждого процесса своя память и регистры
+    assert_eq!("OK", client.write_memory(0x8000_0000, b"core2").unwrap().data);
+    assert_eq!("OK", client.request("Hgp1.1").unwrap().data);
+    assert_eq!(Some(vec![0; 5]), client.read_memory(0x8000_0000, 5).unwrap());
+    assert_eq!("OK", client.write_register(PC_REG, &0x1000u64.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.request("Hgp2.2").unwrap().data);
+    assert_eq!(Some(b"core2".to_vec()), client.read_memory(0x8000_0000, 5).unwrap());
+    assert_eq!(Some(vec![0; REG_SIZE]), client.read_register(PC_REG).unwrap());
+
+    client.send_packet(b"vCont;s:p1.1;c").unwrap();
+    let reply = read_stop_reply(&mut client);
+    assert_stop_reply(&reply, 5);
+    assert!(String::from_utf8_lossy(&reply).contains("thread:p1.1;"));
+    assert_eq!("OK", client.request("D;2").unwrap().data);
+    assert_eq!("mp1.1", client.request("qfThreadInfo").unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_non_stop()
+{
+    let (mut client, handle) = start_target(ServerConfig::default(), SimTarget::with_harts(true, 2));
+    assert!(client.handshake("").unwrap().contains("QNonStop+"));
+    assert_eq!("OK", client.request("QNonStop:1").unwrap().data);
+    //Оба потока исполняются; ответ на vCont - OK сразу, пакеты обрабатываются во время исполнения
+    assert_eq!("OK", client.request("vCont;c").unwrap().data);
+    assert_eq!("OK", client.request("?").unwrap().data);
+    assert_eq!("OK", client.request("vCont;t:2").unwrap().data);
+    //Останов - уведомлением %Stop (без '+'), подтверждение - vStopped до OK
+    let notification = client.read_notification().unwrap();
+    assert!(notification.starts_with("Stop:"), "{:?}", notification);
+    assert_stop_reply(&notification.as_bytes()["Stop:".len()..], 0);
+    assert!(notification.contains("thread:2;"));
+    assert_eq!("OK", client.request("vStopped").unwrap().data);
+    assert_eq!("OK", client.request("vCtrlC").unwrap().data);
+    let notification = client.read_notification().unwrap();
+    assert_stop_reply(&notification.as_bytes()["Stop:".len()..], 2);
+    assert!(notification.contains("thread:1;"));
+    assert_eq!("OK", client.request("vStopped").unwrap().data);
+    finish(client, handle);
+}
+
+
+#[test]
+fn test_non_stop_breakpoint()
+{
+    let (mut client, handle) = start_target(ServerConfig::default(), SimTarget::with_harts(true, 2));
+    client.handshake("swbreak+").unwrap();
+    assert_eq!("OK", client.matchpoint(true, 0, 0x8000_0020, 4).unwrap().data);
+    assert_eq!("OK", client.request("Hg1").unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    assert_eq!("OK", client.request("QNonStop:1").unwrap().data);
+    //Поток исполняется, пока GDB ничего не присылает, и останавливается на breakpoint
+    assert_eq!("OK", client.request("vCont;c:1").unwrap().data);
+    assert_eq!("Stop:T05swbreak:;thread:1;", client.read_notification().unwrap());
+    assert_eq!("OK", client.request("vStopped").unwrap().data);
+    assert_eq!(Some(0x8000_0020u64.to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    finish(client, handle);
+}
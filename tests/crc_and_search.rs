@@ -0,0 +1,41 @@
+//!qCRC и qSearch:memory: CRC-32 и поиск байтов выполняются на стороне сервера над памятью симулятора
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use gdb_rsp_researcher::codec::crc32;
+use gdb_rsp_researcher::cmd_handlers::MEMORY_QUERY_MAX;
+
+use common::{start, finish};
+
+
+#[test]
+fn test_crc_and_search()
+{
+    let (mut client, handle) = start(false);
+    client.handshake("").unwrap();
+
+    //CRC-32 GDB: "123456789" -> 0x0376e6e7. Память, в которую не писали, - нули
+    assert_eq!("OK", client.write_memory(0x8000_0000, b"123456789").unwrap().data);
+    assert_eq!("C0376e6e7", client.request("qCRC:80000000,9").unwrap().data);
+    assert_eq!("Cffffffff", client.request("qCRC:80000000,0").unwrap().data);
+    assert_eq!(format!("C{:08x}", crc32(0xffff_ffff, &[0; 0x2345])), client.request("qCRC:90000000,2345").unwrap().data);
+    assert_eq!("E01", client.request("qCRC:80000000").unwrap().data);
+    //Длина больше MEMORY_QUERY_MAX - ошибка, а не чтение всей памяти
+    assert_eq!("E01", client.request("qCRC:0,ffffffffffffffff").unwrap().data);
+    assert_eq!(format!("C{:08x}", crc32(0xffff_ffff, &vec![0; MEMORY_QUERY_MAX])), client.request(&format!("qCRC:90000000,{:x}", MEMORY_QUERY_MAX)).unwrap().data);
+    assert_eq!("E01", client.request(&format!("qCRC:0,{:x}", MEMORY_QUERY_MAX + 1)).unwrap().data);
+
+    //Образец на стыке частей чтения (0x1000) и образец с байтами, требующими экранирования
+    assert_eq!("OK", client.write_memory(0x8000_0ffe, b"\x7d#$*").unwrap().data);
+    assert_eq!("1,80000ffe", client.request_bytes(b"qSearch:memory:80000000;2000;}]}\x03}\x04}\x0a").unwrap().data);
+    assert_eq!("1,80000002", client.request("qSearch:memory:80000000;2000;345").unwrap().data);
+    assert_eq!("0", client.request("qSearch:memory:80000003;2000;345").unwrap().data);
+    assert_eq!("0", client.request("qSearch:memory:80000000;4;345").unwrap().data);
+    assert_eq!("E01", client.request("qSearch:memory:80000000;2000;").unwrap().data);
+    assert_eq!("E01", client.request("qSearch:memory:0;ffffffffffffffff;345").unwrap().data);
+    assert_eq!("0", client.request(&format!("qSearch:memory:90000000;{:x};345", MEMORY_QUERY_MAX)).unwrap().data);
+    assert_eq!("E01", client.request(&format!("qSearch:memory:90000000;{:x};345", MEMORY_QUERY_MAX + 1)).unwrap().data);
+    finish(client, handle);
+}
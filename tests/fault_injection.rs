@@ -0,0 +1,80 @@
+//!Внесение неисправностей в ответы сервера (--faults): как их видит клиент со стороны GDB
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use gdb_rsp_researcher::{ServerConfig, DummyTarget};
+
+use common::{start_target, finish};
+
+
+const RULES: &str = r#"
+# Каждый второй qC - с неверной контрольной суммой
+[[fault]]
+cmd = "qC"
+action = "corrupt-checksum"
+every = 2
+
+[[fault]]
+cmd = "g"
+action = "error"
+
+[[fault]]
+cmd = "qfThreadInfo"
+action = "duplicate"
+
+[[fault]]
+cmd = "m"
+action = "spurious-stop"
+reply = "T02"
+
+[[fault]]
+cmd = "qSymbol"
+action = "drop"
+
+[[fault]]
+cmd = "qOffsets"
+action = "delay"
+ms = 100
+"#;
+
+
+#[test]
+fn test_faults()
+{
+    let path = env::temp_dir().join(format!("gdb-rsp-faults-{}.toml", std::process::id()));
+    fs::write(&path, RULES).unwrap();
+    let config = ServerConfig{ faults: Some(path.to_string_lossy().into_owned()), ..ServerConfig::default() };
+    let (mut client, handle) = start_target(config, DummyTarget::new(false));
+    client.stream().set_read_timeout(Some(Duration::from_millis(500))); //Отброшенный ответ ждется недолго
+
+    //Испорченная контрольная сумма: '-' и ответ повторяется без неисправности
+    assert_eq!("QC0", client.request("qC").unwrap().data);
+    assert_eq!(io::ErrorKind::InvalidData, client.request("qC").unwrap_err().kind());
+    client.send_raw(b"-").unwrap();
+    assert_eq!(b"QC0".to_vec(), client.read_packet().unwrap());
+
+    assert_eq!("E01", client.request("g").unwrap().data);
+
+    assert_eq!("l", client.request("qfThreadInfo").unwrap().data);
+    assert_eq!(b"l".to_vec(), client.read_packet().unwrap());
+
+    assert!(!client.request("m80000000,4").unwrap().data.starts_with('E'));
+    assert_eq!(b"T02".to_vec(), client.read_packet().unwrap());
+
+    client.send_packet(b"qSymbol::").unwrap();
+    assert!(client.read_packet().is_err(), "dropped reply must not arrive");
+
+    let start = Instant::now();
+    client.request("qOffsets").unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    finish(client, handle);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,28 @@
+//!Регрессионный корпус fuzz-целей (fuzz/regression): входы, на которых сервер раньше падал
+//!Каждый вход проходит через все три цели без cargo fuzz
+
+extern crate gdb_rsp_researcher;
+
+use std::fs;
+use std::path::Path;
+
+use gdb_rsp_researcher::fuzz;
+
+
+#[test]
+fn test_regression_corpus()
+{
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("regression");
+    let mut count = 0;
+    for entry in fs::read_dir(&dir).unwrap()
+    {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        println!("{}", path.display());
+        fuzz::framer(&data);
+        fuzz::command(&data);
+        fuzz::dispatch(&data);
+        count += 1;
+    }
+    assert!(count > 0, "empty corpus in {}", dir.display());
+}
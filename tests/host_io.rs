@@ -0,0 +1,59 @@
+//!Host I/O: vFile-пакеты над каталогом-песочницей (remote get/put/delete в GDB)
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::fs;
+use std::io::ErrorKind;
+
+use gdb_rsp_researcher::{ServerConfig, DummyTarget};
+use gdb_rsp_researcher::codec::hex_encode;
+use gdb_rsp_researcher::host_io::{STAT_SIZE, ENOENT, EBADF};
+
+use common::{start_target, finish, sandbox};
+
+
+#[test]
+fn test_remote_get_put_delete()
+{
+    let (dir, config) = sandbox("host_io_test");
+    let (mut client, handle) = start_target(config, DummyTarget::new(false));
+    client.handshake("").unwrap();
+    assert_eq!(Ok((0, Vec::new())), client.host_io(b"vFile:setfs:0").unwrap());
+
+    //Бинарный файл больше одного пакета: все значения байт, включая '$', '#', '}' и '*'
+    let data: Vec<u8> = (0..3000).map(|i| (i * 7 % 256) as u8).collect();
+    assert_eq!(Ok(()), client.put_file("/firmware.bin", &data).unwrap());
+    assert_eq!(data, fs::read(dir.join("firmware.bin")).unwrap());
+    assert_eq!(Ok(data.clone()), client.get_file("firmware.bin").unwrap());
+
+    //fstat: st_size - 8 байт big-endian после семи 4-байтных полей
+    let open = format!("vFile:open:{},0,0", hex_encode(b"/firmware.bin"));
+    let (fd, _) = client.host_io(open.as_bytes()).unwrap().unwrap();
+    let (len, stat) = client.host_io(format!("vFile:fstat:{:x}", fd).as_bytes()).unwrap().unwrap();
+    assert_eq!((STAT_SIZE as u64, STAT_SIZE), (len, stat.len()));
+    assert_eq!(3000u64.to_be_bytes(), stat[28..36]);
+    assert_eq!(Ok((0, Vec::new())), client.host_io(format!("vFile:close:{:x}", fd).as_bytes()).unwrap());
+    assert_eq!(Err(EBADF), client.host_io(format!("vFile:close:{:x}", fd).as_bytes()).unwrap());
+
+    //Выше корня песочницы подняться нельзя
+    assert_eq!(Ok(()), client.put_file("/../../escape.txt", b"inside").unwrap());
+    assert_eq!(b"inside".to_vec(), fs::read(dir.join("escape.txt")).unwrap());
+
+    assert_eq!(Ok(()), client.delete_file("/firmware.bin").unwrap());
+    assert_eq!(Err(ENOENT), client.get_file("/firmware.bin").unwrap());
+    assert_eq!(Err(ENOENT), client.delete_file("/firmware.bin").unwrap());
+    finish(client, handle);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+
+#[test]
+fn test_without_sandbox()
+{
+    let (mut client, handle) = start_target(ServerConfig::default(), DummyTarget::new(false));
+    client.handshake("").unwrap();
+    assert_eq!(ErrorKind::Unsupported, client.get_file("/etc/passwd").unwrap_err().kind());
+    finish(client, handle);
+}
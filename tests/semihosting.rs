@@ -0,0 +1,145 @@
+//!Semihosting: вызовы программы в симуляторе превращаются в запросы File-I/O, клиент выполняет их как GDB на своей стороне
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::collections::HashMap;
+
+use gdb_rsp_researcher::{RspClient, MemoryStream};
+use gdb_rsp_researcher::codec::escape_binary;
+use gdb_rsp_researcher::semihosting::{SLLI, EBREAK, SRAI, SYS_OPEN, SYS_WRITE, SYS_WRITE0, SYS_READ, SYS_CLOSE, SYS_ERRNO};
+use gdb_rsp_researcher::sim::{PC_REG, A0_REG, A1_REG};
+
+use common::{start, finish};
+
+
+const CODE: u64 = 0x8000_0000;
+const PARAMS: u64 = 0x8000_1000;
+const DATA: u64 = 0x8000_2000;
+
+
+///Файлы на стороне GDB
+#[derive(Default)]
+struct Host
+{
+    files: HashMap<String, Vec<u8>>,
+    open: HashMap<u64, (String, usize)>,            // fd -> (имя, позиция чтения)
+    console: Vec<u8>,
+}
+
+
+///Вызов semihosting: a0/a1, pc на начале последовательности, 'c'. Запросы File-I/O выполняются до Stop Reply
+///Возвращает a0 после вызова
+fn call(client: &mut RspClient<MemoryStream>, host: &mut Host, op: u64, param: u64) -> u64
+{
+    client.write_register(A0_REG, &op.to_le_bytes()).unwrap();
+    client.write_register(A1_REG, &param.to_le_bytes()).unwrap();
+    client.write_register(PC_REG, &CODE.to_le_bytes()).unwrap();
+    let mut reply = client.request("c").unwrap().data;
+    while reply.starts_with('F')
+    {
+        let fields: Vec<&str> = reply[1..].split(',').collect();
+        let arg = |i: usize| u64::from_str_radix(fields[i].split('/').next().unwrap(), 16).unwrap();
+        let result = match fields[0]
+        {
+            "open" =>
+            {
+                let len = u64::from_str_radix(fields[1].split('/').nth(1).unwrap(), 16).unwrap() as usize;
+                let name = client.read_memory(arg(1), len - 1).unwrap().unwrap();
+                let name = String::from_utf8(name).unwrap();
+                if name.starts_with("missing")
+                {
+                    "-1,2".to_string() //ENOENT
+                }
+                else
+                {
+                    host.files.entry(name.clone()).or_default();
+                    let fd = 3 + host.open.len() as u64;
+                    host.open.insert(fd, (name, 0));
+                    format!("{:x}", fd)
+                }
+            },
+            "write" =>
+            {
+                let data = client.read_memory(arg(2), arg(3) as usize).unwrap().unwrap();
+                match arg(1)
+                {
+                    1 => host.console.extend_from_slice(&data),
+                    fd => host.files.get_mut(&host.open[&fd].0).unwrap().extend_from_slice(&data),
+                }
+                format!("{:x}", data.len())
+            },
+            "read" =>
+            {//GDB сам пишет прочитанное в память программы
+                let (name, pos) = host.open.get_mut(&arg(1)).unwrap();
+                let file = &host.files[&name[..]];
+                let data = file[*pos .. file.len().min(*pos + arg(3) as usize)].to_vec();
+                *pos += data.len();
+                let mut packet = format!("X{:x},{:x}:", arg(2), data.len()).into_bytes();
+                packet.extend_from_slice(&escape_binary(&data));
+                assert_eq!("OK", client.request_bytes(&packet).unwrap().data);
+                format!("{:x}", data.len())
+            },
+            "close" =>
+            {
+                host.open.remove(&arg(1));
+                "0".to_string()
+            },
+            _ => "-1,58".to_string(), //ENOSYS
+        };
+        reply = client.request(&format!("F{}", result)).unwrap().data;
+    }
+    //После вызова программа дошла до breakpoint
+    assert!(reply.starts_with("T05"), "{}", reply);
+    assert_eq!(Some((CODE + 12).to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    let a0 = client.read_register(A0_REG).unwrap().unwrap();
+    u64::from_le_bytes([a0[0], a0[1], a0[2], a0[3], a0[4], a0[5], a0[6], a0[7]])
+}
+
+
+fn params(client: &mut RspClient<MemoryStream>, words: &[u64])
+{
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    assert_eq!("OK", client.write_memory(PARAMS, &bytes).unwrap().data);
+}
+
+
+#[test]
+fn test_semihosting_file_io()
+{
+    let (mut client, handle) = start(false);
+    let mut host = Host::default();
+    client.handshake("").unwrap();
+    let code: Vec<u8> = [SLLI, EBREAK, SRAI].iter().flat_map(|w| w.to_le_bytes()).collect();
+    assert_eq!("OK", client.write_memory(CODE, &code).unwrap().data);
+    assert_eq!("OK", client.write_memory(DATA, b"hello\n\0out.txt\0missing\0").unwrap().data);
+
+    //Вывод в консоль GDB
+    assert_eq!(6, call(&mut client, &mut host, SYS_WRITE0, DATA));
+    assert_eq!(b"hello\n".to_vec(), host.console);
+
+    //Файл на стороне GDB: open "w+", write, чтение обратно через новый дескриптор
+    params(&mut client, &[DATA + 7, 6, 7]);
+    let fd = call(&mut client, &mut host, SYS_OPEN, PARAMS);
+    assert_eq!(3, fd);
+    params(&mut client, &[fd, DATA, 5]);
+    assert_eq!(0, call(&mut client, &mut host, SYS_WRITE, PARAMS)); //0 - записано всё
+    assert_eq!(b"hello".to_vec(), host.files["out.txt"]);
+    params(&mut client, &[DATA + 7, 0, 7]);
+    let fd = call(&mut client, &mut host, SYS_OPEN, PARAMS);
+    params(&mut client, &[fd, DATA + 0x100, 8]);
+    assert_eq!(3, call(&mut client, &mut host, SYS_READ, PARAMS)); //3 байта не прочитано
+    assert_eq!(Some(b"hello".to_vec()), client.read_memory(DATA + 0x100, 5).unwrap());
+    params(&mut client, &[fd]);
+    assert_eq!(0, call(&mut client, &mut host, SYS_CLOSE, PARAMS));
+
+    //Ошибка на стороне GDB: -1 и errno
+    params(&mut client, &[DATA + 15, 0, 7]);
+    assert_eq!(u64::MAX, call(&mut client, &mut host, SYS_OPEN, PARAMS));
+    assert_eq!(2, call(&mut client, &mut host, SYS_ERRNO, 0));
+
+    //Ответ F без запроса - ошибка
+    assert_eq!("E01", client.request("F0").unwrap().data);
+    finish(client, handle);
+}
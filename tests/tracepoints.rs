@@ -0,0 +1,84 @@
+//!Tracepoints: эксперимент трассировки на симуляторе, выбор кадров, выгрузка и сохранение трассировки
+
+extern crate gdb_rsp_researcher;
+
+mod common;
+
+use std::fs;
+
+use gdb_rsp_researcher::SimTarget;
+use gdb_rsp_researcher::codec::hex_encode;
+use gdb_rsp_researcher::sim::{PC_REG, A0_REG};
+use gdb_rsp_researcher::trace::TFILE_MAGIC;
+
+use common::{start_target, finish, sandbox};
+
+
+#[test]
+fn test_trace_experiment()
+{
+    let (dir, config) = sandbox("trace_test");
+    let (mut client, handle) = start_target(config, SimTarget::new(false));
+    assert!(client.handshake("").unwrap().contains(";TracepointSource+;EnableDisableTracepoints+;QTBuffer:size+"));
+    assert!(client.request("qTStatus").unwrap().data.starts_with("T0;tnotrun:0;tframes:0;"));
+
+    //Tracepoint 1: регистры и 4 байта памяти. Tracepoint 2: один шаг while-stepping с памятью по a0
+    assert_eq!("OK", client.write_memory(0x8000_1000, b"data").unwrap().data);
+    assert_eq!("OK", client.write_register(A0_REG, &0x8000_1000u64.to_le_bytes()).unwrap().data);
+    for packet in &["QTinit", "QTDP:1:80000008:E:0:0-", "QTDP:-1:80000008:RffffffffM-1,80001000,4", "QTDP:2:80000010:E:1:0-", "QTDP:-2:80000010:SMa,0,2",
+        "QTDPsrc:2:80000010:cmd:0:e:7768696c652d7374657070696e672031", "QTDV:1:0:0:74", "QTBuffer:circular:0", "QTStart"]
+    {
+        assert_eq!("OK", client.request(packet).unwrap().data, "{}", packet);
+    }
+    assert_eq!("E01", client.request("QTDP:-3:80000018:R1").unwrap().data);
+
+    //Продолжение до breakpoint: tracepoints проходятся по пути
+    assert_eq!("OK", client.matchpoint(true, 0, 0x8000_0020, 4).unwrap().data);
+    assert_eq!("OK", client.write_register(PC_REG, &0x8000_0000u64.to_le_bytes()).unwrap().data);
+    assert!(client.request("c").unwrap().data.starts_with("T05"));
+    assert_eq!(Some(0x8000_0020u64.to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    assert!(client.request("qTStatus").unwrap().data.starts_with("T1;tnotrun:0;tframes:3;tcreated:3;"));
+    assert_eq!("OK", client.request("QTStop").unwrap().data);
+    assert!(client.request("qTStatus").unwrap().data.starts_with("T0;tstop:0;tframes:3;"));
+    assert_eq!("V1:19", client.request("qTP:2:80000010").unwrap().data);
+
+    //Кадр tracepoint 1: регистры и собранная память, несобранная память недоступна
+    assert_eq!("F0T1", client.request("QTFrame:0").unwrap().data);
+    assert_eq!(Some(0x8000_0008u64.to_le_bytes().to_vec()), client.read_register(PC_REG).unwrap());
+    assert_eq!(hex_encode(&0x8000_0008u64.to_le_bytes()), client.request("g").unwrap().data[PC_REG * 16 ..]);
+    assert_eq!("E01", client.request("pffffffffffffffff").unwrap().data);
+    assert_eq!(Some(b"data".to_vec()), client.read_memory(0x8000_1000, 4).unwrap());
+    assert_eq!(None, client.read_memory(0x8000_1000, 5).unwrap());
+
+    //Кадр шага while-stepping: регистры не собирались
+    assert_eq!("F2T2", client.request("QTFrame:pc:80000014").unwrap().data);
+    assert_eq!("xx".repeat(33 * 8), client.request("g").unwrap().data);
+    assert_eq!(Some(b"da".to_vec()), client.read_memory(0x8000_1000, 2).unwrap());
+    assert_eq!("F-1", client.request("QTFrame:tdp:1").unwrap().data);
+    assert_eq!("F1T2", client.request("QTFrame:tdp:2").unwrap().data);
+    assert_eq!("OK", client.request("QTFrame:ffffffff").unwrap().data);
+    assert_eq!(Some(b"data\0".to_vec()), client.read_memory(0x8000_1000, 5).unwrap());
+
+    //Выгрузка tracepoints
+    let mut lines = vec![client.request("qTfP").unwrap().data];
+    while lines.last().unwrap() != "l"
+    {
+        lines.push(client.request("qTsP").unwrap().data);
+    }
+    assert_eq!(vec!["T1:0000000080000008:E:0:0", "A1:0000000080000008:RffffffffM-1,80001000,4", "T2:0000000080000010:E:1:0",
+        "S2:0000000080000010:Ma,0,2", "Z2:80000010:cmd:0:e:7768696c652d7374657070696e672031", "l"], lines);
+    assert_eq!("l", client.request("qTfV").unwrap().data);
+
+    //Буфер кадров (tsave) и файл трассировки на стороне цели (tsave -r)
+    let buffer = client.request("qTBuffer:0,1000").unwrap().data;
+    assert_eq!(2 * ((6 + 1 + 33 * 8 + 15) + 6 + (6 + 13)), buffer.len());
+    assert_eq!("l", client.request(&format!("qTBuffer:{:x},10", buffer.len() / 2)).unwrap().data);
+    assert_eq!("OK", client.request(&format!("QTSave:{}", hex_encode(b"/trace.tf"))).unwrap().data);
+    let file = fs::read(dir.join("trace.tf")).unwrap();
+    assert!(file.starts_with(TFILE_MAGIC));
+    assert!(String::from_utf8_lossy(&file).contains("\nR 108\nstatus 0;tstop:0;tframes:3;"));
+    assert_eq!("OK", client.request("QTinit").unwrap().data);
+    assert!(client.request("qTStatus").unwrap().data.starts_with("T0;tnotrun:0;tframes:0;"));
+    finish(client, handle);
+    fs::remove_dir_all(&dir).unwrap();
+}